@@ -0,0 +1,66 @@
+/*
+ * Plugin interface for invoicehandler.
+ *
+ * A plugin is a shared library (.so, .dylib or .dll) in the directory set in
+ * [plugins] that exports invoicehandler_plugin(). It is loaded once at
+ * startup and stays loaded until the daemon exits.
+ *
+ * Callbacks may be called from any thread and must be thread-safe. Strings
+ * are UTF-8 and NUL-terminated. Strings passed to a callback are only valid
+ * for the duration of the call; strings returned by a callback are owned by
+ * the plugin and handed back through free_string once invoicehandler has
+ * copied them.
+ */
+#ifndef INVOICEHANDLER_PLUGIN_H
+#define INVOICEHANDLER_PLUGIN_H
+
+#include <stdint.h>
+
+#define INVOICEHANDLER_PLUGIN_ABI 1
+
+#ifdef _WIN32
+#define INVOICEHANDLER_PLUGIN_EXPORT __declspec(dllexport)
+#else
+#define INVOICEHANDLER_PLUGIN_EXPORT __attribute__((visibility("default")))
+#endif
+
+typedef struct InvoicehandlerPlugin {
+    /* Set to INVOICEHANDLER_PLUGIN_ABI. */
+    uint32_t abi_version;
+
+    /* Shown in logs. May be NULL to use the library's file name. */
+    const char *name;
+
+    /*
+     * Extractor, may be NULL. Called with the path of each renamed file.
+     * Returns a JSON object of string fields, e.g. {"amount":"99.50"}, which
+     * are added to the processed event and can be used as ledger columns, or
+     * NULL for none. Fields captured by the rule take precedence.
+     */
+    char *(*extract)(const char *path);
+
+    /*
+     * Event callback, may be NULL. Called with every published event as
+     * JSON, in the format described under "Event publishing" in the README.
+     * Returns 0 on success; on failure, returns non-zero and may set *error
+     * to a message, which is logged.
+     */
+    int32_t (*on_event)(const char *event, char **error);
+
+    /*
+     * Action, may be NULL. Called for each matched file when the plugin is
+     * listed as plugin:<library name> in the run list of [actions], with a
+     * JSON object of the file's path, original_path, new_name, rule and
+     * fields. Returns 0 on success; on failure, returns non-zero and may set
+     * *error to a message, and the file fails like with any other action.
+     */
+    int32_t (*run)(const char *file, char **error);
+
+    /* Frees a string returned by extract, on_event or run. May be NULL if
+     * the plugin only returns static strings. */
+    void (*free_string)(char *string);
+} InvoicehandlerPlugin;
+
+INVOICEHANDLER_PLUGIN_EXPORT const InvoicehandlerPlugin *invoicehandler_plugin(void);
+
+#endif
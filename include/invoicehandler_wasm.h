@@ -0,0 +1,50 @@
+/*
+ * WebAssembly plugin interface for invoicehandler.
+ *
+ * A WebAssembly plugin is a core module (.wasm) in the directory set in
+ * [plugins], e.g. built for wasm32-unknown-unknown. It gets no imports, so it
+ * can't reach the filesystem, the network or the clock, and each call runs in
+ * a fresh instance limited by wasm_fuel and wasm_max_memory_mb.
+ *
+ * For each call, invoicehandler allocates room for the input with
+ * invoicehandler_alloc, copies the input there and calls the hook with its
+ * pointer and length. A hook returns its output as (pointer << 32) | length,
+ * or 0 for none. The instance is discarded afterwards, so nothing needs to be
+ * freed.
+ */
+#ifndef INVOICEHANDLER_WASM_H
+#define INVOICEHANDLER_WASM_H
+
+#include <stdint.h>
+
+#define INVOICEHANDLER_WASM_ABI 1
+
+#define INVOICEHANDLER_WASM_EXPORT(name) __attribute__((export_name(#name)))
+
+/* Returns INVOICEHANDLER_WASM_ABI. */
+INVOICEHANDLER_WASM_EXPORT(invoicehandler_abi_version)
+int32_t invoicehandler_abi_version(void);
+
+/* Returns a pointer to len bytes of memory for the input. */
+INVOICEHANDLER_WASM_EXPORT(invoicehandler_alloc)
+int32_t invoicehandler_alloc(int32_t len);
+
+/*
+ * Optional. The input is a JSON object with the original "filename", the
+ * "new_name" the rule (or an earlier plugin) produced, the "rule" pattern and
+ * the rule's captured "fields". Returns the filename to use instead, which
+ * must not contain path separators, or 0 to keep new_name.
+ */
+INVOICEHANDLER_WASM_EXPORT(invoicehandler_transform)
+int64_t invoicehandler_transform(int32_t ptr, int32_t len);
+
+/*
+ * Optional. The input is the contents of a renamed file, unless it is larger
+ * than wasm_max_file_mb. Returns a JSON object of string fields, e.g.
+ * {"amount":"99.50"}, or 0 for none. Fields captured by the rule take
+ * precedence.
+ */
+INVOICEHANDLER_WASM_EXPORT(invoicehandler_extract)
+int64_t invoicehandler_extract(int32_t ptr, int32_t len);
+
+#endif
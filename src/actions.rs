@@ -0,0 +1,145 @@
+mod checksum;
+mod exec;
+mod files;
+mod plugin;
+mod tag;
+mod webhook;
+
+pub(crate) use files::rename_no_replace;
+
+use crate::error::{ActionError, FileError};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// One step a matched file goes through, configured under `[actions]`.
+pub trait Action: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn run(&self, file: &mut MatchedFile) -> Result<(), ActionError>;
+
+    /// Cleans up after a run that was interrupted, e.g. by a crash. Called
+    /// once on startup, with whether the watch directory's subdirectories
+    /// are watched too.
+    fn recover(&self, _watch_directory: &Path, _recursive: bool) {}
+
+    /// Whether `filename` is a file the action writes next to the ones it
+    /// processes, such as a checksum.
+    fn writes(&self, _filename: &str) -> bool {
+        false
+    }
+
+    /// Where the action would write the file, for `once --dry-run`. `path`
+    /// is where the file is before the action, and is moved along when the
+    /// file itself goes there.
+    fn plan(&self, _path: &mut PathBuf, _new_name: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// A matched file on its way through the actions.
+pub struct MatchedFile<'a> {
+    /// Where the file is now. Actions that move it update this.
+    pub path: PathBuf,
+    pub original_path: &'a Path,
+    /// The filename the rule produced.
+    pub new_name: &'a str,
+    pub rule: &'a str,
+    pub fields: &'a BTreeMap<String, String>,
+}
+
+type Constructor = fn(&ini::Ini, &ini::Properties) -> Result<Box<dyn Action>, String>;
+
+/// The actions `run` can list besides `plugin:<name>`, each reading its
+/// `<name>_*` keys from `[actions]` and any other section it needs from the
+/// config file.
+const REGISTRY: &[(&str, Constructor)] = &[
+    ("rename", files::Rename::load),
+    ("move", files::Move::load),
+    ("copy", files::Copy::load),
+    ("exec", exec::Exec::load),
+    ("webhook", webhook::Webhook::load),
+    ("checksum", checksum::Checksum::load),
+    ("tag", tag::Tag::load),
+];
+
+/// What happens to a matched file, in order. Without an `[actions]` section
+/// it is only renamed.
+pub struct Actions {
+    actions: Vec<Box<dyn Action>>,
+}
+
+pub fn load_action_settings(ini: &ini::Ini) -> Result<Actions, String> {
+    let Some(section) = ini.section(Some("actions")) else {
+        return Ok(Actions {
+            actions: vec![Box::<files::Rename>::default()],
+        });
+    };
+
+    let actions = section
+        .get("run")
+        .ok_or("Missing 'run' in [actions]")?
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            if let Some(plugin) = name.strip_prefix("plugin:") {
+                return plugin::PluginAction::load(ini, plugin.trim());
+            }
+            let (_, load) = REGISTRY
+                .iter()
+                .find(|(registered, _)| *registered == name)
+                .ok_or_else(|| {
+                    let names: Vec<&str> = REGISTRY.iter().map(|(name, _)| *name).collect();
+                    format!(
+                        "Unknown action '{}' in [actions], expected one of: {} or plugin:<name>",
+                        name,
+                        names.join(", ")
+                    )
+                })?;
+            load(ini, section)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if actions.is_empty() {
+        return Err("No actions configured in [actions]".to_string());
+    }
+
+    Ok(Actions { actions })
+}
+
+impl Actions {
+    /// Stops at the first action that fails. `file.path` is where the file
+    /// ended up either way.
+    pub fn run(&self, file: &mut MatchedFile) -> Result<(), FileError> {
+        for action in &self.actions {
+            action.run(file).map_err(|source| FileError::Action {
+                action: action.name(),
+                path: file.path.clone(),
+                source,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Whether `filename` was written by the actions rather than dropped
+    /// off, so it must not be processed.
+    pub fn wrote(&self, filename: &str) -> bool {
+        files::is_temp_name(filename) || self.actions.iter().any(|action| action.writes(filename))
+    }
+
+    /// Every path the actions would write the file at `path` to, in order,
+    /// without touching it.
+    pub fn plan(&self, path: &Path, new_name: &str) -> Vec<PathBuf> {
+        let mut path = path.to_path_buf();
+        self.actions
+            .iter()
+            .filter_map(|action| action.plan(&mut path, new_name))
+            .collect()
+    }
+
+    pub fn recover(&self, watch_directory: &Path, recursive: bool) {
+        for action in &self.actions {
+            action.recover(watch_directory, recursive);
+        }
+    }
+}
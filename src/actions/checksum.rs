@@ -0,0 +1,167 @@
+use super::{files, Action, MatchedFile};
+use crate::error::ActionError;
+use crate::hashing::{self, HashSettings};
+use std::fs::OpenOptions;
+use std::io::Write;
+
+/// Records the SHA-256 of the file where it is now, in the format of
+/// `sha256sum`, so `sha256sum -c` can verify it later. Usually listed after
+/// `move`, to cover the archived file.
+pub struct Checksum {
+    manifest: Option<String>,
+    hashing: HashSettings,
+    fsync: bool,
+}
+
+impl Checksum {
+    pub fn load(ini: &ini::Ini, section: &ini::Properties) -> Result<Box<dyn Action>, String> {
+        let manifest = match section.get("checksum_mode").unwrap_or("sidecar") {
+            "sidecar" => None,
+            "manifest" => {
+                let manifest = section.get("checksum_manifest").unwrap_or("SHA256SUMS");
+                if manifest.is_empty() || manifest.contains(['/', '\\']) {
+                    return Err(format!(
+                        "Invalid checksum_manifest '{}', expected a file name",
+                        manifest
+                    ));
+                }
+                Some(manifest.to_string())
+            }
+            other => {
+                return Err(format!(
+                    "Invalid checksum_mode '{}', expected sidecar or manifest",
+                    other
+                ))
+            }
+        };
+
+        Ok(Box::new(Checksum {
+            manifest,
+            // An archived file without a checksum can't be proven unaltered.
+            hashing: hashing::load_hash_settings(ini)?.without_size_limit(),
+            fsync: files::fsync(section)?,
+        }))
+    }
+}
+
+impl Action for Checksum {
+    fn name(&self) -> &'static str {
+        "checksum"
+    }
+
+    fn run(&self, file: &mut MatchedFile) -> Result<(), ActionError> {
+        let hash =
+            hashing::sha256_file(&file.path, &self.hashing)?.ok_or("File is too large to hash")?;
+        let name = file
+            .path
+            .file_name()
+            .ok_or("File has no name")?
+            .to_string_lossy();
+        let line = format!("{}  {}\n", hash, name);
+
+        let target = match &self.manifest {
+            Some(manifest) => file.path.with_file_name(manifest),
+            None => file.path.with_file_name(format!("{}.sha256", name)),
+        };
+        let mut output = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(self.manifest.is_some())
+            .truncate(self.manifest.is_none())
+            .open(&target)
+            .map_err(|e| ActionError::io(format!("Failed to open {}", target.display()), e))?;
+        output
+            .write_all(line.as_bytes())
+            .map_err(|e| ActionError::io(format!("Failed to write {}", target.display()), e))?;
+
+        if self.fsync {
+            output
+                .sync_all()
+                .map_err(|e| ActionError::io(format!("Failed to sync {}", target.display()), e))?;
+            files::sync_parent(&target)?;
+        }
+        Ok(())
+    }
+
+    fn writes(&self, filename: &str) -> bool {
+        match &self.manifest {
+            Some(manifest) => filename == manifest,
+            None => filename.ends_with(".sha256"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::path::Path;
+
+    const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    fn run(action: &dyn Action, path: &Path) -> Result<(), ActionError> {
+        let fields = BTreeMap::new();
+        action.run(&mut MatchedFile {
+            path: path.to_path_buf(),
+            original_path: path,
+            new_name: "Invoice.pdf",
+            rule: "test",
+            fields: &fields,
+        })
+    }
+
+    #[test]
+    fn checksum() {
+        let dir =
+            std::env::temp_dir().join(format!("invoicehandler-checksum-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.pdf"), "abc").unwrap();
+        fs::write(dir.join("b.pdf"), "abc").unwrap();
+        let load = |config: &str| {
+            let ini = ini::Ini::load_from_str(&format!("[actions]\n{}\n", config)).unwrap();
+            Checksum::load(&ini, ini.section(Some("actions")).unwrap())
+        };
+
+        let sidecar = load("").unwrap();
+        run(&*sidecar, &dir.join("a.pdf")).unwrap();
+        run(&*sidecar, &dir.join("a.pdf")).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("a.pdf.sha256")).unwrap(),
+            format!("{}  a.pdf\n", ABC)
+        );
+        assert!(sidecar.writes("a.pdf.sha256"));
+        assert!(!sidecar.writes("a.pdf"));
+
+        let manifest = load("checksum_mode = manifest\nchecksum_manifest = SUMS").unwrap();
+        run(&*manifest, &dir.join("a.pdf")).unwrap();
+        run(&*manifest, &dir.join("b.pdf")).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("SUMS")).unwrap(),
+            format!("{}  a.pdf\n{}  b.pdf\n", ABC, ABC)
+        );
+        assert!(manifest.writes("SUMS"));
+        assert!(!manifest.writes("a.pdf.sha256"));
+
+        let cases = [
+            (
+                "checksum_mode = manifest\nchecksum_manifest = ../SUMS",
+                "Invalid checksum_manifest '../SUMS'",
+            ),
+            (
+                "checksum_mode = manifest\nchecksum_manifest =",
+                "Invalid checksum_manifest ''",
+            ),
+            ("checksum_mode = both", "Invalid checksum_mode 'both'"),
+        ];
+        for (config, prefix) in cases {
+            match load(config) {
+                Err(e) => assert!(e.starts_with(prefix), "{}: {}", config, e),
+                Ok(_) => panic!("{}: loaded, expected {:?}", config, prefix),
+            }
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use super::{Action, MatchedFile};
+use crate::error::ActionError;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs `exec_command` with the file's path as its last argument. The rule
+/// and its fields are passed in `INVOICEHANDLER_*` environment variables.
+pub struct Exec {
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl Exec {
+    pub fn load(_ini: &ini::Ini, section: &ini::Properties) -> Result<Box<dyn Action>, String> {
+        let command = section
+            .get("exec_command")
+            .ok_or("Missing 'exec_command' in [actions]")?;
+
+        let mut words = command.split_whitespace().map(str::to_string);
+        let program = words.next().ok_or("Empty exec_command in [actions]")?;
+
+        let timeout_secs: u64 = section
+            .get("exec_timeout_secs")
+            .unwrap_or("60")
+            .parse()
+            .map_err(|e| format!("Invalid exec_timeout_secs: {}", e))?;
+
+        Ok(Box::new(Exec {
+            program,
+            args: words.collect(),
+            timeout: Duration::from_secs(timeout_secs),
+        }))
+    }
+}
+
+impl Action for Exec {
+    fn name(&self) -> &'static str {
+        "exec"
+    }
+
+    fn run(&self, file: &mut MatchedFile) -> Result<(), ActionError> {
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .arg(&file.path)
+            .env("INVOICEHANDLER_ORIGINAL_PATH", file.original_path)
+            .env("INVOICEHANDLER_NEW_NAME", file.new_name)
+            .env("INVOICEHANDLER_RULE", file.rule)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        for (name, value) in file.fields {
+            command.env(
+                format!("INVOICEHANDLER_FIELD_{}", name.to_uppercase()),
+                value,
+            );
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(|e| ActionError::io(format!("Failed to run {}", self.program), e))?;
+
+        // Read on another thread so a chatty command can't fill the pipe and
+        // block before it exits.
+        let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+        let stderr = thread::spawn(move || {
+            let mut stderr = String::new();
+            let _ = stderr_pipe.read_to_string(&mut stderr);
+            stderr
+        });
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child
+                .try_wait()
+                .map_err(|e| ActionError::io(format!("Failed to wait for {}", self.program), e))?
+            {
+                break status;
+            }
+            if started.elapsed() >= self.timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "{} didn't finish within {}s",
+                    self.program,
+                    self.timeout.as_secs()
+                )
+                .into());
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+
+        if status.success() {
+            return Ok(());
+        }
+        let stderr = stderr.join().unwrap_or_default();
+        Err(match stderr.trim() {
+            "" => format!("{} exited with {}", self.program, status),
+            stderr => format!("{} exited with {}: {}", self.program, status, stderr),
+        }
+        .into())
+    }
+}
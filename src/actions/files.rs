@@ -0,0 +1,691 @@
+use super::{Action, MatchedFile};
+use crate::error::ActionError;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Files pass through `<TEMP_PREFIX><id>-<name>` on their way to their new
+/// name, where `name` is the one they had before.
+const TEMP_PREFIX: &str = ".invoicehandler-tmp-";
+
+/// How many `(recovered <n>)` names are tried for a temporary file before
+/// it is left where it is.
+const MAX_RECOVERED_NAMES: u32 = 100;
+
+/// Gives the file its new name in place.
+#[derive(Default)]
+pub struct Rename {
+    fsync: bool,
+}
+
+impl Rename {
+    pub fn load(_ini: &ini::Ini, section: &ini::Properties) -> Result<Box<dyn Action>, String> {
+        Ok(Box::new(Rename {
+            fsync: fsync(section)?,
+        }))
+    }
+}
+
+impl Action for Rename {
+    fn name(&self) -> &'static str {
+        "rename"
+    }
+
+    fn run(&self, file: &mut MatchedFile) -> Result<(), ActionError> {
+        let new_path = file.path.with_file_name(file.new_name);
+        let temp = temp_path(file.path.parent().unwrap_or(Path::new("")), &file.path);
+        rename_checked(&file.path, &temp)?;
+        finish(&temp, &new_path, Some(&file.path))?;
+        file.path = new_path;
+        if self.fsync {
+            sync_parent(&file.path)?;
+        }
+        Ok(())
+    }
+
+    fn plan(&self, path: &mut PathBuf, new_name: &str) -> Option<PathBuf> {
+        *path = path.with_file_name(new_name);
+        Some(path.clone())
+    }
+
+    /// Temporary files in the watch directory, and its subdirectories with
+    /// `recursive`, were renamed away from their original name and are given
+    /// it back, so they are processed again. When a new file has taken the
+    /// name since, they are restored next to it instead.
+    fn recover(&self, watch_directory: &Path, recursive: bool) {
+        for (temp, name) in temp_files(watch_directory, recursive) {
+            restore(&temp, &temp.with_file_name(&name), "rename");
+        }
+    }
+}
+
+/// Moves the file into `move_directory` under its new name.
+pub struct Move {
+    directory: PathBuf,
+    fsync: bool,
+}
+
+impl Move {
+    pub fn load(_ini: &ini::Ini, section: &ini::Properties) -> Result<Box<dyn Action>, String> {
+        Ok(Box::new(Move {
+            directory: directory(section, "move_directory")?,
+            fsync: fsync(section)?,
+        }))
+    }
+}
+
+impl Action for Move {
+    fn name(&self) -> &'static str {
+        "move"
+    }
+
+    fn run(&self, file: &mut MatchedFile) -> Result<(), ActionError> {
+        let new_path = destination(&self.directory, file.new_name)?;
+        let temp = temp_path(&self.directory, &file.path);
+        match fs::rename(&file.path, &temp) {
+            Ok(()) => {}
+            // Archive directories are often on another volume or a share.
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                if let Err(e) = copy_to_temp(&file.path, &temp, self.fsync) {
+                    remove_orphan(&temp);
+                    return Err(e);
+                }
+                finish(&temp, &new_path, None)?;
+                if self.fsync {
+                    sync_parent(&new_path)?;
+                }
+                fs::remove_file(&file.path).map_err(|e| {
+                    ActionError::io(
+                        format!("Copied to {} but failed to remove", new_path.display()),
+                        e,
+                    )
+                })?;
+                let original = std::mem::replace(&mut file.path, new_path);
+                if self.fsync {
+                    sync_parent(&original)?;
+                }
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(ActionError::io(
+                    format!("Failed to move {}", file.path.display()),
+                    e,
+                ))
+            }
+        }
+        check_exists(&temp)?;
+        finish(&temp, &new_path, Some(&file.path))?;
+        let original = std::mem::replace(&mut file.path, new_path);
+        if self.fsync {
+            sync_parent(&file.path)?;
+            sync_parent(&original)?;
+        }
+        Ok(())
+    }
+
+    fn plan(&self, path: &mut PathBuf, new_name: &str) -> Option<PathBuf> {
+        *path = self.directory.join(new_name);
+        Some(path.clone())
+    }
+
+    /// A temporary file with the same contents as a file of its original
+    /// name in the watch directory, or any of its subdirectories with
+    /// `recursive`, is a copy whose original was never removed, and is
+    /// removed itself. One that differs from a file of that name may be half
+    /// a copy or a different invoice, and is left for the operator. Any
+    /// other was moved in full and gets its original name back.
+    fn recover(&self, watch_directory: &Path, recursive: bool) {
+        let mut watched = Vec::new();
+        walk(watch_directory, recursive, &mut watched);
+        for (temp, name) in temp_files(&self.directory, false) {
+            let mut sources = watched
+                .iter()
+                .filter(|path| path.file_name() == Some(OsStr::new(&name)))
+                .peekable();
+            if sources.peek().is_none() {
+                restore(&temp, &self.directory.join(&name), "move");
+            } else if sources.any(|source| same_contents(&temp, source)) {
+                remove_orphan(&temp);
+            } else {
+                warn!(
+                    path = %temp.display(),
+                    "Left temporary file alone, a different file in the watch directory has its name"
+                );
+            }
+        }
+    }
+}
+
+/// Copies the file into `copy_directory` under its new name and leaves it
+/// where it is. With `copy_hardlink` the copy is a hard link to the file
+/// when both are on the same file system.
+pub struct Copy {
+    directory: PathBuf,
+    fsync: bool,
+    hardlink: bool,
+}
+
+impl Copy {
+    pub fn load(_ini: &ini::Ini, section: &ini::Properties) -> Result<Box<dyn Action>, String> {
+        Ok(Box::new(Copy {
+            directory: directory(section, "copy_directory")?,
+            fsync: fsync(section)?,
+            hardlink: section
+                .get("copy_hardlink")
+                .unwrap_or("false")
+                .parse()
+                .map_err(|e| format!("Invalid copy_hardlink: {}", e))?,
+        }))
+    }
+}
+
+impl Action for Copy {
+    fn name(&self) -> &'static str {
+        "copy"
+    }
+
+    fn run(&self, file: &mut MatchedFile) -> Result<(), ActionError> {
+        let copy = destination(&self.directory, file.new_name)?;
+        let temp = temp_path(&self.directory, &file.path);
+        let linked = self.hardlink && link_to_temp(&file.path, &temp)?;
+        if !linked {
+            if let Err(e) = copy_to_temp(&file.path, &temp, self.fsync) {
+                remove_orphan(&temp);
+                return Err(e);
+            }
+        }
+        finish(&temp, &copy, None)?;
+        if self.fsync {
+            sync_parent(&copy)?;
+        }
+        Ok(())
+    }
+
+    fn plan(&self, _path: &mut PathBuf, new_name: &str) -> Option<PathBuf> {
+        Some(self.directory.join(new_name))
+    }
+
+    /// The original of every copy is still around, so temporary files are
+    /// always incomplete leftovers.
+    fn recover(&self, _watch_directory: &Path, _recursive: bool) {
+        for (temp, _) in temp_files(&self.directory, false) {
+            remove_orphan(&temp);
+        }
+    }
+}
+
+fn directory(section: &ini::Properties, key: &str) -> Result<PathBuf, String> {
+    section
+        .get(key)
+        .map(PathBuf::from)
+        .ok_or_else(|| format!("Missing '{}' in [actions]", key))
+}
+
+/// `fsync` in `[actions]`: whether file operations are flushed to disk
+/// before the action succeeds.
+pub(super) fn fsync(section: &ini::Properties) -> Result<bool, String> {
+    section
+        .get("fsync")
+        .unwrap_or("false")
+        .parse()
+        .map_err(|e| format!("Invalid fsync: {}", e))
+}
+
+/// `directory/new_name`, creating the directory if needed.
+fn destination(directory: &Path, new_name: &str) -> Result<PathBuf, ActionError> {
+    fs::create_dir_all(directory)
+        .map_err(|e| ActionError::io(format!("Failed to create {}", directory.display()), e))?;
+    Ok(directory.join(new_name))
+}
+
+/// A temporary name in `directory` for the file at `path`, unique per call.
+fn temp_path(directory: &Path, path: &Path) -> PathBuf {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos())
+        .unwrap_or_default();
+    let id = process::id() ^ nanos ^ COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    directory.join(format!("{}{:08x}-{}", TEMP_PREFIX, id, name))
+}
+
+fn copy_to_temp(from: &Path, temp: &Path, fsync: bool) -> Result<(), ActionError> {
+    fs::copy(from, temp)
+        .map_err(|e| ActionError::io(format!("Failed to copy {}", from.display()), e))?;
+    if fsync {
+        File::open(temp)
+            .and_then(|copy| copy.sync_all())
+            .map_err(|e| ActionError::io(format!("Failed to sync {}", temp.display()), e))?;
+    }
+    Ok(())
+}
+
+/// Returns false when `temp` can't be a hard link to `from` because it is on
+/// another file system, or one without hard links, so it has to be copied.
+fn link_to_temp(from: &Path, temp: &Path) -> Result<bool, ActionError> {
+    match fs::hard_link(from, temp) {
+        Ok(()) => Ok(true),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::CrossesDevices | io::ErrorKind::Unsupported
+            ) =>
+        {
+            warn!(
+                path = %from.display(),
+                error = %e,
+                "Can't hard link into copy_directory, copying instead"
+            );
+            Ok(false)
+        }
+        Err(e) => Err(ActionError::io(
+            format!("Failed to link {}", from.display()),
+            e,
+        )),
+    }
+}
+
+/// Flushes the directory entry of `path`, so that a rename into or out of
+/// its directory survives a power loss. Windows can't sync a directory
+/// through a file handle, and NTFS journals renames anyway.
+pub(super) fn sync_parent(path: &Path) -> Result<(), ActionError> {
+    #[cfg(unix)]
+    {
+        let directory = match path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => Path::new("."),
+        };
+        File::open(directory)
+            .and_then(|directory| directory.sync_all())
+            .map_err(|e| ActionError::io(format!("Failed to sync {}", directory.display()), e))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Gives the temporary file its final name, unless a file already has it.
+/// When that fails the file is renamed back to `original` if given, or
+/// removed otherwise.
+fn finish(temp: &Path, new_path: &Path, original: Option<&Path>) -> Result<(), ActionError> {
+    let renamed = match rename_no_replace(temp, new_path) {
+        Ok(()) => check_exists(new_path),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            Err(ActionError::AlreadyExists(new_path.to_path_buf()))
+        }
+        Err(e) => Err(ActionError::io(
+            format!("Failed to rename {}", temp.display()),
+            e,
+        )),
+    };
+    if let Err(e) = renamed {
+        match original {
+            Some(original) => {
+                if let Err(undo) = fs::rename(temp, original) {
+                    return Err(ActionError::io(
+                        format!(
+                            "{}, and failed to restore {} from {}",
+                            e,
+                            original.display(),
+                            temp.display()
+                        ),
+                        undo,
+                    ));
+                }
+            }
+            None => remove_orphan(temp),
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Renames `from` to `to`, failing with `AlreadyExists` instead of replacing
+/// a file that is there. A hard link can't replace anything, so `to` is
+/// linked and `from` removed; file systems without hard links are checked
+/// first instead, which leaves a short race.
+pub(crate) fn rename_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Ok(()) => fs::remove_file(from),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(e),
+        Err(_) if fs::symlink_metadata(to).is_ok() => Err(io::ErrorKind::AlreadyExists.into()),
+        Err(_) => fs::rename(from, to),
+    }
+}
+
+fn rename_checked(from: &Path, to: &Path) -> Result<(), ActionError> {
+    fs::rename(from, to)
+        .map_err(|e| ActionError::io(format!("Failed to rename {}", from.display()), e))?;
+    check_exists(to)
+}
+
+fn check_exists(path: &Path) -> Result<(), ActionError> {
+    match fs::symlink_metadata(path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ActionError::io(
+            format!("{} is missing after rename", path.display()),
+            e,
+        )),
+    }
+}
+
+/// Gives the temporary file `original` back as its name, or `<name>
+/// (recovered).<ext>` and so on when a new file has taken it since, so it
+/// never replaces anything.
+fn restore(temp: &Path, original: &Path, action: &str) {
+    let stem = original.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = original
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    for attempt in 0..MAX_RECOVERED_NAMES {
+        let restored = match attempt {
+            0 => original.to_path_buf(),
+            1 => original.with_file_name(format!("{} (recovered){}", stem, extension)),
+            n => original.with_file_name(format!("{} (recovered {}){}", stem, n, extension)),
+        };
+        match rename_no_replace(temp, &restored) {
+            Ok(()) => {
+                warn!(
+                    path = %restored.display(),
+                    action,
+                    "Restored file from an interrupted action"
+                );
+                return;
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                warn!(path = %temp.display(), error = %e, "Failed to restore temporary file");
+                return;
+            }
+        }
+    }
+    warn!(path = %temp.display(), "Left temporary file alone, no free name to restore it to");
+}
+
+/// Whether the files at `a` and `b` have the same contents. Files that
+/// can't be read are taken to differ.
+fn same_contents(a: &Path, b: &Path) -> bool {
+    let compare = || -> io::Result<bool> {
+        let (a, b) = (File::open(a)?, File::open(b)?);
+        if a.metadata()?.len() != b.metadata()?.len() {
+            return Ok(false);
+        }
+        let (mut a, mut b) = (BufReader::new(a), BufReader::new(b));
+        loop {
+            let chunk = a.fill_buf()?;
+            if chunk.is_empty() {
+                return Ok(b.fill_buf()?.is_empty());
+            }
+            let other = b.fill_buf()?;
+            let len = chunk.len().min(other.len());
+            if len == 0 || chunk[..len] != other[..len] {
+                return Ok(false);
+            }
+            a.consume(len);
+            b.consume(len);
+        }
+    };
+    compare().unwrap_or(false)
+}
+
+fn remove_orphan(temp: &Path) {
+    match fs::remove_file(temp) {
+        Ok(()) => warn!(path = %temp.display(), "Removed leftover temporary file"),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => warn!(path = %temp.display(), error = %e, "Failed to remove temporary file"),
+    }
+}
+
+/// The temporary files in `directory`, and its subdirectories with
+/// `recursive`, with the names they had before.
+fn temp_files(directory: &Path, recursive: bool) -> Vec<(PathBuf, String)> {
+    let mut files = Vec::new();
+    walk(directory, recursive, &mut files);
+    files
+        .into_iter()
+        .filter_map(|path| {
+            let (_id, name) = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix(TEMP_PREFIX)?
+                .split_once('-')?;
+            let name = name.to_string();
+            (!name.is_empty()).then_some((path, name))
+        })
+        .collect()
+}
+
+/// Every file in `directory`, and its subdirectories with `recursive`.
+/// Symlinked directories are not followed, as for the watch.
+fn walk(directory: &Path, recursive: bool, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => {
+                if recursive {
+                    walk(&entry.path(), recursive, files);
+                }
+            }
+            Ok(_) => files.push(entry.path()),
+            Err(_) => {}
+        }
+    }
+}
+
+/// Whether `filename` is one of the temporary names files pass through.
+pub fn is_temp_name(filename: &str) -> bool {
+    filename.starts_with(TEMP_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("invoicehandler-files-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Runs `action` on the file at `path`, returning where it ended up.
+    fn run(action: &dyn Action, path: &Path, new_name: &str) -> (PathBuf, Result<(), ActionError>) {
+        let fields = BTreeMap::new();
+        let mut file = MatchedFile {
+            path: path.to_path_buf(),
+            original_path: path,
+            new_name,
+            rule: "test",
+            fields: &fields,
+        };
+        let result = action.run(&mut file);
+        (file.path, result)
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn move_collision() {
+        let dir = test_dir("move");
+        let archive = dir.join("archive");
+        let action = Move {
+            directory: archive.clone(),
+            fsync: false,
+        };
+        fs::write(dir.join("a.pdf"), "first").unwrap();
+        fs::write(dir.join("b.pdf"), "second").unwrap();
+
+        let (path, result) = run(&action, &dir.join("a.pdf"), "Invoice.pdf");
+        result.unwrap();
+        assert_eq!(path, archive.join("Invoice.pdf"));
+
+        let (path, result) = run(&action, &dir.join("b.pdf"), "Invoice.pdf");
+        let error = result.unwrap_err();
+        assert!(
+            matches!(&error, ActionError::AlreadyExists(taken) if *taken == archive.join("Invoice.pdf")),
+            "{}",
+            error
+        );
+        assert_eq!(path, dir.join("b.pdf"));
+        assert_eq!(fs::read_to_string(dir.join("b.pdf")).unwrap(), "second");
+        assert_eq!(
+            fs::read_to_string(archive.join("Invoice.pdf")).unwrap(),
+            "first"
+        );
+        assert_eq!(names(&archive), ["Invoice.pdf"]);
+        assert_eq!(names(&dir), ["archive", "b.pdf"]);
+    }
+
+    #[test]
+    fn rename_collision() {
+        let dir = test_dir("rename");
+        let action = Rename::default();
+        fs::write(dir.join("a.pdf"), "first").unwrap();
+        fs::write(dir.join("Invoice.pdf"), "taken").unwrap();
+
+        let (path, result) = run(&action, &dir.join("a.pdf"), "Invoice.pdf");
+        assert!(matches!(result, Err(ActionError::AlreadyExists(_))));
+        assert_eq!(path, dir.join("a.pdf"));
+        assert_eq!(fs::read_to_string(dir.join("a.pdf")).unwrap(), "first");
+        assert_eq!(
+            fs::read_to_string(dir.join("Invoice.pdf")).unwrap(),
+            "taken"
+        );
+        assert_eq!(names(&dir), ["Invoice.pdf", "a.pdf"]);
+
+        let (path, result) = run(&action, &dir.join("a.pdf"), "Invoice_2.pdf");
+        result.unwrap();
+        assert_eq!(path, dir.join("Invoice_2.pdf"));
+        assert_eq!(names(&dir), ["Invoice.pdf", "Invoice_2.pdf"]);
+    }
+
+    #[test]
+    fn copy() {
+        let dir = test_dir("copy");
+        let archive = dir.join("archive");
+        fs::write(dir.join("a.pdf"), "first").unwrap();
+        fs::write(dir.join("b.pdf"), "second").unwrap();
+
+        for hardlink in [false, true] {
+            let _ = fs::remove_dir_all(&archive);
+            let action = Copy {
+                directory: archive.clone(),
+                fsync: false,
+                hardlink,
+            };
+            let (path, result) = run(&action, &dir.join("a.pdf"), "Invoice.pdf");
+            result.unwrap();
+            assert_eq!(path, dir.join("a.pdf"));
+            assert_eq!(
+                fs::read_to_string(archive.join("Invoice.pdf")).unwrap(),
+                "first"
+            );
+
+            let (_, result) = run(&action, &dir.join("b.pdf"), "Invoice.pdf");
+            assert!(result.is_err(), "hardlink = {}", hardlink);
+            assert_eq!(
+                fs::read_to_string(archive.join("Invoice.pdf")).unwrap(),
+                "first"
+            );
+            assert_eq!(names(&archive), ["Invoice.pdf"]);
+        }
+        assert_eq!(names(&dir), ["a.pdf", "archive", "b.pdf"]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let links = fs::metadata(dir.join("a.pdf")).unwrap().nlink();
+            assert_eq!(links, 2);
+        }
+    }
+
+    #[test]
+    fn recover() {
+        let dir = test_dir("recover");
+        let watch = dir.join("watch");
+        let archive = dir.join("archive");
+        let copies = dir.join("copies");
+        for directory in [&watch.join("sub"), &archive, &copies] {
+            fs::create_dir_all(directory).unwrap();
+        }
+        let temp = |directory: &Path, name: &str| {
+            let path = directory.join(format!("{}00000001-{}", TEMP_PREFIX, name));
+            fs::write(&path, name).unwrap();
+            path
+        };
+
+        // Renamed in a subdirectory.
+        let renamed = temp(&watch.join("sub"), "a.pdf");
+        Rename::default().recover(&watch, false);
+        assert!(renamed.exists());
+        Rename::default().recover(&watch, true);
+        assert_eq!(names(&watch.join("sub")), ["a.pdf"]);
+
+        // A new file took the name since, both are kept.
+        fs::write(watch.join("scan.pdf"), "new").unwrap();
+        fs::write(watch.join("scan (recovered).pdf"), "earlier").unwrap();
+        temp(&watch, "scan.pdf");
+        Rename::default().recover(&watch, false);
+        assert_eq!(fs::read_to_string(watch.join("scan.pdf")).unwrap(), "new");
+        assert_eq!(
+            fs::read_to_string(watch.join("scan (recovered 2).pdf")).unwrap(),
+            "scan.pdf"
+        );
+        for name in ["scan.pdf", "scan (recovered).pdf", "scan (recovered 2).pdf"] {
+            fs::remove_file(watch.join(name)).unwrap();
+        }
+
+        // Copied across volumes from a subdirectory, a different file with
+        // the same name, and moved in full twice.
+        let action = Move {
+            directory: archive.clone(),
+            fsync: false,
+        };
+        fs::write(watch.join("sub").join("b.pdf"), "b.pdf").unwrap();
+        fs::write(watch.join("sub").join("d.pdf"), "another d.pdf").unwrap();
+        fs::write(archive.join("e.pdf"), "archived").unwrap();
+        temp(&archive, "b.pdf");
+        temp(&archive, "c.pdf");
+        let different = temp(&archive, "d.pdf");
+        temp(&archive, "e.pdf");
+        action.recover(&watch, true);
+        assert_eq!(
+            names(&archive),
+            [
+                different.file_name().unwrap().to_str().unwrap(),
+                "c.pdf",
+                "e (recovered).pdf",
+                "e.pdf"
+            ]
+        );
+        assert_eq!(names(&watch.join("sub")), ["a.pdf", "b.pdf", "d.pdf"]);
+
+        let action = Copy {
+            directory: copies.clone(),
+            fsync: false,
+            hardlink: false,
+        };
+        temp(&copies, "a.pdf");
+        action.recover(&watch, true);
+        assert!(names(&copies).is_empty());
+    }
+}
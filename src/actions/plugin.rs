@@ -0,0 +1,38 @@
+use super::{Action, MatchedFile};
+use crate::error::ActionError;
+use crate::plugins::{self, NativePlugin};
+use serde_json::json;
+use std::sync::Arc;
+
+/// Hands the file to the `run` callback of a native plugin, listed as
+/// `plugin:<name>` for the library `<name>.so` in the `[plugins]` directory.
+pub struct PluginAction {
+    plugin: Arc<NativePlugin>,
+}
+
+impl PluginAction {
+    pub fn load(ini: &ini::Ini, name: &str) -> Result<Box<dyn Action>, String> {
+        let plugin = plugins::load_native(ini, name)?;
+        if !plugin.has_run() {
+            return Err(format!("Plugin '{}' has no run action", plugin.name));
+        }
+        Ok(Box::new(PluginAction { plugin }))
+    }
+}
+
+impl Action for PluginAction {
+    fn name(&self) -> &'static str {
+        "plugin"
+    }
+
+    fn run(&self, file: &mut MatchedFile) -> Result<(), ActionError> {
+        let file = json!({
+            "path": file.path,
+            "original_path": file.original_path,
+            "new_name": file.new_name,
+            "rule": file.rule,
+            "fields": file.fields,
+        });
+        Ok(self.plugin.run(&file.to_string())?)
+    }
+}
@@ -0,0 +1,117 @@
+use super::{Action, MatchedFile};
+use crate::error::ActionError;
+use std::io;
+use std::path::Path;
+
+const DEFAULT_FIELDS: &str = "vendor,number,date,rule";
+
+/// Writes fields of the file into its extended attributes on Linux and macOS
+/// and into alternate data streams on NTFS, as `invoicehandler.<field>`, so
+/// they stay with the file when it is copied out of the archive. Linux puts
+/// them in the `user.` namespace.
+pub struct Tag {
+    fields: Vec<String>,
+}
+
+impl Tag {
+    pub fn load(_ini: &ini::Ini, section: &ini::Properties) -> Result<Box<dyn Action>, String> {
+        let fields: Vec<String> = section
+            .get("tag_fields")
+            .unwrap_or(DEFAULT_FIELDS)
+            .split(',')
+            .map(|field| field.trim().to_string())
+            .filter(|field| !field.is_empty())
+            .collect();
+        if fields.is_empty() {
+            return Err("No fields in 'tag_fields' in [actions]".to_string());
+        }
+        Ok(Box::new(Tag { fields }))
+    }
+}
+
+impl Action for Tag {
+    fn name(&self) -> &'static str {
+        "tag"
+    }
+
+    fn run(&self, file: &mut MatchedFile) -> Result<(), ActionError> {
+        for field in &self.fields {
+            let value = match field.as_str() {
+                "rule" => Some(file.rule),
+                name => file.fields.get(name).map(String::as_str),
+            };
+            // Fields the rule didn't capture are left untagged.
+            let Some(value) = value.filter(|value| !value.is_empty()) else {
+                continue;
+            };
+            let name = format!("invoicehandler.{}", field);
+            write_tag(&file.path, &name, value).map_err(|e| {
+                ActionError::io(
+                    format!("Failed to tag {} with {}", file.path.display(), name),
+                    e,
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn write_tag(path: &Path, name: &str, value: &str) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[cfg(target_os = "linux")]
+    let name = format!("user.{}", name);
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let name = CString::new(name.as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    // SAFETY: `path` and `name` are NUL-terminated and `value` is valid for
+    // its length.
+    #[cfg(target_os = "macos")]
+    let result = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+            0,
+        )
+    };
+    // SAFETY: as above.
+    #[cfg(target_os = "linux")]
+    let result = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// NTFS opens `file:stream` as a stream of the file.
+#[cfg(windows)]
+fn write_tag(path: &Path, name: &str, value: &str) -> io::Result<()> {
+    let mut stream = path.as_os_str().to_os_string();
+    stream.push(":");
+    stream.push(name);
+    std::fs::write(stream, value)
+}
+
+/// The BSDs have extattr_set_file instead, with namespaces of their own.
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn write_tag(_path: &Path, _name: &str, _value: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "tags are not supported on this platform",
+    ))
+}
@@ -0,0 +1,55 @@
+use super::{Action, MatchedFile};
+use crate::error::ActionError;
+use serde_json::json;
+use std::time::Duration;
+
+/// POSTs the file's path, rule and fields as JSON to `webhook_url`. A status
+/// other than 2xx fails the action.
+pub struct Webhook {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl Webhook {
+    pub fn load(_ini: &ini::Ini, section: &ini::Properties) -> Result<Box<dyn Action>, String> {
+        let url = section
+            .get("webhook_url")
+            .ok_or("Missing 'webhook_url' in [actions]")?;
+
+        let timeout_secs: u64 = section
+            .get("webhook_timeout_secs")
+            .unwrap_or("10")
+            .parse()
+            .map_err(|e| format!("Invalid webhook_timeout_secs: {}", e))?;
+
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(timeout_secs)))
+            .build()
+            .into();
+
+        Ok(Box::new(Webhook {
+            url: url.to_string(),
+            agent,
+        }))
+    }
+}
+
+impl Action for Webhook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn run(&self, file: &mut MatchedFile) -> Result<(), ActionError> {
+        self.agent
+            .post(&self.url)
+            .send_json(json!({
+                "path": file.path,
+                "original_path": file.original_path,
+                "new_name": file.new_name,
+                "rule": file.rule,
+                "fields": file.fields,
+            }))
+            .map(|_| ())
+            .map_err(|e| ActionError::Failed(e.to_string()))
+    }
+}
@@ -0,0 +1,158 @@
+use crate::events::{EventSink, FileEvent, Outcome};
+use chrono::Local;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Number of recent events kept for the dashboard.
+const RECENT_EVENTS: usize = 100;
+
+#[derive(Serialize, Clone, Default)]
+pub struct RuleStats {
+    pub processed: usize,
+    pub failed: usize,
+    pub last_processed: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Stats {
+    pub since: String,
+    pub rules: BTreeMap<String, RuleStats>,
+    pub unmatched: usize,
+}
+
+/// A file still waiting in the watch directory after its last attempt, or
+/// moved into a quarantine directory by the virus scan or duplicate
+/// detection.
+#[derive(Serialize, Clone)]
+pub struct PendingFile {
+    /// Where the file is now.
+    pub path: PathBuf,
+    /// `path` relative to the watch directory, as `/api/reprocess` takes it.
+    /// `None` for quarantined files, which are not reprocessed from there.
+    pub file: Option<String>,
+    pub outcome: Outcome,
+    pub error: Option<String>,
+    pub timestamp: String,
+}
+
+struct ActivityLog {
+    watch_directory: PathBuf,
+    recent: VecDeque<serde_json::Value>,
+    stats: Stats,
+    pending: BTreeMap<PathBuf, PendingFile>,
+}
+
+/// Recent events, per-rule counts since startup, and files left behind
+/// unmatched or failed, for the dashboard and the control API.
+#[derive(Clone)]
+pub struct Activity {
+    log: Arc<Mutex<ActivityLog>>,
+}
+
+impl Activity {
+    pub fn new(watch_directory: &Path) -> Self {
+        Activity {
+            log: Arc::new(Mutex::new(ActivityLog {
+                watch_directory: watch_directory.to_path_buf(),
+                recent: VecDeque::new(),
+                stats: Stats {
+                    since: Local::now().to_rfc3339(),
+                    rules: BTreeMap::new(),
+                    unmatched: 0,
+                },
+                pending: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// Newest first.
+    pub fn recent(&self) -> Vec<serde_json::Value> {
+        self.log
+            .lock()
+            .unwrap()
+            .recent
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.log.lock().unwrap().stats.clone()
+    }
+
+    /// Files that were since removed or renamed by hand, or taken out of
+    /// quarantine, are dropped.
+    pub fn pending(&self) -> Vec<PendingFile> {
+        let mut log = self.log.lock().unwrap();
+        log.pending.retain(|path, _| path.is_file());
+        log.pending.values().cloned().collect()
+    }
+}
+
+impl EventSink for Activity {
+    fn name(&self) -> &'static str {
+        "activity"
+    }
+
+    fn publish(&self, event: &FileEvent, payload: &str) -> Result<(), String> {
+        let mut log = self.log.lock().unwrap();
+
+        if let Ok(value) = serde_json::from_str(payload) {
+            if log.recent.len() == RECENT_EVENTS {
+                log.recent.pop_front();
+            }
+            log.recent.push_back(value);
+        }
+
+        match event.outcome {
+            Outcome::Processed => {
+                let stats = log
+                    .stats
+                    .rules
+                    .entry(event.rule.clone().unwrap_or_default())
+                    .or_default();
+                stats.processed += 1;
+                stats.last_processed = Some(event.timestamp.clone());
+                log.pending.remove(&event.path);
+            }
+            Outcome::Failed | Outcome::Unmatched => {
+                if event.outcome == Outcome::Unmatched {
+                    log.stats.unmatched += 1;
+                } else if let Some(rule) = &event.rule {
+                    log.stats.rules.entry(rule.clone()).or_default().failed += 1;
+                }
+                // Failed files only have a new path when they were
+                // quarantined.
+                let (path, file) = match &event.new_path {
+                    Some(quarantined) => (quarantined.clone(), None),
+                    None => {
+                        let file = event
+                            .path
+                            .strip_prefix(&log.watch_directory)
+                            .unwrap_or(&event.path)
+                            .components()
+                            .map(|component| component.as_os_str().to_string_lossy())
+                            .collect::<Vec<_>>()
+                            .join("/");
+                        (event.path.clone(), Some(file))
+                    }
+                };
+                log.pending.insert(
+                    path.clone(),
+                    PendingFile {
+                        path,
+                        file,
+                        outcome: event.outcome,
+                        error: event.error.clone(),
+                        timestamp: event.timestamp.clone(),
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
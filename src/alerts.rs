@@ -0,0 +1,127 @@
+use crate::events::{EventSink, FileEvent, Outcome};
+use crate::notifications::{Notification, NotificationKind, Notifications};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct AlertSettings {
+    unmatched_threshold: Option<usize>,
+    unmatched_window: Duration,
+}
+
+pub fn load_alert_settings(ini: &ini::Ini) -> Result<Option<AlertSettings>, String> {
+    let section = match ini.section(Some("alerts")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let unmatched_threshold = section
+        .get("unmatched_threshold")
+        .map(|n| {
+            n.parse()
+                .map_err(|e| format!("Invalid unmatched_threshold: {}", e))
+        })
+        .transpose()?;
+
+    let window_mins: u64 = section
+        .get("unmatched_window_mins")
+        .unwrap_or("60")
+        .parse()
+        .map_err(|e| format!("Invalid unmatched_window_mins: {}", e))?;
+
+    if window_mins == 0 {
+        return Err("unmatched_window_mins must be greater than 0".to_string());
+    }
+
+    Ok(Some(AlertSettings {
+        unmatched_threshold,
+        unmatched_window: Duration::from_secs(window_mins * 60),
+    }))
+}
+
+/// Raises an alert once more than `unmatched_threshold` distinct files went
+/// unmatched within the window. A burst like that almost always means a vendor
+/// changed their filename format. The count starts over after each alert.
+pub struct AlertMonitor {
+    settings: AlertSettings,
+    unmatched: Mutex<VecDeque<(Instant, PathBuf)>>,
+    notifications: Notifications,
+}
+
+impl AlertMonitor {
+    pub fn new(settings: &AlertSettings, notifications: Notifications) -> Self {
+        AlertMonitor {
+            settings: settings.clone(),
+            unmatched: Mutex::new(VecDeque::new()),
+            notifications,
+        }
+    }
+
+    fn record_unmatched(&self, threshold: usize, event: &FileEvent) {
+        let mut unmatched = self.unmatched.lock().unwrap();
+        let now = Instant::now();
+
+        while let Some((seen_at, _)) = unmatched.front() {
+            if now.duration_since(*seen_at) < self.settings.unmatched_window {
+                break;
+            }
+            unmatched.pop_front();
+        }
+
+        if unmatched.iter().any(|(_, path)| path == &event.path) {
+            return;
+        }
+        unmatched.push_back((now, event.path.clone()));
+
+        if unmatched.len() <= threshold {
+            return;
+        }
+
+        let window_mins = self.settings.unmatched_window.as_secs() / 60;
+        warn!(
+            count = unmatched.len(),
+            window_mins, "Unmatched file threshold exceeded"
+        );
+
+        let mut body = format!(
+            "{} files matched no rule in the last {} minutes. A vendor may have changed their filename format:\n",
+            unmatched.len(),
+            window_mins
+        );
+        for (_, path) in unmatched.iter() {
+            let _ = writeln!(body, "  {}", path.display());
+        }
+
+        let mut fields = BTreeMap::new();
+        fields.insert("count".to_string(), unmatched.len().to_string());
+        fields.insert("window_mins".to_string(), window_mins.to_string());
+
+        self.notifications.send(Notification {
+            kind: NotificationKind::Alert,
+            title: "Many unmatched files".to_string(),
+            body,
+            fields,
+        });
+
+        unmatched.clear();
+    }
+}
+
+impl EventSink for AlertMonitor {
+    fn name(&self) -> &'static str {
+        "alerts"
+    }
+
+    fn publish(&self, event: &FileEvent, _payload: &str) -> Result<(), String> {
+        if let (Outcome::Unmatched, Some(threshold)) =
+            (event.outcome, self.settings.unmatched_threshold)
+        {
+            self.record_unmatched(threshold, event);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use crate::error::ConfigError;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The identities an age-encrypted config is decrypted with, one per line as
+/// written by `age-keygen`.
+const AGE_KEY_VAR: &str = "INVOICEHANDLER_AGE_KEY";
+/// A file holding those identities, used when `INVOICEHANDLER_AGE_KEY` isn't
+/// set.
+const AGE_KEY_FILE_VAR: &str = "INVOICEHANDLER_AGE_KEY_FILE";
+/// The keyring entry tried last, in builds with the `keyring` feature.
+#[cfg(feature = "keyring")]
+const AGE_KEY_ENTRY: &str = "age-key";
+
+const AGE_HEADER: &[u8] = b"age-encryption.org/";
+const AGE_ARMOR_HEADER: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Reads and parses the config file. Files encrypted with age, binary or
+/// armored, are decrypted first; files encrypted with SOPS, which keep their
+/// metadata in a `[sops]` section, are decrypted by the `sops` binary.
+pub(crate) fn load(config_path: &Path) -> Result<ini::Ini, ConfigError> {
+    let io_error = |source| ConfigError::Io {
+        path: config_path.to_path_buf(),
+        source,
+    };
+    let contents = fs::read(config_path).map_err(io_error)?;
+
+    let text = if contents.starts_with(AGE_HEADER) || contents.starts_with(AGE_ARMOR_HEADER) {
+        decrypt_age(&contents)
+            .map_err(|e| format!("Failed to decrypt {}: {}", config_path.display(), e))?
+    } else {
+        String::from_utf8(contents).map_err(|e| {
+            format!(
+                "Failed to read {}: not UTF-8 or an age-encrypted file: {}",
+                config_path.display(),
+                e
+            )
+        })?
+    };
+
+    let parse = |text: &str| {
+        ini::Ini::load_from_str(text).map_err(|source| ConfigError::Parse {
+            path: config_path.to_path_buf(),
+            source,
+        })
+    };
+    let ini = parse(&text)?;
+    if ini.section(Some("sops")).is_none() {
+        return Ok(ini);
+    }
+
+    let text = decrypt_sops(config_path)
+        .map_err(|e| format!("Failed to decrypt {}: {}", config_path.display(), e))?;
+    parse(&text)
+}
+
+fn decrypt_age(contents: &[u8]) -> Result<String, String> {
+    let identities = age_identities()?;
+    let decryptor =
+        age::Decryptor::new(age::armor::ArmoredReader::new(contents)).map_err(|e| e.to_string())?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|identity| identity.as_ref()))
+        .map_err(|e| e.to_string())?;
+
+    let mut text = String::new();
+    reader
+        .read_to_string(&mut text)
+        .map_err(|e| e.to_string())?;
+    Ok(text)
+}
+
+fn age_identities() -> Result<Vec<Box<dyn age::Identity>>, String> {
+    let keys = if let Ok(keys) = std::env::var(AGE_KEY_VAR) {
+        keys
+    } else if let Ok(path) = std::env::var(AGE_KEY_FILE_VAR) {
+        fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?
+    } else {
+        keyring_identities()?
+    };
+
+    age::IdentityFile::from_buffer(keys.as_bytes())
+        .map_err(|e| e.to_string())
+        .and_then(|file| file.into_identities().map_err(|e| e.to_string()))
+        .map_err(|e| format!("Invalid age identity: {}", e))
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_identities() -> Result<String, String> {
+    crate::secrets::lookup(AGE_KEY_ENTRY).map_err(|e| {
+        format!(
+            "Set {} or {}, or store the key in the keyring: {}",
+            AGE_KEY_VAR, AGE_KEY_FILE_VAR, e
+        )
+    })
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_identities() -> Result<String, String> {
+    Err(format!(
+        "Set {} or {} to the age identity",
+        AGE_KEY_VAR, AGE_KEY_FILE_VAR
+    ))
+}
+
+/// Runs `sops --decrypt`, which finds its keys the usual way, such as
+/// `SOPS_AGE_KEY_FILE`.
+fn decrypt_sops(config_path: &Path) -> Result<String, String> {
+    let output = Command::new("sops")
+        .args(["--decrypt", "--input-type", "ini", "--output-type", "ini"])
+        .arg(config_path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Failed to run sops: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.trim() {
+            "" => format!("sops exited with {}", output.status),
+            stderr => format!("sops: {}", stderr),
+        });
+    }
+    String::from_utf8(output.stdout).map_err(|e| e.to_string())
+}
@@ -0,0 +1,203 @@
+use crate::plugins;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Files with these extensions are read as they are.
+const TEXT_EXTENSIONS: &[&str] = &["txt", "csv", "xml", "json", "html", "htm", "eml"];
+
+/// How the text of a file is extracted for rules with `content_pattern`,
+/// from `[content]`.
+pub struct ContentSettings {
+    pdf_program: String,
+    pdf_args: Vec<String>,
+    max_size: u64,
+    max_memory: u64,
+    timeout: Duration,
+    allow_network: bool,
+}
+
+pub fn load_content_settings(ini: &ini::Ini) -> Result<ContentSettings, String> {
+    let section = ini.section(Some("content"));
+    let get = |key| section.and_then(|section| section.get(key));
+
+    let command = get("pdf_command").unwrap_or("pdftotext -q -enc UTF-8 {file} -");
+    let mut words = command.split_whitespace().map(str::to_string);
+    let pdf_program = words.next().ok_or("Empty pdf_command in [content]")?;
+
+    let max_size_mb: u64 = get("max_size_mb")
+        .unwrap_or("20")
+        .parse()
+        .map_err(|e| format!("Invalid max_size_mb in [content]: {}", e))?;
+
+    let max_memory_mb: u64 = get("max_memory_mb")
+        .unwrap_or("512")
+        .parse()
+        .map_err(|e| format!("Invalid max_memory_mb in [content]: {}", e))?;
+
+    let timeout_secs: u64 = get("timeout_secs")
+        .unwrap_or("30")
+        .parse()
+        .map_err(|e| format!("Invalid timeout_secs in [content]: {}", e))?;
+
+    let allow_network: bool = get("allow_network")
+        .unwrap_or("false")
+        .parse()
+        .map_err(|e| format!("Invalid allow_network in [content]: {}", e))?;
+
+    Ok(ContentSettings {
+        pdf_program,
+        pdf_args: words.collect(),
+        max_size: max_size_mb * 1024 * 1024,
+        max_memory: max_memory_mb * 1024 * 1024,
+        timeout: Duration::from_secs(timeout_secs),
+        allow_network,
+    })
+}
+
+/// The text of the file: text formats as they are, PDFs through
+/// `pdf_command`. Files of other types, or above `max_size_mb`, have none.
+pub fn extract_text(settings: &ContentSettings, path: &Path) -> Result<String, String> {
+    let size = fs::metadata(path).map_err(|e| e.to_string())?.len();
+    if size > settings.max_size {
+        return Err(format!("File is larger than {} bytes", settings.max_size));
+    }
+
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    if TEXT_EXTENSIONS.contains(&extension.as_str()) {
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        return Ok(String::from_utf8_lossy(&bytes).into_owned());
+    }
+    if extension == "pdf" {
+        return run_pdf_command(settings, path);
+    }
+    Err(format!("No text extraction for .{} files", extension))
+}
+
+/// `{file}` in the arguments stands for the path; without it the path is
+/// the last argument. The command parses untrusted files, so it runs with
+/// the limits of a sandboxed plugin.
+fn run_pdf_command(settings: &ContentSettings, path: &Path) -> Result<String, String> {
+    let command = || {
+        let mut command = Command::new(&settings.pdf_program);
+        let mut placed = false;
+        for arg in &settings.pdf_args {
+            if arg == "{file}" {
+                command.arg(path);
+                placed = true;
+            } else {
+                command.arg(arg);
+            }
+        }
+        if !placed {
+            command.arg(path);
+        }
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        command
+    };
+
+    let mut child = plugins::spawn_limited(
+        command,
+        settings.max_memory,
+        settings.timeout,
+        settings.allow_network,
+    )
+    .map_err(|e| format!("Failed to run {}: {}", settings.pdf_program, e))?;
+
+    // Read on another thread so a long document can't fill the pipe and
+    // block the command before it exits.
+    let mut stdout_pipe = child.stdout.take().expect("stdout is piped");
+    let stdout = thread::spawn(move || {
+        let mut stdout = Vec::new();
+        let _ = stdout_pipe.read_to_end(&mut stdout);
+        stdout
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if started.elapsed() >= settings.timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!(
+                "{} didn't finish within {}s",
+                settings.pdf_program,
+                settings.timeout.as_secs()
+            ));
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    if !status.success() {
+        return Err(format!("{} exited with {}", settings.pdf_program, status));
+    }
+    let stdout = stdout.join().unwrap_or_default();
+    Ok(String::from_utf8_lossy(&stdout).into_owned())
+}
+
+/// How much of an email is read looking for its `From:` header.
+const EML_HEADER_LIMIT: u64 = 64 * 1024;
+
+/// The lowercased address in the `From:` header of an email saved as an
+/// `.eml` file, `None` for other files or when it has none.
+pub fn eml_sender(path: &Path) -> Option<String> {
+    let is_eml = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("eml"));
+    if !is_eml {
+        return None;
+    }
+
+    let mut head = Vec::new();
+    fs::File::open(path)
+        .and_then(|file| file.take(EML_HEADER_LIMIT).read_to_end(&mut head))
+        .ok()?;
+    header_sender(&String::from_utf8_lossy(&head))
+}
+
+fn header_sender(message: &str) -> Option<String> {
+    // Headers end at the first empty line; a line starting with whitespace
+    // continues the header before it.
+    let mut from: Option<String> = None;
+    for line in message.lines() {
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some(from) = &mut from {
+                from.push(' ');
+                from.push_str(line.trim());
+            }
+            continue;
+        }
+        if from.is_some() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("from") {
+                from = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let from = from?;
+    let address = match (from.rfind('<'), from.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &from[start + 1..end],
+        _ => from.split_whitespace().find(|word| word.contains('@'))?,
+    };
+    let address = address.trim();
+    address.contains('@').then(|| address.to_ascii_lowercase())
+}
@@ -0,0 +1,252 @@
+use crate::retry::QueuedFile;
+use crate::rules::{Decision, Metadata, RuleSet};
+use notify::Event;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Requests from the control API, carried out by the event loop.
+pub enum Command {
+    Pause,
+    Resume,
+    Reload,
+    Reprocess(PathBuf),
+    CatchUp,
+}
+
+/// Everything the event loop receives.
+pub enum Message {
+    /// A watcher event and when it was reported.
+    File(Event, Instant),
+    Command(Command),
+}
+
+pub enum FileError {
+    InvalidName,
+    NotFound,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileError::InvalidName => write!(f, "Invalid file name"),
+            FileError::NotFound => write!(f, "File not found"),
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct RuleInfo {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+/// What the first matching rule would do with a file name.
+#[derive(Serialize)]
+pub struct RuleTest {
+    pub rule: Option<String>,
+    pub new_name: Option<String>,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl RuleTest {
+    /// `file` is a filename, or a path relative to the watch directory for
+    /// rules that match on the path.
+    pub fn run(rules: &RuleSet, file: &str) -> Self {
+        let filename = file.rsplit('/').next().unwrap_or(file);
+        let metadata = Metadata {
+            relative_path: Some(file.to_string()),
+            ..Metadata::default()
+        };
+        match rules.decide(filename, &metadata) {
+            Decision::Unmatched => RuleTest {
+                rule: None,
+                new_name: None,
+                fields: BTreeMap::new(),
+            },
+            Decision::Keep { rule, fields } => RuleTest {
+                rule: Some(rule),
+                new_name: Some(filename.to_string()),
+                fields,
+            },
+            Decision::Rename {
+                rule,
+                new_name,
+                fields,
+            } => RuleTest {
+                rule: Some(rule),
+                new_name: Some(new_name),
+                fields,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct QueueSnapshot {
+    /// Files that arrived while processing was paused.
+    pub held: Vec<PathBuf>,
+    pub retry: Vec<QueuedFile>,
+}
+
+/// The event loop's view for the control API. The loop publishes its rules
+/// and queues here after every change; commands travel back over the loop's
+/// channel so they run in order with file events.
+pub struct Control {
+    watch_directory: PathBuf,
+    sender: Sender<Message>,
+    paused: AtomicBool,
+    rules: Mutex<RuleSet>,
+    queue: Mutex<QueueSnapshot>,
+}
+
+impl Control {
+    pub fn new(watch_directory: PathBuf, sender: Sender<Message>) -> Self {
+        Control {
+            watch_directory,
+            sender,
+            paused: AtomicBool::new(false),
+            rules: Mutex::new(RuleSet::default()),
+            queue: Mutex::new(QueueSnapshot::default()),
+        }
+    }
+
+    /// Resolves a file from an API request, given by its path relative to
+    /// the watch directory, such as `2024/invoice.pdf`. Paths that lead out
+    /// of the watch directory, also through a symlink, are refused.
+    pub fn watched_file(&self, file: &str) -> Result<PathBuf, FileError> {
+        let relative = Path::new(file);
+        let name_only = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !name_only || relative.file_name().is_none() {
+            return Err(FileError::InvalidName);
+        }
+
+        let path = self.watch_directory.join(relative);
+        let resolved = path.canonicalize().map_err(|_| FileError::NotFound)?;
+        let watch_directory = self
+            .watch_directory
+            .canonicalize()
+            .map_err(|_| FileError::NotFound)?;
+        if !resolved.starts_with(&watch_directory) {
+            return Err(FileError::InvalidName);
+        }
+        if !resolved.is_file() {
+            return Err(FileError::NotFound);
+        }
+        Ok(path)
+    }
+
+    pub fn send(&self, command: Command) -> Result<(), String> {
+        self.sender
+            .send(Message::Command(command))
+            .map_err(|_| "Event loop is not running".to_string())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn rules(&self) -> Vec<RuleInfo> {
+        self.rules
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(regex, replacement)| RuleInfo {
+                pattern: regex.as_str().to_string(),
+                replacement: replacement.to_string(),
+            })
+            .collect()
+    }
+
+    pub fn set_rules(&self, rules: &RuleSet) {
+        *self.rules.lock().unwrap() = rules.clone();
+    }
+
+    /// Runs `filename` through the loaded rules.
+    pub fn test_rules(&self, filename: &str) -> RuleTest {
+        RuleTest::run(&self.rules.lock().unwrap(), filename)
+    }
+
+    pub fn queue(&self) -> QueueSnapshot {
+        self.queue.lock().unwrap().clone()
+    }
+
+    pub fn set_queue(&self, queue: QueueSnapshot) {
+        *self.queue.lock().unwrap() = queue;
+    }
+}
+
+/// Checks an `Authorization` header value against `Bearer <token>` in
+/// constant time.
+pub fn authorized(header: Option<&str>, token: &str) -> bool {
+    let expected = format!("Bearer {}", token);
+    header
+        .map(|given| {
+            given.len() == expected.len()
+                && given
+                    .bytes()
+                    .zip(expected.bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::mpsc;
+
+    #[test]
+    fn watched_file() {
+        let dir =
+            std::env::temp_dir().join(format!("invoicehandler-control-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let watch = dir.join("watch");
+        fs::create_dir_all(watch.join("2024")).unwrap();
+        fs::write(watch.join("a.pdf"), "").unwrap();
+        fs::write(watch.join("2024").join("b.pdf"), "").unwrap();
+        fs::write(dir.join("outside.pdf"), "").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("outside.pdf"), watch.join("link.pdf")).unwrap();
+
+        let (sender, _receiver) = mpsc::channel();
+        let control = Control::new(watch.clone(), sender);
+
+        let cases: Vec<(&str, Result<PathBuf, &str>)> = vec![
+            ("a.pdf", Ok(watch.join("a.pdf"))),
+            ("2024/b.pdf", Ok(watch.join("2024/b.pdf"))),
+            ("./a.pdf", Ok(watch.join("./a.pdf"))),
+            ("missing.pdf", Err("File not found")),
+            ("2024", Err("File not found")),
+            ("", Err("Invalid file name")),
+            ("../outside.pdf", Err("Invalid file name")),
+            ("2024/../../outside.pdf", Err("Invalid file name")),
+            ("/etc/passwd", Err("Invalid file name")),
+            #[cfg(unix)]
+            ("link.pdf", Err("Invalid file name")),
+        ];
+        for (file, expected) in cases {
+            match (control.watched_file(file), expected) {
+                (Ok(path), Ok(expected)) => assert_eq!(path, expected, "{:?}", file),
+                (Err(e), Err(expected)) => assert_eq!(e.to_string(), expected, "{:?}", file),
+                (Ok(path), Err(expected)) => {
+                    panic!("{:?}: {:?}, expected {}", file, path, expected)
+                }
+                (Err(e), Ok(_)) => panic!("{:?}: {}", file, e),
+            }
+        }
+    }
+}
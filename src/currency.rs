@@ -0,0 +1,200 @@
+use crate::sepa;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+const DEFAULT_RATES_URL: &str = "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-daily.xml";
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait after a failed download before trying again, so an
+/// unreachable feed doesn't slow down every file.
+const RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+
+static RATE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"currency=['"]([A-Z]{3})['"]\s+rate=['"]([0-9.]+)['"]"#).unwrap()
+});
+
+/// Converts the `amount` of invoices in another `currency` to a base
+/// currency, from `[currency]`, with the ECB's euro reference rates.
+pub struct CurrencySettings {
+    base: String,
+    rates_url: String,
+    cache_file: PathBuf,
+    cache_for: Duration,
+}
+
+pub fn load_currency_settings(ini: &ini::Ini) -> Result<Option<CurrencySettings>, String> {
+    let section = match ini.section(Some("currency")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let cache_hours: u64 = section
+        .get("cache_hours")
+        .unwrap_or("24")
+        .parse()
+        .map_err(|e| format!("Invalid cache_hours in [currency]: {}", e))?;
+
+    let cache_file = match section.get("cache_file") {
+        Some(file) => PathBuf::from(file),
+        None => dirs::cache_dir()
+            .ok_or("No cache directory, set 'cache_file' in [currency]")?
+            .join("invoicehandler")
+            .join("eurofxref-daily.xml"),
+    };
+
+    Ok(Some(CurrencySettings {
+        base: section.get("base").unwrap_or("EUR").to_uppercase(),
+        rates_url: section
+            .get("rates_url")
+            .unwrap_or(DEFAULT_RATES_URL)
+            .to_string(),
+        cache_file,
+        cache_for: Duration::from_secs(cache_hours * 60 * 60),
+    }))
+}
+
+/// The rates of the last download, in units per euro.
+pub struct CurrencyConverter<'a> {
+    settings: &'a CurrencySettings,
+    rates: HashMap<String, f64>,
+    loaded: Option<SystemTime>,
+    failed: Option<Instant>,
+}
+
+impl<'a> CurrencyConverter<'a> {
+    pub fn new(settings: &'a CurrencySettings) -> Self {
+        CurrencyConverter {
+            settings,
+            rates: HashMap::new(),
+            loaded: None,
+            failed: None,
+        }
+    }
+
+    /// Adds `base_amount`, `base_currency` and `exchange_rate` to fields
+    /// with an `amount` and a `currency`. Without a rate for the currency
+    /// they are left out.
+    pub fn convert(&mut self, fields: &mut BTreeMap<String, String>) {
+        let (Some(amount), Some(currency)) = (fields.get("amount"), fields.get("currency")) else {
+            return;
+        };
+        let currency = currency.trim().to_uppercase();
+        let Some(cents) = sepa::parse_amount(amount) else {
+            warn!(amount, "Amount not converted, it isn't a number");
+            return;
+        };
+
+        self.refresh();
+        let rate = match (self.rate(&currency), self.rate(&self.settings.base)) {
+            (Some(from), Some(to)) => to / from,
+            _ => {
+                warn!(
+                    currency,
+                    base = self.settings.base,
+                    "Amount not converted, no exchange rate"
+                );
+                return;
+            }
+        };
+
+        let base_cents = (cents as f64 * rate).round() as u64;
+        fields.insert(
+            "base_amount".to_string(),
+            format!("{}.{:02}", base_cents / 100, base_cents % 100),
+        );
+        fields.insert("base_currency".to_string(), self.settings.base.clone());
+        fields.insert("exchange_rate".to_string(), format!("{:.6}", rate));
+    }
+
+    fn rate(&self, currency: &str) -> Option<f64> {
+        if currency == "EUR" {
+            return Some(1.0);
+        }
+        self.rates.get(currency).copied()
+    }
+
+    /// Reads the cached rates, and downloads new ones once they are older
+    /// than `cache_hours`. When the download fails the old rates are kept.
+    fn refresh(&mut self) {
+        let fresh = |time: SystemTime| {
+            time.elapsed()
+                .is_ok_and(|age| age < self.settings.cache_for)
+        };
+        if self.loaded.is_some_and(fresh) {
+            return;
+        }
+
+        if self.loaded.is_none() {
+            let modified =
+                fs::metadata(&self.settings.cache_file).and_then(|metadata| metadata.modified());
+            if let (Ok(modified), Ok(xml)) =
+                (modified, fs::read_to_string(&self.settings.cache_file))
+            {
+                self.rates = parse_rates(&xml);
+                self.loaded = Some(modified);
+            }
+            if self.loaded.is_some_and(fresh) {
+                return;
+            }
+        }
+        if self
+            .failed
+            .is_some_and(|failed| failed.elapsed() < RETRY_AFTER)
+        {
+            return;
+        }
+
+        match self.download() {
+            Ok((rates, xml)) => {
+                self.rates = rates;
+                self.loaded = Some(SystemTime::now());
+                self.failed = None;
+                info!(rates = self.rates.len(), "Downloaded exchange rates");
+                if let Some(directory) = self.settings.cache_file.parent() {
+                    let _ = fs::create_dir_all(directory);
+                }
+                if let Err(e) = fs::write(&self.settings.cache_file, xml) {
+                    warn!(
+                        file = %self.settings.cache_file.display(),
+                        error = %e,
+                        "Failed to cache exchange rates"
+                    );
+                }
+            }
+            Err(e) => {
+                warn!(error = %e, "Failed to download exchange rates, using the old ones");
+                self.failed = Some(Instant::now());
+            }
+        }
+    }
+
+    fn download(&self) -> Result<(HashMap<String, f64>, String), String> {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(DOWNLOAD_TIMEOUT))
+            .build()
+            .into();
+        let xml = agent
+            .get(&self.settings.rates_url)
+            .call()
+            .map_err(|e| e.to_string())?
+            .body_mut()
+            .read_to_string()
+            .map_err(|e| e.to_string())?;
+        let rates = parse_rates(&xml);
+        if rates.is_empty() {
+            return Err("No exchange rates in the feed".to_string());
+        }
+        Ok((rates, xml))
+    }
+}
+
+/// The `<Cube currency='USD' rate='1.0876'/>` entries of the ECB feed.
+fn parse_rates(xml: &str) -> HashMap<String, f64> {
+    RATE.captures_iter(xml)
+        .filter_map(|rate| Some((rate[1].to_string(), rate[2].parse().ok()?)))
+        .collect()
+}
@@ -0,0 +1,410 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, Local, Locale, NaiveDate, Utc};
+use chrono_tz::Tz;
+use std::sync::Arc;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const DEFAULT_DATETIME_FORMAT: &str = "%Y-%m-%d_%H%M%S";
+
+/// Month names in English, German, French, Spanish, Italian, Dutch,
+/// Portuguese and the Scandinavian languages, folded as by [`fold`], and the
+/// abbreviations that aren't a prefix of them.
+const MONTHS: &[(&str, u32)] = &[
+    ("january", 1),
+    ("januar", 1),
+    ("janner", 1),
+    ("janvier", 1),
+    ("enero", 1),
+    ("gennaio", 1),
+    ("januari", 1),
+    ("janeiro", 1),
+    ("february", 2),
+    ("februar", 2),
+    ("feber", 2),
+    ("fevrier", 2),
+    ("febrero", 2),
+    ("febbraio", 2),
+    ("februari", 2),
+    ("fevereiro", 2),
+    ("march", 3),
+    ("marz", 3),
+    ("maerz", 3),
+    ("mrz", 3),
+    ("mars", 3),
+    ("marts", 3),
+    ("marzo", 3),
+    ("maart", 3),
+    ("marco", 3),
+    ("april", 4),
+    ("avril", 4),
+    ("abril", 4),
+    ("aprile", 4),
+    ("may", 5),
+    ("mai", 5),
+    ("mayo", 5),
+    ("maggio", 5),
+    ("mei", 5),
+    ("maio", 5),
+    ("maj", 5),
+    ("june", 6),
+    ("juni", 6),
+    ("juin", 6),
+    ("junio", 6),
+    ("giugno", 6),
+    ("junho", 6),
+    ("july", 7),
+    ("juli", 7),
+    ("juillet", 7),
+    ("julio", 7),
+    ("luglio", 7),
+    ("julho", 7),
+    ("august", 8),
+    ("aout", 8),
+    ("agosto", 8),
+    ("augustus", 8),
+    ("augusti", 8),
+    ("september", 9),
+    ("septembre", 9),
+    ("septiembre", 9),
+    ("setiembre", 9),
+    ("settembre", 9),
+    ("setembro", 9),
+    ("october", 10),
+    ("oktober", 10),
+    ("octobre", 10),
+    ("octubre", 10),
+    ("ottobre", 10),
+    ("outubro", 10),
+    ("november", 11),
+    ("novembre", 11),
+    ("noviembre", 11),
+    ("novembro", 11),
+    ("december", 12),
+    ("desember", 12),
+    ("dezember", 12),
+    ("decembre", 12),
+    ("diciembre", 12),
+    ("dicembre", 12),
+    ("dezembro", 12),
+];
+
+/// Abbreviations have to be at least this long to be taken as the start of
+/// a month name.
+const MIN_ABBREVIATION: usize = 3;
+
+/// The timezone of the `date` and `datetime` fields, from `timezone` in
+/// `[settings]`. Without it they are in the server's local time.
+pub struct DateSettings {
+    timezone: Option<Tz>,
+    /// The format in `[settings]`, which rules without one of their own use.
+    pub(crate) format: DateFormat,
+}
+
+pub fn load_date_settings(section: &ini::Properties) -> Result<DateSettings, String> {
+    Ok(DateSettings {
+        timezone: load_timezone(section)?,
+        format: load_date_format(section, &DateFormat::default(), "settings")?,
+    })
+}
+
+/// `timezone` in `section`, `None` for the server's local time.
+pub(crate) fn load_timezone(section: &ini::Properties) -> Result<Option<Tz>, String> {
+    match section.get("timezone") {
+        None | Some("local") => Ok(None),
+        Some(name) => name.parse::<Tz>().map(Some).map_err(|_| {
+            format!(
+                "Invalid timezone '{}', use an IANA name such as Europe/Berlin",
+                name
+            )
+        }),
+    }
+}
+
+impl DateSettings {
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        match self.timezone {
+            Some(timezone) => Utc::now().with_timezone(&timezone).fixed_offset(),
+            None => Local::now().fixed_offset(),
+        }
+    }
+}
+
+/// How the `date` and `datetime` fields are written, from `date_format`,
+/// `datetime_format` and `locale` in `[settings]` or a `[rule.<name>]`.
+#[derive(Clone)]
+pub(crate) struct DateFormat {
+    date: String,
+    datetime: String,
+    /// For the names of months and weekdays, such as `%B`.
+    locale: Option<Locale>,
+    /// Month names from `[months]`, tried before the built-in ones.
+    months: Arc<Vec<(String, u32)>>,
+}
+
+impl Default for DateFormat {
+    fn default() -> Self {
+        DateFormat {
+            date: DEFAULT_DATE_FORMAT.to_string(),
+            datetime: DEFAULT_DATETIME_FORMAT.to_string(),
+            locale: None,
+            months: Arc::default(),
+        }
+    }
+}
+
+/// Keys missing from `section` are taken from `defaults`.
+pub(crate) fn load_date_format(
+    section: &ini::Properties,
+    defaults: &DateFormat,
+    section_name: &str,
+) -> Result<DateFormat, String> {
+    let format = |key, default: &String| match section.get(key) {
+        Some(format) if is_valid(format) => Ok(format.to_string()),
+        Some(format) => Err(format!(
+            "Invalid {} '{}' in [{}]",
+            key, format, section_name
+        )),
+        None => Ok(default.clone()),
+    };
+
+    let locale = match section.get("locale") {
+        Some(name) => Some(Locale::try_from(name).map_err(|_| {
+            format!(
+                "Unknown locale '{}' in [{}], use a name such as de_DE",
+                name, section_name
+            )
+        })?),
+        None => defaults.locale,
+    };
+
+    Ok(DateFormat {
+        date: format("date_format", &defaults.date)?,
+        datetime: format("datetime_format", &defaults.datetime)?,
+        locale,
+        months: defaults.months.clone(),
+    })
+}
+
+/// The `[months]` section: month names suppliers use that the built-in
+/// table doesn't know, as `name = <1-12>`.
+pub(crate) fn load_month_names(section: &ini::Properties) -> Result<Vec<(String, u32)>, String> {
+    section
+        .iter()
+        .map(|(name, month)| match month.parse() {
+            Ok(month @ 1..=12) => Ok((fold(name), month)),
+            _ => Err(format!(
+                "Invalid month '{}' for '{}' in [months], use 1 to 12",
+                month, name
+            )),
+        })
+        .collect()
+}
+
+/// Lowercased, without the accents and umlauts, so `März`, `MÄRZ` and
+/// `marz` are the same name.
+fn fold(name: &str) -> String {
+    name.trim()
+        .trim_end_matches('.')
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => 'a',
+            'ç' => 'c',
+            'è' | 'é' | 'ê' | 'ë' => 'e',
+            'ì' | 'í' | 'î' | 'ï' => 'i',
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' => 'o',
+            'ù' | 'ú' | 'û' | 'ü' => 'u',
+            c => c,
+        })
+        .collect()
+}
+
+fn is_valid(format: &str) -> bool {
+    !StrftimeItems::new(format).any(|item| item == Item::Error)
+}
+
+impl DateFormat {
+    pub(crate) fn with_months(months: Vec<(String, u32)>) -> Self {
+        DateFormat {
+            months: Arc::new(months),
+            ..DateFormat::default()
+        }
+    }
+
+    pub(crate) fn date(&self, date: NaiveDate) -> String {
+        match self.locale {
+            Some(locale) => date.format_localized(&self.date, locale).to_string(),
+            None => date.format(&self.date).to_string(),
+        }
+    }
+
+    /// Reads a date written with this format, or as `%Y-%m-%d`. Month names
+    /// are only read in English.
+    pub(crate) fn parse_date(&self, value: &str) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(value, &self.date)
+            .or_else(|_| NaiveDate::parse_from_str(value, DEFAULT_DATE_FORMAT))
+            .ok()
+    }
+
+    /// The number of a month written as a number, a name or an
+    /// abbreviation, such as `03`, `März`, `Sept` or `juil.`. Abbreviations
+    /// are taken when they start the names of only one month.
+    pub(crate) fn month(&self, value: &str) -> Option<u32> {
+        if let Ok(month) = value.parse() {
+            return Some(month);
+        }
+        let value = fold(value);
+        let names = || {
+            self.months
+                .iter()
+                .map(|(name, month)| (name.as_str(), *month))
+                .chain(MONTHS.iter().copied())
+        };
+        if let Some((_, month)) = names().find(|(name, _)| *name == value) {
+            return Some(month);
+        }
+        if value.chars().count() < MIN_ABBREVIATION || !value.chars().all(char::is_alphabetic) {
+            return None;
+        }
+        let mut months = names()
+            .filter(|(name, _)| name.starts_with(&value))
+            .map(|(_, month)| month);
+        let month = months.next()?;
+        months.all(|other| other == month).then_some(month)
+    }
+
+    pub(crate) fn datetime(&self, datetime: &DateTime<FixedOffset>) -> String {
+        match self.locale {
+            Some(locale) => datetime
+                .format_localized(&self.datetime, locale)
+                .to_string(),
+            None => datetime.format(&self.datetime).to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn month() {
+        let dates = DateFormat::with_months(vec![(fold("Hornung"), 2)]);
+
+        let cases = [
+            ("03", Some(3)),
+            ("12", Some(12)),
+            ("März", Some(3)),
+            ("MÄRZ", Some(3)),
+            ("marz", Some(3)),
+            ("Mrz", Some(3)),
+            ("Sept", Some(9)),
+            ("sept.", Some(9)),
+            ("juil.", Some(7)),
+            ("août", Some(8)),
+            ("Dezember", Some(12)),
+            ("dic", Some(12)),
+            // Danish, Norwegian and Swedish.
+            ("marts", Some(3)),
+            ("mars", Some(3)),
+            ("maj", Some(5)),
+            ("augusti", Some(8)),
+            ("desember", Some(12)),
+            ("des.", Some(12)),
+            ("hornung", Some(2)),
+            ("Horn", Some(2)),
+            // Too short to be taken as an abbreviation.
+            ("de", None),
+            ("o", None),
+            // The start of both März and Mai, and of June and July.
+            ("ma", None),
+            ("jun", Some(6)),
+            ("ju", None),
+            ("mar", Some(3)),
+            ("maxi", None),
+            ("3rd", None),
+            ("", None),
+        ];
+        for (value, month) in cases {
+            assert_eq!(dates.month(value), month, "{}", value);
+        }
+    }
+
+    #[test]
+    fn load_month_names() {
+        let cases = [
+            ("Hornung = 2", Ok(vec![("hornung".to_string(), 2)])),
+            (
+                "Lenzing = 3\nOstermond = 04",
+                Ok(vec![
+                    ("lenzing".to_string(), 3),
+                    ("ostermond".to_string(), 4),
+                ]),
+            ),
+            ("Hornung = 13", Err("Invalid month '13' for 'Hornung'")),
+            ("Hornung = 0", Err("Invalid month '0' for 'Hornung'")),
+            ("Hornung = x", Err("Invalid month 'x' for 'Hornung'")),
+        ];
+        for (config, expected) in cases {
+            let ini = ini::Ini::load_from_str(&format!("[months]\n{}\n", config)).unwrap();
+            let result = super::load_month_names(ini.section(Some("months")).unwrap());
+            match (result, expected) {
+                (Ok(months), Ok(expected)) => assert_eq!(months, expected, "{}", config),
+                (Err(e), Err(prefix)) => assert!(e.starts_with(prefix), "{}: {}", config, e),
+                (result, expected) => panic!("{}: {:?}, expected {:?}", config, result, expected),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_date() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d);
+        let ini = ini::Ini::load_from_str("[settings]\ndate_format = %d.%m.%Y\n").unwrap();
+        let dates = super::load_date_format(
+            ini.section(Some("settings")).unwrap(),
+            &DateFormat::default(),
+            "settings",
+        )
+        .unwrap();
+
+        let cases = [
+            ("24.12.2024", date(2024, 12, 24)),
+            ("2024-12-24", date(2024, 12, 24)),
+            ("31.02.2024", None),
+            ("12/24/2024", None),
+            ("", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(dates.parse_date(value), expected, "{}", value);
+        }
+        assert_eq!(dates.date(date(2024, 3, 5).unwrap()), "05.03.2024");
+    }
+
+    #[test]
+    fn load_date_format() {
+        let cases = [
+            ("date_format = %d.%m.%Y", Ok("05.03.2024")),
+            ("date_format = %B %Y\nlocale = de_DE", Ok("März 2024")),
+            ("locale = fr_FR", Ok("2024-03-05")),
+            (
+                "date_format = %Q",
+                Err("Invalid date_format '%Q' in [settings]"),
+            ),
+            ("locale = xx", Err("Unknown locale 'xx' in [settings]")),
+        ];
+        for (config, expected) in cases {
+            let ini = ini::Ini::load_from_str(&format!("[settings]\n{}\n", config)).unwrap();
+            let result = super::load_date_format(
+                ini.section(Some("settings")).unwrap(),
+                &DateFormat::default(),
+                "settings",
+            )
+            .map(|dates| dates.date(NaiveDate::from_ymd_opt(2024, 3, 5).unwrap()));
+            match (result, expected) {
+                (Ok(date), Ok(expected)) => assert_eq!(date, expected, "{}", config),
+                (Err(e), Err(prefix)) => assert!(e.starts_with(prefix), "{}: {}", config, e),
+                (result, expected) => panic!("{}: {:?}, expected {:?}", config, result, expected),
+            }
+        }
+    }
+}
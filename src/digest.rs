@@ -0,0 +1,357 @@
+use crate::events::{self, EventSink, FileEvent, Outcome};
+use crate::notifications::{Notification, Notifications};
+use crate::sepa;
+use chrono::{DateTime, Datelike, Local, NaiveTime, Weekday};
+use lettre::message::{Mailbox, Message};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{SmtpTransport, Transport};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::{error, info};
+
+#[derive(Clone, Copy)]
+enum Frequency {
+    Daily,
+    Weekly(Weekday),
+}
+
+#[derive(Clone, Copy)]
+enum SmtpSecurity {
+    StartTls,
+    Tls,
+    None,
+}
+
+#[derive(Clone)]
+pub struct DigestSettings {
+    frequency: Frequency,
+    time: NaiveTime,
+    email: Option<EmailSettings>,
+}
+
+#[derive(Clone)]
+struct EmailSettings {
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    smtp_host: String,
+    smtp_port: Option<u16>,
+    smtp_security: SmtpSecurity,
+    smtp_username: Option<String>,
+    smtp_password: Option<String>,
+}
+
+pub fn load_digest_settings(ini: &ini::Ini) -> Result<Option<DigestSettings>, String> {
+    let section = match ini.section(Some("digest")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let frequency = match section.get("schedule").unwrap_or("daily") {
+        "daily" => Frequency::Daily,
+        "weekly" => Frequency::Weekly(
+            section
+                .get("weekday")
+                .unwrap_or("mon")
+                .parse()
+                .map_err(|_| "Invalid weekday in [digest]".to_string())?,
+        ),
+        other => {
+            return Err(format!(
+                "Invalid digest schedule '{}': expected daily or weekly",
+                other
+            ))
+        }
+    };
+
+    let time = NaiveTime::parse_from_str(section.get("time").unwrap_or("08:00"), "%H:%M")
+        .map_err(|e| format!("Invalid digest time: {}", e))?;
+
+    Ok(Some(DigestSettings {
+        frequency,
+        time,
+        email: load_email_settings(section)?,
+    }))
+}
+
+/// The summary is only mailed when `to` is set; otherwise it goes to the
+/// notifiers subscribed to `summary` alone.
+fn load_email_settings(section: &ini::Properties) -> Result<Option<EmailSettings>, String> {
+    let to = match section.get("to") {
+        Some(to) => to,
+        None => return Ok(None),
+    };
+
+    let from = section
+        .get("from")
+        .ok_or("Missing 'from' in [digest]")?
+        .parse()
+        .map_err(|e| format!("Invalid digest from address: {}", e))?;
+
+    let to = to
+        .split(',')
+        .map(|address| {
+            address
+                .trim()
+                .parse()
+                .map_err(|e| format!("Invalid digest to address '{}': {}", address, e))
+        })
+        .collect::<Result<Vec<Mailbox>, String>>()?;
+
+    let smtp_security = match section.get("smtp_security").unwrap_or("starttls") {
+        "starttls" => SmtpSecurity::StartTls,
+        "tls" => SmtpSecurity::Tls,
+        "none" => SmtpSecurity::None,
+        other => {
+            return Err(format!(
+                "Invalid smtp_security '{}': expected starttls, tls or none",
+                other
+            ))
+        }
+    };
+
+    let smtp_port = section
+        .get("smtp_port")
+        .map(|port| {
+            port.parse()
+                .map_err(|e| format!("Invalid smtp_port: {}", e))
+        })
+        .transpose()?;
+
+    Ok(Some(EmailSettings {
+        from,
+        to,
+        smtp_host: section
+            .get("smtp_host")
+            .ok_or("Missing 'smtp_host' in [digest]")?
+            .to_string(),
+        smtp_port,
+        smtp_security,
+        smtp_username: section.get("smtp_username").map(str::to_string),
+        smtp_password: section.get("smtp_password").map(str::to_string),
+    }))
+}
+
+struct DigestStats {
+    since: DateTime<Local>,
+    processed: BTreeMap<String, usize>,
+    /// Cents of the processed invoices by currency, from `[currency]`.
+    totals: BTreeMap<String, u64>,
+    unmatched: BTreeSet<PathBuf>,
+    failed: BTreeMap<PathBuf, String>,
+    locked: BTreeSet<PathBuf>,
+}
+
+impl DigestStats {
+    fn new() -> Self {
+        DigestStats {
+            since: Local::now(),
+            processed: BTreeMap::new(),
+            totals: BTreeMap::new(),
+            unmatched: BTreeSet::new(),
+            failed: BTreeMap::new(),
+            locked: BTreeSet::new(),
+        }
+    }
+
+    fn record(&mut self, event: &FileEvent) {
+        match event.outcome {
+            Outcome::Processed => {
+                let rule = event.rule.clone().unwrap_or_default();
+                *self.processed.entry(rule).or_default() += 1;
+                let amount = event
+                    .fields
+                    .get("base_amount")
+                    .and_then(|a| sepa::parse_amount(a));
+                if let (Some(cents), Some(currency)) = (amount, event.fields.get("base_currency")) {
+                    *self.totals.entry(currency.clone()).or_default() += cents;
+                }
+                self.locked.remove(&event.path);
+            }
+            Outcome::Unmatched => {
+                self.unmatched.insert(event.path.clone());
+            }
+            Outcome::Failed if event.error.as_deref() == Some(events::LOCKED_ERROR) => {
+                self.locked.insert(event.path.clone());
+            }
+            Outcome::Failed => {
+                let error = event.error.clone().unwrap_or_default();
+                self.failed.insert(event.path.clone(), error);
+            }
+        }
+    }
+
+    /// Starts a new period. Locked files carry over since they stay stuck
+    /// until they are processed.
+    fn reset(&mut self) {
+        let locked = std::mem::take(&mut self.locked);
+        *self = DigestStats::new();
+        self.locked = locked;
+    }
+
+    fn render(&self, until: DateTime<Local>) -> String {
+        let mut body = String::new();
+        let _ = writeln!(
+            body,
+            "invoicehandler summary for {} - {}\n",
+            self.since.format("%Y-%m-%d %H:%M"),
+            until.format("%Y-%m-%d %H:%M")
+        );
+
+        let processed: usize = self.processed.values().sum();
+        let _ = writeln!(body, "Processed: {}", processed);
+        for (rule, count) in &self.processed {
+            let _ = writeln!(body, "  {}: {}", rule, count);
+        }
+        for (currency, cents) in &self.totals {
+            let _ = writeln!(
+                body,
+                "Total: {}.{:02} {}",
+                cents / 100,
+                cents % 100,
+                currency
+            );
+        }
+
+        let _ = writeln!(body, "\nUnmatched: {}", self.unmatched.len());
+        for path in &self.unmatched {
+            let _ = writeln!(body, "  {}", path.display());
+        }
+
+        let _ = writeln!(body, "\nFailed: {}", self.failed.len());
+        for (path, error) in &self.failed {
+            let _ = writeln!(body, "  {}: {}", path.display(), error);
+        }
+
+        let _ = writeln!(body, "\nStill locked: {}", self.locked.len());
+        for path in &self.locked {
+            let _ = writeln!(body, "  {}", path.display());
+        }
+
+        body
+    }
+}
+
+/// Collects events into the current period's summary.
+pub struct DigestCollector {
+    stats: Arc<Mutex<DigestStats>>,
+}
+
+impl EventSink for DigestCollector {
+    fn name(&self) -> &'static str {
+        "digest"
+    }
+
+    fn publish(&self, event: &FileEvent, _payload: &str) -> Result<(), String> {
+        self.stats.lock().unwrap().record(event);
+        Ok(())
+    }
+}
+
+/// Spawns the thread that sends the summary on schedule and returns the sink
+/// feeding it.
+pub fn start(settings: &DigestSettings, notifications: Notifications) -> DigestCollector {
+    let stats = Arc::new(Mutex::new(DigestStats::new()));
+    let thread_stats = stats.clone();
+    let settings = settings.clone();
+
+    thread::spawn(move || loop {
+        let next = next_run(Local::now(), settings.time, settings.frequency);
+        let wait = (next - Local::now()).to_std().unwrap_or_default();
+        thread::sleep(wait);
+
+        let body = {
+            let mut stats = thread_stats.lock().unwrap();
+            let body = stats.render(Local::now());
+            stats.reset();
+            body
+        };
+
+        if let Some(email) = &settings.email {
+            match send(email, body.clone()) {
+                Ok(()) => info!("Sent digest email"),
+                Err(e) => error!("Failed to send digest email: {}", e),
+            }
+        }
+
+        notifications.send(Notification::summary(body));
+    });
+
+    DigestCollector { stats }
+}
+
+fn next_run(now: DateTime<Local>, time: NaiveTime, frequency: Frequency) -> DateTime<Local> {
+    let mut date = now.date_naive();
+    loop {
+        let weekday_matches = match frequency {
+            Frequency::Daily => true,
+            Frequency::Weekly(weekday) => date.weekday() == weekday,
+        };
+
+        // Times that don't exist on DST transition days are skipped.
+        if let Some(candidate) = date.and_time(time).and_local_timezone(Local).earliest() {
+            if weekday_matches && candidate > now {
+                return candidate;
+            }
+        }
+
+        date = date.succ_opt().expect("date out of range");
+    }
+}
+
+fn send(settings: &EmailSettings, body: String) -> Result<(), String> {
+    let mut message = Message::builder()
+        .from(settings.from.clone())
+        .subject("invoicehandler summary");
+    for to in &settings.to {
+        message = message.to(to.clone());
+    }
+    let message = message.body(body).map_err(|e| e.to_string())?;
+
+    transport(settings)?
+        .send(&message)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn transport(settings: &EmailSettings) -> Result<SmtpTransport, String> {
+    let mut transport = match settings.smtp_security {
+        SmtpSecurity::StartTls => {
+            SmtpTransport::starttls_relay(&settings.smtp_host).map_err(|e| e.to_string())?
+        }
+        SmtpSecurity::Tls => {
+            SmtpTransport::relay(&settings.smtp_host).map_err(|e| e.to_string())?
+        }
+        SmtpSecurity::None => SmtpTransport::builder_dangerous(&settings.smtp_host),
+    };
+    if let Some(port) = settings.smtp_port {
+        transport = transport.port(port);
+    }
+    if let Some(username) = &settings.smtp_username {
+        transport = transport.credentials(Credentials::new(
+            username.clone(),
+            settings.smtp_password.clone().unwrap_or_default(),
+        ));
+    }
+
+    Ok(transport.build())
+}
+
+/// Connects and logs in to the SMTP server, for `invoicehandler doctor`.
+/// `None` when the summary isn't mailed.
+pub fn check_email(settings: &DigestSettings) -> Option<Result<(), String>> {
+    let email = settings.email.as_ref()?;
+    Some(
+        transport(email)
+            .and_then(|transport| transport.test_connection().map_err(|e| e.to_string()))
+            .and_then(|connected| {
+                if connected {
+                    Ok(())
+                } else {
+                    Err("SMTP server didn't answer".to_string())
+                }
+            }),
+    )
+}
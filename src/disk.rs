@@ -0,0 +1,209 @@
+use crate::notifications::{Notification, NotificationKind, Notifications};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+pub struct DiskSettings {
+    min_free_mb: u64,
+    interval: Duration,
+    paths: Vec<PathBuf>,
+    pause: bool,
+}
+
+pub fn load_disk_settings(ini: &ini::Ini) -> Result<Option<DiskSettings>, String> {
+    let section = match ini.section(Some("disk")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let min_free_mb: u64 = section
+        .get("min_free_mb")
+        .ok_or("Missing 'min_free_mb' in [disk]")?
+        .parse()
+        .map_err(|e| format!("Invalid min_free_mb: {}", e))?;
+
+    let interval_secs: u64 = section
+        .get("check_interval_secs")
+        .unwrap_or("60")
+        .parse()
+        .map_err(|e| format!("Invalid check_interval_secs: {}", e))?;
+
+    if interval_secs == 0 {
+        return Err("check_interval_secs must be greater than 0".to_string());
+    }
+
+    let pause: bool = section
+        .get("pause_below")
+        .unwrap_or("false")
+        .parse()
+        .map_err(|e| format!("Invalid pause_below: {}", e))?;
+
+    Ok(Some(DiskSettings {
+        min_free_mb,
+        interval: Duration::from_secs(interval_secs),
+        paths: section
+            .get("paths")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .collect(),
+        pause,
+    }))
+}
+
+/// A change in whether any monitored volume is below `min_free_mb`.
+pub enum DiskChange {
+    Low,
+    Recovered,
+}
+
+/// Checks the free space on the watch directory, the ledger's directory and
+/// the configured `paths`, and raises an alert for each one that drops below
+/// `min_free_mb`. A path is alerted on again only after it has recovered.
+pub struct DiskMonitor<'a> {
+    settings: &'a DiskSettings,
+    paths: Vec<PathBuf>,
+    low: BTreeSet<PathBuf>,
+    notifications: Notifications,
+    last_check: Option<Instant>,
+}
+
+impl<'a> DiskMonitor<'a> {
+    pub fn new(
+        settings: &'a DiskSettings,
+        mut paths: Vec<PathBuf>,
+        notifications: Notifications,
+    ) -> Self {
+        paths.extend(settings.paths.iter().cloned());
+        paths.sort();
+        paths.dedup();
+        DiskMonitor {
+            settings,
+            paths,
+            low: BTreeSet::new(),
+            notifications,
+            last_check: None,
+        }
+    }
+
+    /// Whether processing should pause while a volume is low (`pause_below`).
+    pub fn pauses(&self) -> bool {
+        self.settings.pause
+    }
+
+    /// How long the event loop may block before the next check is due.
+    pub fn time_until_due(&self) -> Duration {
+        match self.last_check {
+            Some(last_check) => self.settings.interval.saturating_sub(last_check.elapsed()),
+            None => Duration::ZERO,
+        }
+    }
+
+    pub fn check_if_due(&mut self) -> Option<DiskChange> {
+        if !self.time_until_due().is_zero() {
+            return None;
+        }
+        self.last_check = Some(Instant::now());
+
+        let was_low = !self.low.is_empty();
+        let min_free = self.settings.min_free_mb * 1024 * 1024;
+
+        for path in &self.paths {
+            let free = match free_space(path) {
+                Ok(free) => free,
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "Failed to check free disk space");
+                    continue;
+                }
+            };
+
+            if free >= min_free {
+                if self.low.remove(path) {
+                    info!(path = %path.display(), free_mb = free / 1024 / 1024, "Disk space recovered");
+                }
+                continue;
+            }
+
+            if self.low.insert(path.clone()) {
+                self.alert(path, free / 1024 / 1024);
+            }
+        }
+
+        match (was_low, !self.low.is_empty()) {
+            (false, true) => Some(DiskChange::Low),
+            (true, false) => Some(DiskChange::Recovered),
+            _ => None,
+        }
+    }
+
+    fn alert(&self, path: &Path, free_mb: u64) {
+        let min_free_mb = self.settings.min_free_mb;
+        warn!(path = %path.display(), free_mb, min_free_mb, "Low disk space");
+
+        let mut body = format!(
+            "Only {} MB are free on the volume of {} (minimum {} MB).",
+            free_mb,
+            path.display(),
+            min_free_mb
+        );
+        if self.settings.pause {
+            body.push_str(" Processing is paused until space is freed.");
+        }
+
+        let mut fields = BTreeMap::new();
+        fields.insert("path".to_string(), path.display().to_string());
+        fields.insert("free_mb".to_string(), free_mb.to_string());
+        fields.insert("min_free_mb".to_string(), min_free_mb.to_string());
+
+        self.notifications.send(Notification {
+            kind: NotificationKind::Alert,
+            title: "Low disk space".to_string(),
+            body,
+            fields,
+        });
+    }
+}
+
+/// Bytes available to unprivileged users on the volume holding `path`.
+#[cfg(unix)]
+fn free_space(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid statvfs to fill.
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Bytes available to the current user on the volume holding `path`.
+#[cfg(windows)]
+fn free_space(path: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available = 0u64;
+    // SAFETY: `path` is NUL-terminated and the unused outputs may be null.
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(available)
+}
@@ -0,0 +1,478 @@
+use crate::pipeline;
+use crate::plugins::Plugins;
+use crate::rules::RuleSet;
+use crate::settings::Settings;
+use crate::{digest, events, notifications, remote_rules, scan, telemetry};
+use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+use std::io;
+use std::net::TcpListener;
+use std::path::Path;
+
+/// Findings are printed as they are made, grouped by section, with a hint on
+/// how to fix each problem.
+struct Report {
+    failures: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn section(&self, title: &str) {
+        println!("\n{}", title);
+    }
+
+    fn ok(&mut self, message: impl AsRef<str>) {
+        println!("  ok    {}", message.as_ref());
+    }
+
+    fn warn(&mut self, message: impl AsRef<str>, hint: impl AsRef<str>) {
+        self.warnings += 1;
+        println!("  warn  {}", message.as_ref());
+        println!("        {}", hint.as_ref());
+    }
+
+    fn fail(&mut self, message: impl AsRef<str>, hint: impl AsRef<str>) {
+        self.failures += 1;
+        println!("  FAIL  {}", message.as_ref());
+        println!("        {}", hint.as_ref());
+    }
+}
+
+/// Checks the config at `config_path` and the environment it needs, printing
+/// each finding with a hint on how to fix it. Returns false when any check
+/// failed.
+pub fn run(config_path: &Path) -> bool {
+    let mut report = Report {
+        failures: 0,
+        warnings: 0,
+    };
+
+    println!("invoicehandler doctor");
+
+    if let Some(settings) = check_config(&mut report, config_path) {
+        check_settings(&mut report, &settings);
+
+        for tenant in &settings.tenants {
+            println!("\n== Tenant {} ==", tenant.name);
+            if let Some(settings) = check_config(&mut report, &tenant.config_path) {
+                check_settings(&mut report, &settings);
+            }
+        }
+    }
+
+    println!(
+        "\n{} problem(s), {} warning(s)",
+        report.failures, report.warnings
+    );
+    report.failures == 0
+}
+
+fn check_settings(report: &mut Report, settings: &Settings) {
+    check_directories(report, settings);
+    check_watcher(report, &settings.watch_directory);
+    check_listeners(report, settings);
+    check_plugins(report, settings);
+    check_integrations(report, settings);
+}
+
+fn check_config(report: &mut Report, config_path: &Path) -> Option<Settings> {
+    report.section("Config");
+
+    if !config_path.exists() {
+        report.fail(
+            format!("{} not found", config_path.display()),
+            "Create it from config.ini.example",
+        );
+        return None;
+    }
+
+    let settings = match Settings::load(config_path) {
+        Ok(settings) => settings,
+        Err(e) => {
+            report.fail(
+                e.to_string(),
+                format!("Fix {} and run doctor again", config_path.display()),
+            );
+            return None;
+        }
+    };
+
+    match RuleSet::load(config_path) {
+        Ok(rules) if rules.is_empty() => report.warn(
+            "No rules in [translations]",
+            "Files will be reported as unmatched until rules are added",
+        ),
+        Ok(rules) => {
+            report.ok(format!(
+                "{} parsed, {} rules",
+                config_path.display(),
+                rules.len()
+            ));
+            check_conflicts(report, &settings, &rules);
+        }
+        Err(e) => report.fail(
+            e.to_string(),
+            "Backslashes in patterns have to be doubled, e.g. \\\\d for a digit",
+        ),
+    }
+
+    Some(settings)
+}
+
+/// Tests the rules against samples of their patterns and the files in the
+/// watch directory.
+fn check_conflicts(report: &mut Report, settings: &Settings, rules: &RuleSet) {
+    let corpus: Vec<String> = pipeline::watched_files(settings)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|path| path.strip_prefix(&settings.watch_directory).ok())
+        .map(|path| {
+            path.components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .collect();
+
+    let conflicts = rules.conflicts(corpus.iter().map(String::as_str));
+    if conflicts.is_empty() {
+        report.ok("No rule overlaps an earlier one");
+    }
+    for conflict in conflicts {
+        report.warn(
+            format!(
+                "Rule {} overlaps the earlier {}, which renames e.g. {} instead",
+                conflict.rule,
+                conflict.earlier,
+                conflict.examples.join(", ")
+            ),
+            "Make the patterns exclusive, or put the more specific rule first",
+        );
+    }
+}
+
+fn check_directories(report: &mut Report, settings: &Settings) {
+    report.section("Directories");
+
+    let watch_directory = &settings.watch_directory;
+    if !watch_directory.is_dir() {
+        report.fail(
+            format!(
+                "Watch directory {} doesn't exist",
+                watch_directory.display()
+            ),
+            "Create it or fix watch_directory in [settings]",
+        );
+    } else {
+        check_writable(report, "Watch directory", watch_directory);
+    }
+
+    let files = [
+        ("Ledger", settings.ledger.as_ref().map(|l| l.file.as_path())),
+        ("Log file", settings.logging.file()),
+        (
+            "Heartbeat file",
+            settings.heartbeat.as_ref().map(|h| h.file()),
+        ),
+    ];
+    for (name, file) in files {
+        let Some(file) = file else { continue };
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if !dir.is_dir() {
+            report.fail(
+                format!("{} directory {} doesn't exist", name, dir.display()),
+                "Create it or change the path in the config",
+            );
+        } else if file.exists() {
+            check_writable(report, name, file);
+        } else {
+            check_writable(report, &format!("{} directory", name), dir);
+        }
+    }
+}
+
+/// Checks permissions without creating a file, which a running daemon would
+/// pick up from the watch directory.
+fn check_writable(report: &mut Report, name: &str, path: &Path) {
+    match writable(path) {
+        Ok(()) => report.ok(format!("{} {} is writable", name, path.display())),
+        Err(e) => report.fail(
+            format!("{} {} is not writable: {}", name, path.display(), e),
+            "Give the user running invoicehandler write access",
+        ),
+    }
+}
+
+#[cfg(unix)]
+fn writable(path: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: `path` is NUL-terminated.
+    if unsafe { libc::access(path.as_ptr(), libc::W_OK) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn writable(path: &Path) -> io::Result<()> {
+    if path.metadata()?.permissions().readonly() {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only"));
+    }
+    Ok(())
+}
+
+fn check_watcher(report: &mut Report, watch_directory: &Path) {
+    report.section("Watcher");
+
+    #[cfg(target_os = "linux")]
+    check_inotify_limits(report);
+
+    if let Some(filesystem) = network_filesystem(watch_directory) {
+        report.warn(
+            format!("Watch directory is on a network share ({})", filesystem),
+            "Files that other machines write to the share don't produce change events here. \
+             Run invoicehandler on the file server, or have scanners save to a local directory",
+        );
+    }
+
+    let watcher = RecommendedWatcher::new(|_| {}, Config::default()).and_then(|mut watcher| {
+        watcher.watch(watch_directory, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    });
+    match watcher {
+        Ok(_) => report.ok(format!("Can watch {}", watch_directory.display())),
+        Err(e) => report.fail(
+            format!("Can't watch {}: {}", watch_directory.display(), e),
+            if matches!(e.kind, notify::ErrorKind::MaxFilesWatch) {
+                "Raise fs.inotify.max_user_instances or fs.inotify.max_user_watches with sysctl"
+            } else {
+                "Check that the directory is accessible to the user running invoicehandler"
+            },
+        ),
+    }
+}
+
+/// Every running watcher uses an inotify instance, and desktop sessions with
+/// IDEs or sync clients often use up the default limit of 128.
+#[cfg(target_os = "linux")]
+fn check_inotify_limits(report: &mut Report) {
+    use std::fs;
+
+    let limit = |name: &str| -> Option<usize> {
+        fs::read_to_string(format!("/proc/sys/fs/inotify/{}", name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    let (Some(max_instances), Some(max_watches)) =
+        (limit("max_user_instances"), limit("max_user_watches"))
+    else {
+        return;
+    };
+
+    // Only the current user's processes are readable, and only those count
+    // against the limit.
+    let in_use = fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|process| fs::read_dir(process.path().join("fd")).ok())
+        .flatten()
+        .flatten()
+        .filter(|fd| {
+            fs::read_link(fd.path())
+                .map(|target| target.as_os_str() == "anon_inode:inotify")
+                .unwrap_or(false)
+        })
+        .count();
+
+    if in_use >= max_instances {
+        report.fail(
+            format!(
+                "All {} inotify instances of this user are in use",
+                max_instances
+            ),
+            "Raise the limit, e.g. sysctl fs.inotify.max_user_instances=512",
+        );
+    } else {
+        report.ok(format!(
+            "inotify: {} of {} instances in use, {} watches allowed",
+            in_use, max_instances, max_watches
+        ));
+    }
+}
+
+/// The filesystem type when `path` is on a network share.
+#[cfg(target_os = "linux")]
+fn network_filesystem(path: &Path) -> Option<&'static str> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid statfs to fill.
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    match stat.f_type as u32 {
+        0xFF53_4D42 | 0xFE53_4D42 | 0x517B => Some("SMB"),
+        0x6969 => Some("NFS"),
+        0x6573_5546 => Some("FUSE"),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn network_filesystem(path: &Path) -> Option<&'static str> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid statfs to fill.
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // SAFETY: f_fstypename is a NUL-terminated C string.
+    let name = unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    match name.to_bytes() {
+        b"smbfs" => Some("SMB"),
+        b"nfs" => Some("NFS"),
+        b"afpfs" => Some("AFP"),
+        b"webdav" => Some("WebDAV"),
+        _ => None,
+    }
+}
+
+#[cfg(windows)]
+fn network_filesystem(path: &Path) -> Option<&'static str> {
+    use std::path::{Component, Prefix};
+    use windows_sys::Win32::Storage::FileSystem::GetDriveTypeW;
+
+    const DRIVE_REMOTE: u32 = 4;
+
+    let path = std::path::absolute(path).ok()?;
+    let Some(Component::Prefix(prefix)) = path.components().next() else {
+        return None;
+    };
+    match prefix.kind() {
+        Prefix::UNC(..) | Prefix::VerbatimUNC(..) => Some("SMB"),
+        Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => {
+            let root: Vec<u16> = format!("{}:\\", letter as char)
+                .encode_utf16()
+                .chain(Some(0))
+                .collect();
+            // SAFETY: `root` is NUL-terminated.
+            let remote = unsafe { GetDriveTypeW(root.as_ptr()) } == DRIVE_REMOTE;
+            remote.then_some("SMB")
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn network_filesystem(_path: &Path) -> Option<&'static str> {
+    None
+}
+
+fn check_listeners(report: &mut Report, settings: &Settings) {
+    let mut listeners = Vec::new();
+    if let Some(http) = &settings.http {
+        listeners.push(("HTTP server", http.listen()));
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(grpc) = &settings.grpc {
+        listeners.push(("gRPC server", grpc.listen()));
+    }
+    if listeners.is_empty() {
+        return;
+    }
+
+    report.section("Listeners");
+    for (name, address) in listeners {
+        match TcpListener::bind(address) {
+            Ok(_) => report.ok(format!("{} can listen on {}", name, address)),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => report.warn(
+                format!("{} address {} is already in use", name, address),
+                "Expected while invoicehandler is running; otherwise pick another port",
+            ),
+            Err(e) => report.fail(
+                format!("{} can't listen on {}: {}", name, address, e),
+                "Check the listen address in the config",
+            ),
+        }
+    }
+}
+
+fn check_plugins(report: &mut Report, settings: &Settings) {
+    if settings.plugins.is_none() {
+        return;
+    }
+
+    report.section("Plugins");
+    match Plugins::load(settings.plugins.as_ref()) {
+        Ok(plugins) => {
+            let names: Vec<&str> = plugins.names().collect();
+            if names.is_empty() {
+                report.warn(
+                    "No plugins in the plugin directory",
+                    format!(
+                        "Plugins are shared libraries ending in .{} or WebAssembly modules ending in .wasm",
+                        std::env::consts::DLL_EXTENSION
+                    ),
+                );
+            } else {
+                report.ok(format!("Loaded {}", names.join(", ")));
+            }
+        }
+        Err(e) => report.fail(
+            e,
+            "Rebuild the plugin against the headers in include/ or remove it",
+        ),
+    }
+}
+
+fn check_integrations(report: &mut Report, settings: &Settings) {
+    let mut results = events::check_connections(&settings.events);
+    results.extend(notifications::check_notifiers(&settings.notifications));
+    if let Some(result) = settings.digest.as_ref().and_then(digest::check_email) {
+        results.push(("digest email", result));
+    }
+    if let Some(clamav) = &settings.clamav {
+        results.push(("clamav", scan::check(clamav)));
+    }
+    if let Some(otel) = &settings.otel {
+        results.push(("otel", telemetry::check_endpoint(otel)));
+    }
+    if let Some(remote) = &settings.remote_rules {
+        results.push(("remote_rules", remote_rules::check(remote)));
+    }
+    if results.is_empty() {
+        return;
+    }
+
+    report.section("Integrations");
+    for (name, result) in results {
+        match result {
+            Ok(()) => report.ok(format!("{}: connected", name)),
+            Err(e) => report.fail(
+                format!("{}: {}", name, e),
+                format!("Check the address and credentials in [{}]", section(name)),
+            ),
+        }
+    }
+}
+
+fn section(integration: &str) -> &str {
+    match integration {
+        "digest email" => "digest",
+        name => name,
+    }
+}
@@ -0,0 +1,325 @@
+use crate::hashing::{self, HashSettings};
+use crate::ledger::{self, LedgerSettings};
+use crate::notifications::{Notification, NotificationKind, Notifications};
+use crate::scan;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use tracing::{error, warn};
+
+const DEFAULT_KEY: &str = "vendor,number";
+
+/// Catches different files with the same invoice number from the same vendor,
+/// from `[duplicates]`. The ledger is the index of the invoices seen so far.
+pub struct DuplicateSettings {
+    key: Vec<String>,
+    quarantine_directory: Option<PathBuf>,
+    hashing: HashSettings,
+}
+
+pub fn load_duplicate_settings(
+    ini: &ini::Ini,
+    ledger: Option<&LedgerSettings>,
+) -> Result<Option<DuplicateSettings>, String> {
+    let section = match ini.section(Some("duplicates")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+    let ledger = ledger.ok_or("[duplicates] needs a [ledger], which is its index")?;
+
+    let key: Vec<String> = section
+        .get("key")
+        .unwrap_or(DEFAULT_KEY)
+        .split(',')
+        .map(|field| field.trim().to_string())
+        .filter(|field| !field.is_empty())
+        .collect();
+    if key.is_empty() {
+        return Err("No fields in 'key' in [duplicates]".to_string());
+    }
+    for column in key.iter().map(String::as_str).chain(["archived_path"]) {
+        if !ledger.columns.iter().any(|c| c == column) {
+            return Err(format!(
+                "[duplicates] needs a '{}' column in [ledger]",
+                column
+            ));
+        }
+    }
+
+    Ok(Some(DuplicateSettings {
+        key,
+        quarantine_directory: section.get("quarantine_directory").map(PathBuf::from),
+        hashing: hashing::load_hash_settings(ini)?,
+    }))
+}
+
+/// The archived file of every invoice in the ledger, by key.
+pub struct DuplicateIndex<'a> {
+    settings: &'a DuplicateSettings,
+    notifications: Notifications,
+    seen: HashMap<Vec<String>, PathBuf>,
+}
+
+impl<'a> DuplicateIndex<'a> {
+    /// Reads the ledger. When that fails the index starts out empty.
+    pub fn load(
+        settings: &'a DuplicateSettings,
+        ledger: &LedgerSettings,
+        notifications: Notifications,
+    ) -> Self {
+        let mut index = DuplicateIndex {
+            settings,
+            notifications,
+            seen: HashMap::new(),
+        };
+        match ledger::read_entries(ledger) {
+            Ok(entries) => {
+                for entry in entries {
+                    if let Some(path) = entry.get("archived_path") {
+                        index.insert(&entry, Path::new(path));
+                    }
+                }
+            }
+            Err(e) => error!(error = %e, "Failed to read the ledger for duplicate detection"),
+        }
+        index
+    }
+
+    /// The file already in the index with the same key as `fields`, unless
+    /// it is `path` or has the same contents. Files without every key field
+    /// have no duplicates.
+    pub fn find(&self, fields: &BTreeMap<String, String>, path: &Path) -> Option<PathBuf> {
+        let existing = self.seen.get(&self.key(fields)?)?;
+        if existing == path || self.same_contents(existing, path) {
+            return None;
+        }
+        Some(existing.clone())
+    }
+
+    /// Raises an alert for a duplicate of `existing`, and moves it into
+    /// `quarantine_directory` when set. Returns where it went.
+    pub fn report(
+        &self,
+        path: &Path,
+        existing: &Path,
+        fields: &BTreeMap<String, String>,
+    ) -> Result<Option<PathBuf>, String> {
+        let quarantined = self
+            .settings
+            .quarantine_directory
+            .as_deref()
+            .map(|directory| scan::quarantine_file(path, directory))
+            .transpose()?;
+
+        let key = self.key(fields).unwrap_or_default().join(" ");
+        warn!(
+            path = %path.display(),
+            existing = %existing.display(),
+            key,
+            quarantine = quarantined.as_ref().map(|target| target.display().to_string()),
+            "Duplicate invoice"
+        );
+
+        let mut fields = fields.clone();
+        fields.insert("path".to_string(), path.display().to_string());
+        fields.insert("existing".to_string(), existing.display().to_string());
+        let body = match &quarantined {
+            Some(target) => format!(
+                "{} has the same {} as {} and was moved to {}.",
+                path.display(),
+                key,
+                existing.display(),
+                target.display()
+            ),
+            None => format!(
+                "{} has the same {} as {}.",
+                path.display(),
+                key,
+                existing.display()
+            ),
+        };
+        self.notifications.send(Notification {
+            kind: NotificationKind::Alert,
+            title: "Duplicate invoice".to_string(),
+            body,
+            fields,
+        });
+
+        Ok(quarantined)
+    }
+
+    pub fn insert(&mut self, fields: &BTreeMap<String, String>, path: &Path) {
+        if let Some(key) = self.key(fields) {
+            self.seen.insert(key, path.to_path_buf());
+        }
+    }
+
+    fn key(&self, fields: &BTreeMap<String, String>) -> Option<Vec<String>> {
+        self.settings
+            .key
+            .iter()
+            .map(|field| {
+                fields
+                    .get(field)
+                    .map(|value| value.trim().to_lowercase())
+                    .filter(|value| !value.is_empty())
+            })
+            .collect()
+    }
+
+    /// A byte-identical file is the same invoice delivered twice rather
+    /// than a second one with the same number.
+    fn same_contents(&self, existing: &Path, path: &Path) -> bool {
+        let hash = |path| {
+            hashing::sha256_file(path, &self.settings.hashing)
+                .ok()
+                .flatten()
+        };
+        match (hash(existing), hash(path)) {
+            (Some(existing), Some(hash)) => existing == hash,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications;
+    use std::fs;
+
+    fn fields(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn load_duplicate_settings() {
+        let cases = [
+            ("", Ok(None)),
+            (
+                "[duplicates]\n[ledger]\nfile = l.csv\n",
+                Ok(Some("vendor,number")),
+            ),
+            (
+                "[duplicates]\nkey = vendor, amount,\n[ledger]\nfile = l.csv\n",
+                Ok(Some("vendor,amount")),
+            ),
+            ("[duplicates]\n", Err("[duplicates] needs a [ledger]")),
+            (
+                "[duplicates]\nkey = ,\n[ledger]\nfile = l.csv\n",
+                Err("No fields in 'key' in [duplicates]"),
+            ),
+            (
+                "[duplicates]\nkey = iban\n[ledger]\nfile = l.csv\n",
+                Err("[duplicates] needs a 'iban' column in [ledger]"),
+            ),
+            (
+                "[duplicates]\n[ledger]\nfile = l.csv\ncolumns = vendor,number\n",
+                Err("[duplicates] needs a 'archived_path' column in [ledger]"),
+            ),
+        ];
+        for (config, expected) in cases {
+            let ini = ini::Ini::load_from_str(config).unwrap();
+            let ledger = ledger::load_ledger_settings(&ini).unwrap();
+            let result = super::load_duplicate_settings(&ini, ledger.as_ref())
+                .map(|settings| settings.map(|settings| settings.key.join(",")));
+            match (result, expected) {
+                (Ok(key), Ok(expected)) => {
+                    assert_eq!(key.as_deref(), expected, "{}", config)
+                }
+                (Err(e), Err(prefix)) => assert!(e.starts_with(prefix), "{}: {}", config, e),
+                (result, expected) => panic!("{}: {:?}, expected {:?}", config, result, expected),
+            }
+        }
+    }
+
+    #[test]
+    fn find_and_report() {
+        let dir =
+            std::env::temp_dir().join(format!("invoicehandler-duplicates-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archived = dir.join("archived.pdf");
+        let copy = dir.join("copy.pdf");
+        let other = dir.join("other.pdf");
+        fs::write(&archived, "invoice R-1").unwrap();
+        fs::write(&copy, "invoice R-1").unwrap();
+        fs::write(&other, "invoice R-1, corrected").unwrap();
+        fs::write(
+            dir.join("ledger.csv"),
+            format!(
+                "date,vendor,number,amount,currency,archived_path\n\
+                 2024-03-05,Muster AG,R-1,10.00,EUR,{}\n\
+                 2024-03-05,Muster AG,,10.00,EUR,{}\n",
+                archived.display(),
+                dir.join("no-number.pdf").display()
+            ),
+        )
+        .unwrap();
+
+        let ini = ini::Ini::load_from_str(&format!(
+            "[ledger]\nfile = {}\n[duplicates]\nquarantine_directory = {}\n",
+            dir.join("ledger.csv").display(),
+            dir.join("quarantine").display()
+        ))
+        .unwrap();
+        let ledger = ledger::load_ledger_settings(&ini).unwrap().unwrap();
+        let settings = super::load_duplicate_settings(&ini, Some(&ledger))
+            .unwrap()
+            .unwrap();
+        let notifications = Notifications::start(
+            &notifications::load_notification_settings(&ini::Ini::new()).unwrap(),
+        );
+        let mut index = DuplicateIndex::load(&settings, &ledger, notifications);
+
+        let invoice = fields(&[("vendor", " MUSTER AG "), ("number", "r-1")]);
+        let cases = [
+            (
+                fields(&[("vendor", "Muster AG"), ("number", "R-1")]),
+                &other,
+                Some(&archived),
+            ),
+            (invoice.clone(), &other, Some(&archived)),
+            // The archived file itself, and the same file delivered twice.
+            (invoice.clone(), &archived, None),
+            (invoice.clone(), &copy, None),
+            (
+                fields(&[("vendor", "Muster AG"), ("number", "R-2")]),
+                &other,
+                None,
+            ),
+            (fields(&[("vendor", "Muster AG")]), &other, None),
+            (
+                fields(&[("vendor", "Muster AG"), ("number", " ")]),
+                &other,
+                None,
+            ),
+        ];
+        for (fields, path, expected) in cases {
+            assert_eq!(
+                index.find(&fields, path),
+                expected.cloned(),
+                "{:?} {}",
+                fields,
+                path.display()
+            );
+        }
+
+        let next = fields(&[("vendor", "Beispiel AG"), ("number", "7")]);
+        assert_eq!(index.find(&next, &other), None);
+        index.insert(&next, &archived);
+        assert_eq!(index.find(&next, &other), Some(archived.clone()));
+
+        let quarantined = index.report(&other, &archived, &invoice).unwrap();
+        assert_eq!(quarantined, Some(dir.join("quarantine").join("other.pdf")));
+        assert!(!other.exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("quarantine").join("other.pdf")).unwrap(),
+            "invoice R-1, corrected"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+// Exit codes from BSD's sysexits.h, which service managers and scripts
+// already know how to interpret.
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_UNAVAILABLE: i32 = 69;
+const EX_IOERR: i32 = 74;
+const EX_NOPERM: i32 = 77;
+const EX_CONFIG: i32 = 78;
+
+/// The config file can't be read or has invalid settings.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to parse {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: ini::ParseError,
+    },
+    /// A missing or invalid setting, described by the section's loader.
+    #[error("{0}")]
+    Invalid(String),
+    #[error("'{}' is not a valid directory", .0.display())]
+    WatchDirectory(PathBuf),
+}
+
+impl ConfigError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ConfigError::Io { source, .. } if source.kind() == io::ErrorKind::NotFound => {
+                EX_NOINPUT
+            }
+            ConfigError::Io { .. } => EX_IOERR,
+            ConfigError::Parse { .. } | ConfigError::Invalid(_) => EX_CONFIG,
+            ConfigError::WatchDirectory(_) => EX_NOINPUT,
+        }
+    }
+}
+
+impl From<String> for ConfigError {
+    fn from(message: String) -> Self {
+        ConfigError::Invalid(message)
+    }
+}
+
+impl From<&str> for ConfigError {
+    fn from(message: &str) -> Self {
+        ConfigError::Invalid(message.to_string())
+    }
+}
+
+/// The `[translations]` rules can't be loaded.
+#[derive(Debug, Error)]
+pub enum RuleError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("Invalid regex pattern '{pattern}': {source}")]
+    InvalidPattern {
+        pattern: String,
+        #[source]
+        source: regex::Error,
+    },
+}
+
+impl RuleError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RuleError::Config(e) => e.exit_code(),
+            RuleError::InvalidPattern { .. } => EX_DATAERR,
+        }
+    }
+}
+
+/// The daemon or a pipeline can't start.
+#[derive(Debug, Error)]
+pub enum ProcessError {
+    #[error("{0}")]
+    Plugins(String),
+    #[error("Error setting up event publishing: {0}")]
+    Events(String),
+    #[error("Error setting up OpenTelemetry: {0}")]
+    Telemetry(String),
+    #[error("Error starting HTTP server: {0}")]
+    Http(String),
+    #[error("Error starting gRPC server: {0}")]
+    Grpc(String),
+    #[error("Failed to create file watcher: {0}")]
+    Watcher(#[source] notify::Error),
+    #[error("Failed to watch {}: {source}", path.display())]
+    Watch {
+        path: PathBuf,
+        #[source]
+        source: notify::Error,
+    },
+    #[error("Failed to drop privileges: {0}")]
+    Privileges(String),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Rules(#[from] RuleError),
+    #[error("Tenant '{name}': {source}")]
+    Tenant {
+        name: String,
+        #[source]
+        source: Box<ProcessError>,
+    },
+}
+
+impl ProcessError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ProcessError::Plugins(_) => EX_CONFIG,
+            ProcessError::Events(_)
+            | ProcessError::Telemetry(_)
+            | ProcessError::Http(_)
+            | ProcessError::Grpc(_) => EX_UNAVAILABLE,
+            ProcessError::Watcher(_) | ProcessError::Watch { .. } => EX_IOERR,
+            ProcessError::Privileges(_) => EX_NOPERM,
+            ProcessError::Config(e) => e.exit_code(),
+            ProcessError::Rules(e) => e.exit_code(),
+            ProcessError::Tenant { source, .. } => source.exit_code(),
+        }
+    }
+}
+
+/// An action failed on a file.
+#[derive(Debug, Error)]
+pub enum ActionError {
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// A file is already there under the new name, and is left alone.
+    #[error("{} already exists", .0.display())]
+    AlreadyExists(PathBuf),
+    /// A command or request the action made failed.
+    #[error("{0}")]
+    Failed(String),
+}
+
+impl ActionError {
+    pub(crate) fn io(context: impl Into<String>, source: io::Error) -> Self {
+        ActionError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+}
+
+impl From<String> for ActionError {
+    fn from(message: String) -> Self {
+        ActionError::Failed(message)
+    }
+}
+
+impl From<&str> for ActionError {
+    fn from(message: &str) -> Self {
+        ActionError::Failed(message.to_string())
+    }
+}
+
+/// A file a rule matched couldn't be processed. It was published as failed
+/// and logged already.
+#[derive(Debug, Error)]
+pub enum FileError {
+    /// The file stayed locked, and is queued to be tried again.
+    #[error("{}", crate::events::LOCKED_ERROR)]
+    Locked,
+    #[error("Infected with {signature}")]
+    Infected {
+        signature: String,
+        /// Where it was moved, unless that failed too.
+        quarantined: Option<PathBuf>,
+    },
+    #[error("Virus scan failed: {0}")]
+    Scan(String),
+    #[error("Duplicate of {}", existing.display())]
+    Duplicate {
+        existing: PathBuf,
+        /// Where it was moved, unless that failed too.
+        quarantined: Option<PathBuf>,
+    },
+    #[error("{action}: {source}")]
+    Action {
+        action: &'static str,
+        /// Where the file is after the actions before it.
+        path: PathBuf,
+        #[source]
+        source: ActionError,
+    },
+}
@@ -0,0 +1,43 @@
+use crate::logging::BoxedLayer;
+use sentry::types::Dsn;
+use sentry::{ClientInitGuard, ClientOptions};
+use tracing_subscriber::Layer;
+
+pub struct SentrySettings {
+    dsn: Dsn,
+    environment: Option<String>,
+}
+
+pub fn load_sentry_settings(ini: &ini::Ini) -> Result<Option<SentrySettings>, String> {
+    let section = match ini.section(Some("sentry")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let dsn = section
+        .get("dsn")
+        .ok_or("Missing 'dsn' in [sentry]")?
+        .parse()
+        .map_err(|e| format!("Invalid sentry dsn: {}", e))?;
+
+    Ok(Some(SentrySettings {
+        dsn,
+        environment: section.get("environment").map(str::to_string),
+    }))
+}
+
+/// Starts the Sentry client, which reports panics on its own. The returned layer
+/// turns `error!` events into Sentry events and earlier log lines into
+/// breadcrumbs; span fields such as the file path and rule are attached as
+/// context. The guard flushes pending reports when dropped.
+pub fn init(settings: &SentrySettings) -> (BoxedLayer, ClientInitGuard) {
+    let mut options = ClientOptions::default();
+    options.dsn = Some(settings.dsn.clone());
+    options.release = sentry::release_name!();
+    options.environment = settings.environment.clone().map(Into::into);
+    let guard = sentry::init(options);
+
+    let layer = sentry_tracing::layer().enable_span_attributes().boxed();
+
+    (layer, guard)
+}
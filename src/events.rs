@@ -0,0 +1,202 @@
+mod amqp;
+mod kafka;
+mod mqtt;
+mod redis;
+
+use crate::telemetry;
+use amqp::{AmqpPublisher, AmqpSettings};
+use chrono::Local;
+use kafka::{KafkaPublisher, KafkaSettings};
+use mqtt::{MqttPublisher, MqttSettings};
+use redis::{RedisPublisher, RedisSettings};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::{error, warn};
+
+/// Error of failed events for files that stayed locked past all retries.
+pub const LOCKED_ERROR: &str = "File remained locked";
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Processed,
+    Failed,
+    Unmatched,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Processed => "processed",
+            Outcome::Failed => "failed",
+            Outcome::Unmatched => "unmatched",
+        }
+    }
+}
+
+/// A single processing result, published as JSON to every configured sink.
+#[derive(Serialize)]
+pub struct FileEvent {
+    pub outcome: Outcome,
+    pub path: PathBuf,
+    pub new_path: Option<PathBuf>,
+    pub rule: Option<String>,
+    pub error: Option<String>,
+    /// Named capture groups of the matched rule, e.g. vendor or number, and
+    /// fields extracted by plugins.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    pub timestamp: String,
+    pub traceparent: Option<String>,
+}
+
+impl FileEvent {
+    pub fn processed(path: &Path, new_path: &Path, rule: &str) -> Self {
+        FileEvent {
+            new_path: Some(new_path.to_path_buf()),
+            rule: Some(rule.to_string()),
+            ..FileEvent::new(Outcome::Processed, path)
+        }
+    }
+
+    pub fn failed(path: &Path, rule: Option<&str>, error: &str) -> Self {
+        FileEvent {
+            rule: rule.map(str::to_string),
+            error: Some(error.to_string()),
+            ..FileEvent::new(Outcome::Failed, path)
+        }
+    }
+
+    pub fn unmatched(path: &Path) -> Self {
+        FileEvent::new(Outcome::Unmatched, path)
+    }
+
+    pub fn with_fields(mut self, fields: BTreeMap<String, String>) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    /// Adds fields extracted by plugins, keeping the rule's captures where
+    /// both have the same name.
+    pub fn with_extracted_fields(mut self, fields: BTreeMap<String, String>) -> Self {
+        for (name, value) in fields {
+            self.fields.entry(name).or_insert(value);
+        }
+        self
+    }
+
+    fn new(outcome: Outcome, path: &Path) -> Self {
+        FileEvent {
+            outcome,
+            path: path.to_path_buf(),
+            new_path: None,
+            rule: None,
+            error: None,
+            fields: BTreeMap::new(),
+            timestamp: Local::now().to_rfc3339(),
+            traceparent: telemetry::current_traceparent(),
+        }
+    }
+}
+
+/// Receives every published event. Broker publishers are built from
+/// [`EventSettings`]; other subsystems register themselves with
+/// [`EventPublisher::add_sink`].
+pub trait EventSink: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn publish(&self, event: &FileEvent, payload: &str) -> Result<(), String>;
+}
+
+pub struct EventSettings {
+    mqtt: Option<MqttSettings>,
+    amqp: Option<AmqpSettings>,
+    kafka: Option<KafkaSettings>,
+    redis: Option<RedisSettings>,
+}
+
+pub fn load_event_settings(ini: &ini::Ini) -> Result<EventSettings, String> {
+    Ok(EventSettings {
+        mqtt: mqtt::load_mqtt_settings(ini)?,
+        amqp: amqp::load_amqp_settings(ini)?,
+        kafka: kafka::load_kafka_settings(ini)?,
+        redis: redis::load_redis_settings(ini)?,
+    })
+}
+
+/// Connects to each configured broker once, for `invoicehandler doctor`.
+pub fn check_connections(settings: &EventSettings) -> Vec<(&'static str, Result<(), String>)> {
+    let mut results = Vec::new();
+
+    if let Some(mqtt) = &settings.mqtt {
+        results.push(("mqtt", mqtt::check(mqtt)));
+    }
+
+    if let Some(amqp) = &settings.amqp {
+        results.push(("amqp", amqp::check(amqp)));
+    }
+
+    if let Some(kafka) = &settings.kafka {
+        results.push(("kafka", kafka::check(kafka)));
+    }
+
+    if let Some(redis) = &settings.redis {
+        results.push(("redis", redis::check(redis)));
+    }
+
+    results
+}
+
+pub struct EventPublisher {
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl EventPublisher {
+    pub fn connect(settings: &EventSettings) -> Result<Self, String> {
+        let mut sinks: Vec<Box<dyn EventSink>> = Vec::new();
+
+        if let Some(mqtt) = &settings.mqtt {
+            sinks.push(Box::new(MqttPublisher::connect(mqtt)));
+        }
+
+        if let Some(amqp) = &settings.amqp {
+            sinks.push(Box::new(AmqpPublisher::connect(amqp)));
+        }
+
+        if let Some(kafka) = &settings.kafka {
+            sinks.push(Box::new(KafkaPublisher::connect(kafka)));
+        }
+
+        if let Some(redis) = &settings.redis {
+            sinks.push(Box::new(RedisPublisher::connect(redis)?));
+        }
+
+        Ok(EventPublisher { sinks })
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn EventSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn publish(&self, event: &FileEvent) {
+        telemetry::record_event(event);
+
+        if self.sinks.is_empty() {
+            return;
+        }
+
+        let payload = match serde_json::to_string(event) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize event: {}", e);
+                return;
+            }
+        };
+
+        for sink in &self.sinks {
+            if let Err(e) = sink.publish(event, &payload) {
+                warn!(sink = sink.name(), error = %e, "Failed to publish event");
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use super::{EventSink, FileEvent};
+use amiquip::{AmqpProperties, Channel, Connection, Publish};
+use std::sync::Mutex;
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct AmqpSettings {
+    url: String,
+    exchange: String,
+    routing_key: String,
+}
+
+pub fn load_amqp_settings(ini: &ini::Ini) -> Result<Option<AmqpSettings>, String> {
+    let section = match ini.section(Some("amqp")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let url = section.get("url").ok_or("Missing 'url' in [amqp]")?;
+    let exchange = section
+        .get("exchange")
+        .ok_or("Missing 'exchange' in [amqp]")?;
+
+    Ok(Some(AmqpSettings {
+        url: url.to_string(),
+        exchange: exchange.to_string(),
+        routing_key: section
+            .get("routing_key")
+            .unwrap_or("invoicehandler.{outcome}")
+            .to_string(),
+    }))
+}
+
+pub struct AmqpPublisher {
+    settings: AmqpSettings,
+    connection: Mutex<Option<(Connection, Channel)>>,
+}
+
+impl AmqpPublisher {
+    pub fn connect(settings: &AmqpSettings) -> Self {
+        let publisher = AmqpPublisher {
+            settings: settings.clone(),
+            connection: Mutex::new(None),
+        };
+
+        // A broker that is down at startup shouldn't stop the watcher; the
+        // connection is retried on the next publish.
+        match publisher.open() {
+            Ok(connection) => *publisher.connection.lock().unwrap() = Some(connection),
+            Err(e) => warn!("Failed to connect to AMQP broker: {}", e),
+        }
+
+        publisher
+    }
+
+    fn open(&self) -> Result<(Connection, Channel), String> {
+        let mut connection = Connection::open(&self.settings.url).map_err(|e| e.to_string())?;
+        let channel = connection.open_channel(None).map_err(|e| e.to_string())?;
+        Ok((connection, channel))
+    }
+}
+
+/// Opens and closes a connection, for `invoicehandler doctor`.
+pub fn check(settings: &AmqpSettings) -> Result<(), String> {
+    let connection = Connection::open(&settings.url).map_err(|e| e.to_string())?;
+    connection.close().map_err(|e| e.to_string())
+}
+
+impl EventSink for AmqpPublisher {
+    fn name(&self) -> &'static str {
+        "amqp"
+    }
+
+    fn publish(&self, event: &FileEvent, payload: &str) -> Result<(), String> {
+        let mut connection = self.connection.lock().unwrap();
+
+        if connection.is_none() {
+            *connection = Some(self.open()?);
+        }
+
+        let routing_key = self
+            .settings
+            .routing_key
+            .replace("{outcome}", event.outcome.as_str());
+        let properties =
+            AmqpProperties::default().with_content_type("application/json".to_string());
+
+        let (_, channel) = connection.as_ref().expect("connection was just opened");
+        let result = channel
+            .basic_publish(
+                self.settings.exchange.as_str(),
+                Publish::with_properties(payload.as_bytes(), routing_key, properties),
+            )
+            .map_err(|e| e.to_string());
+
+        // Drop a broken connection so the next event reconnects.
+        if result.is_err() {
+            *connection = None;
+        }
+
+        result
+    }
+}
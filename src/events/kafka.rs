@@ -0,0 +1,126 @@
+use super::{EventSink, FileEvent, Outcome};
+use crate::hashing::{self, HashSettings};
+use kafka::producer::{Producer, Record, RequiredAcks};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct KafkaSettings {
+    brokers: Vec<String>,
+    topic: String,
+    client_id: String,
+    hashing: HashSettings,
+}
+
+pub fn load_kafka_settings(ini: &ini::Ini) -> Result<Option<KafkaSettings>, String> {
+    let section = match ini.section(Some("kafka")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let brokers: Vec<String> = section
+        .get("brokers")
+        .ok_or("Missing 'brokers' in [kafka]")?
+        .split(',')
+        .map(|b| b.trim().to_string())
+        .filter(|b| !b.is_empty())
+        .collect();
+
+    if brokers.is_empty() {
+        return Err("No brokers configured in [kafka]".to_string());
+    }
+
+    Ok(Some(KafkaSettings {
+        brokers,
+        topic: section
+            .get("topic")
+            .unwrap_or("invoicehandler.invoices")
+            .to_string(),
+        client_id: section
+            .get("client_id")
+            .unwrap_or("invoicehandler")
+            .to_string(),
+        hashing: hashing::load_hash_settings(ini)?,
+    }))
+}
+
+pub struct KafkaPublisher {
+    settings: KafkaSettings,
+    producer: Mutex<Option<Producer>>,
+}
+
+impl KafkaPublisher {
+    pub fn connect(settings: &KafkaSettings) -> Self {
+        let publisher = KafkaPublisher {
+            settings: settings.clone(),
+            producer: Mutex::new(None),
+        };
+
+        match publisher.open() {
+            Ok(producer) => *publisher.producer.lock().unwrap() = Some(producer),
+            Err(e) => warn!("Failed to connect to Kafka: {}", e),
+        }
+
+        publisher
+    }
+
+    fn open(&self) -> Result<Producer, String> {
+        open(&self.settings)
+    }
+}
+
+fn open(settings: &KafkaSettings) -> Result<Producer, String> {
+    Producer::from_hosts(settings.brokers.clone())
+        .with_client_id(settings.client_id.clone())
+        .with_ack_timeout(Duration::from_secs(5))
+        .with_required_acks(RequiredAcks::One)
+        .create()
+        .map_err(|e| e.to_string())
+}
+
+/// Creating a producer fetches the cluster metadata, so this fails when no
+/// broker is reachable.
+pub fn check(settings: &KafkaSettings) -> Result<(), String> {
+    open(settings).map(|_| ())
+}
+
+impl EventSink for KafkaPublisher {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    /// Only processed invoices become records, keyed by the content hash of the
+    /// renamed file so consumers can deduplicate. Files too large to hash are
+    /// keyed by their path.
+    fn publish(&self, event: &FileEvent, payload: &str) -> Result<(), String> {
+        if event.outcome != Outcome::Processed {
+            return Ok(());
+        }
+
+        let key = match &event.new_path {
+            Some(path) => hashing::sha256_file(path, &self.settings.hashing)?
+                .unwrap_or_else(|| path.display().to_string()),
+            None => return Ok(()),
+        };
+
+        let mut producer = self.producer.lock().unwrap();
+
+        if producer.is_none() {
+            *producer = Some(self.open()?);
+        }
+
+        let record = Record::from_key_value(&self.settings.topic, key, payload);
+        let result = producer
+            .as_mut()
+            .expect("producer was just opened")
+            .send(&record)
+            .map_err(|e| e.to_string());
+
+        if result.is_err() {
+            *producer = None;
+        }
+
+        result
+    }
+}
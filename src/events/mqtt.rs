@@ -0,0 +1,119 @@
+use super::{EventSink, FileEvent};
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+pub struct MqttSettings {
+    host: String,
+    port: u16,
+    client_id: String,
+    topic: String,
+    qos: QoS,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+pub fn load_mqtt_settings(ini: &ini::Ini) -> Result<Option<MqttSettings>, String> {
+    let section = match ini.section(Some("mqtt")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let host = section.get("host").ok_or("Missing 'host' in [mqtt]")?;
+
+    let port: u16 = section
+        .get("port")
+        .unwrap_or("1883")
+        .parse()
+        .map_err(|e| format!("Invalid mqtt port: {}", e))?;
+
+    let qos = match section.get("qos").unwrap_or("1") {
+        "0" => QoS::AtMostOnce,
+        "1" => QoS::AtLeastOnce,
+        "2" => QoS::ExactlyOnce,
+        other => return Err(format!("Invalid mqtt qos '{}': expected 0, 1 or 2", other)),
+    };
+
+    Ok(Some(MqttSettings {
+        host: host.to_string(),
+        port,
+        client_id: section
+            .get("client_id")
+            .unwrap_or("invoicehandler")
+            .to_string(),
+        topic: section
+            .get("topic")
+            .unwrap_or("invoicehandler/events")
+            .to_string(),
+        qos,
+        username: section.get("username").map(str::to_string),
+        password: section.get("password").map(str::to_string),
+    }))
+}
+
+pub struct MqttPublisher {
+    client: Client,
+    topic: String,
+    qos: QoS,
+}
+
+impl MqttPublisher {
+    pub fn connect(settings: &MqttSettings) -> Self {
+        let (client, mut connection) = Client::new(options(settings), 100);
+
+        // The connection has to be polled for anything to be sent; it reconnects
+        // on its own, so errors only need to be logged and throttled.
+        thread::spawn(move || {
+            for notification in connection.iter() {
+                if let Err(e) = notification {
+                    warn!("MQTT connection error: {}. Reconnecting...", e);
+                    thread::sleep(Duration::from_secs(5));
+                }
+            }
+        });
+
+        MqttPublisher {
+            client,
+            topic: settings.topic.clone(),
+            qos: settings.qos,
+        }
+    }
+}
+
+fn options(settings: &MqttSettings) -> MqttOptions {
+    let mut options = MqttOptions::new(&settings.client_id, &settings.host, settings.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = &settings.username {
+        options.set_credentials(username, settings.password.as_deref().unwrap_or(""));
+    }
+    options
+}
+
+/// Waits for the broker to accept the connection, for `invoicehandler doctor`.
+/// A rejected username or password fails the connection.
+pub fn check(settings: &MqttSettings) -> Result<(), String> {
+    let (client, mut connection) = Client::new(options(settings), 10);
+    let result = loop {
+        match connection.recv_timeout(Duration::from_secs(10)) {
+            Ok(Ok(Event::Incoming(Packet::ConnAck(_)))) => break Ok(()),
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => break Err(e.to_string()),
+            Err(_) => break Err("Timed out waiting for the broker".to_string()),
+        }
+    };
+    let _ = client.disconnect();
+    result
+}
+
+impl EventSink for MqttPublisher {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn publish(&self, _event: &FileEvent, payload: &str) -> Result<(), String> {
+        self.client
+            .try_publish(&self.topic, self.qos, false, payload)
+            .map_err(|e| e.to_string())
+    }
+}
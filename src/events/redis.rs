@@ -0,0 +1,122 @@
+use super::{EventSink, FileEvent, Outcome};
+use redis::{Client, Connection};
+use std::sync::Mutex;
+use tracing::warn;
+
+#[derive(Clone, Copy)]
+enum RedisMode {
+    List,
+    Stream,
+}
+
+#[derive(Clone)]
+pub struct RedisSettings {
+    url: String,
+    key: String,
+    mode: RedisMode,
+}
+
+pub fn load_redis_settings(ini: &ini::Ini) -> Result<Option<RedisSettings>, String> {
+    let section = match ini.section(Some("redis")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let url = section.get("url").ok_or("Missing 'url' in [redis]")?;
+
+    let mode = match section.get("mode").unwrap_or("list") {
+        "list" => RedisMode::List,
+        "stream" => RedisMode::Stream,
+        other => {
+            return Err(format!(
+                "Invalid redis mode '{}': expected list or stream",
+                other
+            ))
+        }
+    };
+
+    Ok(Some(RedisSettings {
+        url: url.to_string(),
+        key: section
+            .get("key")
+            .unwrap_or("invoicehandler:processed")
+            .to_string(),
+        mode,
+    }))
+}
+
+pub struct RedisPublisher {
+    settings: RedisSettings,
+    client: Client,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl RedisPublisher {
+    pub fn connect(settings: &RedisSettings) -> Result<Self, String> {
+        let client =
+            Client::open(settings.url.as_str()).map_err(|e| format!("Invalid redis url: {}", e))?;
+
+        let connection = match client.get_connection() {
+            Ok(connection) => Some(connection),
+            Err(e) => {
+                warn!("Failed to connect to Redis: {}", e);
+                None
+            }
+        };
+
+        Ok(RedisPublisher {
+            settings: settings.clone(),
+            client,
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+/// Connects and sends a PING, for `invoicehandler doctor`.
+pub fn check(settings: &RedisSettings) -> Result<(), String> {
+    let client =
+        Client::open(settings.url.as_str()).map_err(|e| format!("Invalid redis url: {}", e))?;
+    let mut connection = client.get_connection().map_err(|e| e.to_string())?;
+    redis::cmd("PING")
+        .query::<String>(&mut connection)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+impl EventSink for RedisPublisher {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn publish(&self, event: &FileEvent, payload: &str) -> Result<(), String> {
+        if event.outcome != Outcome::Processed {
+            return Ok(());
+        }
+
+        let mut connection = self.connection.lock().unwrap();
+
+        if connection.is_none() {
+            *connection = Some(self.client.get_connection().map_err(|e| e.to_string())?);
+        }
+
+        let mut command = match self.settings.mode {
+            RedisMode::List => redis::cmd("LPUSH"),
+            RedisMode::Stream => redis::cmd("XADD"),
+        };
+        command.arg(&self.settings.key);
+        if let RedisMode::Stream = self.settings.mode {
+            command.arg("*").arg("event");
+        }
+        command.arg(payload);
+
+        let result = command
+            .query::<()>(connection.as_mut().expect("connection was just opened"))
+            .map_err(|e| e.to_string());
+
+        if result.is_err() {
+            *connection = None;
+        }
+
+        result
+    }
+}
@@ -0,0 +1,125 @@
+use crate::ledger;
+use crate::search;
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+const USAGE: &str = "usage: invoicehandler export [--format csv|json] [--since YYYY-MM-DD]";
+
+enum Format {
+    Csv,
+    Json,
+}
+
+/// `invoicehandler export`: writes the rows of the ledgers of the config and
+/// its tenants to stdout, for reconciliation against the accounting system.
+/// With tenants, a `tenant` column says whose row it is.
+pub fn run_export_command(config_path: &Path, args: &[String]) -> i32 {
+    let mut format = Format::Csv;
+    let mut since = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next();
+        match (arg.as_str(), value.map(String::as_str)) {
+            ("--format", Some("csv")) => format = Format::Csv,
+            ("--format", Some("json")) => format = Format::Json,
+            ("--since", Some(date)) => match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                Ok(date) => since = Some(date),
+                Err(_) => {
+                    eprintln!("Invalid date '{}', use YYYY-MM-DD", date);
+                    return 2;
+                }
+            },
+            _ => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        }
+    }
+
+    let configs = match search::ledger_configs(config_path) {
+        Ok(configs) => configs,
+        Err(code) => return code,
+    };
+    let with_tenants = configs.len() > 1;
+
+    let mut columns: Vec<String> = Vec::new();
+    if with_tenants {
+        columns.push("tenant".to_string());
+    }
+    let mut rows = Vec::new();
+    let mut undated = 0;
+    for (tenant, settings) in &configs {
+        let Some(ledger) = &settings.ledger else {
+            continue;
+        };
+        for column in &ledger.columns {
+            if !columns.contains(column) {
+                columns.push(column.clone());
+            }
+        }
+
+        let entries = match ledger::read_entries(ledger) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        for mut entry in entries {
+            if let Some(since) = since {
+                let date = entry
+                    .get("date")
+                    .and_then(|date| settings.dates.format.parse_date(date));
+                match date {
+                    Some(date) if date < since => continue,
+                    Some(_) => {}
+                    None => {
+                        undated += 1;
+                        continue;
+                    }
+                }
+            }
+            if let Some(tenant) = tenant {
+                entry.insert("tenant".to_string(), tenant.clone());
+            }
+            rows.push(entry);
+        }
+    }
+    if undated > 0 {
+        eprintln!(
+            "Rows left out because their date can't be read with date_format: {}",
+            undated
+        );
+    }
+
+    let written = match format {
+        Format::Csv => write_csv(&columns, &rows),
+        Format::Json => serde_json::to_writer(io::stdout().lock(), &rows)
+            .map_err(|e| e.to_string())
+            .map(|()| println!()),
+    };
+    match written {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Failed to write the export: {}", e);
+            1
+        }
+    }
+}
+
+fn write_csv(columns: &[String], rows: &[BTreeMap<String, String>]) -> Result<(), String> {
+    let mut writer = csv::Writer::from_writer(io::stdout().lock());
+    writer.write_record(columns).map_err(|e| e.to_string())?;
+    for row in rows {
+        writer
+            .write_record(
+                columns
+                    .iter()
+                    .map(|column| row.get(column).map(String::as_str).unwrap_or_default()),
+            )
+            .map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
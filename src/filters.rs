@@ -0,0 +1,110 @@
+/// The widest `pad` can make a value, the longest filename most file
+/// systems take.
+const MAX_PAD_WIDTH: usize = 255;
+
+/// Runs `value` through `filters`, the `|`-separated text after a
+/// placeholder's name as in `${number|pad:8}` in a rule's replacement or
+/// `{vendor|upper}` in a notification template, left to right. Arguments follow the filter's name after `:`:
+///
+/// - `upper`, `lower` and `title`
+/// - `pad:<width>[:<char>]` - left-pads to `width` characters, at most
+///   [`MAX_PAD_WIDTH`], with `0`, or with `char`
+/// - `slice:<start>[:<end>]` - the characters from `start` up to `end`, both
+///   counted from the end when negative
+/// - `default:<text>` - `text` when the value is empty
+pub(crate) fn apply(value: &str, filters: &str) -> Result<String, String> {
+    let mut value = value.to_string();
+    for filter in filters.split('|') {
+        let (name, args) = match filter.split_once(':') {
+            Some((name, args)) => (name, Some(args)),
+            None => (filter, None),
+        };
+        value = match (name.trim(), args) {
+            ("upper", None) => value.to_uppercase(),
+            ("lower", None) => value.to_lowercase(),
+            ("title", None) => title_case(&value),
+            ("pad", Some(args)) => pad(&value, args)?,
+            ("slice", Some(args)) => slice(&value, args)?,
+            ("default", Some(text)) if value.is_empty() => text.to_string(),
+            ("default", Some(_)) => value,
+            ("pad" | "slice" | "default", None) => {
+                return Err(format!("Filter '{}' needs an argument", name))
+            }
+            ("upper" | "lower" | "title", Some(_)) => {
+                return Err(format!("Filter '{}' takes no argument", name))
+            }
+            (name, _) => return Err(format!("Unknown filter '{}'", name)),
+        };
+    }
+    Ok(value)
+}
+
+/// Checks `filters` when the config is loaded, so a typo is a config error
+/// rather than a misnamed file.
+pub(crate) fn check(filters: &str) -> Result<(), String> {
+    apply("", filters).map(drop)
+}
+
+/// The first letter of every word uppercased, the rest lowercased.
+pub(crate) fn title_case(value: &str) -> String {
+    let mut titled = String::with_capacity(value.len());
+    let mut word_start = true;
+    for c in value.chars() {
+        if word_start {
+            titled.extend(c.to_uppercase());
+        } else {
+            titled.extend(c.to_lowercase());
+        }
+        word_start = !c.is_alphanumeric();
+    }
+    titled
+}
+
+fn pad(value: &str, args: &str) -> Result<String, String> {
+    let (width, fill) = match args.split_once(':') {
+        Some((width, fill)) => {
+            let mut chars = fill.chars();
+            match (chars.next(), chars.next()) {
+                (Some(fill), None) => (width, fill),
+                _ => return Err(format!("Invalid pad character '{}'", fill)),
+            }
+        }
+        None => (args, '0'),
+    };
+    let width = match width.trim().parse::<usize>() {
+        Ok(width) if width <= MAX_PAD_WIDTH => width,
+        Ok(_) => {
+            return Err(format!(
+                "Invalid pad width '{}', at most {}",
+                width, MAX_PAD_WIDTH
+            ))
+        }
+        Err(_) => return Err(format!("Invalid pad width '{}'", width)),
+    };
+
+    let len = value.chars().count();
+    let mut padded: String = std::iter::repeat_n(fill, width.saturating_sub(len)).collect();
+    padded.push_str(value);
+    Ok(padded)
+}
+
+fn slice(value: &str, args: &str) -> Result<String, String> {
+    let len = value.chars().count() as i64;
+    let index = |arg: &str| {
+        let index: i64 = arg
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid slice index '{}'", arg))?;
+        let index = if index < 0 { len + index } else { index };
+        Ok::<usize, String>(index.clamp(0, len) as usize)
+    };
+    let (start, end) = match args.split_once(':') {
+        Some((start, end)) => (index(start)?, index(end)?),
+        None => (index(args)?, len as usize),
+    };
+    Ok(value
+        .chars()
+        .skip(start)
+        .take(end.saturating_sub(start))
+        .collect())
+}
@@ -0,0 +1,267 @@
+mod proto {
+    tonic::include_proto!("invoicehandler.v1");
+}
+
+use crate::control::{self, Command, Control, FileError};
+use crate::events::{EventSink, FileEvent};
+use crate::health::Health;
+use proto::invoice_handler_server::{InvoiceHandler, InvoiceHandlerServer};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tracing::{error, info};
+
+/// Events a slow `WatchEvents` client may fall behind by before it misses
+/// some.
+const EVENT_BUFFER: usize = 256;
+
+pub struct GrpcSettings {
+    listen: String,
+    api_token: String,
+}
+
+impl GrpcSettings {
+    pub fn listen(&self) -> &str {
+        &self.listen
+    }
+}
+
+pub fn load_grpc_settings(ini: &ini::Ini) -> Result<Option<GrpcSettings>, String> {
+    let section = match ini.section(Some("grpc")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    Ok(Some(GrpcSettings {
+        listen: section
+            .get("listen")
+            .unwrap_or("127.0.0.1:50051")
+            .to_string(),
+        api_token: section
+            .get("api_token")
+            .filter(|token| !token.is_empty())
+            .ok_or("Missing 'api_token' in [grpc]")?
+            .to_string(),
+    }))
+}
+
+struct Service {
+    health: Arc<Health>,
+    control: Arc<Control>,
+    events: broadcast::Sender<proto::FileEvent>,
+}
+
+impl Service {
+    fn command(&self, command: Command) -> Result<Response<proto::Accepted>, Status> {
+        self.control
+            .send(command)
+            .map(|()| Response::new(proto::Accepted {}))
+            .map_err(Status::unavailable)
+    }
+}
+
+#[tonic::async_trait]
+impl InvoiceHandler for Service {
+    async fn get_status(
+        &self,
+        _request: Request<proto::GetStatusRequest>,
+    ) -> Result<Response<proto::Status>, Status> {
+        let report = self.health.report();
+        Ok(Response::new(proto::Status {
+            healthy: report.healthy,
+            watcher: report.watcher,
+            last_event: report.last_event,
+            queue_depth: report.queue_depth as u64,
+            config: report.config,
+            paused: self.control.is_paused(),
+            rules: self.control.rules().len() as u32,
+        }))
+    }
+
+    async fn list_rules(
+        &self,
+        _request: Request<proto::ListRulesRequest>,
+    ) -> Result<Response<proto::ListRulesResponse>, Status> {
+        let rules = self
+            .control
+            .rules()
+            .into_iter()
+            .map(|rule| proto::Rule {
+                pattern: rule.pattern,
+                replacement: rule.replacement,
+            })
+            .collect();
+        Ok(Response::new(proto::ListRulesResponse { rules }))
+    }
+
+    async fn get_queue(
+        &self,
+        _request: Request<proto::GetQueueRequest>,
+    ) -> Result<Response<proto::Queue>, Status> {
+        let queue = self.control.queue();
+        Ok(Response::new(proto::Queue {
+            held: queue
+                .held
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+            retry: queue
+                .retry
+                .into_iter()
+                .map(|file| proto::QueuedFile {
+                    path: file.path.display().to_string(),
+                    attempts: file.attempts,
+                    next_attempt_secs: file.next_attempt_secs,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn pause(
+        &self,
+        _request: Request<proto::PauseRequest>,
+    ) -> Result<Response<proto::Accepted>, Status> {
+        self.command(Command::Pause)
+    }
+
+    async fn resume(
+        &self,
+        _request: Request<proto::ResumeRequest>,
+    ) -> Result<Response<proto::Accepted>, Status> {
+        self.command(Command::Resume)
+    }
+
+    async fn reload(
+        &self,
+        _request: Request<proto::ReloadRequest>,
+    ) -> Result<Response<proto::Accepted>, Status> {
+        self.command(Command::Reload)
+    }
+
+    async fn catch_up(
+        &self,
+        _request: Request<proto::CatchUpRequest>,
+    ) -> Result<Response<proto::Accepted>, Status> {
+        self.command(Command::CatchUp)
+    }
+
+    async fn reprocess(
+        &self,
+        request: Request<proto::ReprocessRequest>,
+    ) -> Result<Response<proto::Accepted>, Status> {
+        match self.control.watched_file(&request.get_ref().file) {
+            Ok(path) => self.command(Command::Reprocess(path)),
+            Err(e @ FileError::InvalidName) => Err(Status::invalid_argument(e.to_string())),
+            Err(e @ FileError::NotFound) => Err(Status::not_found(e.to_string())),
+        }
+    }
+
+    type WatchEventsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::FileEvent, Status>> + Send + 'static>>;
+
+    /// Clients that fall more than `EVENT_BUFFER` events behind skip the ones
+    /// they missed.
+    async fn watch_events(
+        &self,
+        _request: Request<proto::WatchEventsRequest>,
+    ) -> Result<Response<Self::WatchEventsStream>, Status> {
+        let stream =
+            BroadcastStream::new(self.events.subscribe()).filter_map(|event| event.ok().map(Ok));
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Forwards published events to `WatchEvents` subscribers.
+pub struct GrpcEvents {
+    sender: broadcast::Sender<proto::FileEvent>,
+}
+
+impl EventSink for GrpcEvents {
+    fn name(&self) -> &'static str {
+        "grpc"
+    }
+
+    fn publish(&self, event: &FileEvent, _payload: &str) -> Result<(), String> {
+        // Sending only fails when nobody is subscribed.
+        let _ = self.sender.send(proto::FileEvent {
+            outcome: event.outcome.as_str().to_string(),
+            path: event.path.display().to_string(),
+            new_path: event.new_path.as_ref().map(|p| p.display().to_string()),
+            rule: event.rule.clone(),
+            error: event.error.clone(),
+            fields: event.fields.clone().into_iter().collect(),
+            timestamp: event.timestamp.clone(),
+            traceparent: event.traceparent.clone(),
+        });
+        Ok(())
+    }
+}
+
+/// Starts the gRPC server on its own thread and returns the sink feeding the
+/// event stream.
+pub fn start(
+    settings: &GrpcSettings,
+    health: Arc<Health>,
+    control: Arc<Control>,
+) -> Result<GrpcEvents, String> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Failed to start gRPC runtime: {}", e))?;
+
+    let listener = std::net::TcpListener::bind(&settings.listen)
+        .and_then(|listener| listener.set_nonblocking(true).map(|()| listener))
+        .map_err(|e| format!("Failed to listen on {}: {}", settings.listen, e))?;
+
+    let (sender, _) = broadcast::channel(EVENT_BUFFER);
+    let service = Service {
+        health,
+        control,
+        events: sender.clone(),
+    };
+    let api_token = settings.api_token.clone();
+    let listen = settings.listen.clone();
+
+    thread::spawn(move || {
+        runtime.block_on(async move {
+            let listener = match tokio::net::TcpListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("Failed to start gRPC server: {}", e);
+                    return;
+                }
+            };
+
+            info!("gRPC server listening on {}", listen);
+            let server = InvoiceHandlerServer::with_interceptor(service, move |request| {
+                authorize(request, &api_token)
+            });
+            if let Err(e) = Server::builder()
+                .add_service(server)
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await
+            {
+                error!("gRPC server failed: {}", e);
+            }
+        });
+    });
+
+    Ok(GrpcEvents { sender })
+}
+
+fn authorize(request: Request<()>, token: &str) -> Result<Request<()>, Status> {
+    let header = request
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok());
+    if control::authorized(header, token) {
+        Ok(request)
+    } else {
+        Err(Status::unauthenticated("Invalid or missing api_token"))
+    }
+}
@@ -0,0 +1,137 @@
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Limits for hashing file contents, read from `[hashing]`.
+#[derive(Clone, Default)]
+pub struct HashSettings {
+    max_size: Option<u64>,
+    mmap: bool,
+}
+
+pub fn load_hash_settings(ini: &ini::Ini) -> Result<HashSettings, String> {
+    let section = match ini.section(Some("hashing")) {
+        Some(section) => section,
+        None => return Ok(HashSettings::default()),
+    };
+
+    let max_size = section
+        .get("max_size_mb")
+        .map(|mb| {
+            mb.parse::<u64>()
+                .map(|mb| mb * 1024 * 1024)
+                .map_err(|e| format!("Invalid max_size_mb: {}", e))
+        })
+        .transpose()?;
+
+    let mmap: bool = section
+        .get("mmap")
+        .unwrap_or("false")
+        .parse()
+        .map_err(|e| format!("Invalid mmap: {}", e))?;
+
+    Ok(HashSettings { max_size, mmap })
+}
+
+impl HashSettings {
+    /// The same settings without `max_size_mb`, for hashes that must not be
+    /// skipped.
+    pub fn without_size_limit(&self) -> Self {
+        HashSettings {
+            max_size: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Hex-encoded SHA-256 of the file's contents, or `None` when the file is
+/// larger than `max_size_mb`. The file is read in chunks, or mapped into
+/// memory with `mmap`, so large files are never held in memory at once.
+pub fn sha256_file(path: &Path, settings: &HashSettings) -> Result<Option<String>, String> {
+    let hash = |path: &Path| -> io::Result<Option<String>> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        if settings.max_size.is_some_and(|max_size| size > max_size) {
+            return Ok(None);
+        }
+
+        let mut hasher = Sha256::new();
+        // Mapping an empty file fails on some platforms.
+        if settings.mmap && size > 0 {
+            // SAFETY: the file is mapped read-only. If another process
+            // truncates it meanwhile, reading the mapping can fault, which is
+            // why mmap is opt-in.
+            let map = unsafe { Mmap::map(&file)? };
+            hasher.update(&map[..]);
+        } else {
+            io::copy(
+                &mut BufReader::with_capacity(READ_BUFFER_SIZE, file),
+                &mut hasher,
+            )?;
+        }
+        Ok(Some(format!("{:x}", hasher.finalize())))
+    };
+
+    hash(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn sha256_file() {
+        let dir =
+            std::env::temp_dir().join(format!("invoicehandler-hashing-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let large: Vec<u8> = (0..3 * READ_BUFFER_SIZE + 17).map(|i| i as u8).collect();
+        let large_hash = format!("{:x}", Sha256::digest(&large));
+        let files: [(&str, &[u8]); 3] = [("empty", b""), ("abc", b"abc"), ("large", &large)];
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+
+        let streamed = HashSettings::default();
+        let mapped = HashSettings {
+            max_size: None,
+            mmap: true,
+        };
+        let limited = HashSettings {
+            max_size: Some(READ_BUFFER_SIZE as u64),
+            mmap: false,
+        };
+
+        let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let cases = [
+            ("empty", &streamed, Some(empty)),
+            ("empty", &mapped, Some(empty)),
+            ("abc", &streamed, Some(abc)),
+            ("abc", &mapped, Some(abc)),
+            ("abc", &limited, Some(abc)),
+            ("large", &streamed, Some(large_hash.as_str())),
+            ("large", &mapped, Some(large_hash.as_str())),
+            ("large", &limited, None),
+        ];
+        for (name, settings, expected) in cases {
+            let hash = super::sha256_file(&dir.join(name), settings).unwrap();
+            assert_eq!(
+                hash.as_deref(),
+                expected,
+                "{} (mmap: {})",
+                name,
+                settings.mmap
+            );
+        }
+
+        assert!(super::sha256_file(&dir.join("missing"), &streamed).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+use chrono::Local;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Runtime state shared between the event loop, the watcher callback and the
+/// HTTP server.
+pub struct Health {
+    watch_directory: PathBuf,
+    last_event: Mutex<Option<String>>,
+    queue_depth: AtomicUsize,
+    watcher_error: Mutex<Option<String>>,
+    config_error: Mutex<Option<String>>,
+}
+
+#[derive(Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub watcher: String,
+    pub last_event: Option<String>,
+    pub queue_depth: usize,
+    pub config: String,
+}
+
+impl Health {
+    pub fn new(watch_directory: PathBuf) -> Self {
+        Health {
+            watch_directory,
+            last_event: Mutex::new(None),
+            queue_depth: AtomicUsize::new(0),
+            watcher_error: Mutex::new(None),
+            config_error: Mutex::new(None),
+        }
+    }
+
+    pub fn event_queued(&self) {
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn event_received(&self) {
+        let _ = self
+            .queue_depth
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| {
+                depth.checked_sub(1)
+            });
+        *self.last_event.lock().unwrap() = Some(Local::now().to_rfc3339());
+    }
+
+    pub fn watcher_failed(&self, error: String) {
+        *self.watcher_error.lock().unwrap() = Some(error);
+    }
+
+    /// Clears the watcher error once the watch is set up again.
+    pub fn watcher_recovered(&self) {
+        *self.watcher_error.lock().unwrap() = None;
+    }
+
+    pub fn config_loaded(&self, result: Result<(), String>) {
+        *self.config_error.lock().unwrap() = result.err();
+    }
+
+    /// The watch counts as broken when the watcher reported an error or the
+    /// watched directory is gone. A failed config reload is reported but
+    /// doesn't make the daemon unhealthy, since the previous rules stay active.
+    pub fn report(&self) -> HealthReport {
+        let watcher_error = self.watcher_error.lock().unwrap().clone().or_else(|| {
+            (!self.watch_directory.is_dir()).then(|| {
+                format!(
+                    "Watch directory '{}' is missing",
+                    self.watch_directory.display()
+                )
+            })
+        });
+
+        HealthReport {
+            healthy: watcher_error.is_none(),
+            watcher: watcher_error.unwrap_or_else(|| "ok".to_string()),
+            last_event: self.last_event.lock().unwrap().clone(),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            config: self
+                .config_error
+                .lock()
+                .unwrap()
+                .clone()
+                .unwrap_or_else(|| "ok".to_string()),
+        }
+    }
+}
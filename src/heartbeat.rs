@@ -0,0 +1,79 @@
+use chrono::Local;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+pub struct HeartbeatSettings {
+    file: PathBuf,
+    interval: Duration,
+}
+
+pub fn load_heartbeat_settings(
+    section: &ini::Properties,
+) -> Result<Option<HeartbeatSettings>, String> {
+    let file = match section.get("heartbeat_file") {
+        Some(file) => PathBuf::from(file),
+        None => return Ok(None),
+    };
+
+    let interval_secs: u64 = section
+        .get("heartbeat_interval_secs")
+        .unwrap_or("30")
+        .parse()
+        .map_err(|e| format!("Invalid heartbeat_interval_secs: {}", e))?;
+
+    if interval_secs == 0 {
+        return Err("heartbeat_interval_secs must be greater than 0".to_string());
+    }
+
+    Ok(Some(HeartbeatSettings {
+        file,
+        interval: Duration::from_secs(interval_secs),
+    }))
+}
+
+impl HeartbeatSettings {
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+}
+
+/// Rewrites the heartbeat file with the current time, so its modification time
+/// tells file-age monitors when the event loop last ran.
+pub struct Heartbeat<'a> {
+    settings: &'a HeartbeatSettings,
+    last_beat: Option<Instant>,
+}
+
+impl<'a> Heartbeat<'a> {
+    pub fn new(settings: &'a HeartbeatSettings) -> Self {
+        Heartbeat {
+            settings,
+            last_beat: None,
+        }
+    }
+
+    /// How long the event loop may block before the next beat is due.
+    pub fn time_until_due(&self) -> Duration {
+        match self.last_beat {
+            Some(last_beat) => self.settings.interval.saturating_sub(last_beat.elapsed()),
+            None => Duration::ZERO,
+        }
+    }
+
+    pub fn beat_if_due(&mut self) {
+        if !self.time_until_due().is_zero() {
+            return;
+        }
+
+        if let Err(e) = fs::write(&self.settings.file, Local::now().to_rfc3339()) {
+            warn!(
+                "Failed to write heartbeat file '{}': {}",
+                self.settings.file.display(),
+                e
+            );
+        }
+        self.last_beat = Some(Instant::now());
+    }
+}
@@ -0,0 +1,254 @@
+use crate::activity::{Activity, Stats};
+use crate::control::{self, Command, Control, FileError, RuleTest};
+use crate::health::{Health, HealthReport};
+use crate::metrics::{Metrics, StageSummary};
+use crate::rules::RuleSet;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{debug, info, warn};
+
+const DASHBOARD: &str = include_str!("../assets/dashboard.html");
+
+pub struct HttpSettings {
+    listen: String,
+    api_token: Option<String>,
+    dashboard: bool,
+    metrics: bool,
+}
+
+impl HttpSettings {
+    pub fn listen(&self) -> &str {
+        &self.listen
+    }
+}
+
+pub fn load_http_settings(ini: &ini::Ini) -> Result<Option<HttpSettings>, String> {
+    let section = match ini.section(Some("http")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let api_token = section
+        .get("api_token")
+        .filter(|token| !token.is_empty())
+        .map(str::to_string);
+
+    let dashboard: bool = section
+        .get("dashboard")
+        .unwrap_or("false")
+        .parse()
+        .map_err(|e| format!("Invalid dashboard: {}", e))?;
+
+    let metrics: bool = section
+        .get("metrics")
+        .unwrap_or("false")
+        .parse()
+        .map_err(|e| format!("Invalid metrics: {}", e))?;
+
+    if dashboard && api_token.is_none() {
+        return Err("dashboard needs an api_token in [http]".to_string());
+    }
+
+    Ok(Some(HttpSettings {
+        listen: section
+            .get("listen")
+            .unwrap_or("127.0.0.1:8080")
+            .to_string(),
+        api_token,
+        dashboard,
+        metrics,
+    }))
+}
+
+pub fn start(
+    settings: &HttpSettings,
+    health: Arc<Health>,
+    control: Arc<Control>,
+    activity: Activity,
+    metrics: Metrics,
+) -> Result<(), String> {
+    let server = Server::http(&settings.listen)
+        .map_err(|e| format!("Failed to listen on {}: {}", settings.listen, e))?;
+
+    let api = Api {
+        health,
+        control,
+        activity,
+        metrics,
+        token: settings.api_token.clone(),
+        dashboard: settings.dashboard,
+        prometheus: settings.metrics,
+    };
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            handle(request, &api);
+        }
+    });
+
+    Ok(())
+}
+
+type JsonResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+struct Api {
+    health: Arc<Health>,
+    control: Arc<Control>,
+    activity: Activity,
+    metrics: Metrics,
+    token: Option<String>,
+    dashboard: bool,
+    /// Whether `/metrics` is served.
+    prometheus: bool,
+}
+
+fn handle(mut request: Request, api: &Api) {
+    debug!(method = %request.method(), url = request.url(), "HTTP request");
+
+    let response = match (request.method(), request.url()) {
+        (Method::Get, "/healthz") => {
+            let report = api.health.report();
+            let status = if report.healthy { 200 } else { 500 };
+            json_response(&report, status)
+        }
+        (Method::Get, "/") if api.dashboard => Response::from_string(DASHBOARD).with_header(
+            Header::from_bytes("Content-Type", "text/html; charset=utf-8").expect("valid header"),
+        ),
+        (Method::Get, "/metrics") if api.prometheus => {
+            Response::from_string(api.metrics.render_prometheus()).with_header(
+                Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+                    .expect("valid header"),
+            )
+        }
+        (_, url) if url.starts_with("/api/") => match &api.token {
+            Some(token) if authorized(&request, token) => handle_api(&mut request, api),
+            Some(_) => json_response(&json!({ "error": "Unauthorized" }), 401).with_header(
+                Header::from_bytes("WWW-Authenticate", "Bearer").expect("valid header"),
+            ),
+            None => Response::from_string("Not found").with_status_code(404),
+        },
+        _ => Response::from_string("Not found").with_status_code(404),
+    };
+
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to send HTTP response: {}", e);
+    }
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    #[serde(flatten)]
+    health: HealthReport,
+    paused: bool,
+    rules: usize,
+}
+
+#[derive(Serialize)]
+struct StatsResponse {
+    #[serde(flatten)]
+    stats: Stats,
+    latency: Vec<StageSummary>,
+}
+
+#[derive(Deserialize)]
+struct ReprocessRequest {
+    file: String,
+}
+
+/// Tests `filename` against the loaded rules, or against `pattern` and
+/// `replacement` when given.
+#[derive(Deserialize)]
+struct TestRuleRequest {
+    filename: String,
+    pattern: Option<String>,
+    replacement: Option<String>,
+}
+
+/// The control API. Commands are queued for the event loop and answered with
+/// 202 Accepted.
+fn handle_api(request: &mut Request, api: &Api) -> JsonResponse {
+    let control = &api.control;
+    let command = match (request.method(), request.url()) {
+        (Method::Get, "/api/status") => {
+            let status = StatusResponse {
+                health: api.health.report(),
+                paused: control.is_paused(),
+                rules: control.rules().len(),
+            };
+            return json_response(&status, 200);
+        }
+        (Method::Get, "/api/rules") => return json_response(&control.rules(), 200),
+        (Method::Get, "/api/queue") => return json_response(&control.queue(), 200),
+        (Method::Get, "/api/activity") => return json_response(&api.activity.recent(), 200),
+        (Method::Get, "/api/stats") => {
+            let stats = StatsResponse {
+                stats: api.activity.stats(),
+                latency: api.metrics.summary(),
+            };
+            return json_response(&stats, 200);
+        }
+        (Method::Get, "/api/pending") => return json_response(&api.activity.pending(), 200),
+        (Method::Post, "/api/test-rule") => {
+            let body: TestRuleRequest = match serde_json::from_reader(request.as_reader()) {
+                Ok(body) => body,
+                Err(e) => return json_response(&json!({ "error": e.to_string() }), 400),
+            };
+            let result = match &body.pattern {
+                Some(pattern) => {
+                    let replacement = body.replacement.as_deref().unwrap_or_default();
+                    match RuleSet::parse([(pattern.as_str(), replacement)]) {
+                        Ok(rules) => RuleTest::run(&rules, &body.filename),
+                        Err(e) => return json_response(&json!({ "error": e.to_string() }), 400),
+                    }
+                }
+                None => control.test_rules(&body.filename),
+            };
+            return json_response(&result, 200);
+        }
+        (Method::Post, "/api/pause") => Command::Pause,
+        (Method::Post, "/api/resume") => Command::Resume,
+        (Method::Post, "/api/reload") => Command::Reload,
+        (Method::Post, "/api/catch-up") => Command::CatchUp,
+        (Method::Post, "/api/reprocess") => {
+            let body: ReprocessRequest = match serde_json::from_reader(request.as_reader()) {
+                Ok(body) => body,
+                Err(e) => return json_response(&json!({ "error": e.to_string() }), 400),
+            };
+
+            match control.watched_file(&body.file) {
+                Ok(path) => Command::Reprocess(path),
+                Err(e @ FileError::InvalidName) => {
+                    return json_response(&json!({ "error": e.to_string() }), 400)
+                }
+                Err(e @ FileError::NotFound) => {
+                    return json_response(&json!({ "error": e.to_string() }), 404)
+                }
+            }
+        }
+        _ => return json_response(&json!({ "error": "Not found" }), 404),
+    };
+
+    info!(url = request.url(), "Control API command");
+    match control.send(command) {
+        Ok(()) => json_response(&json!({ "status": "accepted" }), 202),
+        Err(e) => json_response(&json!({ "error": e }), 503),
+    }
+}
+
+fn authorized(request: &Request, token: &str) -> bool {
+    let header = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .map(|header| header.value.as_str());
+    control::authorized(header, token)
+}
+
+fn json_response<T: serde::Serialize>(body: &T, status: u16) -> JsonResponse {
+    let json = serde_json::to_string(body).unwrap_or_else(|_| "{}".to_string());
+    Response::from_string(json)
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").expect("valid header"))
+}
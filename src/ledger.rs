@@ -0,0 +1,123 @@
+use crate::dates::DateSettings;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_COLUMNS: &str = "date,vendor,number,amount,currency,archived_path";
+
+pub struct LedgerSettings {
+    pub file: PathBuf,
+    pub columns: Vec<String>,
+}
+
+pub fn load_ledger_settings(ini: &ini::Ini) -> Result<Option<LedgerSettings>, String> {
+    let section = match ini.section(Some("ledger")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let file = section.get("file").ok_or("Missing 'file' in [ledger]")?;
+
+    let columns: Vec<String> = section
+        .get("columns")
+        .unwrap_or(DEFAULT_COLUMNS)
+        .split(',')
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty())
+        .collect();
+
+    if columns.is_empty() {
+        return Err("No columns configured in [ledger]".to_string());
+    }
+
+    Ok(Some(LedgerSettings {
+        file: PathBuf::from(file),
+        columns,
+    }))
+}
+
+/// Appends one row for a renamed file. `archived_path` and `original_path`
+/// are filled in by the ledger, and `date` too when the event has no `date`
+/// field, as today in the `[settings]` timezone and date format; every
+/// other column is taken from the event field of the same name, i.e. a
+/// named capture group of the rule or a field extracted by a plugin, and
+/// left empty when there is none.
+pub fn append_entry(
+    settings: &LedgerSettings,
+    dates: &DateSettings,
+    fields: &BTreeMap<String, String>,
+    original_path: &Path,
+    archived_path: &Path,
+) -> Result<(), String> {
+    let write_header = fs::metadata(&settings.file)
+        .map(|m| m.len() == 0)
+        .unwrap_or(true);
+
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&settings.file)
+        .map_err(|e| format!("Failed to open ledger '{}': {}", settings.file.display(), e))?;
+
+    let mut writer = csv::Writer::from_writer(file);
+
+    if write_header {
+        writer
+            .write_record(&settings.columns)
+            .map_err(|e| format!("Failed to write ledger header: {}", e))?;
+    }
+
+    let record: Vec<String> = settings
+        .columns
+        .iter()
+        .map(|column| match column.as_str() {
+            "date" => fields
+                .get("date")
+                .cloned()
+                .unwrap_or_else(|| dates.format.date(dates.now().date_naive())),
+            "archived_path" => archived_path.display().to_string(),
+            "original_path" => original_path.display().to_string(),
+            name => fields.get(name).cloned().unwrap_or_default(),
+        })
+        .collect();
+
+    writer
+        .write_record(&record)
+        .map_err(|e| format!("Failed to write ledger entry: {}", e))?;
+    writer
+        .flush()
+        .map_err(|e| format!("Failed to flush ledger: {}", e))
+}
+
+/// The rows written so far, by column. A ledger that doesn't exist yet has
+/// none.
+pub fn read_entries(settings: &LedgerSettings) -> Result<Vec<BTreeMap<String, String>>, String> {
+    let file = match fs::File::open(&settings.file) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(format!(
+                "Failed to open ledger '{}': {}",
+                settings.file.display(),
+                e
+            ))
+        }
+    };
+
+    let mut reader = csv::Reader::from_reader(file);
+    let header = reader
+        .headers()
+        .map_err(|e| format!("Failed to read ledger header: {}", e))?
+        .clone();
+    reader
+        .records()
+        .map(|record| {
+            let record = record.map_err(|e| format!("Failed to read ledger entry: {}", e))?;
+            Ok(header
+                .iter()
+                .zip(record.iter())
+                .map(|(column, value)| (column.to_string(), value.to_string()))
+                .collect())
+        })
+        .collect()
+}
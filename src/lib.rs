@@ -0,0 +1,85 @@
+//! Watches a directory for incoming invoices and renames them by regex rules.
+//!
+//! The `invoicehandler` binary is a thin command line around [`Watcher`], which
+//! runs the whole daemon as configured in the config file. To embed only the
+//! rename engine, load a [`RuleSet`] and run files through a [`Pipeline`]:
+//!
+//! ```no_run
+//! use invoicehandler::{Pipeline, RuleSet, Settings};
+//! use std::path::Path;
+//!
+//! let config = Path::new("/etc/invoicehandler/config.ini");
+//! let settings = Settings::load(config)?;
+//! let rules = RuleSet::load(config)?;
+//!
+//! let mut pipeline = Pipeline::new(&settings)?;
+//! match pipeline.process(Path::new("/srv/inbox/acme_42.pdf"), &rules) {
+//!     Ok(Some(renamed)) => println!("Renamed to {}", renamed.display()),
+//!     Ok(None) => println!("No rule renames it"),
+//!     Err(e) => eprintln!("Failed: {}", e),
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Every outcome is published as a [`FileEvent`] to the event sinks configured
+//! in the config file and to any added with [`Pipeline::add_sink`].
+
+mod actions;
+mod activity;
+mod alerts;
+mod config;
+mod content;
+mod control;
+mod currency;
+mod dates;
+mod digest;
+mod disk;
+pub mod doctor;
+mod duplicates;
+mod error;
+mod error_reporting;
+mod events;
+mod export;
+mod filters;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod hashing;
+mod health;
+mod heartbeat;
+mod http;
+mod ledger;
+mod logging;
+mod metrics;
+mod notifications;
+mod once;
+mod pipeline;
+mod plugins;
+mod privileges;
+mod remote_rules;
+mod retry;
+mod rules;
+mod scan;
+mod schedule;
+mod search;
+mod secrets;
+mod sepa;
+mod settings;
+mod telemetry;
+mod tenants;
+#[cfg(all(feature = "tray", any(windows, target_os = "macos")))]
+mod tray;
+mod vies;
+mod watcher;
+
+pub use error::{ActionError, ConfigError, FileError, ProcessError, RuleError};
+pub use events::{EventSink, FileEvent, Outcome};
+pub use export::run_export_command;
+pub use logging::{init_logging, LoggingGuard};
+pub use once::run_once_command;
+pub use pipeline::Pipeline;
+pub use plugins::run_extract_worker;
+pub use rules::{Conflict, ContentType, Decision, LockRetry, Metadata, RuleMatch, RuleSet};
+pub use search::run_search_command;
+pub use secrets::run_keyring_command;
+pub use settings::{default_config_path, Settings};
+pub use watcher::{RunLimits, Watcher};
@@ -0,0 +1,327 @@
+#[cfg(windows)]
+mod eventlog;
+
+use crate::error::ProcessError;
+use crate::error_reporting;
+use crate::settings::Settings;
+use crate::telemetry::{self, Telemetry};
+
+use file_rotate::compression::Compression;
+use file_rotate::suffix::AppendCount;
+use file_rotate::{ContentLimit, FileRotate, TimeFrequency};
+use sentry::ClientInitGuard;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+#[cfg(unix)]
+use syslog_tracing::{Facility, Options, Syslog};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::{DefaultFields, Writer};
+use tracing_subscriber::fmt::{FormatFields, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer, Registry};
+
+#[derive(Clone, Copy)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy)]
+enum LogOutput {
+    Stdout,
+    #[cfg(unix)]
+    Syslog(Facility),
+    #[cfg(unix)]
+    Journald,
+}
+
+pub struct LogSettings {
+    format: LogFormat,
+    output: LogOutput,
+    #[cfg(windows)]
+    event_log: bool,
+    file: Option<PathBuf>,
+    rotation: ContentLimit,
+    retention: usize,
+}
+
+pub fn load_log_settings(section: &ini::Properties) -> Result<LogSettings, String> {
+    let format = match section.get("log_format").unwrap_or("text") {
+        "text" => LogFormat::Text,
+        "json" => LogFormat::Json,
+        other => {
+            return Err(format!(
+                "Invalid log_format '{}': expected text or json",
+                other
+            ))
+        }
+    };
+
+    let output = match section.get("log_output").unwrap_or("stdout") {
+        "stdout" => LogOutput::Stdout,
+        #[cfg(unix)]
+        "syslog" => LogOutput::Syslog(parse_facility(
+            section.get("syslog_facility").unwrap_or("daemon"),
+        )?),
+        #[cfg(unix)]
+        "journald" => LogOutput::Journald,
+        other => {
+            return Err(format!(
+                "Invalid log_output '{}': expected stdout, syslog or journald",
+                other
+            ))
+        }
+    };
+
+    let event_log: bool = section
+        .get("event_log")
+        .unwrap_or("false")
+        .parse()
+        .map_err(|e| format!("Invalid event_log: {}", e))?;
+
+    if event_log && !cfg!(windows) {
+        return Err("event_log is only supported on Windows".to_string());
+    }
+
+    let rotation = parse_rotation(section.get("log_rotation").unwrap_or("daily"))?;
+
+    let retention: usize = section
+        .get("log_retention")
+        .unwrap_or("7")
+        .parse()
+        .map_err(|e| format!("Invalid log_retention: {}", e))?;
+
+    Ok(LogSettings {
+        format,
+        output,
+        #[cfg(windows)]
+        event_log,
+        file: section.get("log_file").map(PathBuf::from),
+        rotation,
+        retention,
+    })
+}
+
+impl LogSettings {
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+}
+
+#[cfg(unix)]
+fn parse_facility(value: &str) -> Result<Facility, String> {
+    Ok(match value {
+        "user" => Facility::User,
+        "daemon" => Facility::Daemon,
+        "local0" => Facility::Local0,
+        "local1" => Facility::Local1,
+        "local2" => Facility::Local2,
+        "local3" => Facility::Local3,
+        "local4" => Facility::Local4,
+        "local5" => Facility::Local5,
+        "local6" => Facility::Local6,
+        "local7" => Facility::Local7,
+        other => return Err(format!("Invalid syslog_facility '{}'", other)),
+    })
+}
+
+/// Accepts `hourly`, `daily`, `weekly`, `monthly` or a size such as `10MB`.
+fn parse_rotation(value: &str) -> Result<ContentLimit, String> {
+    let frequency = match value.to_ascii_lowercase().as_str() {
+        "hourly" => Some(TimeFrequency::Hourly),
+        "daily" => Some(TimeFrequency::Daily),
+        "weekly" => Some(TimeFrequency::Weekly),
+        "monthly" => Some(TimeFrequency::Monthly),
+        _ => None,
+    };
+    if let Some(frequency) = frequency {
+        return Ok(ContentLimit::Time(frequency));
+    }
+
+    let upper = value.trim().to_ascii_uppercase();
+    let (number, multiplier) = if let Some(n) = upper.strip_suffix("GB") {
+        (n, 1024 * 1024 * 1024)
+    } else if let Some(n) = upper.strip_suffix("MB") {
+        (n, 1024 * 1024)
+    } else if let Some(n) = upper.strip_suffix("KB") {
+        (n, 1024)
+    } else {
+        (upper.strip_suffix('B').unwrap_or(&upper), 1)
+    };
+
+    number
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .map(ContentLimit::BytesSurpassed)
+        .ok_or_else(|| format!("Invalid log_rotation '{}'", value))
+}
+
+/// Span fields are formatted once per field formatter type and shared between
+/// layers, so the file layer needs its own type to avoid inheriting the ANSI
+/// colors of the stdout layer.
+struct PlainFields(DefaultFields);
+
+impl<'writer> FormatFields<'writer> for PlainFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        writer: Writer<'writer>,
+        fields: R,
+    ) -> std::fmt::Result {
+        self.0.format_fields(writer, fields)
+    }
+}
+
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// JSON lines flatten the event fields (rule, filename, outcome, ...) into the
+/// top-level object and list the enclosing spans alongside.
+fn layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Json => fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Text if ansi => fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Text => fmt::layer()
+            .with_ansi(false)
+            .fmt_fields(PlainFields(DefaultFields::new()))
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+/// syslog and journald map tracing levels onto their own priorities. If the
+/// chosen output can't be opened, logging falls back to stdout.
+fn output_layer(settings: &LogSettings) -> BoxedLayer {
+    match settings.output {
+        LogOutput::Stdout => layer(settings.format, std::io::stdout, true),
+        #[cfg(unix)]
+        LogOutput::Syslog(facility) => {
+            match Syslog::new(c"invoicehandler", Options::default(), facility) {
+                Some(syslog) => layer(settings.format, syslog, false),
+                None => {
+                    eprintln!("Failed to open syslog, logging to stdout");
+                    layer(settings.format, std::io::stdout, true)
+                }
+            }
+        }
+        #[cfg(unix)]
+        LogOutput::Journald => match tracing_journald::layer() {
+            Ok(journald) => journald
+                .with_syslog_identifier("invoicehandler".to_string())
+                .boxed(),
+            Err(e) => {
+                eprintln!("Failed to connect to journald: {}. Logging to stdout", e);
+                layer(settings.format, std::io::stdout, true)
+            }
+        },
+    }
+}
+
+/// Logs go to the configured output and, when `log_file` is set, to a rotated
+/// log file as well. `extra` holds the layers of enabled integrations such as
+/// OpenTelemetry and Sentry.
+fn init(settings: &LogSettings, extra: Vec<BoxedLayer>) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let mut layers = vec![output_layer(settings)];
+
+    if let Some(path) = &settings.file {
+        let writer = FileRotate::new(
+            path,
+            AppendCount::new(settings.retention),
+            settings.rotation.clone(),
+            Compression::None,
+            None,
+        );
+        layers.push(layer(settings.format, Mutex::new(writer), false));
+    }
+
+    layers.extend(extra);
+
+    #[cfg(windows)]
+    if settings.event_log {
+        match eventlog::EventLogLayer::new() {
+            Ok(event_log) => layers.push(event_log.boxed()),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .init();
+}
+
+/// Flushes pending Sentry reports and OpenTelemetry data when dropped, so it
+/// has to be kept until the process exits.
+pub struct LoggingGuard {
+    _sentry: Option<ClientInitGuard>,
+    _telemetry: Option<Telemetry>,
+}
+
+/// Sets up the global logger as configured in `settings`, including the
+/// Sentry and OpenTelemetry integrations. Can only be called once.
+pub fn init_logging(settings: &Settings) -> Result<LoggingGuard, ProcessError> {
+    let mut extra_layers = Vec::new();
+
+    let sentry = settings.sentry.as_ref().map(|sentry| {
+        let (layer, guard) = error_reporting::init(sentry);
+        extra_layers.push(layer);
+        guard
+    });
+
+    let telemetry = match &settings.otel {
+        Some(otel) => {
+            let (layer, telemetry) = telemetry::init(otel).map_err(ProcessError::Telemetry)?;
+            extra_layers.push(layer);
+            Some(telemetry)
+        }
+        None => None,
+    };
+
+    init(&settings.logging, extra_layers);
+
+    Ok(LoggingGuard {
+        _sentry: sentry,
+        _telemetry: telemetry,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse_rotation() {
+        let cases = [
+            ("daily", Ok("Time(Daily)")),
+            ("Weekly", Ok("Time(Weekly)")),
+            ("512", Ok("BytesSurpassed(512)")),
+            ("100kb", Ok("BytesSurpassed(102400)")),
+            (" 10 MB ", Ok("BytesSurpassed(10485760)")),
+            ("2GB", Ok("BytesSurpassed(2147483648)")),
+            ("", Err("Invalid log_rotation ''")),
+            ("10TB", Err("Invalid log_rotation '10TB'")),
+            ("-1MB", Err("Invalid log_rotation '-1MB'")),
+            (
+                "18446744073709551615GB",
+                Err("Invalid log_rotation '18446744073709551615GB'"),
+            ),
+        ];
+        for (value, expected) in cases {
+            match (super::parse_rotation(value), expected) {
+                (Ok(limit), Ok(debug)) => assert_eq!(format!("{:?}", limit), debug, "{:?}", value),
+                (Err(e), Err(message)) => assert_eq!(e, message, "{:?}", value),
+                (result, expected) => panic!("{:?}: {:?}, expected {:?}", value, result, expected),
+            }
+        }
+    }
+}
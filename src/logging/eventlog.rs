@@ -0,0 +1,203 @@
+use std::fmt::{Debug, Write as _};
+use std::ptr;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+use windows_sys::Win32::Foundation::{ERROR_SUCCESS, HANDLE};
+use windows_sys::Win32::System::EventLog::{
+    DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE,
+    EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+};
+use windows_sys::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_SET_VALUE,
+    REG_DWORD, REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE,
+};
+
+const SOURCE: &str = "invoicehandler";
+const SOURCE_KEY: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application\invoicehandler";
+
+// Every message id in the .NET runtime's message file renders its first
+// insertion string as is, which is all a source without its own message
+// resources needs.
+const MESSAGE_FILE: &str =
+    r"%SystemRoot%\Microsoft.NET\Framework64\v4.0.30319\EventLogMessages.dll";
+
+const ERROR_EVENT_ID: u32 = 1;
+const WARNING_EVENT_ID: u32 = 2;
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+/// Registers the event source under the Application log. This needs
+/// administrator rights; without it events are still written, but Event
+/// Viewer prefixes them with a note about the missing description.
+fn register_source() -> Result<(), u32> {
+    let subkey = wide(SOURCE_KEY);
+    let message_file = wide(MESSAGE_FILE);
+    let types_supported =
+        u32::from(EVENTLOG_ERROR_TYPE | EVENTLOG_WARNING_TYPE | EVENTLOG_INFORMATION_TYPE);
+
+    unsafe {
+        let mut key: HKEY = ptr::null_mut();
+        let status = RegCreateKeyExW(
+            HKEY_LOCAL_MACHINE,
+            subkey.as_ptr(),
+            0,
+            ptr::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_SET_VALUE,
+            ptr::null(),
+            &mut key,
+            ptr::null_mut(),
+        );
+        if status != ERROR_SUCCESS {
+            return Err(status);
+        }
+
+        let mut status = RegSetValueExW(
+            key,
+            wide("EventMessageFile").as_ptr(),
+            0,
+            REG_EXPAND_SZ,
+            message_file.as_ptr() as *const u8,
+            (message_file.len() * 2) as u32,
+        );
+        if status == ERROR_SUCCESS {
+            status = RegSetValueExW(
+                key,
+                wide("TypesSupported").as_ptr(),
+                0,
+                REG_DWORD,
+                &types_supported as *const u32 as *const u8,
+                4,
+            );
+        }
+        RegCloseKey(key);
+
+        if status == ERROR_SUCCESS {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+}
+
+/// Writes warnings and errors to the Application event log, with the fields of
+/// the enclosing spans (file path, rule) appended to the message.
+pub struct EventLogLayer {
+    // HANDLE is a raw pointer, which isn't Send; the event log handle itself
+    // may be used from any thread.
+    handle: usize,
+}
+
+impl EventLogLayer {
+    pub fn new() -> Result<Self, String> {
+        if let Err(code) = register_source() {
+            eprintln!(
+                "Failed to register event log source (error {}). Run once as administrator to register it.",
+                code
+            );
+        }
+
+        let source = wide(SOURCE);
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), source.as_ptr()) };
+        if handle.is_null() {
+            return Err(format!(
+                "Failed to open event log: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        Ok(EventLogLayer {
+            handle: handle as usize,
+        })
+    }
+}
+
+impl Drop for EventLogLayer {
+    fn drop(&mut self) {
+        unsafe {
+            DeregisterEventSource(self.handle as HANDLE);
+        }
+    }
+}
+
+struct SpanFields(String);
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+impl<S> Layer<S> for EventLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(visitor.fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let (event_type, event_id) = match *event.metadata().level() {
+            Level::ERROR => (EVENTLOG_ERROR_TYPE, ERROR_EVENT_ID),
+            Level::WARN => (EVENTLOG_WARNING_TYPE, WARNING_EVENT_ID),
+            _ => return,
+        };
+
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+
+        let mut text = visitor.message;
+        text.push_str(&visitor.fields);
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(fields) = span.extensions().get::<SpanFields>() {
+                    text.push_str(&fields.0);
+                }
+            }
+        }
+
+        let text = wide(&text);
+        let strings = [text.as_ptr()];
+        unsafe {
+            ReportEventW(
+                self.handle as HANDLE,
+                event_type,
+                0,
+                event_id,
+                ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                ptr::null(),
+            );
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use crate::events::{EventSink, FileEvent};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    LockWait,
+    Match,
+    Rename,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::LockWait => "lock_wait",
+            Stage::Match => "match",
+            Stage::Rename => "rename",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+    max: f64,
+}
+
+impl Histogram {
+    fn record(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+        self.max = self.max.max(seconds);
+    }
+
+    /// Upper bound of the bucket holding the given quantile; the largest
+    /// observed value when it falls past the last bucket.
+    fn quantile(&self, q: f64) -> f64 {
+        let rank = (self.count as f64 * q).ceil() as u64;
+        BUCKETS
+            .iter()
+            .zip(self.buckets)
+            .find(|(_, count)| *count >= rank)
+            .map(|(bound, _)| bound.min(self.max))
+            .unwrap_or(self.max)
+    }
+}
+
+/// Processing stage timings and file counts per rule, for the HTTP
+/// server's `/metrics` and `/api/stats`. Files that matched no rule are
+/// recorded without one. The main config and every tenant have their own,
+/// shared by the clones.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    recorded: Arc<Mutex<Recorded>>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    stages: BTreeMap<(Option<String>, Stage), Histogram>,
+    files: BTreeMap<(Option<String>, &'static str), u64>,
+}
+
+#[derive(Serialize)]
+pub struct StageSummary {
+    pub rule: Option<String>,
+    pub stage: &'static str,
+    pub count: u64,
+    pub mean_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Metrics {
+    pub fn record_stage(&self, stage: Stage, rule: Option<&str>, elapsed: Duration) {
+        self.recorded
+            .lock()
+            .unwrap()
+            .stages
+            .entry((rule.map(str::to_string), stage))
+            .or_default()
+            .record(elapsed.as_secs_f64());
+    }
+
+    pub fn summary(&self) -> Vec<StageSummary> {
+        self.recorded
+            .lock()
+            .unwrap()
+            .stages
+            .iter()
+            .map(|((rule, stage), histogram)| StageSummary {
+                rule: rule.clone(),
+                stage: stage.as_str(),
+                count: histogram.count,
+                mean_ms: histogram.sum / histogram.count as f64 * 1000.0,
+                p95_ms: histogram.quantile(0.95) * 1000.0,
+                max_ms: histogram.max * 1000.0,
+            })
+            .collect()
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let metrics = self.recorded.lock().unwrap();
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# HELP invoicehandler_files_total Files handled, by rule and outcome"
+        );
+        let _ = writeln!(out, "# TYPE invoicehandler_files_total counter");
+        for ((rule, outcome), count) in &metrics.files {
+            let _ = writeln!(
+                out,
+                "invoicehandler_files_total{{rule=\"{}\",outcome=\"{}\"}} {}",
+                escape(rule.as_deref().unwrap_or_default()),
+                outcome,
+                count
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP invoicehandler_stage_duration_seconds Time spent in each processing stage, by rule"
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE invoicehandler_stage_duration_seconds histogram"
+        );
+        for ((rule, stage), histogram) in &metrics.stages {
+            let labels = format!(
+                "rule=\"{}\",stage=\"{}\"",
+                escape(rule.as_deref().unwrap_or_default()),
+                stage.as_str()
+            );
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                let _ = writeln!(
+                    out,
+                    "invoicehandler_stage_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "invoicehandler_stage_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "invoicehandler_stage_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "invoicehandler_stage_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            );
+        }
+
+        out
+    }
+}
+
+/// Counts the files handled, by rule and outcome.
+impl EventSink for Metrics {
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+
+    fn publish(&self, event: &FileEvent, _payload: &str) -> Result<(), String> {
+        *self
+            .recorded
+            .lock()
+            .unwrap()
+            .files
+            .entry((event.rule.clone(), event.outcome.as_str()))
+            .or_default() += 1;
+        Ok(())
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn separate_per_watcher() {
+        let main = Metrics::default();
+        let tenant = Metrics::default();
+        let clone = main.clone();
+        let processed = FileEvent::processed(Path::new("a.pdf"), Path::new("A.pdf"), "acme");
+        for event in [
+            &processed,
+            &processed,
+            &FileEvent::unmatched(Path::new("b.pdf")),
+        ] {
+            clone.publish(event, "").unwrap();
+        }
+        clone.record_stage(Stage::Match, Some("acme"), Duration::from_millis(20));
+
+        let prometheus = main.render_prometheus();
+        assert!(
+            prometheus
+                .contains("invoicehandler_files_total{rule=\"acme\",outcome=\"processed\"} 2"),
+            "{}",
+            prometheus
+        );
+        assert!(
+            prometheus.contains("invoicehandler_files_total{rule=\"\",outcome=\"unmatched\"} 1"),
+            "{}",
+            prometheus
+        );
+        assert_eq!(main.summary().len(), 1);
+        assert_eq!(main.summary()[0].count, 1);
+
+        assert!(!tenant
+            .render_prometheus()
+            .contains("invoicehandler_files_total{"));
+        assert!(tenant.summary().is_empty());
+    }
+}
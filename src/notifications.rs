@@ -0,0 +1,276 @@
+mod desktop;
+mod discord;
+mod slack;
+mod telegram;
+
+use crate::events::{self, EventSink, FileEvent, Outcome};
+use crate::filters;
+use desktop::{DesktopNotifier, DesktopSettings};
+use discord::{DiscordNotifier, DiscordSettings};
+use regex::Regex;
+use slack::{SlackNotifier, SlackSettings};
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::LazyLock;
+use std::thread;
+use telegram::{TelegramNotifier, TelegramSettings};
+use tracing::warn;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum NotificationKind {
+    Failed,
+    Unmatched,
+    Summary,
+    Alert,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::Failed => "failed",
+            NotificationKind::Unmatched => "unmatched",
+            NotificationKind::Summary => "summary",
+            NotificationKind::Alert => "alert",
+        }
+    }
+
+    fn parse_list(value: &str) -> Result<Vec<NotificationKind>, String> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|kind| !kind.is_empty())
+            .map(|kind| match kind {
+                "failed" => Ok(NotificationKind::Failed),
+                "unmatched" => Ok(NotificationKind::Unmatched),
+                "summary" => Ok(NotificationKind::Summary),
+                "alert" => Ok(NotificationKind::Alert),
+                other => Err(format!(
+                    "Invalid notification event '{}': expected failed, unmatched, summary or alert",
+                    other
+                )),
+            })
+            .collect()
+    }
+}
+
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub title: String,
+    pub body: String,
+    /// Values available to message templates besides `title` and `body`.
+    pub fields: BTreeMap<String, String>,
+}
+
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{(\w+)(?:\|([^{}]*))?\}").unwrap());
+
+impl Notification {
+    pub fn summary(body: String) -> Self {
+        Notification {
+            kind: NotificationKind::Summary,
+            title: "invoicehandler summary".to_string(),
+            body,
+            fields: BTreeMap::new(),
+        }
+    }
+
+    fn from_event(event: &FileEvent) -> Option<Self> {
+        let filename = event
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| event.path.display().to_string());
+
+        let (kind, title, body) = match event.outcome {
+            Outcome::Processed => return None,
+            Outcome::Failed => (
+                NotificationKind::Failed,
+                if event.error.as_deref() == Some(events::LOCKED_ERROR) {
+                    "File stayed locked"
+                } else {
+                    "Failed to process file"
+                },
+                format!(
+                    "{}: {}",
+                    filename,
+                    event.error.as_deref().unwrap_or("unknown error")
+                ),
+            ),
+            Outcome::Unmatched => (
+                NotificationKind::Unmatched,
+                "No matching rule",
+                filename.clone(),
+            ),
+        };
+
+        let mut fields = event.fields.clone();
+        fields.insert("filename".to_string(), filename);
+        fields.insert("path".to_string(), event.path.display().to_string());
+        if let Some(rule) = &event.rule {
+            fields.insert("rule".to_string(), rule.clone());
+        }
+        if let Some(error) = &event.error {
+            fields.insert("error".to_string(), error.clone());
+        }
+
+        Some(Notification {
+            kind,
+            title: title.to_string(),
+            body,
+            fields,
+        })
+    }
+
+    /// Fills `{name}` placeholders from the title, body and fields, through
+    /// the filters after the name, as in `{vendor|upper}`. Unknown names
+    /// render as empty text.
+    pub fn render(&self, template: &str) -> String {
+        PLACEHOLDER
+            .replace_all(template, |caps: &regex::Captures| {
+                let value = match &caps[1] {
+                    "title" => self.title.as_str(),
+                    "body" => self.body.as_str(),
+                    "kind" => self.kind.as_str(),
+                    name => self
+                        .fields
+                        .get(name)
+                        .map(String::as_str)
+                        .unwrap_or_default(),
+                };
+                match caps.get(2) {
+                    // Checked when the template was loaded.
+                    Some(filters) => filters::apply(value, filters.as_str())
+                        .unwrap_or_else(|_| value.to_string()),
+                    None => value.to_string(),
+                }
+            })
+            .into_owned()
+    }
+}
+
+/// Checks the filters in a template's placeholders, for the notifiers'
+/// settings loaders.
+fn check_template(template: &str, section: &str) -> Result<(), String> {
+    for caps in PLACEHOLDER.captures_iter(template) {
+        if let Some(filters) = caps.get(2) {
+            filters::check(filters.as_str())
+                .map_err(|e| format!("Invalid template in [{}]: {}", section, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// A notification channel. Each notifier is only handed the kinds it was
+/// configured for.
+trait Notifier: Send {
+    fn name(&self) -> &'static str;
+    fn kinds(&self) -> &[NotificationKind];
+    fn notify(&self, notification: &Notification) -> Result<(), String>;
+}
+
+pub struct NotificationSettings {
+    desktop: Option<DesktopSettings>,
+    slack: Option<SlackSettings>,
+    telegram: Option<TelegramSettings>,
+    discord: Option<DiscordSettings>,
+}
+
+pub fn load_notification_settings(ini: &ini::Ini) -> Result<NotificationSettings, String> {
+    Ok(NotificationSettings {
+        desktop: desktop::load_desktop_settings(ini)?,
+        slack: slack::load_slack_settings(ini)?,
+        telegram: telegram::load_telegram_settings(ini)?,
+        discord: discord::load_discord_settings(ini)?,
+    })
+}
+
+/// Checks the credentials of each configured notifier without sending
+/// anything, for `invoicehandler doctor`. Desktop notifications have nothing
+/// to check.
+pub fn check_notifiers(settings: &NotificationSettings) -> Vec<(&'static str, Result<(), String>)> {
+    let mut results = Vec::new();
+
+    if let Some(slack) = &settings.slack {
+        results.push(("slack", slack::check(slack)));
+    }
+
+    if let Some(telegram) = &settings.telegram {
+        results.push(("telegram", telegram::check(telegram)));
+    }
+
+    if let Some(discord) = &settings.discord {
+        results.push(("discord", discord::check(discord)));
+    }
+
+    results
+}
+
+/// Handle for sending notifications. Delivery happens on a background thread so
+/// slow notifiers never hold up file processing.
+#[derive(Clone)]
+pub struct Notifications {
+    sender: Option<Sender<Notification>>,
+}
+
+impl Notifications {
+    pub fn start(settings: &NotificationSettings) -> Self {
+        let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+
+        if let Some(desktop) = &settings.desktop {
+            notifiers.push(Box::new(DesktopNotifier::new(desktop)));
+        }
+
+        if let Some(slack) = &settings.slack {
+            notifiers.push(Box::new(SlackNotifier::new(slack)));
+        }
+
+        if let Some(telegram) = &settings.telegram {
+            notifiers.push(Box::new(TelegramNotifier::new(telegram)));
+        }
+
+        if let Some(discord) = &settings.discord {
+            notifiers.push(Box::new(DiscordNotifier::new(discord)));
+        }
+
+        if notifiers.is_empty() {
+            return Notifications { sender: None };
+        }
+
+        let (sender, receiver) = channel::<Notification>();
+        thread::spawn(move || {
+            for notification in receiver {
+                for notifier in &notifiers {
+                    if !notifier.kinds().contains(&notification.kind) {
+                        continue;
+                    }
+                    if let Err(e) = notifier.notify(&notification) {
+                        warn!(notifier = notifier.name(), error = %e, "Failed to send notification");
+                    }
+                }
+            }
+        });
+
+        Notifications {
+            sender: Some(sender),
+        }
+    }
+
+    pub fn send(&self, notification: Notification) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(notification);
+        }
+    }
+}
+
+impl EventSink for Notifications {
+    fn name(&self) -> &'static str {
+        "notifications"
+    }
+
+    fn publish(&self, event: &FileEvent, _payload: &str) -> Result<(), String> {
+        if let Some(notification) = Notification::from_event(event) {
+            self.send(notification);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use super::{Notification, NotificationKind, Notifier};
+
+pub struct DesktopSettings {
+    kinds: Vec<NotificationKind>,
+}
+
+pub fn load_desktop_settings(ini: &ini::Ini) -> Result<Option<DesktopSettings>, String> {
+    let section = match ini.section(Some("desktop")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    Ok(Some(DesktopSettings {
+        kinds: NotificationKind::parse_list(
+            section.get("events").unwrap_or("failed,unmatched,alert"),
+        )?,
+    }))
+}
+
+pub struct DesktopNotifier {
+    kinds: Vec<NotificationKind>,
+}
+
+impl DesktopNotifier {
+    pub fn new(settings: &DesktopSettings) -> Self {
+        DesktopNotifier {
+            kinds: settings.kinds.clone(),
+        }
+    }
+}
+
+impl Notifier for DesktopNotifier {
+    fn name(&self) -> &'static str {
+        "desktop"
+    }
+
+    fn kinds(&self) -> &[NotificationKind] {
+        &self.kinds
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<(), String> {
+        notify_rust::Notification::new()
+            .appname("invoicehandler")
+            .summary(&notification.title)
+            .body(&notification.body)
+            .show()
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
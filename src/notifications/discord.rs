@@ -0,0 +1,88 @@
+use super::{check_template, Notification, NotificationKind, Notifier};
+use serde_json::json;
+
+const DEFAULT_TEMPLATE: &str = "**{title}**\n{body}";
+
+/// Discord rejects messages longer than this.
+const MAX_CONTENT_LENGTH: usize = 2000;
+
+pub struct DiscordSettings {
+    webhook_url: String,
+    kinds: Vec<NotificationKind>,
+    template: String,
+}
+
+pub fn load_discord_settings(ini: &ini::Ini) -> Result<Option<DiscordSettings>, String> {
+    let section = match ini.section(Some("discord")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let template = section.get("template").unwrap_or(DEFAULT_TEMPLATE);
+    check_template(template, "discord")?;
+
+    Ok(Some(DiscordSettings {
+        webhook_url: section
+            .get("webhook_url")
+            .ok_or("Missing 'webhook_url' in [discord]")?
+            .to_string(),
+        kinds: NotificationKind::parse_list(
+            section.get("events").unwrap_or("failed,summary,alert"),
+        )?,
+        template: template.to_string(),
+    }))
+}
+
+pub struct DiscordNotifier {
+    webhook_url: String,
+    kinds: Vec<NotificationKind>,
+    template: String,
+}
+
+impl DiscordNotifier {
+    pub fn new(settings: &DiscordSettings) -> Self {
+        DiscordNotifier {
+            webhook_url: settings.webhook_url.clone(),
+            kinds: settings.kinds.clone(),
+            template: settings.template.clone(),
+        }
+    }
+}
+
+/// Fetching a webhook returns its details without posting anything.
+pub fn check(settings: &DiscordSettings) -> Result<(), String> {
+    ureq::get(&settings.webhook_url)
+        .call()
+        .map(|_| ())
+        .map_err(|e| match e {
+            ureq::Error::StatusCode(401 | 404) => {
+                "Discord doesn't know this webhook_url".to_string()
+            }
+            ureq::Error::StatusCode(status) => format!("Discord returned {}", status),
+            e => e.to_string(),
+        })
+}
+
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    fn kinds(&self) -> &[NotificationKind] {
+        &self.kinds
+    }
+
+    /// Long summaries are cut off at Discord's message limit.
+    fn notify(&self, notification: &Notification) -> Result<(), String> {
+        let mut content = notification.render(&self.template);
+        if content.chars().count() > MAX_CONTENT_LENGTH {
+            content = content.chars().take(MAX_CONTENT_LENGTH - 1).collect();
+            content.push('…');
+        }
+
+        ureq::post(&self.webhook_url)
+            .send_json(json!({ "username": "invoicehandler", "content": content }))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,117 @@
+use super::{check_template, Notification, NotificationKind, Notifier};
+use serde_json::json;
+
+const DEFAULT_TEMPLATE: &str = "*{title}*\n{body}";
+
+/// Where one kind of notification is posted. Incoming webhooks are bound to a
+/// channel, so routing by kind means a webhook per channel.
+#[derive(Clone)]
+struct Route {
+    kind: NotificationKind,
+    webhook_url: String,
+    template: String,
+}
+
+pub struct SlackSettings {
+    kinds: Vec<NotificationKind>,
+    routes: Vec<Route>,
+}
+
+/// Reads `[slack]`. `webhook_url` and `template` apply to every kind in
+/// `events` unless overridden by `<kind>_webhook_url` / `<kind>_template`.
+pub fn load_slack_settings(ini: &ini::Ini) -> Result<Option<SlackSettings>, String> {
+    let section = match ini.section(Some("slack")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let kinds =
+        NotificationKind::parse_list(section.get("events").unwrap_or("failed,unmatched,alert"))?;
+
+    let routes = kinds
+        .iter()
+        .map(|kind| {
+            let webhook_url = section
+                .get(format!("{}_webhook_url", kind.as_str()))
+                .or(section.get("webhook_url"))
+                .ok_or_else(|| format!("Missing webhook_url for {} in [slack]", kind.as_str()))?;
+            let template = section
+                .get(format!("{}_template", kind.as_str()))
+                .or(section.get("template"))
+                .unwrap_or(DEFAULT_TEMPLATE);
+            check_template(template, "slack")?;
+
+            Ok(Route {
+                kind: *kind,
+                webhook_url: webhook_url.to_string(),
+                template: template.to_string(),
+            })
+        })
+        .collect::<Result<Vec<Route>, String>>()?;
+
+    Ok(Some(SlackSettings { kinds, routes }))
+}
+
+pub struct SlackNotifier {
+    kinds: Vec<NotificationKind>,
+    routes: Vec<Route>,
+}
+
+impl SlackNotifier {
+    pub fn new(settings: &SlackSettings) -> Self {
+        SlackNotifier {
+            kinds: settings.kinds.clone(),
+            routes: settings.routes.clone(),
+        }
+    }
+}
+
+/// Slack answers an empty message with `400 no_text` on a valid webhook and
+/// `403`/`404` on a revoked or mistyped one, so webhooks can be checked without
+/// posting anything.
+pub fn check(settings: &SlackSettings) -> Result<(), String> {
+    let mut checked: Vec<&str> = Vec::new();
+    for route in &settings.routes {
+        if checked.contains(&route.webhook_url.as_str()) {
+            continue;
+        }
+        checked.push(&route.webhook_url);
+
+        match ureq::post(&route.webhook_url).send_json(json!({})) {
+            Ok(_) | Err(ureq::Error::StatusCode(400)) => {}
+            Err(ureq::Error::StatusCode(403 | 404 | 410)) => {
+                return Err(format!(
+                    "Slack rejected the webhook for {}",
+                    route.kind.as_str()
+                ))
+            }
+            Err(ureq::Error::StatusCode(status)) => {
+                return Err(format!("Slack returned {}", status))
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(())
+}
+
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &'static str {
+        "slack"
+    }
+
+    fn kinds(&self) -> &[NotificationKind] {
+        &self.kinds
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<(), String> {
+        let route = match self.routes.iter().find(|r| r.kind == notification.kind) {
+            Some(route) => route,
+            None => return Ok(()),
+        };
+
+        ureq::post(&route.webhook_url)
+            .send_json(json!({ "text": notification.render(&route.template) }))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
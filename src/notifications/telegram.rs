@@ -0,0 +1,102 @@
+use super::{check_template, Notification, NotificationKind, Notifier};
+use serde_json::json;
+
+const DEFAULT_TEMPLATE: &str = "{title}\n{body}";
+
+pub struct TelegramSettings {
+    bot_token: String,
+    chat_id: String,
+    kinds: Vec<NotificationKind>,
+    template: String,
+}
+
+pub fn load_telegram_settings(ini: &ini::Ini) -> Result<Option<TelegramSettings>, String> {
+    let section = match ini.section(Some("telegram")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let template = section.get("template").unwrap_or(DEFAULT_TEMPLATE);
+    check_template(template, "telegram")?;
+
+    Ok(Some(TelegramSettings {
+        bot_token: section
+            .get("bot_token")
+            .ok_or("Missing 'bot_token' in [telegram]")?
+            .to_string(),
+        chat_id: section
+            .get("chat_id")
+            .ok_or("Missing 'chat_id' in [telegram]")?
+            .to_string(),
+        kinds: NotificationKind::parse_list(
+            section.get("events").unwrap_or("failed,unmatched,alert"),
+        )?,
+        template: template.to_string(),
+    }))
+}
+
+pub struct TelegramNotifier {
+    url: String,
+    chat_id: String,
+    kinds: Vec<NotificationKind>,
+    template: String,
+}
+
+impl TelegramNotifier {
+    pub fn new(settings: &TelegramSettings) -> Self {
+        TelegramNotifier {
+            url: format!(
+                "https://api.telegram.org/bot{}/sendMessage",
+                settings.bot_token
+            ),
+            chat_id: settings.chat_id.clone(),
+            kinds: settings.kinds.clone(),
+            template: settings.template.clone(),
+        }
+    }
+}
+
+/// Looks up the chat with the bot's token, which fails for a wrong token or a
+/// chat the bot hasn't been added to.
+pub fn check(settings: &TelegramSettings) -> Result<(), String> {
+    ureq::get(format!(
+        "https://api.telegram.org/bot{}/getChat",
+        settings.bot_token
+    ))
+    .query("chat_id", &settings.chat_id)
+    .call()
+    .map(|_| ())
+    .map_err(|e| match e {
+        ureq::Error::StatusCode(401 | 404) => "Telegram rejected the bot_token".to_string(),
+        ureq::Error::StatusCode(400 | 403) => {
+            format!("The bot can't access chat {}", settings.chat_id)
+        }
+        ureq::Error::StatusCode(status) => format!("Telegram API returned {}", status),
+        _ => "Failed to reach the Telegram API".to_string(),
+    })
+}
+
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    fn kinds(&self) -> &[NotificationKind] {
+        &self.kinds
+    }
+
+    /// Errors are reported without the request URL, which contains the bot
+    /// token.
+    fn notify(&self, notification: &Notification) -> Result<(), String> {
+        ureq::post(&self.url)
+            .send_json(json!({
+                "chat_id": self.chat_id,
+                "text": notification.render(&self.template),
+            }))
+            .map(|_| ())
+            .map_err(|e| match e {
+                ureq::Error::StatusCode(status) => format!("Telegram API returned {}", status),
+                _ => "Failed to reach the Telegram API".to_string(),
+            })
+    }
+}
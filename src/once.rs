@@ -0,0 +1,343 @@
+use crate::events::{EventSink, FileEvent, Outcome};
+use crate::logging;
+use crate::pipeline::{self, Pipeline};
+use crate::plugins::Plugins;
+use crate::remote_rules;
+use crate::rules::{Decision, RuleSet};
+use crate::settings::Settings;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const USAGE: &str = "usage: invoicehandler once [--dry-run [--format csv|json] [--report <file>]]";
+
+#[derive(Clone, Copy)]
+enum Format {
+    Csv,
+    Json,
+}
+
+/// What `once --dry-run` expects to happen to one file.
+#[derive(Serialize)]
+struct PlannedFile {
+    path: PathBuf,
+    /// `rename`, `unchanged` or `unmatched`.
+    outcome: &'static str,
+    rule: Option<String>,
+    new_name: Option<String>,
+    /// Every path the actions would write the file to.
+    targets: Vec<PathBuf>,
+    /// What one of `targets` would overwrite: a file that is already there,
+    /// or another file of the run going to the same place.
+    collision: Option<PathBuf>,
+    fields: BTreeMap<String, String>,
+}
+
+/// `invoicehandler once`: processes the files that are in the watch directory
+/// and exits, with 1 when one of them failed. With `--dry-run` nothing is
+/// touched; the plan for every file is written as a report for sign-off
+/// instead, and the exit code is 1 when it predicts a collision.
+pub fn run_once_command(config_path: &Path, args: &[String]) -> i32 {
+    let mut dry_run = false;
+    let mut format = Format::Csv;
+    let mut report = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--format" => match args.next().map(String::as_str) {
+                Some("csv") => format = Format::Csv,
+                Some("json") => format = Format::Json,
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return 2;
+                }
+            },
+            "--report" => match args.next() {
+                Some(file) => report = Some(PathBuf::from(file)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return 2;
+                }
+            },
+            _ => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        }
+    }
+    if !dry_run && report.is_some() {
+        eprintln!("--report is only written with --dry-run");
+        return 2;
+    }
+
+    let settings = match Settings::load(config_path) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Error loading settings: {}", e);
+            return e.exit_code();
+        }
+    };
+    if let Err(e) = settings.check_watch_directory() {
+        eprintln!("{}", e);
+        return e.exit_code();
+    }
+    // Only a real run logs, so the report can go to stdout.
+    let _logging = if dry_run {
+        None
+    } else {
+        match logging::init_logging(&settings) {
+            Ok(guard) => Some(guard),
+            Err(e) => {
+                eprintln!("{}", e);
+                return e.exit_code();
+            }
+        }
+    };
+    if let Some(remote) = &settings.remote_rules {
+        remote_rules::refresh(remote);
+    }
+    let rules = match RuleSet::load(config_path) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("Error loading rules: {}", e);
+            return e.exit_code();
+        }
+    };
+
+    if dry_run {
+        return plan(&settings, &rules, format, report.as_deref());
+    }
+    process(&settings, &rules)
+}
+
+/// Counts the outcomes of the run.
+#[derive(Clone, Default)]
+struct Outcomes(Arc<Mutex<HashMap<&'static str, usize>>>);
+
+impl EventSink for Outcomes {
+    fn name(&self) -> &'static str {
+        "once"
+    }
+
+    fn publish(&self, event: &FileEvent, _payload: &str) -> Result<(), String> {
+        *self
+            .0
+            .lock()
+            .unwrap()
+            .entry(event.outcome.as_str())
+            .or_default() += 1;
+        Ok(())
+    }
+}
+
+fn process(settings: &Settings, rules: &RuleSet) -> i32 {
+    let mut pipeline = match Pipeline::new(settings) {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            eprintln!("{}", e);
+            return e.exit_code();
+        }
+    };
+    // Before listing, so files an interrupted run left under a temporary
+    // name are processed too.
+    settings
+        .actions
+        .recover(&settings.watch_directory, settings.recursive);
+    let files = match pipeline::unprocessed_files(settings, rules) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    let outcomes = Outcomes::default();
+    pipeline.add_sink(Box::new(outcomes.clone()));
+    pipeline.process_batch(&files, rules, || false);
+
+    let outcomes = outcomes.0.lock().unwrap();
+    let count = |outcome: Outcome| outcomes.get(outcome.as_str()).copied().unwrap_or(0);
+    eprintln!(
+        "Processed: {}, failed: {}",
+        count(Outcome::Processed),
+        count(Outcome::Failed)
+    );
+    if count(Outcome::Failed) > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+fn plan(settings: &Settings, rules: &RuleSet, format: Format, report: Option<&Path>) -> i32 {
+    let files = match pipeline::watched_files(settings) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let plugins = match Plugins::load(settings.plugins.as_ref()) {
+        Ok(plugins) => plugins,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    // Where each target goes, to find two files going to the same place.
+    let mut planned_targets: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut planned = Vec::new();
+    for path in files {
+        let Some(decision) = pipeline::decide(&path, settings, rules) else {
+            continue;
+        };
+        let filename = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        let mut file = match decision {
+            Decision::Unmatched => PlannedFile {
+                path,
+                outcome: "unmatched",
+                rule: None,
+                new_name: None,
+                targets: Vec::new(),
+                collision: None,
+                fields: BTreeMap::new(),
+            },
+            Decision::Keep { rule, fields } => PlannedFile {
+                path,
+                outcome: "unchanged",
+                rule: Some(rule),
+                new_name: None,
+                targets: Vec::new(),
+                collision: None,
+                fields,
+            },
+            Decision::Rename {
+                rule,
+                new_name,
+                fields,
+            } => {
+                let new_name = plugins.transform(filename, new_name, &rule, &fields);
+                let outcome = if new_name == filename {
+                    "unchanged"
+                } else {
+                    "rename"
+                };
+                PlannedFile {
+                    targets: if outcome == "rename" {
+                        settings.actions.plan(&path, &new_name)
+                    } else {
+                        Vec::new()
+                    },
+                    path,
+                    outcome,
+                    rule: Some(rule),
+                    new_name: Some(new_name),
+                    collision: None,
+                    fields,
+                }
+            }
+        };
+
+        for target in &file.targets {
+            if let Some(other) = planned_targets.get(target) {
+                file.collision = Some(other.clone());
+            } else if *target != file.path && target.exists() {
+                file.collision = Some(target.clone());
+            }
+            if file.collision.is_some() {
+                break;
+            }
+        }
+        for target in &file.targets {
+            planned_targets.insert(target.clone(), file.path.clone());
+        }
+        planned.push(file);
+    }
+
+    let written = match report {
+        Some(report) => File::create(report)
+            .map_err(|e| format!("Failed to create {}: {}", report.display(), e))
+            .and_then(|file| write_report(file, format, &planned)),
+        None => write_report(io::stdout().lock(), format, &planned),
+    };
+    if let Err(e) = written {
+        eprintln!("Failed to write the report: {}", e);
+        return 1;
+    }
+
+    let count = |outcome| planned.iter().filter(|f| f.outcome == outcome).count();
+    let collisions = planned.iter().filter(|f| f.collision.is_some()).count();
+    eprintln!(
+        "Would rename: {}, unchanged: {}, unmatched: {}, collisions: {}",
+        count("rename"),
+        count("unchanged"),
+        count("unmatched"),
+        collisions
+    );
+    if collisions > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// The CSV has a column for every field any file has, after the fixed ones.
+fn write_report(output: impl Write, format: Format, planned: &[PlannedFile]) -> Result<(), String> {
+    let mut output = output;
+    if let Format::Json = format {
+        serde_json::to_writer_pretty(&mut output, planned).map_err(|e| e.to_string())?;
+        return writeln!(output).map_err(|e| e.to_string());
+    }
+
+    let mut fields: Vec<&String> = planned.iter().flat_map(|f| f.fields.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    let mut writer = csv::Writer::from_writer(output);
+    let header = [
+        "path",
+        "outcome",
+        "rule",
+        "new_name",
+        "targets",
+        "collision",
+    ];
+    writer
+        .write_record(
+            header
+                .iter()
+                .copied()
+                .chain(fields.iter().map(|f| f.as_str())),
+        )
+        .map_err(|e| e.to_string())?;
+    for file in planned {
+        let display = |path: &Path| path.display().to_string();
+        let targets: Vec<String> = file.targets.iter().map(|t| display(t)).collect();
+        let record = [
+            display(&file.path),
+            file.outcome.to_string(),
+            file.rule.clone().unwrap_or_default(),
+            file.new_name.clone().unwrap_or_default(),
+            targets.join(";"),
+            file.collision.as_deref().map(display).unwrap_or_default(),
+        ];
+        writer
+            .write_record(
+                record.into_iter().chain(
+                    fields
+                        .iter()
+                        .map(|f| file.fields.get(*f).cloned().unwrap_or_default()),
+                ),
+            )
+            .map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
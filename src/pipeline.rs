@@ -0,0 +1,749 @@
+use crate::actions::MatchedFile;
+use crate::content;
+use crate::currency::CurrencyConverter;
+use crate::duplicates::DuplicateIndex;
+use crate::error::{FileError, ProcessError};
+use crate::events::{self, EventPublisher, EventSink, FileEvent};
+use crate::ledger;
+use crate::metrics::{Metrics, Stage};
+use crate::notifications::Notifications;
+use crate::plugins::Plugins;
+use crate::retry::{QueuedFile, RetryQueue};
+use crate::rules::{ContentType, Decision, Metadata, RuleSet};
+use crate::scan::{Scanner, Verdict};
+use crate::sepa;
+use crate::settings::Settings;
+use crate::telemetry;
+use crate::vies::Vies;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, info_span, instrument, warn};
+
+const RENAME_ECHO_WINDOW: Duration = Duration::from_secs(2);
+/// Files per worker whose locks are checked before they are processed.
+const BATCH_PER_WORKER: usize = 16;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Processes one file at a time: waits for it to be unlocked, matches it
+/// against the rules, runs the configured actions on it (by default only the
+/// rename) and publishes the outcome. Files that stay
+/// locked are queued and retried through [`Pipeline::process_due_retries`].
+pub struct Pipeline<'a> {
+    settings: &'a Settings,
+    events: EventPublisher,
+    plugins: Plugins,
+    retries: RetryQueue<'a>,
+    scanner: Option<Scanner<'a>>,
+    duplicates: Option<DuplicateIndex<'a>>,
+    vies: Option<Vies<'a>>,
+    currency: Option<CurrencyConverter<'a>>,
+    metrics: Metrics,
+    // Renaming a file produces watcher events for its new name; those are
+    // ignored for a short while instead of being processed (and reported as
+    // unmatched) again.
+    recent_renames: HashMap<PathBuf, Instant>,
+    /// Files the lock check opened for writing, which Linux reports as a
+    /// close-write once they are closed again.
+    recent_checks: HashMap<PathBuf, Instant>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// Loads the plugins and connects the event sinks and notifiers
+    /// configured in `settings`.
+    pub fn new(settings: &'a Settings) -> Result<Self, ProcessError> {
+        let plugins = Plugins::load(settings.plugins.as_ref()).map_err(ProcessError::Plugins)?;
+        let mut events = EventPublisher::connect(&settings.events).map_err(ProcessError::Events)?;
+        plugins.add_sinks(&mut events);
+        let notifications = Notifications::start(&settings.notifications);
+        events.add_sink(Box::new(notifications.clone()));
+        let metrics = Metrics::default();
+        events.add_sink(Box::new(metrics.clone()));
+        Ok(Self::with_events(
+            settings,
+            events,
+            plugins,
+            notifications,
+            metrics,
+        ))
+    }
+
+    /// `metrics` has to be among the sinks of `events` to count the files.
+    pub(crate) fn with_events(
+        settings: &'a Settings,
+        events: EventPublisher,
+        plugins: Plugins,
+        notifications: Notifications,
+        metrics: Metrics,
+    ) -> Self {
+        Pipeline {
+            settings,
+            events,
+            plugins,
+            retries: RetryQueue::new(&settings.retry, notifications.clone()),
+            duplicates: settings
+                .duplicates
+                .as_ref()
+                .zip(settings.ledger.as_ref())
+                .map(|(duplicates, ledger)| {
+                    DuplicateIndex::load(duplicates, ledger, notifications.clone())
+                }),
+            vies: settings
+                .vies
+                .as_ref()
+                .map(|vies| Vies::new(vies, notifications.clone())),
+            currency: settings.currency.as_ref().map(CurrencyConverter::new),
+            scanner: settings
+                .clamav
+                .as_ref()
+                .map(|clamav| Scanner::new(clamav, notifications)),
+            metrics,
+            recent_renames: HashMap::new(),
+            recent_checks: HashMap::new(),
+        }
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn EventSink>) {
+        self.events.add_sink(sink);
+    }
+
+    fn record_stage(&self, stage: Stage, rule: Option<&str>, elapsed: Duration) {
+        telemetry::record_stage(stage, rule, elapsed);
+        self.metrics.record_stage(stage, rule, elapsed);
+    }
+
+    /// Returns the file's new path when the actions moved it, and `None`
+    /// when no rule renamed it. Failures are published and logged as well.
+    pub fn process(
+        &mut self,
+        file_path: &Path,
+        rules: &RuleSet,
+    ) -> Result<Option<PathBuf>, FileError> {
+        self.process_unlocked(file_path, rules, None)
+    }
+
+    /// Processes `files` in order, waiting for `scan_workers` of them at a
+    /// time to be unlocked, which is what a large catch-up spends most of
+    /// its time on. Logs its progress every few seconds and stops early
+    /// once `stop` returns true.
+    pub fn process_batch(&mut self, files: &[PathBuf], rules: &RuleSet, stop: impl Fn() -> bool) {
+        let settings = self.settings;
+        let workers = settings.scan_workers;
+        let mut done = 0;
+        let mut last_progress = Instant::now();
+        for chunk in files.chunks(workers * BATCH_PER_WORKER) {
+            let locks = parallel_map(chunk, workers, |path| {
+                let _file = info_span!("file", path = %path.display()).entered();
+                let started = Instant::now();
+                // Whatever isn't a file is left to process() to skip.
+                let unlocked = !path.is_file() || wait_for_file_unlock(path, settings, rules);
+                (unlocked, started.elapsed())
+            });
+            for (path, lock) in chunk.iter().zip(locks) {
+                if stop() {
+                    return;
+                }
+                // Failures are published as events.
+                let _ = self.process_unlocked(path, rules, Some(lock));
+                done += 1;
+                if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                    info!(done, total = files.len(), "Processing files");
+                    last_progress = Instant::now();
+                }
+            }
+        }
+    }
+
+    /// `lock` is whether the file was found unlocked and how long that took,
+    /// when it was already checked.
+    fn process_unlocked(
+        &mut self,
+        file_path: &Path,
+        rules: &RuleSet,
+        lock: Option<(bool, Duration)>,
+    ) -> Result<Option<PathBuf>, FileError> {
+        let started = Instant::now();
+        let result = self.process_file(file_path, rules, lock);
+        let new_path = match &result {
+            Ok(new_path) => new_path.as_deref(),
+            // Actions before the failed one may have moved it already.
+            Err(FileError::Action { path, .. }) if path != file_path => Some(path.as_path()),
+            Err(_) => None,
+        };
+        if let Some(new_path) = new_path {
+            self.recent_renames
+                .insert(new_path.to_path_buf(), Instant::now());
+        }
+        self.recent_checks
+            .insert(file_path.to_path_buf(), Instant::now());
+        telemetry::record_duration(started.elapsed());
+        result
+    }
+
+    /// Tries the locked files whose retry is due.
+    pub fn process_due_retries(&mut self, rules: &RuleSet) {
+        for path in self.retries.due() {
+            let _retry = info_span!("retry").entered();
+            let _ = self.process(&path, rules);
+        }
+    }
+
+    /// How long until the next locked file is due for a retry.
+    pub fn time_until_retry(&self) -> Option<Duration> {
+        self.retries.time_until_due()
+    }
+
+    /// Whether `path` is waiting in the retry queue.
+    pub fn is_queued(&self, path: &Path) -> bool {
+        self.retries.contains(path)
+    }
+
+    pub(crate) fn retry_snapshot(&self) -> Vec<QueuedFile> {
+        self.retries.snapshot()
+    }
+
+    /// Whether `path` was just renamed by this pipeline, so a watcher event
+    /// for it `received` at that time is an echo of the rename. Events are
+    /// compared by when they were received rather than handled, since they
+    /// queue up while slow files are processed.
+    pub fn renamed_recently(&mut self, path: &Path, received: Instant) -> bool {
+        self.recent_renames
+            .retain(|_, renamed_at| received < *renamed_at + RENAME_ECHO_WINDOW);
+        self.recent_renames.contains_key(path)
+    }
+
+    /// Lets the next event for `path` through even if it was just renamed.
+    pub fn forget_rename(&mut self, path: &Path) {
+        self.recent_renames.remove(path);
+    }
+
+    /// Whether `path` was just processed, so a close-write for it `received`
+    /// by then is the lock check closing it, as with
+    /// [`Pipeline::renamed_recently`].
+    pub fn checked_recently(&mut self, path: &Path, received: Instant) -> bool {
+        self.recent_checks
+            .retain(|_, checked_at| received < *checked_at + RENAME_ECHO_WINDOW);
+        self.recent_checks.contains_key(path)
+    }
+
+    #[instrument(name = "file", skip_all, fields(path = %file_path.display()))]
+    fn process_file(
+        &mut self,
+        file_path: &Path,
+        rules: &RuleSet,
+        lock: Option<(bool, Duration)>,
+    ) -> Result<Option<PathBuf>, FileError> {
+        if !file_path.exists() {
+            self.retries.remove(file_path);
+            return Ok(None);
+        }
+        // New subdirectories of a recursive watch.
+        if file_path.is_dir() {
+            return Ok(None);
+        }
+
+        let Some(filename) = file_path.file_name().and_then(|n| n.to_str()) else {
+            return Ok(None);
+        };
+        if self.settings.actions.wrote(filename) {
+            return Ok(None);
+        }
+
+        debug!(filename, "Extracted filename");
+
+        let (unlocked, lock_wait) = lock.unwrap_or_else(|| {
+            let lock_started = Instant::now();
+            let unlocked = wait_for_file_unlock(file_path, self.settings, rules);
+            (unlocked, lock_started.elapsed())
+        });
+
+        if !unlocked {
+            self.record_stage(Stage::LockWait, None, lock_wait);
+            self.events
+                .publish(&FileEvent::failed(file_path, None, events::LOCKED_ERROR));
+            self.retries.locked(file_path);
+            return Err(FileError::Locked);
+        }
+        self.retries.remove(file_path);
+
+        if let Some(scanner) = &self.scanner {
+            let _scan = info_span!("scan").entered();
+            let (error, message) = match scanner.scan(file_path) {
+                Ok(Verdict::Clean) => (None, None),
+                Ok(Verdict::Infected(signature)) => match scanner.quarantine(file_path, &signature)
+                {
+                    Ok(quarantined) => {
+                        let error = FileError::Infected {
+                            signature,
+                            quarantined: Some(quarantined.clone()),
+                        };
+                        let mut event = FileEvent::failed(file_path, None, &error.to_string());
+                        event.new_path = Some(quarantined);
+                        self.events.publish(&event);
+                        return Err(error);
+                    }
+                    Err(e) => {
+                        let error = FileError::Infected {
+                            signature,
+                            quarantined: None,
+                        };
+                        let message = format!("{}. {}", error, e);
+                        (Some(error), Some(message))
+                    }
+                },
+                Err(e) => (Some(FileError::Scan(e)), None),
+            };
+            if let Some(error) = error {
+                let message = message.unwrap_or_else(|| error.to_string());
+                error!(outcome = "failed", error = %message, "File left unprocessed");
+                self.events
+                    .publish(&FileEvent::failed(file_path, None, &message));
+                return Err(error);
+            }
+        }
+
+        let _match = info_span!("match").entered();
+        let match_started = Instant::now();
+
+        let metadata = file_metadata(file_path, self.settings);
+
+        let decision =
+            rules.decide_with_content(filename, &metadata, || file_text(file_path, self.settings));
+        let (rule, new_filename, fields) = match decision {
+            Decision::Unmatched => {
+                self.record_stage(Stage::LockWait, None, lock_wait);
+                self.record_stage(Stage::Match, None, match_started.elapsed());
+
+                info!(outcome = "unmatched", filename, "No matching rule");
+                self.events.publish(&FileEvent::unmatched(file_path));
+                return Ok(None);
+            }
+            Decision::Keep { rule, .. } => {
+                self.record_stage(Stage::LockWait, Some(&rule), lock_wait);
+                self.record_stage(Stage::Match, Some(&rule), match_started.elapsed());
+                return Ok(None);
+            }
+            Decision::Rename {
+                rule,
+                new_name,
+                fields,
+            } => (rule, new_name, fields),
+        };
+        let rule = rule.as_str();
+        self.record_stage(Stage::LockWait, Some(rule), lock_wait);
+        self.record_stage(Stage::Match, Some(rule), match_started.elapsed());
+
+        let _rename = info_span!("rename", rule).entered();
+        let new_filename = self
+            .plugins
+            .transform(filename, new_filename, rule, &fields);
+
+        if new_filename == filename {
+            return Ok(None);
+        }
+
+        if let Some(duplicates) = &self.duplicates {
+            if let Some(existing) = duplicates.find(&fields, file_path) {
+                match duplicates.report(file_path, &existing, &fields) {
+                    Ok(None) => {}
+                    Ok(Some(quarantined)) => {
+                        let error = FileError::Duplicate {
+                            existing,
+                            quarantined: Some(quarantined.clone()),
+                        };
+                        let mut event =
+                            FileEvent::failed(file_path, Some(rule), &error.to_string())
+                                .with_fields(fields);
+                        event.new_path = Some(quarantined);
+                        self.events.publish(&event);
+                        return Err(error);
+                    }
+                    Err(e) => {
+                        let error = FileError::Duplicate {
+                            existing,
+                            quarantined: None,
+                        };
+                        let message = format!("{}. {}", error, e);
+                        error!(outcome = "failed", error = %message, "File left unprocessed");
+                        self.events.publish(
+                            &FileEvent::failed(file_path, Some(rule), &message).with_fields(fields),
+                        );
+                        return Err(error);
+                    }
+                }
+            }
+        }
+
+        let mut file = MatchedFile {
+            path: file_path.to_path_buf(),
+            original_path: file_path,
+            new_name: &new_filename,
+            rule,
+            fields: &fields,
+        };
+
+        let actions_started = Instant::now();
+        let result = self.settings.actions.run(&mut file);
+        self.record_stage(Stage::Rename, Some(rule), actions_started.elapsed());
+
+        let new_path = file.path;
+        if let Err(e) = result {
+            error!(
+                outcome = "failed",
+                from = filename,
+                to = %new_filename,
+                error = %e,
+                "Failed to process file"
+            );
+            self.events.publish(
+                &FileEvent::failed(file_path, Some(rule), &e.to_string()).with_fields(fields),
+            );
+            return Err(e);
+        }
+
+        info!(
+            outcome = "processed",
+            from = filename,
+            to = %new_path.display(),
+            "Processed file"
+        );
+        let mut event = FileEvent::processed(file_path, &new_path, rule)
+            .with_fields(fields)
+            .with_extracted_fields(self.plugins.extract(&new_path));
+        if let Some(currency) = &mut self.currency {
+            currency.convert(&mut event.fields);
+        }
+        if let Some(vies) = &mut self.vies {
+            vies.check(&new_path, &mut event.fields);
+        }
+        self.events.publish(&event);
+
+        if let Some(ledger) = &self.settings.ledger {
+            if let Err(e) = ledger::append_entry(
+                ledger,
+                &self.settings.dates,
+                &event.fields,
+                file_path,
+                &new_path,
+            ) {
+                error!(error = %e, "Failed to update ledger");
+            }
+        }
+        if let Some(duplicates) = &mut self.duplicates {
+            duplicates.insert(&event.fields, &new_path);
+        }
+        if let Some(sepa) = &self.settings.sepa {
+            match sepa::add_payment(sepa, &self.settings.dates, &event.fields) {
+                Ok(Some(batch)) => info!(batch = %batch.display(), "Prepared SEPA payment"),
+                Ok(None) => {}
+                Err(e) => error!(error = %e, "Failed to prepare SEPA payment"),
+            }
+        }
+
+        Ok(Some(new_path))
+    }
+}
+
+/// The files in the watch directory, and its subdirectories with
+/// `recursive`, that a rule would still rename, i.e. the ones that arrived
+/// while nothing was watching. Files no rule matches are left out so they
+/// aren't reported as unmatched again on every catch-up.
+pub fn unprocessed_files(settings: &Settings, rules: &RuleSet) -> Result<Vec<PathBuf>, String> {
+    Ok(scan(settings, rules)?.unprocessed)
+}
+
+/// What the rules make of the files in the watch directory.
+pub(crate) struct Scan {
+    /// As [`unprocessed_files`].
+    pub(crate) unprocessed: Vec<PathBuf>,
+    /// Files no rule matches.
+    pub(crate) unmatched: usize,
+}
+
+pub(crate) fn scan(settings: &Settings, rules: &RuleSet) -> Result<Scan, String> {
+    let files = watched_files(settings)?;
+    let decisions = parallel_map(&files, settings.scan_workers, |path| {
+        match decide(path, settings, rules) {
+            Some(Decision::Rename { .. }) => Some(true),
+            Some(Decision::Unmatched) => Some(false),
+            _ => None,
+        }
+    });
+    let mut scan = Scan {
+        unprocessed: Vec::new(),
+        unmatched: 0,
+    };
+    for (path, renamed) in files.into_iter().zip(decisions) {
+        match renamed {
+            Some(true) => scan.unprocessed.push(path),
+            Some(false) => scan.unmatched += 1,
+            None => {}
+        }
+    }
+    Ok(scan)
+}
+
+/// `f` of every item, in order, computed on up to `workers` threads.
+fn parallel_map<T: Sync, R: Send>(
+    items: &[T],
+    workers: usize,
+    f: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    if workers <= 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let chunk = items.len().div_ceil(workers);
+    thread::scope(|scope| {
+        let f = &f;
+        let handles: Vec<_> = items
+            .chunks(chunk)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("worker panicked"))
+            .collect()
+    })
+}
+
+/// Every file in the watch directory, and its subdirectories with
+/// `recursive`, sorted, except the ones the actions wrote and the ones last
+/// modified before `ignore_older_than`.
+pub(crate) fn watched_files(settings: &Settings) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    list_files(&settings.watch_directory, settings.recursive, &mut files)?;
+    files.retain(|path| {
+        path.file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|filename| !settings.actions.wrote(filename))
+    });
+    if let Some(max_age) = settings.ignore_older_than {
+        let listed = files.len();
+        files.retain(|path| !is_older(path, max_age));
+        if files.len() < listed {
+            debug!(files = listed - files.len(), "Ignoring old files");
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Files whose age can't be told are kept.
+fn is_older(path: &Path, max_age: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > max_age)
+}
+
+/// What the rules would do with the file at `path`, without waiting for it
+/// to be unlocked. `None` when its name isn't valid UTF-8.
+pub(crate) fn decide(path: &Path, settings: &Settings, rules: &RuleSet) -> Option<Decision> {
+    let filename = path.file_name().and_then(|n| n.to_str())?;
+    let metadata = file_metadata(path, settings);
+    Some(rules.decide_with_content(filename, &metadata, || file_text(path, settings)))
+}
+
+/// Symlinked directories are not followed, so a link back up can't loop.
+fn list_files(directory: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries = fs::read_dir(directory)
+        .map_err(|e| format!("Failed to list {}: {}", directory.display(), e))?;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() && recursive => {
+                list_files(&path, recursive, files)?
+            }
+            _ if path.is_file() => files.push(path),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn file_metadata(path: &Path, settings: &Settings) -> Metadata {
+    let metadata = fs::metadata(path).ok();
+    Metadata {
+        size: metadata.as_ref().map(|metadata| metadata.len()),
+        modified: metadata.and_then(|metadata| metadata.modified().ok()),
+        relative_path: relative_path(path, settings),
+        content_type: sniff(path),
+        sender: content::eml_sender(path),
+        received: Some(settings.dates.now()),
+    }
+}
+
+/// `path` relative to the watch directory, with `/` between directories.
+fn relative_path(path: &Path, settings: &Settings) -> Option<String> {
+    path.strip_prefix(&settings.watch_directory)
+        .ok()
+        .map(|relative| {
+            relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+}
+
+fn sniff(path: &Path) -> Option<ContentType> {
+    let mut head = Vec::with_capacity(16);
+    File::open(path)
+        .and_then(|file| file.take(16).read_to_end(&mut head))
+        .ok()?;
+    ContentType::detect(&head)
+}
+
+/// The file's text for rules with a `content_pattern`, `None` when it has
+/// none.
+fn file_text(path: &Path, settings: &Settings) -> Option<String> {
+    match content::extract_text(&settings.content, path) {
+        Ok(text) => Some(text),
+        Err(e) => {
+            debug!(path = %path.display(), error = %e, "No text for content patterns");
+            None
+        }
+    }
+}
+
+/// Waits as long as the rules say for the file, or else as `[settings]`
+/// says.
+#[instrument(name = "lock_wait", skip_all)]
+fn wait_for_file_unlock(file_path: &Path, settings: &Settings, rules: &RuleSet) -> bool {
+    let metadata = Metadata {
+        relative_path: relative_path(file_path, settings),
+        content_type: sniff(file_path),
+        ..Metadata::default()
+    };
+    let filename = file_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let lock = rules.lock_retry(filename, &metadata);
+    let max_lock_retries = lock.max_retries.unwrap_or(settings.max_lock_retries);
+    let delay = Duration::from_millis(lock.delay_ms.unwrap_or(settings.lock_retry_delay_ms));
+
+    for attempt in 1..=max_lock_retries {
+        match OpenOptions::new().read(true).write(true).open(file_path) {
+            Ok(_file) => {
+                return true;
+            }
+            Err(e) => {
+                if attempt < max_lock_retries {
+                    info!(
+                        attempt,
+                        max_attempts = max_lock_retries,
+                        error = %e,
+                        "File is locked, retrying"
+                    );
+                    thread::sleep(delay);
+                } else {
+                    warn!(
+                        outcome = "failed",
+                        attempts = max_lock_retries,
+                        "File remained locked, skipping"
+                    );
+                    return false;
+                }
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    #[test]
+    fn watched_files() {
+        let dir =
+            std::env::temp_dir().join(format!("invoicehandler-pipeline-{}", std::process::id()));
+        let watch = dir.join("watch");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(watch.join("sub")).unwrap();
+        let config = dir.join("config.ini");
+        fs::write(
+            &config,
+            format!(
+                "[settings]\nwatch_directory = {}\nrecursive = true\nignore_older_than = 1d\nscan_workers = 2\n\
+                 [actions]\nrun = rename, checksum\n\
+                 [translations]\n^invoice_(\\\\d+)\\\\.pdf$ = Invoice_$1.pdf\n",
+                watch.display()
+            ),
+        )
+        .unwrap();
+        for name in [
+            "invoice_1.pdf",
+            "invoice_1.pdf.sha256",
+            "sub/invoice_2.pdf",
+            "notes.txt",
+            "invoice_3.pdf",
+            ".invoicehandler-tmp-invoice_4.pdf",
+        ] {
+            fs::write(watch.join(name), "%PDF-1.7").unwrap();
+        }
+        File::options()
+            .write(true)
+            .open(watch.join("invoice_3.pdf"))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60))
+            .unwrap();
+
+        let mut settings = Settings::load(&config).unwrap();
+        let rules = RuleSet::load(&config).unwrap();
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files
+                .iter()
+                .map(|path| {
+                    path.strip_prefix(&watch)
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            names(super::watched_files(&settings).unwrap()),
+            ["invoice_1.pdf", "notes.txt", "sub/invoice_2.pdf"]
+        );
+        let scan = super::scan(&settings, &rules).unwrap();
+        assert_eq!(
+            names(scan.unprocessed),
+            ["invoice_1.pdf", "sub/invoice_2.pdf"]
+        );
+        assert_eq!(scan.unmatched, 1);
+
+        settings.recursive = false;
+        settings.ignore_older_than = None;
+        assert_eq!(
+            names(super::watched_files(&settings).unwrap()),
+            ["invoice_1.pdf", "invoice_3.pdf", "notes.txt"]
+        );
+        assert_eq!(
+            names(unprocessed_files(&settings, &rules).unwrap()),
+            ["invoice_1.pdf", "invoice_3.pdf"]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parallel_map() {
+        let items: Vec<usize> = (0..10).collect();
+        for workers in [1, 3, 16] {
+            assert_eq!(
+                super::parallel_map(&items, workers, |i| i * 2),
+                (0..20).step_by(2).collect::<Vec<_>>(),
+                "{}",
+                workers
+            );
+        }
+    }
+}
@@ -0,0 +1,239 @@
+mod native;
+mod sandbox;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+use crate::events::EventPublisher;
+pub(crate) use native::NativePlugin;
+pub(crate) use sandbox::spawn_limited;
+use sandbox::SandboxSettings;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+#[cfg(feature = "wasm")]
+use wasm::{WasmLimits, WasmPlugin};
+
+pub struct PluginSettings {
+    directory: PathBuf,
+    sandbox: Option<SandboxSettings>,
+    #[cfg(feature = "wasm")]
+    wasm: WasmLimits,
+}
+
+pub fn load_plugin_settings(ini: &ini::Ini) -> Result<Option<PluginSettings>, String> {
+    let section = match ini.section(Some("plugins")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let directory = section
+        .get("directory")
+        .ok_or("Missing 'directory' in [plugins]")?;
+
+    Ok(Some(PluginSettings {
+        directory: PathBuf::from(directory),
+        sandbox: sandbox::load_sandbox_settings(section)?,
+        #[cfg(feature = "wasm")]
+        wasm: wasm::load_wasm_limits(section)?,
+    }))
+}
+
+#[derive(Clone)]
+enum Plugin {
+    Native(Arc<NativePlugin>),
+    #[cfg(feature = "wasm")]
+    Wasm(Arc<WasmPlugin>),
+}
+
+impl Plugin {
+    fn name(&self) -> &str {
+        match self {
+            Plugin::Native(plugin) => &plugin.name,
+            #[cfg(feature = "wasm")]
+            Plugin::Wasm(plugin) => &plugin.name,
+        }
+    }
+}
+
+/// The native plugin `<name>.so` (or `.dylib`, `.dll`) in the `[plugins]`
+/// directory, for `plugin:<name>` in the `run` list of `[actions]`.
+pub(crate) fn load_native(ini: &ini::Ini, name: &str) -> Result<Arc<NativePlugin>, String> {
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return Err(format!("Invalid plugin name '{}' in [actions]", name));
+    }
+    let settings = load_plugin_settings(ini)?
+        .ok_or_else(|| format!("plugin:{} in [actions] needs a [plugins] directory", name))?;
+    let path = settings
+        .directory
+        .join(format!("{}.{}", name, std::env::consts::DLL_EXTENSION));
+    // SAFETY: the plugin directory is trusted, see `Plugins`.
+    let plugin = unsafe { NativePlugin::load(&path) }
+        .map_err(|e| format!("Failed to load plugin '{}': {}", path.display(), e))?;
+    Ok(Arc::new(plugin))
+}
+
+/// Entry point of the `extract-worker` subcommand that runs native
+/// extractors with `sandbox = true`. Programs embedding the daemon need to
+/// call this from their own `main` when started with that subcommand.
+#[doc(hidden)]
+pub fn run_extract_worker(args: &[String]) -> i32 {
+    sandbox::run_worker(args)
+}
+
+/// The shared libraries and WebAssembly modules in the `[plugins]` directory,
+/// loaded in file name order. Loading a shared library runs its code with the
+/// daemon's privileges, so the directory must only be writable by trusted
+/// users.
+#[derive(Clone, Default)]
+pub struct Plugins {
+    plugins: Vec<Plugin>,
+    sandbox: Option<SandboxSettings>,
+}
+
+impl Plugins {
+    /// No plugins when `settings` is `None`. Fails on the first plugin that
+    /// can't be loaded.
+    pub fn load(settings: Option<&PluginSettings>) -> Result<Self, String> {
+        let Some(settings) = settings else {
+            return Ok(Plugins::default());
+        };
+
+        let entries = fs::read_dir(&settings.directory).map_err(|e| {
+            format!(
+                "Failed to read plugin directory '{}': {}",
+                settings.directory.display(),
+                e
+            )
+        })?;
+
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+
+        let mut plugins = Vec::new();
+        for path in paths {
+            let Some(plugin) = Self::load_plugin(settings, &path)
+                .map_err(|e| format!("Failed to load plugin '{}': {}", path.display(), e))?
+            else {
+                continue;
+            };
+            info!(plugin = plugin.name(), path = %path.display(), "Loaded plugin");
+            plugins.push(plugin);
+        }
+
+        Ok(Plugins {
+            plugins,
+            sandbox: settings.sandbox.clone(),
+        })
+    }
+
+    /// `None` for files that aren't plugins.
+    #[cfg_attr(not(feature = "wasm"), allow(unused_variables))]
+    fn load_plugin(settings: &PluginSettings, path: &Path) -> Result<Option<Plugin>, String> {
+        let extension = path.extension().and_then(|e| e.to_str());
+
+        if extension == Some(std::env::consts::DLL_EXTENSION) {
+            // SAFETY: the plugin directory is trusted, see above.
+            let plugin = unsafe { NativePlugin::load(path) }?;
+            return Ok(Some(Plugin::Native(Arc::new(plugin))));
+        }
+
+        #[cfg(feature = "wasm")]
+        if extension == Some("wasm") {
+            let plugin = WasmPlugin::load(path, &settings.wasm)?;
+            return Ok(Some(Plugin::Wasm(Arc::new(plugin))));
+        }
+
+        #[cfg(not(feature = "wasm"))]
+        if extension == Some("wasm") {
+            return Err("WebAssembly plugins need a build with the 'wasm' feature".to_string());
+        }
+
+        Ok(None)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.plugins.iter().map(Plugin::name)
+    }
+
+    /// Registers the native plugins that have an `on_event` callback as event
+    /// sinks.
+    pub fn add_sinks(&self, events: &mut EventPublisher) {
+        for plugin in &self.plugins {
+            let sink = match plugin {
+                Plugin::Native(plugin) => plugin.sink(),
+                #[cfg(feature = "wasm")]
+                Plugin::Wasm(_) => None,
+            };
+            if let Some(sink) = sink {
+                events.add_sink(sink);
+            }
+        }
+    }
+
+    /// Runs the rule's new filename through the WebAssembly plugins'
+    /// transforms, each one getting the previous one's result. A transform
+    /// that fails is skipped.
+    #[cfg(feature = "wasm")]
+    pub fn transform(
+        &self,
+        filename: &str,
+        mut new_name: String,
+        rule: &str,
+        fields: &BTreeMap<String, String>,
+    ) -> String {
+        for plugin in &self.plugins {
+            let Plugin::Wasm(plugin) = plugin else {
+                continue;
+            };
+            match plugin.transform(filename, &new_name, rule, fields) {
+                Ok(Some(transformed)) => new_name = transformed,
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(plugin = %plugin.name, error = %e, "Plugin failed to transform filename")
+                }
+            }
+        }
+        new_name
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    pub fn transform(
+        &self,
+        _filename: &str,
+        new_name: String,
+        _rule: &str,
+        _fields: &BTreeMap<String, String>,
+    ) -> String {
+        new_name
+    }
+
+    /// The fields the plugins extract from the file at `path`. When two
+    /// plugins return the same field, the one loaded later wins.
+    pub fn extract(&self, path: &Path) -> BTreeMap<String, String> {
+        let mut fields = BTreeMap::new();
+        for plugin in &self.plugins {
+            let extracted = match plugin {
+                Plugin::Native(plugin) => match &self.sandbox {
+                    Some(sandbox) if plugin.has_extract() => {
+                        sandbox::extract(sandbox, &plugin.path, path)
+                    }
+                    _ => plugin.extract(path),
+                },
+                #[cfg(feature = "wasm")]
+                Plugin::Wasm(plugin) => plugin.extract(path),
+            };
+            match extracted {
+                Ok(extracted) => fields.extend(extracted),
+                Err(e) => {
+                    warn!(plugin = plugin.name(), error = %e, "Plugin failed to extract fields")
+                }
+            }
+        }
+        fields
+    }
+}
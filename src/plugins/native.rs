@@ -0,0 +1,184 @@
+use crate::events::{EventSink, FileEvent};
+use libloading::{Library, Symbol};
+use std::collections::BTreeMap;
+use std::ffi::{c_char, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The version of the interface in `include/invoicehandler_plugin.h`. Plugins
+/// built against another version are refused.
+pub const ABI_VERSION: u32 = 1;
+
+/// `const InvoicehandlerPlugin *invoicehandler_plugin(void)`, exported by every
+/// plugin.
+const ENTRY_POINT: &[u8] = b"invoicehandler_plugin\0";
+
+/// `on_event` and `run`, which get a JSON document and return 0 on success.
+type Callback = unsafe extern "C" fn(json: *const c_char, error: *mut *mut c_char) -> i32;
+
+/// `InvoicehandlerPlugin` from the header.
+#[repr(C)]
+struct PluginVtable {
+    abi_version: u32,
+    name: *const c_char,
+    extract: Option<unsafe extern "C" fn(path: *const c_char) -> *mut c_char>,
+    on_event: Option<Callback>,
+    run: Option<Callback>,
+    free_string: Option<unsafe extern "C" fn(string: *mut c_char)>,
+}
+
+/// A shared library implementing `include/invoicehandler_plugin.h`.
+pub struct NativePlugin {
+    pub name: String,
+    pub path: PathBuf,
+    vtable: *const PluginVtable,
+    // Keeps the code and data behind `vtable` loaded.
+    _library: Library,
+}
+
+// SAFETY: the plugin interface requires the callbacks to be callable from any
+// thread, and the vtable is never written to.
+unsafe impl Send for NativePlugin {}
+unsafe impl Sync for NativePlugin {}
+
+impl NativePlugin {
+    /// # Safety
+    ///
+    /// Runs the library's initialisers and entry point, which can do anything.
+    pub unsafe fn load(path: &Path) -> Result<Self, String> {
+        let library = Library::new(path).map_err(|e| e.to_string())?;
+
+        let entry: Symbol<unsafe extern "C" fn() -> *const PluginVtable> =
+            library.get(ENTRY_POINT).map_err(|e| e.to_string())?;
+        let vtable = entry();
+        if vtable.is_null() {
+            return Err("invoicehandler_plugin() returned NULL".to_string());
+        }
+
+        let abi_version = (*vtable).abi_version;
+        if abi_version != ABI_VERSION {
+            return Err(format!(
+                "Built for plugin ABI {}, this invoicehandler supports {}",
+                abi_version, ABI_VERSION
+            ));
+        }
+
+        let name = if (*vtable).name.is_null() {
+            path.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        } else {
+            CStr::from_ptr((*vtable).name)
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        Ok(NativePlugin {
+            name,
+            path: path.to_path_buf(),
+            vtable,
+            _library: library,
+        })
+    }
+
+    /// An event sink calling `on_event`, if the plugin has one.
+    pub fn sink(self: &Arc<Self>) -> Option<Box<dyn EventSink>> {
+        self.vtable().on_event?;
+        Some(Box::new(PluginSink {
+            plugin: self.clone(),
+        }))
+    }
+
+    fn vtable(&self) -> &PluginVtable {
+        // SAFETY: checked to be non-null on load, and valid while the library
+        // is loaded.
+        unsafe { &*self.vtable }
+    }
+
+    /// Copies a string returned by the plugin and hands it back to be freed.
+    fn take_string(&self, string: *mut c_char) -> Option<String> {
+        if string.is_null() {
+            return None;
+        }
+        // SAFETY: the plugin returns NUL-terminated strings that stay valid
+        // until passed to free_string.
+        let copy = unsafe { CStr::from_ptr(string) }
+            .to_string_lossy()
+            .into_owned();
+        if let Some(free_string) = self.vtable().free_string {
+            // SAFETY: `string` came from this plugin and isn't used again.
+            unsafe { free_string(string) };
+        }
+        Some(copy)
+    }
+
+    pub fn has_extract(&self) -> bool {
+        self.vtable().extract.is_some()
+    }
+
+    pub fn has_run(&self) -> bool {
+        self.vtable().run.is_some()
+    }
+
+    /// Calls the `run` action with `file`, the JSON of the matched file.
+    pub fn run(&self, file: &str) -> Result<(), String> {
+        match self.vtable().run {
+            Some(run) => self.call(run, "run", file),
+            None => Ok(()),
+        }
+    }
+
+    fn call(&self, callback: Callback, name: &str, json: &str) -> Result<(), String> {
+        let json = CString::new(json).map_err(|e| e.to_string())?;
+        let mut error: *mut c_char = std::ptr::null_mut();
+        // SAFETY: `json` is NUL-terminated and outlives the call, and `error`
+        // is a valid place for the plugin to store a string.
+        let status = unsafe { callback(json.as_ptr(), &mut error) };
+        let error = self.take_string(error);
+
+        if status == 0 {
+            return Ok(());
+        }
+        Err(format!(
+            "{}: {}",
+            self.name,
+            error.unwrap_or_else(|| format!("{} returned {}", name, status))
+        ))
+    }
+
+    pub fn extract(&self, path: &Path) -> Result<BTreeMap<String, String>, String> {
+        let Some(extract) = self.vtable().extract else {
+            return Ok(BTreeMap::new());
+        };
+
+        let path = path.to_str().ok_or("Path is not valid UTF-8")?;
+        let path = CString::new(path).map_err(|e| e.to_string())?;
+        // SAFETY: `path` is NUL-terminated and outlives the call.
+        let fields = unsafe { extract(path.as_ptr()) };
+
+        match self.take_string(fields) {
+            Some(fields) => serde_json::from_str(&fields)
+                .map_err(|e| format!("Invalid fields returned by extract: {}", e)),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+}
+
+/// Hands every published event to a plugin's `on_event` callback.
+struct PluginSink {
+    plugin: Arc<NativePlugin>,
+}
+
+impl EventSink for PluginSink {
+    fn name(&self) -> &'static str {
+        "plugin"
+    }
+
+    fn publish(&self, _event: &FileEvent, payload: &str) -> Result<(), String> {
+        match self.plugin.vtable().on_event {
+            Some(on_event) => self.plugin.call(on_event, "on_event", payload),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use super::native::NativePlugin;
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+#[cfg(target_os = "linux")]
+use tracing::warn;
+
+/// The hidden subcommand a sandboxed extraction runs as.
+pub const WORKER_COMMAND: &str = "extract-worker";
+
+/// Limits for native extractors run with `sandbox = true`.
+#[derive(Clone)]
+pub struct SandboxSettings {
+    max_memory: u64,
+    timeout: Duration,
+    allow_network: bool,
+}
+
+pub fn load_sandbox_settings(section: &ini::Properties) -> Result<Option<SandboxSettings>, String> {
+    let sandbox: bool = section
+        .get("sandbox")
+        .unwrap_or("false")
+        .parse()
+        .map_err(|e| format!("Invalid sandbox: {}", e))?;
+    if !sandbox {
+        return Ok(None);
+    }
+
+    let max_memory_mb: u64 = section
+        .get("sandbox_max_memory_mb")
+        .unwrap_or("512")
+        .parse()
+        .map_err(|e| format!("Invalid sandbox_max_memory_mb: {}", e))?;
+
+    let timeout_secs: u64 = section
+        .get("sandbox_timeout_secs")
+        .unwrap_or("30")
+        .parse()
+        .map_err(|e| format!("Invalid sandbox_timeout_secs: {}", e))?;
+
+    if timeout_secs == 0 {
+        return Err("sandbox_timeout_secs must be greater than 0".to_string());
+    }
+
+    let allow_network: bool = section
+        .get("sandbox_allow_network")
+        .unwrap_or("false")
+        .parse()
+        .map_err(|e| format!("Invalid sandbox_allow_network: {}", e))?;
+
+    Ok(Some(SandboxSettings {
+        max_memory: max_memory_mb * 1024 * 1024,
+        timeout: Duration::from_secs(timeout_secs),
+        allow_network,
+    }))
+}
+
+/// Runs the `extract` of the native plugin at `plugin` on `path` in a child
+/// process, so a plugin that crashes or hangs on a malformed file only takes
+/// the child down, limited as by [`spawn_limited`].
+pub fn extract(
+    settings: &SandboxSettings,
+    plugin: &Path,
+    path: &Path,
+) -> Result<BTreeMap<String, String>, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to find own binary: {}", e))?;
+
+    let command = || {
+        let mut command = Command::new(&exe);
+        command
+            .arg(WORKER_COMMAND)
+            .arg(plugin)
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        command
+    };
+
+    let mut child = spawn_limited(
+        command,
+        settings.max_memory,
+        settings.timeout,
+        settings.allow_network,
+    )
+    .map_err(|e| format!("Failed to start sandbox: {}", e))?;
+
+    // Read on other threads so a chatty child can't fill a pipe and block
+    // before it exits.
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    wait(&mut child, settings.timeout)?;
+    let status = child.wait().map_err(|e| e.to_string())?;
+
+    let stdout = stdout.join().unwrap_or_default();
+    if !status.success() {
+        let stderr = stderr.join().unwrap_or_default();
+        return Err(match stderr.trim() {
+            "" => format!("Sandbox exited with {}", status),
+            stderr => format!("Sandbox exited with {}: {}", status, stderr),
+        });
+    }
+
+    serde_json::from_str(&stdout).map_err(|e| format!("Invalid fields returned by extract: {}", e))
+}
+
+fn read_pipe(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_string(&mut output);
+        }
+        output
+    })
+}
+
+fn wait(child: &mut Child, timeout: Duration) -> Result<(), String> {
+    let started = Instant::now();
+    while child.try_wait().map_err(|e| e.to_string())?.is_none() {
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!(
+                "Sandbox didn't finish within {}s",
+                timeout.as_secs()
+            ));
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    Ok(())
+}
+
+/// Set once creating namespaces failed for a child allowed the network, so
+/// later ones that are go without.
+#[cfg(target_os = "linux")]
+static NO_NAMESPACES: AtomicBool = AtomicBool::new(false);
+
+/// Starts the command made by `command` with its memory capped at
+/// `max_memory` bytes and its CPU time at `cpu_time`, on Unix. On Linux it
+/// also gets a network namespace of its own, without any interfaces, and
+/// fails to start where unprivileged user namespaces are disabled, unless
+/// `allow_network` lets it run with the limits alone, after a warning.
+/// Other platforms have no network isolation.
+pub(crate) fn spawn_limited(
+    command: impl Fn() -> Command,
+    max_memory: u64,
+    cpu_time: Duration,
+    allow_network: bool,
+) -> io::Result<Child> {
+    #[cfg(target_os = "linux")]
+    if !(allow_network && NO_NAMESPACES.load(Ordering::Relaxed)) {
+        let mut isolated = command();
+        limit(&mut isolated, max_memory, cpu_time, true);
+        let error = match isolated.spawn() {
+            Ok(child) => return Ok(child),
+            Err(e) if !allow_network => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!(
+                        "{}, without network access. Where unprivileged user namespaces \
+                         are disabled, it can only run when allowed the network",
+                        e
+                    ),
+                ))
+            }
+            Err(e) => e,
+        };
+        // A failed unshare can't be told from a failed exec, so the
+        // namespaces are only blamed once the child starts without them.
+        let mut command = command();
+        limit(&mut command, max_memory, cpu_time, false);
+        let child = command.spawn()?;
+        NO_NAMESPACES.store(true, Ordering::Relaxed);
+        warn!(
+            error = %error,
+            "Failed to create a network namespace, running child processes with resource limits only"
+        );
+        return Ok(child);
+    }
+
+    let mut command = command();
+    #[cfg(unix)]
+    limit(&mut command, max_memory, cpu_time, false);
+    #[cfg(not(unix))]
+    let _ = (max_memory, cpu_time);
+    #[cfg(not(target_os = "linux"))]
+    let _ = allow_network;
+    command.spawn()
+}
+
+#[cfg(unix)]
+fn limit(command: &mut Command, max_memory: u64, cpu_time: Duration, namespaces: bool) {
+    use std::os::unix::process::CommandExt;
+
+    let cpu_secs = cpu_time.as_secs();
+    // SAFETY: the closure only makes system calls, which is all that is safe
+    // between fork and exec.
+    unsafe {
+        command.pre_exec(move || {
+            set_limit(libc::RLIMIT_AS, max_memory)?;
+            set_limit(libc::RLIMIT_CPU, cpu_secs)?;
+            set_limit(libc::RLIMIT_CORE, 0)?;
+            #[cfg(target_os = "linux")]
+            if namespaces && libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            #[cfg(not(target_os = "linux"))]
+            let _ = namespaces;
+            Ok(())
+        });
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+type Resource = libc::c_int;
+
+#[cfg(unix)]
+fn set_limit(resource: Resource, value: u64) -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+    // SAFETY: `limit` is a valid rlimit that outlives the call.
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The sandboxed side of [`extract`]: loads the plugin given as the first
+/// argument, extracts the fields of the file given as the second and writes
+/// them to stdout as JSON. Returns the exit code.
+pub fn run_worker(args: &[String]) -> i32 {
+    let [plugin, path] = args else {
+        eprintln!("usage: invoicehandler {} <plugin> <file>", WORKER_COMMAND);
+        return 2;
+    };
+
+    // SAFETY: the daemon only passes plugins from the trusted plugin
+    // directory, which it already loaded itself.
+    let fields = unsafe { NativePlugin::load(Path::new(plugin)) }
+        .and_then(|plugin| plugin.extract(Path::new(path)));
+    match fields.and_then(|fields| serde_json::to_string(&fields).map_err(|e| e.to_string())) {
+        Ok(json) => {
+            println!("{}", json);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawn_limited_caps_memory() {
+        let command = || {
+            let mut command = Command::new("sh");
+            command.args(["-c", "ulimit -v"]).stdout(Stdio::piped());
+            command
+        };
+        let child = spawn_limited(command, 64 * 1024 * 1024, Duration::from_secs(5), true).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "65536");
+    }
+
+    #[test]
+    fn spawn_limited_isolates_network() {
+        let command = || {
+            let mut command = Command::new("cat");
+            command.arg("/proc/net/dev").stdout(Stdio::piped());
+            command
+        };
+        // Without user namespaces it must refuse instead of going online.
+        match spawn_limited(command, 64 * 1024 * 1024, Duration::from_secs(5), false) {
+            Ok(child) => {
+                let output = child.wait_with_output().unwrap();
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let interfaces: Vec<&str> = stdout
+                    .lines()
+                    .skip(2)
+                    .filter_map(|line| line.split(':').next())
+                    .map(str::trim)
+                    .collect();
+                assert_eq!(interfaces, ["lo"]);
+            }
+            Err(e) => assert!(e.to_string().contains("without network access"), "{}", e),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// The version of the exports described in `include/invoicehandler_wasm.h`.
+pub const ABI_VERSION: i32 = 1;
+
+const TRANSFORM: &str = "invoicehandler_transform";
+const EXTRACT: &str = "invoicehandler_extract";
+
+/// How much a WebAssembly plugin may use per call.
+pub struct WasmLimits {
+    fuel: u64,
+    max_memory: usize,
+    max_file_size: u64,
+}
+
+pub fn load_wasm_limits(section: &ini::Properties) -> Result<WasmLimits, String> {
+    let fuel: u64 = section
+        .get("wasm_fuel")
+        .unwrap_or("1000000000")
+        .parse()
+        .map_err(|e| format!("Invalid wasm_fuel: {}", e))?;
+
+    let max_memory_mb: usize = section
+        .get("wasm_max_memory_mb")
+        .unwrap_or("64")
+        .parse()
+        .map_err(|e| format!("Invalid wasm_max_memory_mb: {}", e))?;
+
+    let max_file_mb: u64 = section
+        .get("wasm_max_file_mb")
+        .unwrap_or("32")
+        .parse()
+        .map_err(|e| format!("Invalid wasm_max_file_mb: {}", e))?;
+
+    Ok(WasmLimits {
+        fuel,
+        max_memory: max_memory_mb
+            .checked_mul(1024 * 1024)
+            .ok_or("Invalid wasm_max_memory_mb: too large")?,
+        max_file_size: max_file_mb
+            .checked_mul(1024 * 1024)
+            .ok_or("Invalid wasm_max_file_mb: too large")?,
+    })
+}
+
+/// A WebAssembly module. It is given no imports, so it can't reach the
+/// filesystem, the network or the clock, and every call runs in a fresh
+/// instance that is thrown away afterwards.
+pub struct WasmPlugin {
+    pub name: String,
+    engine: Engine,
+    module: Module,
+    fuel: u64,
+    max_memory: usize,
+    max_file_size: u64,
+}
+
+impl WasmPlugin {
+    pub fn load(path: &Path, limits: &WasmLimits) -> Result<Self, String> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        // Keeps trap messages to one line in the log.
+        config.wasm_backtrace_max_frames(None);
+        let engine = Engine::new(&config).map_err(|e| format!("{:#}", e))?;
+
+        let module = Module::from_file(&engine, path).map_err(|e| format!("{:#}", e))?;
+
+        if let Some(import) = module.imports().next() {
+            return Err(format!(
+                "Imports {}::{}, but plugins get no host functions",
+                import.module(),
+                import.name()
+            ));
+        }
+
+        let plugin = WasmPlugin {
+            name: path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            engine,
+            module,
+            fuel: limits.fuel,
+            max_memory: limits.max_memory,
+            max_file_size: limits.max_file_size,
+        };
+
+        let (mut store, instance) = plugin.instantiate()?;
+        let abi_version = instance
+            .get_typed_func::<(), i32>(&mut store, "invoicehandler_abi_version")
+            .and_then(|abi_version| abi_version.call(&mut store, ()))
+            .map_err(|e| format!("invoicehandler_abi_version: {:#}", e))?;
+        if abi_version != ABI_VERSION {
+            return Err(format!(
+                "Built for WebAssembly plugin ABI {}, this invoicehandler supports {}",
+                abi_version, ABI_VERSION
+            ));
+        }
+
+        Ok(plugin)
+    }
+
+    /// Passes the rule's result to `invoicehandler_transform`, which returns
+    /// the filename to use instead, or `None` to keep it.
+    pub fn transform(
+        &self,
+        filename: &str,
+        new_name: &str,
+        rule: &str,
+        fields: &BTreeMap<String, String>,
+    ) -> Result<Option<String>, String> {
+        if self.module.get_export(TRANSFORM).is_none() {
+            return Ok(None);
+        }
+
+        let input = serde_json::json!({
+            "filename": filename,
+            "new_name": new_name,
+            "rule": rule,
+            "fields": fields,
+        });
+        let Some(output) = self.call(TRANSFORM, input.to_string().as_bytes())? else {
+            return Ok(None);
+        };
+
+        let transformed = String::from_utf8(output)
+            .map_err(|_| "Transformed filename is not valid UTF-8".to_string())?;
+        if transformed.is_empty()
+            || transformed == "."
+            || transformed == ".."
+            || transformed.contains(['/', '\\', '\0'])
+        {
+            return Err(format!(
+                "Transformed filename '{}' is not a plain filename",
+                transformed
+            ));
+        }
+        Ok(Some(transformed))
+    }
+
+    /// Passes the contents of `path` to `invoicehandler_extract`, which
+    /// returns a JSON object of string fields. Files above `wasm_max_file_mb`
+    /// are skipped.
+    pub fn extract(&self, path: &Path) -> Result<BTreeMap<String, String>, String> {
+        if self.module.get_export(EXTRACT).is_none() {
+            return Ok(BTreeMap::new());
+        }
+
+        let size = path.metadata().map_err(|e| e.to_string())?.len();
+        if size > self.max_file_size {
+            return Ok(BTreeMap::new());
+        }
+
+        let contents = std::fs::read(path).map_err(|e| e.to_string())?;
+        match self.call(EXTRACT, &contents)? {
+            Some(fields) => serde_json::from_slice(&fields)
+                .map_err(|e| format!("Invalid fields returned by extract: {}", e)),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    fn instantiate(&self) -> Result<(Store<StoreLimits>, Instance), String> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(self.fuel).map_err(|e| format!("{:#}", e))?;
+
+        let instance =
+            Instance::new(&mut store, &self.module, &[]).map_err(|e| format!("{:#}", e))?;
+        Ok((store, instance))
+    }
+
+    /// Copies `input` into a fresh instance's memory through
+    /// `invoicehandler_alloc`, calls `export` with its pointer and length and
+    /// copies out the output it returns as `pointer << 32 | length`. A return
+    /// value of 0 means no output.
+    fn call(&self, export: &str, input: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let (mut store, instance) = self.instantiate()?;
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or("Doesn't export its memory")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "invoicehandler_alloc")
+            .map_err(|e| format!("invoicehandler_alloc: {:#}", e))?;
+        let func = instance
+            .get_typed_func::<(i32, i32), i64>(&mut store, export)
+            .map_err(|e| format!("{}: {:#}", export, e))?;
+
+        let len = i32::try_from(input.len()).map_err(|_| "Input too large".to_string())?;
+        let ptr = alloc
+            .call(&mut store, len)
+            .map_err(|e| format!("invoicehandler_alloc: {:#}", e))?;
+        memory
+            .write(&mut store, ptr as u32 as usize, input)
+            .map_err(|e| format!("invoicehandler_alloc returned an invalid pointer: {}", e))?;
+
+        let packed = func
+            .call(&mut store, (ptr, len))
+            .map_err(|e| format!("{}: {:#}", export, e))? as u64;
+        if packed == 0 {
+            return Ok(None);
+        }
+
+        // Checked against the memory before anything is allocated, so a
+        // made-up length can't exhaust the host's.
+        let (ptr, len) = ((packed >> 32) as usize, (packed & 0xFFFF_FFFF) as usize);
+        let output = ptr
+            .checked_add(len)
+            .filter(|end| *end <= memory.data_size(&store))
+            .and_then(|end| memory.data(&store).get(ptr..end))
+            .ok_or_else(|| format!("{} returned an invalid pointer", export))?;
+        Ok(Some(output.to_vec()))
+    }
+}
@@ -0,0 +1,141 @@
+/// The user and group to switch to once the daemon is set up, from `user`
+/// and `group` in `[settings]`.
+pub struct PrivilegeSettings {
+    user: Option<String>,
+    group: Option<String>,
+}
+
+pub fn load_privilege_settings(
+    section: &ini::Properties,
+) -> Result<Option<PrivilegeSettings>, String> {
+    let user = section.get("user").map(str::to_string);
+    let group = section.get("group").map(str::to_string);
+    if user.is_none() && group.is_none() {
+        return Ok(None);
+    }
+
+    if cfg!(not(unix)) {
+        return Err("user and group in [settings] are only supported on Unix".to_string());
+    }
+
+    Ok(Some(PrivilegeSettings { user, group }))
+}
+
+/// Switches to the configured group and user for good, with the user's
+/// supplementary groups. Only root can do so; when already running as the
+/// configured user and group this does nothing.
+#[cfg(unix)]
+pub fn drop_privileges(settings: &PrivilegeSettings) -> Result<(), String> {
+    let user = settings.user.as_deref().map(lookup_user).transpose()?;
+    let gid = match (&settings.group, &user) {
+        (Some(group), _) => lookup_group(group)?,
+        (None, Some((_, _, gid))) => *gid,
+        (None, None) => unreachable!("user or group is set"),
+    };
+
+    // SAFETY: these calls only read and set the process's credentials.
+    unsafe {
+        let uid = user.as_ref().map_or(libc::getuid(), |(_, uid, _)| *uid);
+        if libc::getuid() == uid && libc::getgid() == gid && libc::geteuid() == uid {
+            return Ok(());
+        }
+        if libc::geteuid() != 0 {
+            return Err("Switching user or group needs to start as root".to_string());
+        }
+
+        let groups = match &user {
+            Some((name, _, _)) => libc::initgroups(name.as_ptr(), gid as _),
+            None => libc::setgroups(1, &gid),
+        };
+        if groups != 0 {
+            return Err(format!(
+                "Failed to set supplementary groups: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        if libc::setgid(gid) != 0 {
+            return Err(format!(
+                "Failed to switch to group {}: {}",
+                gid,
+                std::io::Error::last_os_error()
+            ));
+        }
+        if libc::setuid(uid) != 0 {
+            return Err(format!(
+                "Failed to switch to user {}: {}",
+                uid,
+                std::io::Error::last_os_error()
+            ));
+        }
+        // Make sure there is no way back.
+        if uid != 0 && libc::setuid(0) == 0 {
+            return Err("Could still switch back to root after dropping privileges".to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(_settings: &PrivilegeSettings) -> Result<(), String> {
+    Ok(())
+}
+
+/// The user's name, uid and primary gid.
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Result<(std::ffi::CString, libc::uid_t, libc::gid_t), String> {
+    let c_name = std::ffi::CString::new(name).map_err(|e| e.to_string())?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: every pointer is valid for the call, and `buffer` is as large
+    // as passed.
+    let status = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if result.is_null() {
+        return Err(match status {
+            0 => format!("Unknown user '{}'", name),
+            errno => format!(
+                "Failed to look up user '{}': {}",
+                name,
+                std::io::Error::from_raw_os_error(errno)
+            ),
+        });
+    }
+    Ok((c_name, passwd.pw_uid, passwd.pw_gid))
+}
+
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Result<libc::gid_t, String> {
+    let c_name = std::ffi::CString::new(name).map_err(|e| e.to_string())?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: as in lookup_user.
+    let status = unsafe {
+        libc::getgrnam_r(
+            c_name.as_ptr(),
+            &mut group,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if result.is_null() {
+        return Err(match status {
+            0 => format!("Unknown group '{}'", name),
+            errno => format!(
+                "Failed to look up group '{}': {}",
+                name,
+                std::io::Error::from_raw_os_error(errno)
+            ),
+        });
+    }
+    Ok(group.gr_gid)
+}
@@ -0,0 +1,533 @@
+use crate::control::{Command, Control};
+use crate::rules;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info, warn, Span};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const DEFAULT_FILE: &str = "rules.ini";
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where the rules come from.
+#[derive(Clone)]
+enum Source {
+    Http(String),
+    Git {
+        repository: String,
+        branch: Option<String>,
+        file: String,
+    },
+}
+
+/// Rules shared from a central HTTPS URL or Git repository, from
+/// `[remote_rules]`. The last copy that was fetched, verified and parsed is
+/// kept in `cache_file`, and is what the rules are loaded from.
+#[derive(Clone)]
+pub struct RemoteRuleSettings {
+    source: Source,
+    interval: Duration,
+    cache_file: PathBuf,
+    signing_key: Option<String>,
+}
+
+pub fn load_remote_rule_settings(ini: &ini::Ini) -> Result<Option<RemoteRuleSettings>, String> {
+    let section = match ini.section(Some("remote_rules")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let source = match (section.get("url"), section.get("git")) {
+        (Some(url), None) => Source::Http(url.to_string()),
+        (None, Some(repository)) => Source::Git {
+            repository: repository.to_string(),
+            branch: section.get("git_branch").map(str::to_string),
+            file: section.get("file").unwrap_or(DEFAULT_FILE).to_string(),
+        },
+        (Some(_), Some(_)) => {
+            return Err("Set either 'url' or 'git' in [remote_rules], not both".to_string())
+        }
+        (None, None) => return Err("Missing 'url' or 'git' in [remote_rules]".to_string()),
+    };
+
+    let signing_key = section.get("signing_key").map(str::to_string);
+    let unencrypted = match &source {
+        Source::Http(url) if url.starts_with("https://") => false,
+        Source::Http(url) if url.starts_with("http://") => true,
+        Source::Http(url) => {
+            return Err(format!(
+                "Invalid url '{}' in [remote_rules], expected an https:// URL",
+                url
+            ))
+        }
+        Source::Git { repository, .. } => {
+            repository.starts_with("http://") || repository.starts_with("git://")
+        }
+    };
+    // Anyone on the network could swap the rules of an unencrypted source.
+    if unencrypted && signing_key.is_none() {
+        return Err(
+            "[remote_rules] needs an https:// source, or a 'signing_key' to verify the rules"
+                .to_string(),
+        );
+    }
+
+    let interval = section
+        .get("interval")
+        .map(|interval| crate::settings::parse_interval("interval in [remote_rules]", interval))
+        .transpose()?
+        .unwrap_or(DEFAULT_INTERVAL);
+
+    Ok(Some(RemoteRuleSettings {
+        source,
+        interval,
+        cache_file: cache_file(section)?,
+        signing_key,
+    }))
+}
+
+fn cache_file(section: &ini::Properties) -> Result<PathBuf, String> {
+    match section.get("cache_file") {
+        Some(file) => Ok(PathBuf::from(file)),
+        None => Ok(dirs::cache_dir()
+            .ok_or("No cache directory, set 'cache_file' in [remote_rules]")?
+            .join("invoicehandler")
+            .join("remote-rules.ini")),
+    }
+}
+
+/// The cached remote rules of the config, to be loaded after its own. None
+/// without `[remote_rules]` or before anything was fetched.
+pub(crate) fn cached(ini: &ini::Ini) -> Result<Option<String>, String> {
+    let Some(section) = ini.section(Some("remote_rules")) else {
+        return Ok(None);
+    };
+    let file = cache_file(section)?;
+    match fs::read_to_string(&file) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!(
+                file = %file.display(),
+                "No remote rules fetched yet, using the local ones only"
+            );
+            Ok(None)
+        }
+        Err(e) => Err(format!("Failed to read {}: {}", file.display(), e)),
+    }
+}
+
+/// What a fetch got. `Unchanged` when the server says the cached copy is
+/// still current.
+enum Fetched {
+    Unchanged,
+    Changed { text: String, etag: Option<String> },
+}
+
+/// Spawns the thread that fetches the rules right away and every `interval`
+/// after that, off the event loop, and asks it through `control` to reload
+/// the rules whenever the cached copy was replaced. Stops with the event
+/// loop.
+pub(crate) fn start(settings: &RemoteRuleSettings, control: Arc<Control>) {
+    let settings = settings.clone();
+    let span = Span::current();
+    thread::spawn(move || {
+        let _span = span.enter();
+        loop {
+            if refresh(&settings) && control.send(Command::Reload).is_err() {
+                break;
+            }
+            thread::sleep(settings.interval);
+        }
+    });
+}
+
+/// Fetches the rules now and caches them. Returns true when the cache was
+/// replaced, so the rules have to be reloaded. When the fetch, the
+/// signature or the rules are bad the last good copy is kept.
+pub(crate) fn refresh(settings: &RemoteRuleSettings) -> bool {
+    match settings
+        .fetch()
+        .and_then(|fetched| store(settings, fetched))
+    {
+        Ok(changed) => changed,
+        Err(e) => {
+            warn!(error = %e, "Failed to fetch remote rules, keeping the last good copy");
+            false
+        }
+    }
+}
+
+fn store(settings: &RemoteRuleSettings, fetched: Fetched) -> Result<bool, String> {
+    let Fetched::Changed { text, etag } = fetched else {
+        debug!("Remote rules unchanged");
+        return Ok(false);
+    };
+    let cache_file = &settings.cache_file;
+    if fs::read_to_string(cache_file).is_ok_and(|cached| cached == text) {
+        debug!("Remote rules unchanged");
+        return Ok(false);
+    }
+
+    let count = rules::count_rules(&text)?;
+    if let Some(directory) = cache_file.parent() {
+        let _ = fs::create_dir_all(directory);
+    }
+    // Written next to the cache and renamed over it, so a crash can't
+    // leave half a file.
+    let temp = cache_file.with_extension("tmp");
+    fs::write(&temp, &text)
+        .and_then(|()| fs::rename(&temp, cache_file))
+        .map_err(|e| format!("Failed to write {}: {}", cache_file.display(), e))?;
+    let etag_file = etag_file(cache_file);
+    match etag {
+        Some(etag) => {
+            let _ = fs::write(etag_file, etag);
+        }
+        None => {
+            let _ = fs::remove_file(etag_file);
+        }
+    }
+    info!(rules = count, "Fetched new remote rules");
+    Ok(true)
+}
+
+impl RemoteRuleSettings {
+    fn fetch(&self) -> Result<Fetched, String> {
+        match &self.source {
+            Source::Http(url) => self.fetch_http(url),
+            Source::Git {
+                repository,
+                branch,
+                file,
+            } => self.fetch_git(repository, branch.as_deref(), file),
+        }
+    }
+
+    /// Sends the ETag of the cached copy, so an unchanged file isn't
+    /// downloaded again.
+    fn fetch_http(&self, url: &str) -> Result<Fetched, String> {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(DOWNLOAD_TIMEOUT))
+            .build()
+            .into();
+        let mut request = agent.get(url);
+        let cached_etag = fs::read_to_string(etag_file(&self.cache_file)).ok();
+        if let Some(etag) = cached_etag.as_deref().filter(|_| self.cache_file.exists()) {
+            request = request.header("If-None-Match", etag);
+        }
+        let mut response = request.call().map_err(|e| e.to_string())?;
+        if response.status() == 304 {
+            return Ok(Fetched::Unchanged);
+        }
+        let etag = response
+            .headers()
+            .get("etag")
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        let text = response
+            .body_mut()
+            .read_to_string()
+            .map_err(|e| e.to_string())?;
+
+        if let Some(key) = &self.signing_key {
+            let signature_url = format!("{}.sig", url);
+            let signature = agent
+                .get(&signature_url)
+                .call()
+                .map_err(|e| format!("Failed to download {}: {}", signature_url, e))?
+                .body_mut()
+                .read_to_string()
+                .map_err(|e| e.to_string())?;
+            verify(key, &text, &signature)?;
+        }
+        Ok(Fetched::Changed { text, etag })
+    }
+
+    /// Keeps a shallow clone next to the cache and resets it to the newest
+    /// commit of the branch, with the `git` binary and its credentials.
+    fn fetch_git(
+        &self,
+        repository: &str,
+        branch: Option<&str>,
+        file: &str,
+    ) -> Result<Fetched, String> {
+        let checkout = self.cache_file.with_extension("git");
+        if checkout.join(".git").is_dir() {
+            git(
+                &checkout,
+                &[
+                    "fetch",
+                    "--quiet",
+                    "--depth",
+                    "1",
+                    "origin",
+                    branch.unwrap_or("HEAD"),
+                ],
+            )?;
+            git(&checkout, &["reset", "--quiet", "--hard", "FETCH_HEAD"])?;
+        } else {
+            if let Some(directory) = checkout.parent() {
+                let _ = fs::create_dir_all(directory);
+            }
+            let checkout = checkout.to_string_lossy();
+            let mut args = vec!["clone", "--quiet", "--depth", "1"];
+            if let Some(branch) = branch {
+                args.extend(["--branch", branch]);
+            }
+            args.extend(["--", repository, &checkout]);
+            git(Path::new("."), &args)?;
+        }
+
+        let path = checkout.join(file);
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {} from {}: {}", file, repository, e))?;
+        if let Some(key) = &self.signing_key {
+            let signature_path = checkout.join(format!("{}.sig", file));
+            let signature = fs::read_to_string(&signature_path)
+                .map_err(|e| format!("Failed to read {}.sig from {}: {}", file, repository, e))?;
+            verify(key, &text, &signature)?;
+        }
+        Ok(Fetched::Changed { text, etag: None })
+    }
+}
+
+fn etag_file(cache_file: &Path) -> PathBuf {
+    cache_file.with_extension("etag")
+}
+
+fn git(directory: &Path, args: &[&str]) -> Result<(), String> {
+    let output = process::Command::new("git")
+        .current_dir(directory)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.trim() {
+            "" => format!("git exited with {}", output.status),
+            stderr => format!("git: {}", stderr),
+        });
+    }
+    Ok(())
+}
+
+/// Checks `signature`, the hex HMAC-SHA256 of `text` with the shared
+/// `signing_key`, as made by
+/// `openssl dgst -sha256 -hmac <key> -r rules.ini | cut -d' ' -f1`.
+fn verify(key: &str, text: &str, signature: &str) -> Result<(), String> {
+    let signature = decode_hex(signature.trim()).ok_or("The signature isn't hex")?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).map_err(|e| e.to_string())?;
+    mac.update(text.as_bytes());
+    mac.verify_slice(&signature)
+        .map_err(|_| "The signature doesn't match the rules".to_string())
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    // from_str_radix would also take a sign.
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Fetches the rules without storing them, for the doctor.
+pub fn check(settings: &RemoteRuleSettings) -> Result<(), String> {
+    match settings.fetch()? {
+        Fetched::Unchanged => Ok(()),
+        Fetched::Changed { text, .. } => rules::count_rules(&text).map(|_| ()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = "[translations]\n^acme_(\\\\d+)\\\\.pdf$ = Acme_$1.pdf\n";
+    const KEY: &str = "secret";
+
+    fn sign(text: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(KEY.as_bytes()).unwrap();
+        mac.update(text.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn test_settings(name: &str, source: Source) -> RemoteRuleSettings {
+        let dir = std::env::temp_dir().join(format!(
+            "invoicehandler-remote-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        RemoteRuleSettings {
+            source,
+            interval: DEFAULT_INTERVAL,
+            cache_file: dir.join("remote-rules.ini"),
+            signing_key: Some(KEY.to_string()),
+        }
+    }
+
+    #[test]
+    fn load_settings() {
+        let cases = [
+            ("url = https://example.com/rules.ini", Ok(())),
+            (
+                "url = http://example.com/rules.ini",
+                Err("[remote_rules] needs"),
+            ),
+            (
+                "url = http://example.com/rules.ini\nsigning_key = x",
+                Ok(()),
+            ),
+            ("url = ftp://example.com/rules.ini", Err("Invalid url")),
+            ("git = https://example.com/rules.git", Ok(())),
+            ("git = git@example.com:rules.git", Ok(())),
+            (
+                "git = git://example.com/rules.git",
+                Err("[remote_rules] needs"),
+            ),
+            (
+                "url = https://example.com/rules.ini\ngit = https://example.com/rules.git",
+                Err("Set either"),
+            ),
+            ("interval = 5m", Err("Missing")),
+            (
+                "url = https://example.com/rules.ini\ninterval = 0",
+                Err("interval in [remote_rules] must be"),
+            ),
+        ];
+        for (section, expected) in cases {
+            let text = format!("[remote_rules]\ncache_file = /tmp/rules.ini\n{}", section);
+            let ini = ini::Ini::load_from_str(&text).unwrap();
+            match (load_remote_rule_settings(&ini), expected) {
+                (Ok(settings), Ok(())) => assert!(settings.is_some(), "{}", section),
+                (Err(e), Err(prefix)) => assert!(e.starts_with(prefix), "{}: {}", section, e),
+                (Ok(_), Err(_)) => panic!("{}: loaded", section),
+                (Err(e), Ok(())) => panic!("{}: {}", section, e),
+            }
+        }
+    }
+
+    #[test]
+    fn verify_signature() {
+        let signature = sign(RULES);
+        assert_eq!(verify(KEY, RULES, &signature), Ok(()));
+        assert_eq!(verify(KEY, RULES, &format!("{}\n", signature)), Ok(()));
+        assert_eq!(verify(KEY, RULES, &signature.to_uppercase()), Ok(()));
+
+        let mismatch = Err("The signature doesn't match the rules".to_string());
+        assert_eq!(verify("other", RULES, &signature), mismatch);
+        assert_eq!(verify(KEY, "[translations]\n", &signature), mismatch);
+        assert_eq!(verify(KEY, RULES, &signature[..62]), mismatch);
+        assert_eq!(
+            verify(KEY, RULES, "not hex"),
+            Err("The signature isn't hex".to_string())
+        );
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("00ff7A"), Some(vec![0x00, 0xff, 0x7a]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(decode_hex("+1"), None);
+        assert_eq!(decode_hex("éa"), None);
+    }
+
+    #[test]
+    fn cache() {
+        let settings = test_settings("cache", Source::Http(String::new()));
+        let etag = etag_file(&settings.cache_file);
+        let changed = |text: &str, etag: Option<&str>| Fetched::Changed {
+            text: text.to_string(),
+            etag: etag.map(str::to_string),
+        };
+
+        assert_eq!(store(&settings, changed(RULES, Some("\"v1\""))), Ok(true));
+        assert_eq!(fs::read_to_string(&settings.cache_file).unwrap(), RULES);
+        assert_eq!(fs::read_to_string(&etag).unwrap(), "\"v1\"");
+
+        assert_eq!(store(&settings, Fetched::Unchanged), Ok(false));
+        assert_eq!(store(&settings, changed(RULES, Some("\"v2\""))), Ok(false));
+
+        // Rules that don't load leave the last good copy.
+        let broken = "[translations]\nacme_(\\\\d+ = x\n";
+        assert!(store(&settings, changed(broken, None)).is_err());
+        assert_eq!(fs::read_to_string(&settings.cache_file).unwrap(), RULES);
+        assert!(etag.exists());
+
+        let rules = "[translations]\n^x$ = y\n";
+        assert_eq!(store(&settings, changed(rules, None)), Ok(true));
+        assert_eq!(fs::read_to_string(&settings.cache_file).unwrap(), rules);
+        assert!(!etag.exists());
+
+        let ini = ini::Ini::load_from_str(&format!(
+            "[remote_rules]\ncache_file = {}\n",
+            settings.cache_file.display()
+        ))
+        .unwrap();
+        assert_eq!(cached(&ini), Ok(Some(rules.to_string())));
+    }
+
+    /// Serves `RULES` with an ETag, and its signature, answering requests
+    /// with the ETag with 304.
+    fn serve(signature: String) -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let cached = request.headers().iter().any(|header| {
+                    header.field.equiv("If-None-Match") && header.value.as_str() == "\"v1\""
+                });
+                let response = match request.url() {
+                    "/rules.ini.sig" => tiny_http::Response::from_string(signature.clone()),
+                    "/rules.ini" if cached => {
+                        tiny_http::Response::from_string("").with_status_code(304)
+                    }
+                    "/rules.ini" => tiny_http::Response::from_string(RULES).with_header(
+                        tiny_http::Header::from_bytes(&b"ETag"[..], &b"\"v1\""[..]).unwrap(),
+                    ),
+                    _ => tiny_http::Response::from_string("").with_status_code(404),
+                };
+                let _ = request.respond(response);
+            }
+        });
+        format!("http://127.0.0.1:{}/rules.ini", port)
+    }
+
+    #[test]
+    fn fetch_http() {
+        let url = serve(sign(RULES));
+        let settings = test_settings("http", Source::Http(url.clone()));
+
+        assert!(refresh(&settings));
+        assert_eq!(fs::read_to_string(&settings.cache_file).unwrap(), RULES);
+        assert!(matches!(settings.fetch(), Ok(Fetched::Unchanged)));
+        assert!(!refresh(&settings));
+
+        // Without the cache the ETag isn't sent.
+        fs::remove_file(&settings.cache_file).unwrap();
+        assert!(matches!(settings.fetch(), Ok(Fetched::Changed { .. })));
+
+        let forged = test_settings("forged", Source::Http(serve(sign("other"))));
+        assert_eq!(
+            forged.fetch().err().as_deref(),
+            Some("The signature doesn't match the rules")
+        );
+        assert!(!refresh(&forged));
+        assert!(!forged.cache_file.exists());
+    }
+}
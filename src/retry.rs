@@ -0,0 +1,181 @@
+use crate::notifications::{Notification, NotificationKind, Notifications};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+pub struct RetrySettings {
+    interval: Duration,
+    attempts: u32,
+}
+
+pub fn load_retry_settings(section: &ini::Properties) -> Result<RetrySettings, String> {
+    let interval_secs: u64 = section
+        .get("locked_retry_interval_secs")
+        .unwrap_or("300")
+        .parse()
+        .map_err(|e| format!("Invalid locked_retry_interval_secs: {}", e))?;
+
+    if interval_secs == 0 {
+        return Err("locked_retry_interval_secs must be greater than 0".to_string());
+    }
+
+    let attempts: u32 = section
+        .get("locked_retry_attempts")
+        .unwrap_or("12")
+        .parse()
+        .map_err(|e| format!("Invalid locked_retry_attempts: {}", e))?;
+
+    Ok(RetrySettings {
+        interval: Duration::from_secs(interval_secs),
+        attempts,
+    })
+}
+
+#[derive(Serialize, Clone)]
+pub struct QueuedFile {
+    pub path: PathBuf,
+    pub attempts: u32,
+    pub next_attempt_secs: u64,
+}
+
+struct RetryEntry {
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+/// Files that stayed locked past `max_lock_retries`. They are tried again
+/// every `locked_retry_interval_secs` until they unlock or
+/// `locked_retry_attempts` runs out. Entering the queue and giving up both
+/// raise an `alert`, since these are the invoices that end up paid late.
+pub struct RetryQueue<'a> {
+    settings: &'a RetrySettings,
+    notifications: Notifications,
+    entries: HashMap<PathBuf, RetryEntry>,
+}
+
+impl<'a> RetryQueue<'a> {
+    pub fn new(settings: &'a RetrySettings, notifications: Notifications) -> Self {
+        RetryQueue {
+            settings,
+            notifications,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Records another failed lock wait for `path`.
+    pub fn locked(&mut self, path: &Path) {
+        let next_attempt = Instant::now() + self.settings.interval;
+        let attempts = match self.entries.get_mut(path) {
+            Some(entry) => {
+                entry.attempts += 1;
+                entry.next_attempt = next_attempt;
+                entry.attempts
+            }
+            None => {
+                self.entries.insert(
+                    path.to_path_buf(),
+                    RetryEntry {
+                        attempts: 0,
+                        next_attempt,
+                    },
+                );
+                0
+            }
+        };
+
+        if attempts == 0 && self.settings.attempts > 0 {
+            warn!(path = %path.display(), "Queued locked file for retry");
+            self.escalate(
+                path,
+                "Invoice stuck locked",
+                format!(
+                    "{} stayed locked and will be retried every {} seconds.",
+                    path.display(),
+                    self.settings.interval.as_secs()
+                ),
+            );
+        }
+
+        if attempts >= self.settings.attempts {
+            self.entries.remove(path);
+            error!(
+                outcome = "failed",
+                path = %path.display(),
+                attempts,
+                "Giving up on locked file"
+            );
+            self.escalate(
+                path,
+                "Gave up on locked invoice",
+                format!(
+                    "{} is still locked after {} retries and needs to be handled by hand.",
+                    path.display(),
+                    attempts
+                ),
+            );
+        }
+    }
+
+    /// Drops `path` from the queue once it was processed or disappeared.
+    pub fn remove(&mut self, path: &Path) {
+        self.entries.remove(path);
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    /// The files whose next retry is due.
+    pub fn due(&self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.next_attempt <= now)
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    pub fn time_until_due(&self) -> Option<Duration> {
+        self.entries
+            .values()
+            .map(|entry| entry.next_attempt.saturating_duration_since(Instant::now()))
+            .min()
+    }
+
+    pub fn snapshot(&self) -> Vec<QueuedFile> {
+        let mut files: Vec<QueuedFile> = self
+            .entries
+            .iter()
+            .map(|(path, entry)| QueuedFile {
+                path: path.clone(),
+                attempts: entry.attempts,
+                next_attempt_secs: entry
+                    .next_attempt
+                    .saturating_duration_since(Instant::now())
+                    .as_secs(),
+            })
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        files
+    }
+
+    fn escalate(&self, path: &Path, title: &str, body: String) {
+        let mut fields = BTreeMap::new();
+        fields.insert("path".to_string(), path.display().to_string());
+        if let Some(filename) = path.file_name() {
+            fields.insert(
+                "filename".to_string(),
+                filename.to_string_lossy().into_owned(),
+            );
+        }
+
+        self.notifications.send(Notification {
+            kind: NotificationKind::Alert,
+            title: title.to_string(),
+            body,
+            fields,
+        });
+    }
+}
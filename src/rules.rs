@@ -0,0 +1,1315 @@
+mod conflicts;
+mod template;
+
+use crate::config;
+use crate::dates::{self, DateFormat};
+use crate::error::{ConfigError, RuleError};
+use crate::remote_rules;
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use regex::{Captures, Regex, RegexSet};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+/// The lock retries of each content type.
+type TypeLocks = Vec<(ContentType, LockRetry)>;
+
+/// The `[translations]` and `[rule.<name>]` sections of a config file: regex
+/// patterns and their replacements, tried in order. The first rule that
+/// matches a filename renames it.
+#[derive(Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    // All patterns combined, to find the matching rules in one pass over the
+    // filename instead of one per rule. `None` when the combined patterns
+    // exceed the regex size limit, in which case the rules are tried in turn.
+    set: Option<RegexSet>,
+    // Whether any rule has `match_on = path`, which takes a second pass.
+    matches_paths: bool,
+    /// From `[locks.<type>]`.
+    type_locks: TypeLocks,
+}
+
+#[derive(Clone)]
+struct Rule {
+    regex: Regex,
+    replacement: String,
+    match_on: MatchOn,
+    /// Has to match the file's text too.
+    content_pattern: Option<Regex>,
+    /// From a `[translations.<type>]` group: only files of this type match.
+    content_type: Option<ContentType>,
+    /// Has to match the sender of an email.
+    sender_pattern: Option<Regex>,
+    dates: DateFormat,
+    lock: LockRetry,
+}
+
+/// How long to wait for a file to be unlocked, where a rule or a file type
+/// says otherwise than `max_lock_retries` and `lock_retry_delay_ms` in
+/// `[settings]`: huge scans stay locked for minutes, while small e-invoices
+/// should go to the retry queue fast.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LockRetry {
+    pub max_retries: Option<u32>,
+    pub delay_ms: Option<u64>,
+}
+
+impl LockRetry {
+    fn or(self, other: LockRetry) -> LockRetry {
+        LockRetry {
+            max_retries: self.max_retries.or(other.max_retries),
+            delay_ms: self.delay_ms.or(other.delay_ms),
+        }
+    }
+}
+
+/// What a rule's pattern is matched against.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatchOn {
+    #[default]
+    Filename,
+    /// The path relative to the watch directory, such as
+    /// `sales/scan_0042.pdf`.
+    Path,
+}
+
+/// What a file is, from its first bytes rather than its extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentType {
+    Pdf,
+    Xml,
+    Image,
+}
+
+impl ContentType {
+    const GROUPS: [(&'static str, ContentType); 3] = [
+        ("pdf", ContentType::Pdf),
+        ("xml", ContentType::Xml),
+        ("image", ContentType::Image),
+    ];
+
+    /// Recognizes PDFs, XML documents and the common image formats from the
+    /// start of a file.
+    pub fn detect(head: &[u8]) -> Option<ContentType> {
+        let text = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+        let text = &text[text.iter().take_while(|b| b.is_ascii_whitespace()).count()..];
+        if head.starts_with(b"%PDF-") {
+            Some(ContentType::Pdf)
+        } else if text.starts_with(b"<?xml")
+            || (text.first() == Some(&b'<') && text.get(1).is_some_and(u8::is_ascii_alphabetic))
+        {
+            Some(ContentType::Xml)
+        } else if [
+            &b"\x89PNG"[..],
+            b"\xFF\xD8\xFF",
+            b"GIF8",
+            b"II*\0",
+            b"MM\0*",
+        ]
+        .iter()
+        .any(|magic| head.starts_with(magic))
+            || (head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP"))
+            || matches!(
+                head.get(4..12),
+                Some(b"ftypheic" | b"ftypheix" | b"ftypmif1")
+            )
+        {
+            Some(ContentType::Image)
+        } else {
+            None
+        }
+    }
+}
+
+/// What is known about a file besides its name. The caller fills it in, so
+/// that deciding what to do with a file needs no filesystem access.
+#[derive(Clone, Debug, Default)]
+pub struct Metadata {
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+    /// The path relative to the watch directory, with `/` between
+    /// components. Rules with `match_on = path` match the filename when it's
+    /// missing.
+    pub relative_path: Option<String>,
+    /// Rules in a `[translations.<type>]` group only match files of that
+    /// type.
+    pub content_type: Option<ContentType>,
+    /// The sender's address for emails saved as `.eml` files, matched by
+    /// `sender_pattern` and available as the `sender` and `sender_domain`
+    /// fields.
+    pub sender: Option<String>,
+    /// When the file is handled, in the configured timezone, for the
+    /// `datetime` field and the `date` field of rules that don't capture the
+    /// invoice's own date.
+    pub received: Option<DateTime<FixedOffset>>,
+}
+
+/// Two rules that match the same files and rename them differently, where
+/// the earlier one always wins.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub rule: String,
+    pub earlier: String,
+    /// A few of the files, as paths relative to the watch directory.
+    pub examples: Vec<String>,
+}
+
+/// What the rules say should happen to a file.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// No rule matches the filename.
+    Unmatched,
+    /// The first matching rule leaves the filename as it is.
+    Keep {
+        rule: String,
+        fields: BTreeMap<String, String>,
+    },
+    /// The first matching rule gives the file a new name.
+    Rename {
+        rule: String,
+        new_name: String,
+        fields: BTreeMap<String, String>,
+    },
+}
+
+/// The rule that matched a filename, with the groups it captured.
+pub struct RuleMatch<'r, 'h> {
+    /// What the rule matched: the filename, or the relative path for rules
+    /// with `match_on = path`.
+    pub filename: &'h str,
+    pub regex: &'r Regex,
+    pub replacement: &'r str,
+    pub captures: Captures<'h>,
+    /// Fields known about the file itself, such as the `sender` of an email.
+    /// A group of the same name takes precedence.
+    pub file_fields: BTreeMap<String, String>,
+}
+
+impl RuleMatch<'_, '_> {
+    /// The filename with the matched part replaced, as
+    /// [`Regex::replace`] would. For a matched path only the part after the
+    /// last `/` is kept, as files are renamed in their directory.
+    pub fn new_name(&self) -> String {
+        let whole = self.captures.get(0).expect("group 0 always participates");
+        let mut new_name = self.filename[..whole.start()].to_string();
+        template::expand(
+            self.replacement,
+            &self.captures,
+            &self.file_fields,
+            &mut new_name,
+        );
+        new_name.push_str(&self.filename[whole.end()..]);
+        match new_name.rfind('/') {
+            Some(i) => new_name[i + 1..].to_string(),
+            None => new_name,
+        }
+    }
+
+    /// The named capture groups that participated in the match, and the
+    /// fields known about the file.
+    pub fn fields(&self) -> BTreeMap<String, String> {
+        let mut fields = self.file_fields.clone();
+        fields.extend(self.regex.capture_names().flatten().filter_map(|name| {
+            let value = self.captures.name(name)?;
+            Some((name.to_string(), value.as_str().to_string()))
+        }));
+        fields
+    }
+}
+
+impl RuleSet {
+    /// Reads the `[translations]` section of the config file, followed by
+    /// the `[translations.<type>]` groups and `[rule.<name>]` sections in the
+    /// order they appear, and then those of the cached `[remote_rules]`. A
+    /// config without any has no rules.
+    pub fn load(config_path: &Path) -> Result<Self, RuleError> {
+        let ini = config::load(config_path)?;
+
+        let defaults = match ini.section(Some("months")) {
+            Some(section) => DateFormat::with_months(
+                dates::load_month_names(section).map_err(ConfigError::Invalid)?,
+            ),
+            None => DateFormat::default(),
+        };
+        let dates = match ini.section(Some("settings")) {
+            Some(section) => dates::load_date_format(section, &defaults, "settings")
+                .map_err(ConfigError::Invalid)?,
+            None => defaults,
+        };
+        let (mut rules, mut type_locks) = load_sections(&ini, &dates)?;
+        if let Some(text) = remote_rules::cached(&ini).map_err(ConfigError::Invalid)? {
+            let remote = ini::Ini::load_from_str(&text)
+                .map_err(|e| ConfigError::Invalid(format!("Invalid remote rules: {}", e)))?;
+            let (remote_rules, remote_locks) = load_sections(&remote, &dates)?;
+            info!(rules = remote_rules.len(), "Loaded remote rules");
+            rules.extend(remote_rules);
+            type_locks.extend(remote_locks);
+        }
+
+        let mut rules = RuleSet::from_rules(rules);
+        rules.type_locks = type_locks;
+        for (regex, replacement) in rules.iter() {
+            info!(rule = regex.as_str(), replacement, "Loaded rule");
+        }
+        for conflict in rules.conflicts([]) {
+            warn!(
+                rule = conflict.rule,
+                earlier = conflict.earlier,
+                examples = ?conflict.examples,
+                "Rule overlaps an earlier rule, which renames these files instead"
+            );
+        }
+
+        Ok(rules)
+    }
+
+    /// Compiles `(pattern, replacement)` pairs, failing on the first invalid
+    /// pattern.
+    pub fn parse<'a>(
+        rules: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self, RuleError> {
+        Ok(RuleSet::from_rules(parse_rules(rules)?))
+    }
+
+    fn from_rules(rules: Vec<Rule>) -> Self {
+        let set = RegexSet::new(rules.iter().map(|rule| rule.regex.as_str())).ok();
+        let matches_paths = rules.iter().any(|rule| rule.match_on == MatchOn::Path);
+        RuleSet {
+            rules,
+            set,
+            matches_paths,
+            type_locks: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Regex, &str)> {
+        self.rules
+            .iter()
+            .map(|rule| (&rule.regex, rule.replacement.as_str()))
+    }
+
+    /// Finds the rules whose files an earlier rule renames instead, testing
+    /// samples made from every pattern and the files in `corpus` (paths
+    /// relative to the watch directory). Earlier rules that also need a
+    /// content pattern, a sender or a content type the later one doesn't
+    /// let files through to it, so they are left out; so are rules that
+    /// would give the same name, and exceptions put before a more general
+    /// rule, whose samples all match the later rule as well. Overlaps no
+    /// sample shows aren't found.
+    pub fn conflicts<'a>(&self, corpus: impl IntoIterator<Item = &'a str>) -> Vec<Conflict> {
+        let metadata = Metadata::default();
+        let matches = |rule: &Rule, file: &str| {
+            let filename = file.rsplit('/').next().unwrap_or(file);
+            rule.captures(filename, file, &metadata).is_some()
+        };
+        let samples: Vec<Vec<String>> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let mut samples = conflicts::samples(rule.regex.as_str());
+                samples.retain(|sample| matches(rule, sample));
+                samples
+            })
+            .collect();
+        let mut files: Vec<String> = samples.iter().flatten().cloned().collect();
+        files.extend(corpus.into_iter().map(str::to_string));
+
+        let mut found: BTreeMap<(usize, usize), Vec<String>> = BTreeMap::new();
+        for file in &files {
+            let filename = file.rsplit('/').next().unwrap_or(file);
+            let by_name = self.set.as_ref().map(|set| set.matches(filename));
+            let by_path = self.set.as_ref().map(|set| set.matches(file));
+            let matches: Vec<(usize, String)> = self
+                .rules
+                .iter()
+                .enumerate()
+                .filter(|(i, rule)| match (rule.match_on, &by_name, &by_path) {
+                    (MatchOn::Filename, Some(by_name), _) => by_name.matched(*i),
+                    (MatchOn::Path, _, Some(by_path)) => by_path.matched(*i),
+                    _ => true,
+                })
+                .filter_map(|(i, rule)| {
+                    let matched = rule.captures(filename, file, &metadata)?;
+                    Some((i, matched.new_name()))
+                })
+                .collect();
+            for (k, (later, new_name)) in matches.iter().enumerate() {
+                let shadowing = matches[..k].iter().find(|(earlier, _)| {
+                    self.rules[*earlier].always_wins_over(&self.rules[*later])
+                });
+                let Some((earlier, earlier_name)) = shadowing else {
+                    continue;
+                };
+                if earlier_name == new_name {
+                    continue;
+                }
+                let examples = found.entry((*earlier, *later)).or_default();
+                if examples.len() < 3 && !examples.contains(file) {
+                    examples.push(file.clone());
+                }
+            }
+        }
+
+        found
+            .into_iter()
+            .filter(|((earlier, later), _)| {
+                let exception = !samples[*earlier].is_empty()
+                    && samples[*earlier]
+                        .iter()
+                        .all(|sample| matches(&self.rules[*later], sample));
+                !exception
+            })
+            .map(|((earlier, later), examples)| Conflict {
+                rule: self.rules[later].regex.as_str().to_string(),
+                earlier: self.rules[earlier].regex.as_str().to_string(),
+                examples,
+            })
+            .collect()
+    }
+
+    /// The lock retries for a file, from the first rule whose pattern and
+    /// group match it, which is all that is known before it is unlocked,
+    /// and then from `[locks.<type>]` for its content type.
+    pub fn lock_retry(&self, filename: &str, metadata: &Metadata) -> LockRetry {
+        let path = metadata.relative_path.as_deref().unwrap_or(filename);
+        let by_rule = self
+            .rules
+            .iter()
+            .find(|rule| {
+                let haystack = match rule.match_on {
+                    MatchOn::Filename => filename,
+                    MatchOn::Path => path,
+                };
+                (rule.content_type.is_none() || rule.content_type == metadata.content_type)
+                    && rule.regex.is_match(haystack)
+            })
+            .map(|rule| rule.lock)
+            .unwrap_or_default();
+        let by_type = self
+            .type_locks
+            .iter()
+            .find(|(content_type, _)| Some(*content_type) == metadata.content_type)
+            .map(|(_, lock)| *lock)
+            .unwrap_or_default();
+        by_rule.or(by_type)
+    }
+
+    /// Decides what to do with a file from its name and metadata alone.
+    /// Rules with a `content_pattern` are skipped.
+    pub fn decide(&self, filename: &str, metadata: &Metadata) -> Decision {
+        self.decide_with_content(filename, metadata, || None)
+    }
+
+    /// Like [`RuleSet::decide`], with `content` giving the file's text for
+    /// rules with a `content_pattern`. It is only called once such a rule
+    /// matches the filename, and at most once.
+    pub fn decide_with_content(
+        &self,
+        filename: &str,
+        metadata: &Metadata,
+        content: impl FnOnce() -> Option<String>,
+    ) -> Decision {
+        let Some(matched) = self.find_with_content(
+            filename,
+            metadata.relative_path.as_deref(),
+            metadata,
+            content,
+        ) else {
+            return Decision::Unmatched;
+        };
+
+        let rule = matched.regex.as_str().to_string();
+        let fields = matched.fields();
+        let new_name = matched.new_name();
+        if new_name == filename {
+            Decision::Keep { rule, fields }
+        } else {
+            Decision::Rename {
+                rule,
+                new_name,
+                fields,
+            }
+        }
+    }
+
+    /// The first rule matching `filename`, with rules that match on the
+    /// path matching the filename too.
+    pub fn find<'h>(&self, filename: &'h str) -> Option<RuleMatch<'_, 'h>> {
+        self.find_file(filename, None)
+    }
+
+    /// The first rule matching a file, by its name or by its path relative
+    /// to the watch directory as each rule says. Rules limited to a content
+    /// type or a sender are skipped.
+    pub fn find_file<'h>(
+        &self,
+        filename: &'h str,
+        relative_path: Option<&'h str>,
+    ) -> Option<RuleMatch<'_, 'h>> {
+        self.find_with_content(filename, relative_path, &Metadata::default(), || None)
+    }
+
+    /// `metadata` gives the content type and sender, and `relative_path` is
+    /// passed apart so matches can borrow it.
+    fn find_with_content<'h>(
+        &self,
+        filename: &'h str,
+        relative_path: Option<&'h str>,
+        metadata: &Metadata,
+        content: impl FnOnce() -> Option<String>,
+    ) -> Option<RuleMatch<'_, 'h>> {
+        let path = relative_path.unwrap_or(filename);
+        let mut content = Some(content);
+        let mut text: Option<Option<String>> = None;
+        let mut content_matches = |rule: &Rule| {
+            if rule.content_type.is_some() && rule.content_type != metadata.content_type {
+                return false;
+            }
+            if let Some(pattern) = &rule.sender_pattern {
+                if !metadata
+                    .sender
+                    .as_deref()
+                    .is_some_and(|sender| pattern.is_match(sender))
+                {
+                    return false;
+                }
+            }
+            let Some(pattern) = &rule.content_pattern else {
+                return true;
+            };
+            let text = text.get_or_insert_with(|| content.take().and_then(|content| content()));
+            text.as_deref().is_some_and(|text| pattern.is_match(text))
+        };
+
+        if let Some(set) = &self.set {
+            let by_name = set.matches(filename);
+            let by_path = (self.matches_paths && path != filename).then(|| set.matches(path));
+            return self
+                .rules
+                .iter()
+                .enumerate()
+                .filter(|(i, rule)| match (rule.match_on, &by_path) {
+                    (MatchOn::Path, Some(by_path)) => by_path.matched(*i),
+                    _ => by_name.matched(*i),
+                })
+                .find(|(_, rule)| content_matches(rule))
+                .and_then(|(_, rule)| rule.captures(filename, path, metadata));
+        }
+
+        self.rules
+            .iter()
+            .filter_map(|rule| Some((rule, rule.captures(filename, path, metadata)?)))
+            .find(|(rule, _)| content_matches(rule))
+            .map(|(_, matched)| matched)
+    }
+}
+
+impl Rule {
+    /// Whether every file `later` matches by name that this rule matches by
+    /// name goes to this rule.
+    fn always_wins_over(&self, later: &Rule) -> bool {
+        self.content_pattern.is_none()
+            && self.sender_pattern.is_none()
+            && (self.content_type.is_none() || self.content_type == later.content_type)
+    }
+
+    fn captures<'r, 'h>(
+        &'r self,
+        filename: &'h str,
+        path: &'h str,
+        metadata: &Metadata,
+    ) -> Option<RuleMatch<'r, 'h>> {
+        let haystack = match self.match_on {
+            MatchOn::Filename => filename,
+            MatchOn::Path => path,
+        };
+        self.regex.captures(haystack).map(|captures| RuleMatch {
+            filename: haystack,
+            regex: &self.regex,
+            replacement: &self.replacement,
+            file_fields: self.file_fields(metadata, &captures),
+            captures,
+        })
+    }
+
+    /// The fields known about the file, with dates as the rule writes them.
+    fn file_fields(&self, metadata: &Metadata, captures: &Captures) -> BTreeMap<String, String> {
+        let mut fields = BTreeMap::new();
+        if let Some(sender) = &metadata.sender {
+            if let Some((_, domain)) = sender.rsplit_once('@') {
+                fields.insert("sender_domain".to_string(), domain.to_string());
+            }
+            fields.insert("sender".to_string(), sender.clone());
+        }
+        if let Some(received) = &metadata.received {
+            fields.insert("date".to_string(), self.dates.date(received.date_naive()));
+            fields.insert("datetime".to_string(), self.dates.datetime(received));
+        }
+        if let Some(date) = invoice_date(captures, &self.dates) {
+            fields.insert("date".to_string(), self.dates.date(date));
+        }
+        fields
+    }
+}
+
+/// The date from `year`, `month` and `day` groups, with two-digit years in
+/// this century. The month can be a name, as [`DateFormat::month`] reads it.
+fn invoice_date(captures: &Captures, dates: &DateFormat) -> Option<NaiveDate> {
+    let number = |name| captures.name(name)?.as_str().parse::<u32>().ok();
+    let year = match number("year")? {
+        year @ 0..=99 => year + 2000,
+        year => year,
+    };
+    let month = dates.month(captures.name("month")?.as_str())?;
+    NaiveDate::from_ymd_opt(year as i32, month, number("day")?)
+}
+
+/// The rules of the `[translations]`, `[translations.<type>]` and
+/// `[rule.<name>]` sections and the `[locks.<type>]` settings of `ini`.
+fn load_sections(ini: &ini::Ini, dates: &DateFormat) -> Result<(Vec<Rule>, TypeLocks), RuleError> {
+    let mut rules = match ini.section(Some("translations")) {
+        Some(section) => parse_rules(section.iter())?,
+        None => Vec::new(),
+    };
+    for rule in &mut rules {
+        rule.dates = dates.clone();
+    }
+    let mut type_locks = Vec::new();
+
+    for (name, section) in ini.iter() {
+        let Some(name) = name else {
+            continue;
+        };
+        if let Some(group) = name.strip_prefix("translations.") {
+            let content_type = group_type(group, name)?;
+            for mut rule in parse_rules(section.iter())? {
+                rule.content_type = Some(content_type);
+                rule.dates = dates.clone();
+                rules.push(rule);
+            }
+        } else if let Some(name) = name.strip_prefix("rule.") {
+            rules.push(parse_rule_section(name, section, dates)?);
+        } else if let Some(group) = name.strip_prefix("locks.") {
+            type_locks.push((group_type(group, name)?, load_lock_retry(section, name)?));
+        }
+    }
+    Ok((rules, type_locks))
+}
+
+/// Checks that `text` is a config whose rules all load, as fetched by
+/// `[remote_rules]`, and counts them.
+pub(crate) fn count_rules(text: &str) -> Result<usize, String> {
+    let ini = ini::Ini::load_from_str(text).map_err(|e| e.to_string())?;
+    let (rules, _) = load_sections(&ini, &DateFormat::default()).map_err(|e| e.to_string())?;
+    Ok(rules.len())
+}
+
+fn parse_rules<'a>(
+    rules: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<Vec<Rule>, RuleError> {
+    rules
+        .into_iter()
+        .map(|(pattern, replacement)| compile(pattern, replacement, MatchOn::Filename))
+        .collect()
+}
+
+/// A rule with options, from `[rule.<name>]`. `date_format` and `locale`
+/// default to `defaults` from `[settings]`.
+fn parse_rule_section(
+    name: &str,
+    section: &ini::Properties,
+    defaults: &DateFormat,
+) -> Result<Rule, RuleError> {
+    let missing = |key| ConfigError::Invalid(format!("Missing '{}' in [rule.{}]", key, name));
+    let pattern = section.get("pattern").ok_or_else(|| missing("pattern"))?;
+    let replacement = section
+        .get("replacement")
+        .ok_or_else(|| missing("replacement"))?;
+
+    let match_on = match section.get("match_on").unwrap_or("filename") {
+        "filename" => MatchOn::Filename,
+        "path" => MatchOn::Path,
+        other => {
+            return Err(ConfigError::Invalid(format!(
+                "Invalid match_on '{}' in [rule.{}], use filename or path",
+                other, name
+            ))
+            .into())
+        }
+    };
+
+    let mut rule = compile(pattern, replacement, match_on)?;
+    rule.content_pattern = optional_pattern(section, "content_pattern")?;
+    rule.sender_pattern = optional_pattern(section, "sender_pattern")?;
+    rule.dates = dates::load_date_format(section, defaults, &format!("rule.{}", name))
+        .map_err(ConfigError::Invalid)?;
+    rule.lock = load_lock_retry(section, &format!("rule.{}", name))?;
+    Ok(rule)
+}
+
+/// The content type of a `[translations.<type>]` or `[locks.<type>]`
+/// section.
+fn group_type(group: &str, section_name: &str) -> Result<ContentType, RuleError> {
+    ContentType::GROUPS
+        .iter()
+        .find(|(name, _)| *name == group)
+        .map(|(_, content_type)| *content_type)
+        .ok_or_else(|| {
+            ConfigError::Invalid(format!(
+                "Unknown group [{}], use pdf, xml or image",
+                section_name
+            ))
+            .into()
+        })
+}
+
+fn load_lock_retry(section: &ini::Properties, section_name: &str) -> Result<LockRetry, RuleError> {
+    let invalid = |key, e: std::num::ParseIntError| {
+        ConfigError::Invalid(format!("Invalid {} in [{}]: {}", key, section_name, e))
+    };
+    Ok(LockRetry {
+        max_retries: section
+            .get("max_lock_retries")
+            .map(|value| value.parse().map_err(|e| invalid("max_lock_retries", e)))
+            .transpose()?,
+        delay_ms: section
+            .get("lock_retry_delay_ms")
+            .map(|value| value.parse().map_err(|e| invalid("lock_retry_delay_ms", e)))
+            .transpose()?,
+    })
+}
+
+fn optional_pattern(section: &ini::Properties, key: &str) -> Result<Option<Regex>, RuleError> {
+    section
+        .get(key)
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|source| RuleError::InvalidPattern {
+                pattern: pattern.to_string(),
+                source,
+            })
+        })
+        .transpose()
+}
+
+fn compile(pattern: &str, replacement: &str, match_on: MatchOn) -> Result<Rule, RuleError> {
+    template::check(replacement).map_err(|e| {
+        ConfigError::Invalid(format!("Invalid replacement '{}': {}", replacement, e))
+    })?;
+    Regex::new(pattern)
+        .map(|regex| Rule {
+            regex,
+            replacement: replacement.to_string(),
+            match_on,
+            content_pattern: None,
+            content_type: None,
+            sender_pattern: None,
+            dates: DateFormat::default(),
+            lock: LockRetry::default(),
+        })
+        .map_err(|source| RuleError::InvalidPattern {
+            pattern: pattern.to_string(),
+            source,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Rules = Vec<(&'static str, &'static str)>;
+
+    fn fields(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn rename(rule: &str, new_name: &str, captured: &[(&str, &str)]) -> Decision {
+        Decision::Rename {
+            rule: rule.to_string(),
+            new_name: new_name.to_string(),
+            fields: fields(captured),
+        }
+    }
+
+    fn keep(rule: &str, captured: &[(&str, &str)]) -> Decision {
+        Decision::Keep {
+            rule: rule.to_string(),
+            fields: fields(captured),
+        }
+    }
+
+    const ACME: (&str, &str) = (r"^acme_(?P<number>\d+)\.pdf$", "Acme_Invoice_${number}.pdf");
+    const DATED: (&str, &str) = (
+        r"invoice_(\d{4})_(\d{2})_(\d{2})_(.+)\.pdf",
+        "Invoice_${4}_${1}-${2}-${3}.pdf",
+    );
+    const ANY_PDF: (&str, &str) = (r"^(?P<name>.+)\.pdf$", "Other_${name}.pdf");
+
+    #[test]
+    fn decide() {
+        let cases: Vec<(&str, Rules, &str, Decision)> = vec![
+            ("no rules", vec![], "acme_1.pdf", Decision::Unmatched),
+            (
+                "no matching rule",
+                vec![ACME, DATED],
+                "acme_1.txt",
+                Decision::Unmatched,
+            ),
+            (
+                "named capture",
+                vec![ACME],
+                "acme_42.pdf",
+                rename(ACME.0, "Acme_Invoice_42.pdf", &[("number", "42")]),
+            ),
+            (
+                "numbered captures",
+                vec![DATED],
+                "invoice_2024_03_15_acme.pdf",
+                rename(DATED.0, "Invoice_acme_2024-03-15.pdf", &[]),
+            ),
+            (
+                "first matching rule wins",
+                vec![ACME, ANY_PDF],
+                "acme_7.pdf",
+                rename(ACME.0, "Acme_Invoice_7.pdf", &[("number", "7")]),
+            ),
+            (
+                "later rule when earlier ones don't match",
+                vec![ACME, ANY_PDF],
+                "globex.pdf",
+                rename(ANY_PDF.0, "Other_globex.pdf", &[("name", "globex")]),
+            ),
+            (
+                "unanchored match keeps the rest of the name",
+                vec![DATED],
+                "scan-invoice_2024_03_15_acme.pdf.bak",
+                rename(DATED.0, "scan-Invoice_acme_2024-03-15.pdf.bak", &[]),
+            ),
+            (
+                "rule that keeps the name",
+                vec![(r"^(?P<name>Acme_.+)$", "${name}")],
+                "Acme_Invoice_42.pdf",
+                keep(r"^(?P<name>Acme_.+)$", &[("name", "Acme_Invoice_42.pdf")]),
+            ),
+            (
+                "optional group that didn't participate",
+                vec![(
+                    r"^(?P<vendor>[a-z]+)(?:_(?P<number>\d+))?\.pdf$",
+                    "${vendor}.pdf",
+                )],
+                "acme.pdf",
+                keep(
+                    r"^(?P<vendor>[a-z]+)(?:_(?P<number>\d+))?\.pdf$",
+                    &[("vendor", "acme")],
+                ),
+            ),
+            (
+                "non-ASCII filename",
+                vec![ANY_PDF],
+                "Müller_Rechnung.pdf",
+                rename(
+                    ANY_PDF.0,
+                    "Other_Müller_Rechnung.pdf",
+                    &[("name", "Müller_Rechnung")],
+                ),
+            ),
+            (
+                "literal dollar sign in the replacement",
+                vec![(r"^price_(\d+)\.pdf$", "price_$$${1}.pdf")],
+                "price_10.pdf",
+                rename(r"^price_(\d+)\.pdf$", "price_$10.pdf", &[]),
+            ),
+            (
+                "uppercased vendor code",
+                vec![(r"^(?P<vendor>[a-z]+)_(\d+)\.pdf$", r"\U${vendor}\E_$2.pdf")],
+                "acme_42.pdf",
+                rename(
+                    r"^(?P<vendor>[a-z]+)_(\d+)\.pdf$",
+                    "ACME_42.pdf",
+                    &[("vendor", "acme")],
+                ),
+            ),
+            (
+                "lowercase and title case up to the end",
+                vec![(r"^(\w+)-(.+)\.PDF$", r"\L${1}_\T$2.pdf")],
+                "INV-ACME corp_o'brien.PDF",
+                rename(r"^(\w+)-(.+)\.PDF$", "inv_Acme Corp_O'Brien.Pdf", &[]),
+            ),
+            (
+                "filters",
+                vec![(
+                    r"^(?P<vendor>[A-Za-z]+)_(?P<number>\d+)(?:_(?P<year>\d{4}))?\.pdf$",
+                    "${vendor|lower|slice:0:4}_${number|pad:8}_${year|default:na}.pdf",
+                )],
+                "AcmeCorp_42.pdf",
+                rename(
+                    r"^(?P<vendor>[A-Za-z]+)_(?P<number>\d+)(?:_(?P<year>\d{4}))?\.pdf$",
+                    "acme_00000042_na.pdf",
+                    &[("vendor", "AcmeCorp"), ("number", "42")],
+                ),
+            ),
+            (
+                "other backslashes are kept",
+                vec![(r"^(\d+)\.pdf$", r"a\b\$1.pdf")],
+                "7.pdf",
+                rename(r"^(\d+)\.pdf$", r"a\b\7.pdf", &[]),
+            ),
+        ];
+
+        for (description, rules, filename, expected) in cases {
+            let rules = RuleSet::parse(rules).unwrap();
+            assert_eq!(
+                rules.decide(filename, &Metadata::default()),
+                expected,
+                "{}",
+                description
+            );
+        }
+    }
+
+    #[test]
+    fn match_on_path() {
+        const DEPARTMENT: &str = r"^(?P<department>\w+)/scan_(?P<number>\d+)\.pdf$";
+        let rules = RuleSet::from_rules(vec![
+            compile(
+                DEPARTMENT,
+                "${department}_Invoice_${number}.pdf",
+                MatchOn::Path,
+            )
+            .unwrap(),
+            compile(r"scan_(\d+)", "Scan_$1", MatchOn::Path).unwrap(),
+            compile(
+                r"^(?P<name>.+)\.xml$",
+                "EInvoice_${name}.xml",
+                MatchOn::Filename,
+            )
+            .unwrap(),
+        ]);
+        let decide = |filename: &str, relative_path: Option<&str>| {
+            let metadata = Metadata {
+                relative_path: relative_path.map(str::to_string),
+                ..Metadata::default()
+            };
+            rules.decide(filename, &metadata)
+        };
+
+        assert_eq!(
+            decide("scan_7.pdf", Some("sales/scan_7.pdf")),
+            rename(
+                DEPARTMENT,
+                "sales_Invoice_7.pdf",
+                &[("department", "sales"), ("number", "7")]
+            ),
+            "captures from the directory"
+        );
+        assert_eq!(
+            decide("scan_7.tif", Some("a/b/scan_7.tif")),
+            rename(r"scan_(\d+)", "Scan_7.tif", &[]),
+            "directories are dropped from the new name"
+        );
+        assert_eq!(
+            decide("scan_7.pdf", None),
+            rename(r"scan_(\d+)", "Scan_7.pdf", &[]),
+            "the filename without a path"
+        );
+        assert_eq!(
+            decide("x.xml", Some("sales/x.xml")),
+            rename(r"^(?P<name>.+)\.xml$", "EInvoice_x.xml", &[("name", "x")]),
+            "filename rules ignore the path"
+        );
+    }
+
+    #[test]
+    fn content_pattern() {
+        const SCAN: &str = r"^scan_(\d+)\.pdf$";
+        let with_content = |replacement, content_pattern| {
+            let mut rule = compile(SCAN, replacement, MatchOn::Filename).unwrap();
+            rule.content_pattern = Some(Regex::new(content_pattern).unwrap());
+            rule
+        };
+        let rules = RuleSet::from_rules(vec![
+            compile(r"^acme_(\d+)\.pdf$", "Acme_$1.pdf", MatchOn::Filename).unwrap(),
+            with_content("Acme_$1.pdf", r"DE123456789"),
+            with_content("Globex_$1.pdf", r"DE987654321"),
+        ]);
+
+        let calls = std::cell::Cell::new(0);
+        let decide = |filename: &str, text: Option<&str>| {
+            rules.decide_with_content(filename, &Metadata::default(), || {
+                calls.set(calls.get() + 1);
+                text.map(str::to_string)
+            })
+        };
+
+        assert_eq!(
+            decide("scan_1.pdf", Some("VAT ID DE987654321")),
+            rename(SCAN, "Globex_1.pdf", &[])
+        );
+        assert_eq!(calls.get(), 1, "text is extracted once");
+        assert_eq!(
+            decide("scan_1.pdf", Some("VAT ID DE123456789")),
+            rename(SCAN, "Acme_1.pdf", &[])
+        );
+        assert_eq!(decide("scan_1.pdf", None), Decision::Unmatched);
+        calls.set(0);
+        assert_eq!(
+            decide("acme_1.pdf", None),
+            rename(r"^acme_(\d+)\.pdf$", "Acme_1.pdf", &[])
+        );
+        assert_eq!(calls.get(), 0, "no text needed");
+    }
+
+    #[test]
+    fn content_type() {
+        let cases: [(&[u8], Option<ContentType>); 9] = [
+            (b"%PDF-1.7\n", Some(ContentType::Pdf)),
+            (b"<?xml version=\"1.0\"?>", Some(ContentType::Xml)),
+            (b"\xEF\xBB\xBF\n  <Invoice xmlns=", Some(ContentType::Xml)),
+            (b"\x89PNG\r\n\x1a\n", Some(ContentType::Image)),
+            (b"\xFF\xD8\xFF\xE0", Some(ContentType::Image)),
+            (b"II*\0\x08\0", Some(ContentType::Image)),
+            (b"RIFF\0\0\0\0WEBPVP8 ", Some(ContentType::Image)),
+            (b"<3 invoices", None),
+            (b"PK\x03\x04", None),
+        ];
+        for (head, expected) in cases {
+            assert_eq!(ContentType::detect(head), expected, "{:?}", head);
+        }
+
+        let mut xml = compile(r"^(.+)\.dat$", "EInvoice_$1.xml", MatchOn::Filename).unwrap();
+        xml.content_type = Some(ContentType::Xml);
+        let rules = RuleSet::from_rules(vec![
+            xml,
+            compile(r"^(.+)\.dat$", "Other_$1.dat", MatchOn::Filename).unwrap(),
+        ]);
+        let decide = |content_type| {
+            let metadata = Metadata {
+                content_type,
+                ..Metadata::default()
+            };
+            rules.decide("x.dat", &metadata)
+        };
+        assert_eq!(
+            decide(Some(ContentType::Xml)),
+            rename(r"^(.+)\.dat$", "EInvoice_x.xml", &[])
+        );
+        assert_eq!(
+            decide(Some(ContentType::Pdf)),
+            rename(r"^(.+)\.dat$", "Other_x.dat", &[])
+        );
+        assert_eq!(decide(None), rename(r"^(.+)\.dat$", "Other_x.dat", &[]));
+    }
+
+    #[test]
+    fn sender() {
+        let mut acme = compile(
+            r"^(?P<name>.+)\.eml$",
+            "${sender_domain}_${name}.eml",
+            MatchOn::Filename,
+        )
+        .unwrap();
+        acme.sender_pattern = Some(Regex::new(r"@acme\.example$").unwrap());
+        let rules = RuleSet::from_rules(vec![
+            acme,
+            compile(r"^(.+)\.eml$", "Mail_${1}_$sender.eml", MatchOn::Filename).unwrap(),
+        ]);
+        let decide = |sender: Option<&str>| {
+            let metadata = Metadata {
+                sender: sender.map(str::to_string),
+                ..Metadata::default()
+            };
+            rules.decide("inv.eml", &metadata)
+        };
+
+        let acme_fields = [
+            ("name", "inv"),
+            ("sender", "billing@acme.example"),
+            ("sender_domain", "acme.example"),
+        ];
+        assert_eq!(
+            decide(Some("billing@acme.example")),
+            rename(r"^(?P<name>.+)\.eml$", "acme.example_inv.eml", &acme_fields)
+        );
+        assert_eq!(
+            decide(Some("ap@globex.example")),
+            rename(
+                r"^(.+)\.eml$",
+                "Mail_inv_ap@globex.example.eml",
+                &[
+                    ("sender", "ap@globex.example"),
+                    ("sender_domain", "globex.example")
+                ]
+            )
+        );
+        assert_eq!(decide(None), rename(r"^(.+)\.eml$", "Mail_inv_.eml", &[]));
+    }
+
+    #[test]
+    fn dates() {
+        let received = DateTime::parse_from_rfc3339("2024-03-31T23:30:00+02:00").unwrap();
+        let metadata = Metadata {
+            received: Some(received),
+            ..Metadata::default()
+        };
+        let new_name = |rule: (&str, &str), filename| {
+            let rules = RuleSet::parse([rule]).unwrap();
+            match rules.decide(filename, &metadata) {
+                Decision::Rename { new_name, .. } => new_name,
+                other => panic!("{}: {:?}", filename, other),
+            }
+        };
+
+        let received_date = (r"^scan_(\d+)\.pdf$", "${date}_${datetime}_$1.pdf");
+        assert_eq!(
+            new_name(received_date, "scan_7.pdf"),
+            "2024-03-31_2024-03-31_233000_7.pdf"
+        );
+
+        let invoice_date = (
+            r"^(?P<day>\d{2})\.(?P<month>\d{2})\.(?P<year>\d{2,4})\.pdf$",
+            "Invoice_${date}.pdf",
+        );
+        assert_eq!(
+            new_name(invoice_date, "15.01.24.pdf"),
+            "Invoice_2024-01-15.pdf"
+        );
+        assert_eq!(
+            new_name(invoice_date, "31.02.2024.pdf"),
+            "Invoice_2024-03-31.pdf",
+            "an invalid invoice date falls back to the received date"
+        );
+
+        let month_name = (
+            r"^(?P<day>\d{1,2})_(?P<month>[^_]+)_(?P<year>\d{4})\.pdf$",
+            "Invoice_${date}.pdf",
+        );
+        for (filename, expected) in [
+            ("3_März_2024.pdf", "Invoice_2024-03-03.pdf"),
+            ("3_Sept._2024.pdf", "Invoice_2024-09-03.pdf"),
+            ("3_juillet_2024.pdf", "Invoice_2024-07-03.pdf"),
+            ("3_juil_2024.pdf", "Invoice_2024-07-03.pdf"),
+            ("3_DIC_2024.pdf", "Invoice_2024-12-03.pdf"),
+            ("3_jui_2024.pdf", "Invoice_2024-03-31.pdf"),
+            ("3_sty_2024.pdf", "Invoice_2024-03-31.pdf"),
+        ] {
+            assert_eq!(new_name(month_name, filename), expected, "{}", filename);
+        }
+
+        let mut rule = compile(month_name.0, month_name.1, MatchOn::Filename).unwrap();
+        rule.dates = DateFormat::with_months(vec![("sty".to_string(), 1)]);
+        let rules = RuleSet::from_rules(vec![rule]);
+        assert_eq!(
+            rules
+                .find_with_content("3_sty_2024.pdf", None, &metadata, || None)
+                .map(|m| m.new_name()),
+            Some("Invoice_2024-01-03.pdf".to_string()),
+            "names from [months]"
+        );
+
+        let ini = ini::Ini::load_from_str("date_format = %-d. %B %Y\nlocale = de_DE").unwrap();
+        let mut rule = compile(r"^scan_(\d+)\.pdf$", "${date}_$1.pdf", MatchOn::Filename).unwrap();
+        rule.dates =
+            dates::load_date_format(ini.general_section(), &DateFormat::default(), "rule.x")
+                .unwrap();
+        let rules = RuleSet::from_rules(vec![rule]);
+        assert_eq!(
+            rules
+                .find_with_content("scan_7.pdf", None, &metadata, || None)
+                .map(|m| m.new_name()),
+            Some("31. März 2024_7.pdf".to_string())
+        );
+    }
+
+    #[test]
+    fn conflicts() {
+        let rules = RuleSet::parse([
+            (r"^inv_(\w+)\.pdf$", "Invoice_$1.pdf"),
+            (r"^inv_acme\.pdf$", "Acme.pdf"),
+            (r"^(?:bill|receipt)_(\d+)\.pdf$", "Bill_$1.pdf"),
+            (r"^receipt_(\d+)\.pdf$", "Bill_$1.pdf"),
+            (r"^order_(\d+)\.pdf$", "Order_$1.pdf"),
+            (r"(\d+)\.pdf$", "Scan_$1.pdf"),
+        ])
+        .unwrap();
+        let conflicts = rules.conflicts(["inv_acme.pdf"]);
+        let found: Vec<(&str, &str)> = conflicts
+            .iter()
+            .map(|c| (c.earlier.as_str(), c.rule.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (r"^inv_(\w+)\.pdf$", r"^inv_acme\.pdf$"),
+                (r"^inv_(\w+)\.pdf$", r"(\d+)\.pdf$"),
+            ],
+            "same names and exceptions are no conflicts"
+        );
+        assert_eq!(conflicts[0].examples, ["inv_acme.pdf"]);
+
+        // Samples of unanchored patterns don't show this one, files do.
+        let rules = RuleSet::parse([("acme", "Acme.pdf"), (r"^\w+\.pdf$", "Other.pdf")]).unwrap();
+        assert!(rules.conflicts([]).is_empty());
+        assert_eq!(
+            rules.conflicts(["scans/acme.pdf"])[0].examples,
+            ["scans/acme.pdf"]
+        );
+
+        let mut pdf_only = compile(r"^scan_(\d+)\.pdf$", "Scan_$1.pdf", MatchOn::Filename).unwrap();
+        pdf_only.content_type = Some(ContentType::Pdf);
+        let generic = compile(r"^scan_(\d+)\.pdf$", "Other_$1.pdf", MatchOn::Filename).unwrap();
+        let rules = RuleSet::from_rules(vec![pdf_only, generic]);
+        assert!(rules.conflicts([]).is_empty(), "other files fall through");
+    }
+
+    #[test]
+    fn lock_retry() {
+        let mut scans = compile(r"^scan_(\d+)\.pdf$", "Scan_$1.pdf", MatchOn::Filename).unwrap();
+        scans.lock = LockRetry {
+            max_retries: Some(240),
+            delay_ms: None,
+        };
+        let mut rules = RuleSet::from_rules(vec![
+            scans,
+            compile(r"^(\d+)\.\w+$", "Invoice_$1.pdf", MatchOn::Filename).unwrap(),
+        ]);
+        let xml = LockRetry {
+            max_retries: Some(1),
+            delay_ms: Some(100),
+        };
+        let pdf = LockRetry {
+            max_retries: Some(5),
+            delay_ms: Some(5000),
+        };
+        rules.type_locks = vec![(ContentType::Xml, xml), (ContentType::Pdf, pdf)];
+
+        let of_type = |content_type| Metadata {
+            content_type,
+            ..Metadata::default()
+        };
+        assert_eq!(
+            rules.lock_retry("scan_1.pdf", &of_type(Some(ContentType::Pdf))),
+            LockRetry {
+                max_retries: Some(240),
+                delay_ms: Some(5000),
+            },
+            "the rule's own setting comes first"
+        );
+        assert_eq!(
+            rules.lock_retry("42.xml", &of_type(Some(ContentType::Xml))),
+            xml
+        );
+        assert_eq!(
+            rules.lock_retry("42.txt", &of_type(None)),
+            LockRetry::default()
+        );
+    }
+
+    #[test]
+    fn many_rules() {
+        let patterns: Vec<(String, String)> = (0..400)
+            .map(|i| {
+                (
+                    format!(r"^vendor{}_(\d+)\.pdf$", i),
+                    format!("Vendor{}_$1.pdf", i),
+                )
+            })
+            .collect();
+        let rules = RuleSet::parse(patterns.iter().map(|(p, r)| (p.as_str(), r.as_str()))).unwrap();
+
+        let cases = [
+            ("vendor0_1.pdf", Some("Vendor0_1.pdf")),
+            ("vendor399_7.pdf", Some("Vendor399_7.pdf")),
+            ("vendor42_123.pdf", Some("Vendor42_123.pdf")),
+            ("vendor400_1.pdf", None),
+        ];
+        for (filename, expected) in cases {
+            let new_name = rules.find(filename).map(|matched| matched.new_name());
+            assert_eq!(new_name.as_deref(), expected, "{}", filename);
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let cases: Vec<(&str, Rules, Result<usize, &str>)> = vec![
+            ("empty", vec![], Ok(0)),
+            ("valid rules", vec![ACME, DATED, ANY_PDF], Ok(3)),
+            (
+                "invalid pattern",
+                vec![ACME, (r"acme_(\d+", "x")],
+                Err(r"Invalid regex pattern 'acme_(\d+'"),
+            ),
+            (
+                "unknown filter",
+                vec![(r"^(\d+)\.pdf$", "${1|pad:8|shout}.pdf")],
+                Err("Invalid replacement '${1|pad:8|shout}.pdf': Unknown filter 'shout'"),
+            ),
+            (
+                "pad as wide as a filename",
+                vec![(r"^(\d+)\.pdf$", "${1|pad:255}.pdf")],
+                Ok(1),
+            ),
+            (
+                "pad wider than a filename",
+                vec![(r"^(\d+)\.pdf$", "${1|pad:999999999999}.pdf")],
+                Err("Invalid replacement '${1|pad:999999999999}.pdf': Invalid pad width '999999999999', at most 255"),
+            ),
+        ];
+
+        for (description, rules, expected) in cases {
+            match (RuleSet::parse(rules), expected) {
+                (Ok(rules), Ok(len)) => assert_eq!(rules.len(), len, "{}", description),
+                (Err(e), Err(prefix)) => {
+                    assert!(e.to_string().starts_with(prefix), "{}: {}", description, e)
+                }
+                (Ok(_), Err(_)) => panic!("{}: parsed", description),
+                (Err(e), Ok(_)) => panic!("{}: {}", description, e),
+            }
+        }
+    }
+
+    #[test]
+    fn count_rules() {
+        let cases: Vec<(&str, &str, Result<usize, &str>)> = vec![
+            ("empty", "", Ok(0)),
+            (
+                "every section",
+                "[translations]\n^acme_(\\\\d+)\\\\.pdf$ = Acme_$1.pdf\n\n\
+                 [translations.xml]\n^(\\\\d+)\\\\.xml$ = E_$1.xml\n\n\
+                 [rule.scan]\npattern = ^scan_(\\\\d+)\\\\.pdf$\nreplacement = Scan_$1.pdf\n\n\
+                 [locks.pdf]\nmax_lock_retries = 5\n",
+                Ok(3),
+            ),
+            (
+                "invalid pattern",
+                "[translations]\nacme_(\\\\d+ = x\n",
+                Err(r"Invalid regex pattern 'acme_(\d+'"),
+            ),
+            (
+                "unknown group",
+                "[translations.doc]\n^(\\\\d+)\\\\.doc$ = $1.doc\n",
+                Err("Unknown"),
+            ),
+        ];
+
+        for (description, text, expected) in cases {
+            match (super::count_rules(text), expected) {
+                (Ok(count), Ok(expected)) => assert_eq!(count, expected, "{}", description),
+                (Err(e), Err(prefix)) => assert!(e.starts_with(prefix), "{}: {}", description, e),
+                (Ok(_), Err(_)) => panic!("{}: loaded", description),
+                (Err(e), Ok(_)) => panic!("{}: {}", description, e),
+            }
+        }
+    }
+}
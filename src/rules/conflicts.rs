@@ -0,0 +1,98 @@
+use regex_syntax::hir::{Class, Hir, HirKind};
+
+/// The most samples made for one pattern, so alternations and classes don't
+/// multiply without bound.
+const MAX_SAMPLES: usize = 8;
+
+/// Characters a sample prefers for a class, being likely in filenames.
+const PREFERRED: &str = "a0A_-.";
+
+/// A few strings the pattern can match, made from its structure: every
+/// branch of an alternation, a couple of characters of every class, and
+/// repetitions as short as they can be and with one repeat. Some may not
+/// match, e.g. because of a `\b`, so check them against the pattern.
+pub(super) fn samples(pattern: &str) -> Vec<String> {
+    match regex_syntax::parse(pattern) {
+        Ok(hir) => {
+            let mut samples = expand(&hir);
+            samples.sort();
+            samples.dedup();
+            samples
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+fn expand(hir: &Hir) -> Vec<String> {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => vec![String::new()],
+        HirKind::Literal(literal) => vec![String::from_utf8_lossy(&literal.0).into_owned()],
+        HirKind::Class(class) => class_samples(class).into_iter().map(String::from).collect(),
+        HirKind::Capture(capture) => expand(&capture.sub),
+        HirKind::Repetition(repetition) => {
+            let sub = expand(&repetition.sub);
+            let min = repetition.min as usize;
+            let mut counts = vec![min];
+            if repetition.max.is_none_or(|max| max as usize > min) {
+                counts.push(min.max(1));
+            }
+            counts.dedup();
+            counts
+                .into_iter()
+                .flat_map(|count| sub.iter().map(move |s| s.repeat(count)))
+                .take(MAX_SAMPLES)
+                .collect()
+        }
+        HirKind::Concat(subs) => subs.iter().fold(vec![String::new()], |prefixes, sub| {
+            let sub = expand(sub);
+            prefixes
+                .iter()
+                .flat_map(|prefix| sub.iter().map(move |s| format!("{}{}", prefix, s)))
+                .take(MAX_SAMPLES)
+                .collect()
+        }),
+        HirKind::Alternation(subs) => subs.iter().flat_map(expand).take(MAX_SAMPLES).collect(),
+    }
+}
+
+/// Up to two characters of the class, preferring the common ones.
+fn class_samples(class: &Class) -> Vec<char> {
+    let (contains, starts): (Box<dyn Fn(char) -> bool>, Vec<char>) = match class {
+        Class::Unicode(class) => (
+            Box::new(|c| {
+                class
+                    .ranges()
+                    .iter()
+                    .any(|r| r.start() <= c && c <= r.end())
+            }),
+            class.ranges().iter().map(|r| r.start()).collect(),
+        ),
+        Class::Bytes(class) => (
+            Box::new(|c| {
+                c.is_ascii()
+                    && class
+                        .ranges()
+                        .iter()
+                        .any(|r| r.start() <= c as u8 && c as u8 <= r.end())
+            }),
+            class
+                .ranges()
+                .iter()
+                .map(|r| r.start())
+                .filter(u8::is_ascii)
+                .map(char::from)
+                .collect(),
+        ),
+    };
+    let mut chars = Vec::new();
+    let printable = starts.iter().copied().filter(|c| c.is_ascii_graphic());
+    for c in PREFERRED.chars().filter(|&c| contains(c)).chain(printable) {
+        if !chars.contains(&c) && chars.len() < 2 {
+            chars.push(c);
+        }
+    }
+    if chars.is_empty() {
+        chars.extend(starts.first());
+    }
+    chars
+}
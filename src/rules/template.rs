@@ -0,0 +1,142 @@
+use crate::filters;
+use regex::Captures;
+use std::collections::BTreeMap;
+
+/// How text is written until the next `\E`, or the next case escape.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Case {
+    AsIs,
+    /// `\U`
+    Upper,
+    /// `\L`
+    Lower,
+    /// `\T`: the first letter of every word uppercased, the rest lowercased.
+    Title,
+}
+
+/// Expands a rule's replacement into `out`: `$name` and `${name}` stand for
+/// the group of that name or number, or else for the field of that name
+/// known about the file, such as `sender`, and `$$` for a literal `$`.
+/// Unknown names expand to nothing, as with [`Captures::expand`]. Filters
+/// follow the name in braces, as in `${number|pad:8}`.
+///
+/// `\U`, `\L` and `\T` upper-, lower- or title-case what follows, groups and
+/// literal text alike, up to `\E` or the end. Any other backslash is kept.
+pub(super) fn expand(
+    replacement: &str,
+    captures: &Captures,
+    fields: &BTreeMap<String, String>,
+    out: &mut String,
+) {
+    let mut case = Case::AsIs;
+    // Text written since the last case change, so title case can see where
+    // words start across groups.
+    let mut pending = String::new();
+    let mut rest = replacement;
+    while let Some(i) = rest.find(['$', '\\']) {
+        pending.push_str(&rest[..i]);
+        let escape = &rest[i..];
+
+        if let Some(after) = escape.strip_prefix('\\') {
+            let next = match after.chars().next() {
+                Some('U') => Case::Upper,
+                Some('L') => Case::Lower,
+                Some('T') => Case::Title,
+                Some('E') => Case::AsIs,
+                _ => {
+                    pending.push('\\');
+                    rest = after;
+                    continue;
+                }
+            };
+            flush(&mut pending, case, out);
+            case = next;
+            rest = &after[1..];
+            continue;
+        }
+
+        rest = &escape[1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            pending.push('$');
+            rest = after;
+            continue;
+        }
+
+        let (placeholder, after) = match placeholder(rest) {
+            Some(found) => found,
+            None => {
+                pending.push('$');
+                continue;
+            }
+        };
+        let (name, filters) = match placeholder.split_once('|') {
+            Some((name, filters)) => (name, Some(filters)),
+            None => (placeholder, None),
+        };
+
+        let group = match name.parse::<usize>() {
+            Ok(index) => captures.get(index),
+            Err(_) => captures.name(name),
+        };
+        let value = match group {
+            Some(group) => group.as_str(),
+            None => fields.get(name).map(String::as_str).unwrap_or_default(),
+        };
+        match filters {
+            // Checked when the rule was loaded.
+            Some(filters) => pending
+                .push_str(&filters::apply(value, filters).unwrap_or_else(|_| value.to_string())),
+            None => pending.push_str(value),
+        }
+        rest = after;
+    }
+    pending.push_str(rest);
+    flush(&mut pending, case, out);
+}
+
+/// Checks the filters in a replacement's placeholders.
+pub(super) fn check(replacement: &str) -> Result<(), String> {
+    let mut rest = replacement;
+    while let Some(i) = rest.find('$') {
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            rest = after;
+            continue;
+        }
+        if let Some((placeholder, after)) = placeholder(rest) {
+            if let Some((_, filters)) = placeholder.split_once('|') {
+                filters::check(filters)?;
+            }
+            rest = after;
+        }
+    }
+    Ok(())
+}
+
+/// The placeholder at the start of `rest`, just after a `$`, and the text
+/// after it.
+fn placeholder(rest: &str) -> Option<(&str, &str)> {
+    let (placeholder, after) = match rest.strip_prefix('{') {
+        Some(braced) => {
+            let end = braced.find('}')?;
+            (&braced[..end], &braced[end + 1..])
+        }
+        None => {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        }
+    };
+    (!placeholder.is_empty()).then_some((placeholder, after))
+}
+
+fn flush(pending: &mut String, case: Case, out: &mut String) {
+    match case {
+        Case::AsIs => out.push_str(pending),
+        Case::Upper => out.push_str(&pending.to_uppercase()),
+        Case::Lower => out.push_str(&pending.to_lowercase()),
+        Case::Title => out.push_str(&filters::title_case(pending)),
+    }
+    pending.clear();
+}
@@ -0,0 +1,302 @@
+use crate::actions;
+use crate::notifications::{Notification, NotificationKind, Notifications};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+/// How many `<name>-<n>` names are tried before quarantining fails.
+const MAX_QUARANTINE_NAMES: u32 = 1000;
+
+pub struct ClamavSettings {
+    address: ClamdAddress,
+    timeout: Duration,
+    quarantine_directory: PathBuf,
+}
+
+enum ClamdAddress {
+    #[cfg(unix)]
+    Socket(PathBuf),
+    Tcp(String),
+}
+
+pub fn load_clamav_settings(ini: &ini::Ini) -> Result<Option<ClamavSettings>, String> {
+    let section = match ini.section(Some("clamav")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let address = match (section.get("socket"), section.get("address")) {
+        #[cfg(unix)]
+        (Some(socket), None) => ClamdAddress::Socket(PathBuf::from(socket)),
+        #[cfg(not(unix))]
+        (Some(_), None) => {
+            return Err("socket in [clamav] is only supported on Unix, use address".to_string())
+        }
+        (None, Some(address)) => ClamdAddress::Tcp(address.to_string()),
+        (Some(_), Some(_)) => return Err("Set either socket or address in [clamav]".to_string()),
+        (None, None) => return Err("Missing 'socket' or 'address' in [clamav]".to_string()),
+    };
+
+    let timeout_secs: u64 = section
+        .get("timeout_secs")
+        .unwrap_or("60")
+        .parse()
+        .map_err(|e| format!("Invalid timeout_secs: {}", e))?;
+
+    let quarantine_directory = section
+        .get("quarantine_directory")
+        .ok_or("Missing 'quarantine_directory' in [clamav]")?;
+
+    Ok(Some(ClamavSettings {
+        address,
+        timeout: Duration::from_secs(timeout_secs),
+        quarantine_directory: PathBuf::from(quarantine_directory),
+    }))
+}
+
+pub enum Verdict {
+    Clean,
+    /// The name of the signature that matched.
+    Infected(String),
+}
+
+trait Connection: Read + Write {}
+
+impl<T: Read + Write> Connection for T {}
+
+/// Scans files with clamd before anything else touches them and moves
+/// infected ones into the quarantine directory.
+pub struct Scanner<'a> {
+    settings: &'a ClamavSettings,
+    notifications: Notifications,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new(settings: &'a ClamavSettings, notifications: Notifications) -> Self {
+        Scanner {
+            settings,
+            notifications,
+        }
+    }
+
+    /// Streams the file to clamd with `INSTREAM`.
+    pub fn scan(&self, path: &Path) -> Result<Verdict, String> {
+        let mut file =
+            File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let mut clamd = connect(self.settings)?;
+
+        let mut send = || -> io::Result<()> {
+            clamd.write_all(b"zINSTREAM\0")?;
+            let mut chunk = vec![0; CHUNK_SIZE];
+            loop {
+                let len = file.read(&mut chunk)?;
+                clamd.write_all(&(len as u32).to_be_bytes())?;
+                if len == 0 {
+                    return Ok(());
+                }
+                clamd.write_all(&chunk[..len])?;
+            }
+        };
+        let sent = send();
+
+        // clamd hangs up early on files above its StreamMaxLength, and says
+        // why.
+        let reply = read_reply(clamd.as_mut());
+        if let Err(e) = sent {
+            return Err(match reply {
+                Ok(reply) if !reply.is_empty() => format!("clamd: {}", reply),
+                _ => format!("Failed to send file to clamd: {}", e),
+            });
+        }
+        let reply = reply?;
+        match reply.strip_prefix("stream: ").unwrap_or(&reply) {
+            "OK" => Ok(Verdict::Clean),
+            result => match result.strip_suffix(" FOUND") {
+                Some(signature) => Ok(Verdict::Infected(signature.to_string())),
+                None => Err(format!("clamd: {}", result)),
+            },
+        }
+    }
+
+    /// Moves an infected file into the quarantine directory and raises an
+    /// alert. Returns where the file went.
+    pub fn quarantine(&self, path: &Path, signature: &str) -> Result<PathBuf, String> {
+        let target = quarantine_file(path, &self.settings.quarantine_directory)?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        warn!(
+            path = %path.display(),
+            quarantine = %target.display(),
+            signature,
+            "Quarantined infected file"
+        );
+        let mut fields = BTreeMap::new();
+        fields.insert("filename".to_string(), name.into_owned());
+        fields.insert("path".to_string(), path.display().to_string());
+        fields.insert("signature".to_string(), signature.to_string());
+        self.notifications.send(Notification {
+            kind: NotificationKind::Alert,
+            title: "Infected file quarantined".to_string(),
+            body: format!(
+                "{} contains {} and was moved to {}.",
+                path.display(),
+                signature,
+                target.display()
+            ),
+            fields,
+        });
+
+        Ok(target)
+    }
+}
+
+/// Moves a file into `directory`, created if it doesn't exist, as
+/// `<name>-<n>.<ext>` when its name is taken. A file already in quarantine
+/// is never replaced. Returns where the file went.
+pub(crate) fn quarantine_file(path: &Path, directory: &Path) -> Result<PathBuf, String> {
+    fs::create_dir_all(directory)
+        .map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+
+    let name = Path::new(path.file_name().unwrap_or_default());
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = name
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    for n in 0..MAX_QUARANTINE_NAMES {
+        let target = match n {
+            0 => directory.join(name),
+            n => directory.join(format!("{}-{}{}", stem, n, extension)),
+        };
+        match actions::rename_no_replace(path, &target) {
+            Ok(()) => return Ok(target),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                // Taken before copying, so a file that arrives meanwhile
+                // can't be overwritten.
+                match File::options().write(true).create_new(true).open(&target) {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                    Err(e) => return Err(format!("Failed to quarantine: {}", e)),
+                }
+                if let Err(e) = fs::copy(path, &target) {
+                    let _ = fs::remove_file(&target);
+                    return Err(format!("Failed to quarantine: {}", e));
+                }
+                fs::remove_file(path).map_err(|e| {
+                    format!(
+                        "Copied to {} but failed to remove the original: {}",
+                        target.display(),
+                        e
+                    )
+                })?;
+                return Ok(target);
+            }
+            Err(e) => return Err(format!("Failed to quarantine: {}", e)),
+        }
+    }
+    Err(format!(
+        "Failed to quarantine: no free name for {} in {}",
+        name.display(),
+        directory.display()
+    ))
+}
+
+/// Pings clamd, for `invoicehandler doctor`.
+pub fn check(settings: &ClamavSettings) -> Result<(), String> {
+    let mut clamd = connect(settings)?;
+    clamd
+        .write_all(b"zPING\0")
+        .map_err(|e| format!("Failed to reach clamd: {}", e))?;
+    match read_reply(clamd.as_mut())?.as_str() {
+        "PONG" => Ok(()),
+        reply => Err(format!("Unexpected reply from clamd: {}", reply)),
+    }
+}
+
+fn connect(settings: &ClamavSettings) -> Result<Box<dyn Connection>, String> {
+    let timeout = Some(settings.timeout);
+    let connect = || -> io::Result<Box<dyn Connection>> {
+        match &settings.address {
+            #[cfg(unix)]
+            ClamdAddress::Socket(path) => {
+                let stream = UnixStream::connect(path)?;
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)?;
+                Ok(Box::new(stream))
+            }
+            ClamdAddress::Tcp(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)?;
+                Ok(Box::new(stream))
+            }
+        }
+    };
+    connect().map_err(|e| format!("Failed to connect to clamd: {}", e))
+}
+
+/// Reads one `\0`-terminated reply.
+fn read_reply(clamd: &mut dyn Connection) -> Result<String, String> {
+    let mut reply = Vec::new();
+    let mut byte = [0];
+    loop {
+        match clamd.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) if byte[0] == 0 => break,
+            Ok(_) => reply.push(byte[0]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(format!("Failed to read reply from clamd: {}", e)),
+        }
+    }
+    Ok(String::from_utf8_lossy(&reply).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarantine_file() {
+        let dir = std::env::temp_dir().join(format!("invoicehandler-scan-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let quarantine = dir.join("quarantine");
+        for (n, directory) in ["a", "b", "c"].iter().enumerate() {
+            fs::create_dir_all(dir.join(directory)).unwrap();
+            fs::write(dir.join(directory).join("scan.pdf"), n.to_string()).unwrap();
+        }
+        fs::create_dir_all(dir.join("d")).unwrap();
+        fs::write(dir.join("d").join("README"), "3").unwrap();
+
+        let cases = [
+            ("a/scan.pdf", "scan.pdf"),
+            ("b/scan.pdf", "scan-1.pdf"),
+            ("c/scan.pdf", "scan-2.pdf"),
+            ("d/README", "README"),
+        ];
+        for (path, target) in cases {
+            assert_eq!(
+                super::quarantine_file(&dir.join(path), &quarantine).unwrap(),
+                quarantine.join(target),
+                "{}",
+                path
+            );
+            assert!(!dir.join(path).exists(), "{}", path);
+        }
+        for (n, (_, target)) in cases.iter().enumerate() {
+            assert_eq!(
+                fs::read_to_string(quarantine.join(target)).unwrap(),
+                n.to_string()
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+use crate::dates;
+use chrono::{Local, NaiveTime, Timelike, Utc};
+use chrono_tz::Tz;
+use std::time::Duration;
+
+const DAY_SECS: u32 = 24 * 60 * 60;
+
+/// Daily windows during which processing is deferred. Files that arrive
+/// meanwhile are held and processed once the window ends.
+pub struct ScheduleSettings {
+    quiet_hours: Vec<(NaiveTime, NaiveTime)>,
+    /// The zone of the windows: `timezone` in `[schedule]`, or else in
+    /// `[settings]`, or else the server's local time.
+    timezone: Option<Tz>,
+}
+
+pub fn load_schedule_settings(ini: &ini::Ini) -> Result<Option<ScheduleSettings>, String> {
+    let section = match ini.section(Some("schedule")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let quiet_hours = section
+        .get("quiet_hours")
+        .ok_or("Missing 'quiet_hours' in [schedule]")?
+        .split(',')
+        .map(str::trim)
+        .filter(|window| !window.is_empty())
+        .map(parse_window)
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if quiet_hours.is_empty() {
+        return Err("No quiet_hours configured in [schedule]".to_string());
+    }
+
+    let timezone = match section.get("timezone") {
+        Some(_) => dates::load_timezone(section)?,
+        None => match ini.section(Some("settings")) {
+            Some(settings) => dates::load_timezone(settings)?,
+            None => None,
+        },
+    };
+
+    Ok(Some(ScheduleSettings {
+        quiet_hours,
+        timezone,
+    }))
+}
+
+/// Accepts `HH:MM-HH:MM`. A window whose end is before its start runs past
+/// midnight.
+fn parse_window(value: &str) -> Result<(NaiveTime, NaiveTime), String> {
+    let invalid = || {
+        format!(
+            "Invalid quiet_hours window '{}', expected HH:MM-HH:MM",
+            value
+        )
+    };
+
+    let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+    let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").map_err(|_| invalid())?;
+    let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").map_err(|_| invalid())?;
+
+    if start == end {
+        return Err(format!("quiet_hours window '{}' is empty", value));
+    }
+    Ok((start, end))
+}
+
+impl ScheduleSettings {
+    /// The time of day in the zone of the windows.
+    pub fn now(&self) -> NaiveTime {
+        match self.timezone {
+            Some(timezone) => Utc::now().with_timezone(&timezone).time(),
+            None => Local::now().time(),
+        }
+    }
+
+    /// Whether `now` falls inside a window, counting its start but not its
+    /// end.
+    pub fn is_quiet(&self, now: NaiveTime) -> bool {
+        self.quiet_hours.iter().any(|&(start, end)| {
+            if start < end {
+                start <= now && now < end
+            } else {
+                now >= start || now < end
+            }
+        })
+    }
+
+    /// How long until the next window starts or ends.
+    pub fn time_until_change(&self, now: NaiveTime) -> Duration {
+        let now = now.num_seconds_from_midnight();
+        let secs = self
+            .quiet_hours
+            .iter()
+            .flat_map(|&(start, end)| [start, end])
+            .map(|boundary| {
+                let secs = (boundary.num_seconds_from_midnight() + DAY_SECS - now) % DAY_SECS;
+                if secs == 0 {
+                    DAY_SECS
+                } else {
+                    secs
+                }
+            })
+            .min()
+            .unwrap_or(DAY_SECS);
+        Duration::from_secs(secs.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(value, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn is_quiet() {
+        let schedule = ScheduleSettings {
+            quiet_hours: vec![
+                parse_window("01:00-03:30").unwrap(),
+                parse_window("22:00-00:30").unwrap(),
+            ],
+            timezone: None,
+        };
+
+        let cases = [
+            ("00:00", true),
+            ("00:30", false),
+            ("00:59", false),
+            ("01:00", true),
+            ("03:29", true),
+            ("03:30", false),
+            ("12:00", false),
+            ("22:00", true),
+            ("23:59", true),
+        ];
+        for (now, quiet) in cases {
+            assert_eq!(schedule.is_quiet(time(now)), quiet, "{}", now);
+        }
+
+        assert_eq!(
+            schedule.time_until_change(time("00:00")),
+            Duration::from_secs(30 * 60)
+        );
+        assert_eq!(
+            schedule.time_until_change(time("03:30")),
+            Duration::from_secs(18 * 60 * 60 + 30 * 60)
+        );
+
+        assert!(parse_window("01:00").is_err());
+        assert!(parse_window("25:00-26:00").is_err());
+        assert!(parse_window("01:00-01:00").is_err());
+    }
+
+    /// The timezone in `[settings]` and in `[schedule]`.
+    type Timezones = (Option<&'static str>, Option<&'static str>);
+
+    #[test]
+    fn timezone() {
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        let tokyo: Tz = "Asia/Tokyo".parse().unwrap();
+        let cases: Vec<(Timezones, Result<Option<Tz>, &str>)> = vec![
+            ((None, None), Ok(None)),
+            ((Some("Europe/Berlin"), None), Ok(Some(berlin))),
+            ((None, Some("Asia/Tokyo")), Ok(Some(tokyo))),
+            ((Some("Europe/Berlin"), Some("Asia/Tokyo")), Ok(Some(tokyo))),
+            ((Some("Europe/Berlin"), Some("local")), Ok(None)),
+            ((None, Some("Mars/Olympus")), Err("Invalid timezone")),
+        ];
+        for ((settings, schedule), expected) in cases {
+            let mut config = String::new();
+            if let Some(timezone) = settings {
+                config += &format!("[settings]\ntimezone = {}\n", timezone);
+            }
+            config += "[schedule]\nquiet_hours = 01:00-03:30\n";
+            if let Some(timezone) = schedule {
+                config += &format!("timezone = {}\n", timezone);
+            }
+            let ini = ini::Ini::load_from_str(&config).unwrap();
+            match (load_schedule_settings(&ini), expected) {
+                (Ok(loaded), Ok(timezone)) => {
+                    assert_eq!(loaded.unwrap().timezone, timezone, "{:?}", config)
+                }
+                (Err(e), Err(prefix)) => assert!(e.starts_with(prefix), "{:?}: {}", config, e),
+                (Ok(loaded), Err(_)) => panic!("{:?}: {:?}", config, loaded.map(|s| s.timezone)),
+                (Err(e), Ok(_)) => panic!("{:?}: {}", config, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::error::ConfigError;
+use crate::ledger;
+use crate::settings::Settings;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+const USAGE: &str = "usage: invoicehandler search [--<column> <value>]... [text]...";
+
+/// What `invoicehandler search` looks for in the ledger.
+#[derive(Default)]
+struct Query {
+    /// `--vendor acme`: the column has this value, ignoring case.
+    columns: Vec<(String, String)>,
+    /// Words that have to appear in some column, such as part of a name.
+    text: Vec<String>,
+}
+
+impl Query {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut query = Query::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(column) if !column.is_empty() => {
+                    let value = args.next()?;
+                    query
+                        .columns
+                        .push((column.replace('-', "_"), value.to_lowercase()));
+                }
+                _ => query.text.push(arg.to_lowercase()),
+            }
+        }
+        (!query.columns.is_empty() || !query.text.is_empty()).then_some(query)
+    }
+
+    fn matches(&self, entry: &BTreeMap<String, String>) -> bool {
+        let columns = self.columns.iter().all(|(column, value)| {
+            entry
+                .get(column)
+                .is_some_and(|entry| entry.to_lowercase() == *value)
+        });
+        let text = self.text.iter().all(|word| {
+            entry
+                .values()
+                .any(|value| value.to_lowercase().contains(word.as_str()))
+        });
+        columns && text
+    }
+}
+
+/// `invoicehandler search`: prints where the invoices in the ledgers of the
+/// config and its tenants that match went, with the other columns of their
+/// rows. Exits with 1 when nothing matches.
+pub fn run_search_command(config_path: &Path, args: &[String]) -> i32 {
+    let Some(query) = Query::parse(args) else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+
+    let configs = match ledger_configs(config_path) {
+        Ok(configs) => configs,
+        Err(code) => return code,
+    };
+
+    let mut found = false;
+    for (tenant, settings) in &configs {
+        let Some(ledger) = &settings.ledger else {
+            continue;
+        };
+        let entries = match ledger::read_entries(ledger) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        for entry in entries.iter().filter(|entry| query.matches(entry)) {
+            found = true;
+            print_entry(tenant.as_deref(), &ledger.columns, entry);
+        }
+    }
+    if found {
+        0
+    } else {
+        1
+    }
+}
+
+/// The settings of the config and of each of its tenants, by tenant name, for
+/// the commands that read their ledgers. Errors are printed and returned as
+/// the exit code.
+pub(crate) fn ledger_configs(config_path: &Path) -> Result<Vec<(Option<String>, Settings)>, i32> {
+    let settings = Settings::load(config_path).map_err(|e| {
+        eprintln!("Error loading settings: {}", e);
+        e.exit_code()
+    })?;
+    let mut configs = Vec::new();
+    for tenant in &settings.tenants {
+        let tenant_settings = Settings::load(&tenant.config_path).map_err(|e| {
+            eprintln!("Error loading settings of tenant '{}': {}", tenant.name, e);
+            e.exit_code()
+        })?;
+        configs.push((Some(tenant.name.clone()), tenant_settings));
+    }
+    configs.insert(0, (None, settings));
+
+    if configs
+        .iter()
+        .all(|(_, settings)| settings.ledger.is_none())
+    {
+        let e = ConfigError::from("No [ledger], which records where every renamed file went");
+        eprintln!("{}", e);
+        return Err(e.exit_code());
+    }
+    Ok(configs)
+}
+
+/// The archived path first, then the other columns that have a value.
+fn print_entry(tenant: Option<&str>, columns: &[String], entry: &BTreeMap<String, String>) {
+    let mut line = String::new();
+    if let Some(tenant) = tenant {
+        line.push_str(&format!("[{}] ", tenant));
+    }
+    line.push_str(
+        entry
+            .get("archived_path")
+            .map(String::as_str)
+            .unwrap_or("-"),
+    );
+    for column in columns.iter().filter(|column| *column != "archived_path") {
+        if let Some(value) = entry.get(column).filter(|value| !value.is_empty()) {
+            line.push_str(&format!("  {}={}", column, value));
+        }
+    }
+    println!("{}", line);
+}
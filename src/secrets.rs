@@ -0,0 +1,152 @@
+/// Config values starting with this are looked up in the OS keyring, e.g.
+/// `password = keyring:digest-smtp`.
+const KEYRING_PREFIX: &str = "keyring:";
+
+/// The service secrets are stored under in the keyring.
+#[cfg(feature = "keyring")]
+const SERVICE: &str = "invoicehandler";
+
+/// Replaces every `keyring:<name>` value outside the data sections with the
+/// secret stored under `<name>`.
+pub fn resolve(ini: &mut ini::Ini) -> Result<(), String> {
+    for (section, properties) in ini.iter_mut() {
+        if section.is_some_and(is_data_section) {
+            continue;
+        }
+        for (key, value) in properties.iter_mut() {
+            let Some(name) = value.strip_prefix(KEYRING_PREFIX) else {
+                continue;
+            };
+            *value = lookup(name).map_err(|e| {
+                format!(
+                    "Failed to read '{}' in [{}] from the keyring: {}",
+                    key,
+                    section.unwrap_or_default(),
+                    e
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Sections whose keys and values are rules, month names or tenants rather
+/// than settings, so a value that happens to start with `keyring:` is data.
+fn is_data_section(section: &str) -> bool {
+    matches!(section, "translations" | "months" | "tenants")
+        || ["translations.", "rule.", "locks."]
+            .iter()
+            .any(|prefix| section.starts_with(prefix))
+}
+
+#[cfg(feature = "keyring")]
+pub(crate) fn lookup(name: &str) -> Result<String, String> {
+    keyring::Entry::new(SERVICE, name)
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| match e {
+            keyring::Error::NoEntry => format!(
+                "No secret named '{}', store it with `invoicehandler keyring set {}`",
+                name, name
+            ),
+            e => e.to_string(),
+        })
+}
+
+#[cfg(not(feature = "keyring"))]
+fn lookup(_name: &str) -> Result<String, String> {
+    Err("keyring: values need a build with the 'keyring' feature".to_string())
+}
+
+/// `invoicehandler keyring set <name>`: stores the first line of stdin
+/// under `name`, so it never shows up in the shell history. Returns the exit
+/// code.
+pub fn run_keyring_command(args: &[String]) -> i32 {
+    let [command, name] = args else {
+        eprintln!("usage: invoicehandler keyring set <name>");
+        return 2;
+    };
+    if command != "set" {
+        eprintln!("usage: invoicehandler keyring set <name>");
+        return 2;
+    }
+
+    let mut secret = String::new();
+    if let Err(e) = std::io::stdin().read_line(&mut secret) {
+        eprintln!("Failed to read the secret from stdin: {}", e);
+        return 1;
+    }
+    let secret = secret.trim_end_matches(['\r', '\n']);
+    if secret.is_empty() {
+        eprintln!("No secret given on stdin");
+        return 1;
+    }
+
+    match store(name, secret) {
+        Ok(()) => {
+            println!("Stored '{}', use it as {}{}", name, KEYRING_PREFIX, name);
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to store '{}': {}", name, e);
+            1
+        }
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn store(name: &str, secret: &str) -> Result<(), String> {
+    keyring::Entry::new(SERVICE, name)
+        .and_then(|entry| entry.set_password(secret))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "keyring"))]
+fn store(_name: &str, _secret: &str) -> Result<(), String> {
+    Err("Storing secrets needs a build with the 'keyring' feature".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_skips_data_sections() {
+        let data = r#"
+[translations]
+^a\.pdf$ = keyring:a.pdf
+[translations.pdf]
+^b\.pdf$ = keyring:b.pdf
+[rule.acme]
+pattern = ^keyring:
+replacement = keyring:c.pdf
+[locks.pdf]
+max_lock_retries = 3
+[months]
+keyring: = 1
+[tenants]
+acme = keyring:acme.ini
+"#;
+        let mut ini = ini::Ini::load_from_str(data).unwrap();
+        resolve(&mut ini).unwrap();
+        assert_eq!(
+            ini.get_from(Some("rule.acme"), "replacement"),
+            Some("keyring:c.pdf")
+        );
+        assert_eq!(
+            ini.get_from(Some("tenants"), "acme"),
+            Some("keyring:acme.ini")
+        );
+
+        let mut ini = ini::Ini::load_from_str(&format!(
+            "{}[digest]\npassword = keyring:invoicehandler-test-missing\n",
+            data
+        ))
+        .unwrap();
+        let error = resolve(&mut ini).unwrap_err();
+        assert!(
+            error.starts_with("Failed to read 'password' in [digest]"),
+            "{}",
+            error
+        );
+    }
+}
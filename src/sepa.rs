@@ -0,0 +1,688 @@
+use crate::dates::DateSettings;
+use chrono::{Datelike, NaiveDate};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+static PAYMENT_INFO: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?s)<PmtInf>.*?<ReqdExctnDt>([0-9-]+)</ReqdExctnDt>.*?</PmtInf>").unwrap()
+});
+static TRANSACTION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<CdtTrfTxInf>.*?</CdtTrfTxInf>").unwrap());
+static INSTRUCTED_AMOUNT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<InstdAmt Ccy="EUR">([0-9]+)\.([0-9]{2})</InstdAmt>"#).unwrap());
+
+/// The largest instructed amount of a SEPA transfer, 999999999.99 EUR.
+const MAX_CENTS: u64 = 99_999_999_999;
+
+/// How often a new payment batch is started.
+enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+/// Prepares a SEPA credit transfer for every renamed invoice with an IBAN and
+/// an amount, from `[sepa]`. The transfers of a period are collected in one
+/// pain.001 file, ready to be uploaded to the bank.
+pub struct SepaSettings {
+    directory: PathBuf,
+    period: Period,
+    debtor_name: String,
+    debtor_iban: String,
+    debtor_bic: Option<String>,
+}
+
+pub fn load_sepa_settings(ini: &ini::Ini) -> Result<Option<SepaSettings>, String> {
+    let section = match ini.section(Some("sepa")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let directory = section
+        .get("directory")
+        .ok_or("Missing 'directory' in [sepa]")?;
+    let period = match section.get("period").unwrap_or("month") {
+        "day" => Period::Day,
+        "week" => Period::Week,
+        "month" => Period::Month,
+        other => {
+            return Err(format!(
+                "Invalid period '{}' in [sepa], use day, week or month",
+                other
+            ))
+        }
+    };
+    let debtor_name = section
+        .get("debtor_name")
+        .ok_or("Missing 'debtor_name' in [sepa]")?;
+    let debtor_iban = normalize_iban(
+        section
+            .get("debtor_iban")
+            .ok_or("Missing 'debtor_iban' in [sepa]")?,
+    );
+    if !is_valid_iban(&debtor_iban) {
+        return Err(format!("Invalid debtor_iban '{}' in [sepa]", debtor_iban));
+    }
+
+    Ok(Some(SepaSettings {
+        directory: PathBuf::from(directory),
+        period,
+        debtor_name: debtor_name.to_string(),
+        debtor_iban,
+        debtor_bic: section.get("debtor_bic").map(str::to_string),
+    }))
+}
+
+/// One credit transfer, in cents.
+struct Payment {
+    creditor: String,
+    iban: String,
+    bic: Option<String>,
+    cents: u64,
+    reference: String,
+    end_to_end_id: String,
+    execution_date: NaiveDate,
+}
+
+/// Adds the payment for the invoice with `fields` to the batch of the
+/// current period, and returns the batch file. `Ok(None)` when the fields
+/// have no IBAN or amount, such as for an invoice already paid by direct
+/// debit. A Swiss QR-bill in the `qr_bill` field fills in what the rule
+/// didn't capture.
+pub fn add_payment(
+    settings: &SepaSettings,
+    dates: &DateSettings,
+    fields: &BTreeMap<String, String>,
+) -> Result<Option<PathBuf>, String> {
+    let mut fields = fields.clone();
+    if let Some(qr_bill) = fields.get("qr_bill").cloned() {
+        for (name, value) in qr_bill_fields(&qr_bill) {
+            fields.entry(name.to_string()).or_insert(value);
+        }
+    }
+
+    let (Some(iban), Some(amount)) = (fields.get("iban"), fields.get("amount")) else {
+        return Ok(None);
+    };
+    let iban = normalize_iban(iban);
+    if !is_valid_iban(&iban) {
+        return Err(format!("Invalid IBAN '{}'", iban));
+    }
+    let cents = parse_amount(amount).ok_or_else(|| format!("Invalid amount '{}'", amount))?;
+    if let Some(currency) = fields
+        .get("currency")
+        .filter(|c| !c.eq_ignore_ascii_case("EUR"))
+    {
+        return Err(format!(
+            "SEPA transfers are in EUR, the invoice is in {}",
+            currency
+        ));
+    }
+
+    // Banks refuse execution dates in the past, so overdue invoices are paid
+    // right away.
+    let today = dates.now().date_naive();
+    let execution_date = fields
+        .get("due_date")
+        .and_then(|date| dates.format.parse_date(date))
+        .filter(|date| *date > today)
+        .unwrap_or(today);
+
+    let payment = Payment {
+        creditor: fields
+            .get("vendor")
+            .cloned()
+            .unwrap_or_else(|| "NOTPROVIDED".to_string()),
+        iban,
+        bic: fields.get("bic").cloned(),
+        cents,
+        reference: fields.get("reference").cloned().unwrap_or_default(),
+        end_to_end_id: fields
+            .get("number")
+            .cloned()
+            .unwrap_or_else(|| "NOTPROVIDED".to_string()),
+        execution_date,
+    };
+
+    let label = match settings.period {
+        Period::Day => today.format("%Y-%m-%d").to_string(),
+        Period::Week => format!(
+            "{}-W{:02}",
+            today.iso_week().year(),
+            today.iso_week().week()
+        ),
+        Period::Month => today.format("%Y-%m").to_string(),
+    };
+    let file = settings.directory.join(format!("payments-{}.xml", label));
+    write_batch(settings, dates, &file, &label, &payment)?;
+    Ok(Some(file))
+}
+
+/// Rewrites the batch with the transfers already in it and `payment`,
+/// grouped by execution date.
+fn write_batch(
+    settings: &SepaSettings,
+    dates: &DateSettings,
+    file: &Path,
+    label: &str,
+    payment: &Payment,
+) -> Result<(), String> {
+    let existing = match fs::read_to_string(file) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Failed to read '{}': {}", file.display(), e)),
+    };
+
+    let mut transactions: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for payment_info in PAYMENT_INFO.captures_iter(&existing) {
+        transactions
+            .entry(payment_info[1].to_string())
+            .or_default()
+            .extend(
+                TRANSACTION
+                    .find_iter(&payment_info[0])
+                    .map(|transaction| transaction.as_str().to_string()),
+            );
+    }
+    let transaction = transaction_xml(payment);
+    if transactions
+        .values()
+        .flatten()
+        .any(|existing| is_same_transfer(existing, &transaction))
+    {
+        return Err(format!(
+            "A transfer for this invoice is already in {}",
+            file.display()
+        ));
+    }
+    transactions
+        .entry(payment.execution_date.format("%Y-%m-%d").to_string())
+        .or_default()
+        .push(transaction);
+
+    let now = dates.now();
+    let message_id = format!("{}-{}", label, now.format("%Y%m%d%H%M%S"));
+    let all: Vec<&String> = transactions.values().flatten().collect();
+    let total = sum(&all).ok_or("The total of the batch is too large")?;
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<Document xmlns=\"urn:iso:std:iso:20022:tech:xsd:pain.001.001.03\">\n");
+    xml.push_str("  <CstmrCdtTrfInitn>\n");
+    xml.push_str("    <GrpHdr>\n");
+    xml.push_str(&format!("      <MsgId>{}</MsgId>\n", escape(&message_id)));
+    xml.push_str(&format!(
+        "      <CreDtTm>{}</CreDtTm>\n",
+        now.format("%Y-%m-%dT%H:%M:%S")
+    ));
+    xml.push_str(&format!("      <NbOfTxs>{}</NbOfTxs>\n", all.len()));
+    xml.push_str(&format!("      <CtrlSum>{}</CtrlSum>\n", total));
+    xml.push_str(&format!(
+        "      <InitgPty><Nm>{}</Nm></InitgPty>\n",
+        escape(&settings.debtor_name)
+    ));
+    xml.push_str("    </GrpHdr>\n");
+    for (date, transactions) in &transactions {
+        let transactions: Vec<&String> = transactions.iter().collect();
+        xml.push_str("    <PmtInf>\n");
+        xml.push_str(&format!(
+            "      <PmtInfId>{}-{}</PmtInfId>\n",
+            escape(label),
+            date
+        ));
+        xml.push_str("      <PmtMtd>TRF</PmtMtd>\n");
+        xml.push_str(&format!(
+            "      <NbOfTxs>{}</NbOfTxs>\n",
+            transactions.len()
+        ));
+        // Never more than the total of the whole batch.
+        xml.push_str(&format!(
+            "      <CtrlSum>{}</CtrlSum>\n",
+            sum(&transactions).unwrap_or_default()
+        ));
+        xml.push_str("      <PmtTpInf><SvcLvl><Cd>SEPA</Cd></SvcLvl></PmtTpInf>\n");
+        xml.push_str(&format!("      <ReqdExctnDt>{}</ReqdExctnDt>\n", date));
+        xml.push_str(&format!(
+            "      <Dbtr><Nm>{}</Nm></Dbtr>\n",
+            escape(&settings.debtor_name)
+        ));
+        xml.push_str(&format!(
+            "      <DbtrAcct><Id><IBAN>{}</IBAN></Id></DbtrAcct>\n",
+            settings.debtor_iban
+        ));
+        match &settings.debtor_bic {
+            Some(bic) => xml.push_str(&format!(
+                "      <DbtrAgt><FinInstnId><BIC>{}</BIC></FinInstnId></DbtrAgt>\n",
+                escape(bic)
+            )),
+            None => xml.push_str(
+                "      <DbtrAgt><FinInstnId><Othr><Id>NOTPROVIDED</Id></Othr></FinInstnId></DbtrAgt>\n",
+            ),
+        }
+        xml.push_str("      <ChrgBr>SLEV</ChrgBr>\n");
+        for transaction in transactions {
+            xml.push_str("      ");
+            xml.push_str(transaction);
+            xml.push('\n');
+        }
+        xml.push_str("    </PmtInf>\n");
+    }
+    xml.push_str("  </CstmrCdtTrfInitn>\n");
+    xml.push_str("</Document>\n");
+
+    fs::create_dir_all(&settings.directory)
+        .map_err(|e| format!("Failed to create '{}': {}", settings.directory.display(), e))?;
+    // Written next to the batch and renamed over it, so the bank upload never
+    // sees half a file.
+    let partial = file.with_extension("xml.partial");
+    fs::write(&partial, xml)
+        .and_then(|()| fs::rename(&partial, file))
+        .map_err(|e| format!("Failed to write '{}': {}", file.display(), e))
+}
+
+fn transaction_xml(payment: &Payment) -> String {
+    let mut xml = String::from("<CdtTrfTxInf>");
+    xml.push_str(&format!(
+        "<PmtId><EndToEndId>{}</EndToEndId></PmtId>",
+        escape(&truncate(&payment.end_to_end_id, 35))
+    ));
+    xml.push_str(&format!(
+        "<Amt><InstdAmt Ccy=\"EUR\">{}.{:02}</InstdAmt></Amt>",
+        payment.cents / 100,
+        payment.cents % 100
+    ));
+    if let Some(bic) = &payment.bic {
+        xml.push_str(&format!(
+            "<CdtrAgt><FinInstnId><BIC>{}</BIC></FinInstnId></CdtrAgt>",
+            escape(bic)
+        ));
+    }
+    xml.push_str(&format!(
+        "<Cdtr><Nm>{}</Nm></Cdtr>",
+        escape(&truncate(&payment.creditor, 70))
+    ));
+    xml.push_str(&format!(
+        "<CdtrAcct><Id><IBAN>{}</IBAN></Id></CdtrAcct>",
+        payment.iban
+    ));
+    if !payment.reference.is_empty() {
+        xml.push_str(&format!(
+            "<RmtInf><Ustrd>{}</Ustrd></RmtInf>",
+            escape(&truncate(&payment.reference, 140))
+        ));
+    }
+    xml.push_str("</CdtTrfTxInf>");
+    xml
+}
+
+/// The total of the transactions as written in `CtrlSum`, `None` when it
+/// doesn't fit in cents.
+fn sum(transactions: &[&String]) -> Option<String> {
+    let cents = transactions
+        .iter()
+        .filter_map(|transaction| INSTRUCTED_AMOUNT.captures(transaction))
+        .try_fold(0u64, |total, amount| {
+            let cents = amount[1]
+                .parse::<u64>()
+                .ok()?
+                .checked_mul(100)?
+                .checked_add(amount[2].parse().ok()?)?;
+            total.checked_add(cents)
+        })?;
+    Some(format!("{}.{:02}", cents / 100, cents % 100))
+}
+
+/// Whether two `CdtTrfTxInf` elements pay the same invoice: they have the
+/// same end-to-end ID, i.e. invoice number, or the same creditor IBAN,
+/// amount and reference. Transfers with different invoice numbers and no
+/// reference are different invoices, such as a monthly fee.
+fn is_same_transfer(a: &str, b: &str) -> bool {
+    let id = |xml| element(xml, "EndToEndId").filter(|id| *id != "NOTPROVIDED");
+    let details = |xml| {
+        (
+            element(xml, "IBAN"),
+            element(xml, "InstdAmt"),
+            element(xml, "Ustrd"),
+        )
+    };
+    match (id(a), id(b)) {
+        (Some(a_id), Some(b_id)) if a_id == b_id => true,
+        (Some(_), Some(_)) => element(a, "Ustrd").is_some() && details(a) == details(b),
+        _ => details(a) == details(b),
+    }
+}
+
+/// The text of the first `name` element in `xml`.
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}", name))?;
+    let rest = &xml[start..];
+    let text = &rest[rest.find('>')? + 1..];
+    Some(&text[..text.find(&format!("</{}>", name))?])
+}
+
+/// The fields of a Swiss QR-bill payload: the creditor's IBAN and name, the
+/// amount, currency and reference.
+fn qr_bill_fields(payload: &str) -> Vec<(&'static str, String)> {
+    let lines: Vec<&str> = payload.lines().map(str::trim).collect();
+    if lines.first() != Some(&"SPC") {
+        return Vec::new();
+    }
+    [
+        ("iban", 3),
+        ("vendor", 5),
+        ("amount", 18),
+        ("currency", 19),
+        ("reference", 28),
+    ]
+    .into_iter()
+    .filter_map(|(name, line)| {
+        lines
+            .get(line)
+            .filter(|value| !value.is_empty())
+            .map(|value| (name, value.to_string()))
+    })
+    .collect()
+}
+
+/// Reads `1234.50`, `1.234,50`, `1'234.50` or `1234,5` as cents, up to the
+/// largest amount a SEPA transfer can have.
+pub(crate) fn parse_amount(amount: &str) -> Option<u64> {
+    let amount: String = amount
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '\'' | '€'))
+        .collect();
+    let (units, fraction) = match amount.rfind(['.', ',']) {
+        Some(i) if amount.len() - i <= 3 => (&amount[..i], &amount[i + 1..]),
+        _ => (amount.as_str(), ""),
+    };
+    let units: String = units.chars().filter(|c| !matches!(c, '.' | ',')).collect();
+    if units.is_empty() || !units.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let fraction = format!("{:0<2}", fraction);
+    if !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let cents = units
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(100)?
+        .checked_add(fraction.parse().ok()?)?;
+    (1..=MAX_CENTS).contains(&cents).then_some(cents)
+}
+
+fn normalize_iban(iban: &str) -> String {
+    iban.chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// The ISO 13616 check: the country code and check digits moved to the end,
+/// letters as numbers from 10, modulo 97 is 1.
+fn is_valid_iban(iban: &str) -> bool {
+    if iban.len() < 15 || iban.len() > 34 || !iban.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return false;
+    }
+    let (start, rest) = iban.split_at(4);
+    let remainder = rest.chars().chain(start.chars()).fold(0u32, |acc, c| {
+        let value = c.to_digit(36).unwrap_or(0);
+        if value < 10 {
+            (acc * 10 + value) % 97
+        } else {
+            (acc * 100 + value) % 97
+        }
+    });
+    remainder == 1
+}
+
+fn truncate(value: &str, max: usize) -> String {
+    value.chars().take(max).collect()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dates;
+
+    #[test]
+    fn parse_amount() {
+        let cases = [
+            ("1234.50", Some(123450)),
+            ("1.234,50", Some(123450)),
+            ("1,234.50", Some(123450)),
+            ("1'234.50", Some(123450)),
+            ("1 234,50 €", Some(123450)),
+            ("1234,5", Some(123450)),
+            ("1234", Some(123400)),
+            ("1.234", Some(123400)),
+            ("0.01", Some(1)),
+            ("0", None),
+            ("0,00", None),
+            ("-12.00", None),
+            ("12.5x", None),
+            ("abc", None),
+            ("", None),
+            ("999999999.99", Some(MAX_CENTS)),
+            ("999.999.999,99", Some(MAX_CENTS)),
+            ("1000000000.00", None),
+            ("184467440737095516.16", None),
+            ("99999999999999999999", None),
+        ];
+        for (amount, cents) in cases {
+            assert_eq!(super::parse_amount(amount), cents, "{}", amount);
+        }
+    }
+
+    #[test]
+    fn is_valid_iban() {
+        let cases = [
+            ("DE89370400440532013000", true),
+            ("CH9300762011623852957", true),
+            ("GB82WEST12345698765432", true),
+            ("DE89370400440532013001", false),
+            ("DE8937040044", false),
+            ("DE89-3704-0044-0532-0130-00", false),
+        ];
+        for (iban, valid) in cases {
+            assert_eq!(super::is_valid_iban(iban), valid, "{}", iban);
+        }
+        assert_eq!(
+            normalize_iban("de89 3704 0044 0532 0130 00"),
+            "DE89370400440532013000"
+        );
+    }
+
+    #[test]
+    fn qr_bill_fields() {
+        let mut lines = vec![""; 31];
+        lines[0] = "SPC";
+        lines[3] = "CH9300762011623852957";
+        lines[5] = "Muster AG";
+        lines[18] = "199.95";
+        lines[19] = "EUR";
+        lines[28] = "210000000003139471430009017";
+        let payload = lines.join("\r\n");
+
+        assert_eq!(
+            super::qr_bill_fields(&payload),
+            vec![
+                ("iban", "CH9300762011623852957".to_string()),
+                ("vendor", "Muster AG".to_string()),
+                ("amount", "199.95".to_string()),
+                ("currency", "EUR".to_string()),
+                ("reference", "210000000003139471430009017".to_string()),
+            ]
+        );
+        assert!(super::qr_bill_fields(&payload.replace("SPC", "BCD")).is_empty());
+    }
+
+    #[test]
+    fn add_payment() {
+        let dir = std::env::temp_dir().join(format!("invoicehandler-sepa-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let ini = ini::Ini::load_from_str(&format!(
+            "[settings]\ntimezone = UTC\ndate_format = %d.%m.%Y\n\
+             [sepa]\ndirectory = {}\ndebtor_name = Fluffis & Co\ndebtor_iban = DE89 3704 0044 0532 0130 00\n",
+            dir.display()
+        ))
+        .unwrap();
+        let settings = load_sepa_settings(&ini).unwrap().unwrap();
+        let dates = dates::load_date_settings(ini.section(Some("settings")).unwrap()).unwrap();
+        let fields = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+
+        let first = fields(&[
+            ("vendor", "Muster <GmbH>"),
+            ("number", "R-1"),
+            ("iban", "GB82 WEST 1234 5698 7654 32"),
+            ("amount", "1.234,50"),
+            ("due_date", "15.01.2099"),
+            ("reference", "R-1 & R-2"),
+        ]);
+        let second = fields(&[
+            ("vendor", "Beispiel AG"),
+            ("number", "R-2"),
+            ("iban", "DE89370400440532013000"),
+            ("amount", "10"),
+            ("due_date", "01.01.2000"),
+        ]);
+        let file = super::add_payment(&settings, &dates, &first)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            super::add_payment(&settings, &dates, &second).unwrap(),
+            Some(file.clone())
+        );
+
+        let xml = fs::read_to_string(&file).unwrap();
+        let today = dates.now().date_naive().format("%Y-%m-%d").to_string();
+        let header = xml.split("</GrpHdr>").next().unwrap();
+        assert!(header.contains("<NbOfTxs>2</NbOfTxs>"), "{}", xml);
+        assert!(header.contains("<CtrlSum>1244.50</CtrlSum>"), "{}", xml);
+        assert!(header.contains("<Nm>Fluffis &amp; Co</Nm>"), "{}", xml);
+
+        // One batch for each execution date, the overdue invoice paid today.
+        let batches: Vec<&str> = xml.split("<PmtInf>").skip(1).collect();
+        assert_eq!(batches.len(), 2, "{}", xml);
+        let batch = |date: &str| {
+            *batches
+                .iter()
+                .find(|batch| batch.contains(&format!("<ReqdExctnDt>{}</ReqdExctnDt>", date)))
+                .unwrap_or_else(|| panic!("no batch for {}: {}", date, xml))
+        };
+        let later = batch("2099-01-15");
+        assert!(later.contains("<CtrlSum>1234.50</CtrlSum>"), "{}", later);
+        assert!(later.contains("<Nm>Muster &lt;GmbH&gt;</Nm>"), "{}", later);
+        assert!(
+            later.contains("<IBAN>GB82WEST12345698765432</IBAN>"),
+            "{}",
+            later
+        );
+        assert!(later.contains("<Ustrd>R-1 &amp; R-2</Ustrd>"), "{}", later);
+        let now = batch(&today);
+        assert!(
+            now.contains("<InstdAmt Ccy=\"EUR\">10.00</InstdAmt>"),
+            "{}",
+            now
+        );
+        assert!(now.contains("<EndToEndId>R-2</EndToEndId>"), "{}", now);
+
+        let cases = [
+            (fields(&[("vendor", "Muster AG")]), Ok(None)),
+            (
+                fields(&[("iban", "DE89370400440532013001"), ("amount", "1")]),
+                Err("Invalid IBAN 'DE89370400440532013001'"),
+            ),
+            (
+                fields(&[("iban", "DE89370400440532013000"), ("amount", "x")]),
+                Err("Invalid amount 'x'"),
+            ),
+            (
+                fields(&[
+                    ("iban", "DE89370400440532013000"),
+                    ("amount", "1"),
+                    ("currency", "CHF"),
+                ]),
+                Err("SEPA transfers are in EUR"),
+            ),
+            // Delivered again, and under another number.
+            (
+                first.clone(),
+                Err("A transfer for this invoice is already in"),
+            ),
+            (
+                fields(&[
+                    ("number", "R-9"),
+                    ("iban", "GB82WEST12345698765432"),
+                    ("amount", "1234.50"),
+                    ("reference", "R-1 & R-2"),
+                ]),
+                Err("A transfer for this invoice is already in"),
+            ),
+            // The same fee as the second invoice, the next month.
+            (
+                fields(&[
+                    ("vendor", "Beispiel AG"),
+                    ("number", "R-3"),
+                    ("iban", "DE89370400440532013000"),
+                    ("amount", "10"),
+                ]),
+                Ok(Some(file.clone())),
+            ),
+        ];
+        for (fields, expected) in cases {
+            match (super::add_payment(&settings, &dates, &fields), expected) {
+                (Ok(file), Ok(expected)) => assert_eq!(file, expected, "{:?}", fields),
+                (Err(e), Err(prefix)) => assert!(e.starts_with(prefix), "{:?}: {}", fields, e),
+                (result, expected) => panic!("{:?}: {:?}, expected {:?}", fields, result, expected),
+            }
+        }
+        assert_eq!(
+            fs::read_to_string(&file)
+                .unwrap()
+                .matches("<CdtTrfTxInf>")
+                .count(),
+            3
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sum() {
+        let amount = |amount: &str| format!("<InstdAmt Ccy=\"EUR\">{}</InstdAmt>", amount);
+        let cases = [
+            (vec![], Some("0.00")),
+            (
+                vec![amount("1.50"), amount("999999999.99")],
+                Some("1000000001.49"),
+            ),
+            (vec![amount("184467440737095516.15"), amount("0.01")], None),
+        ];
+        for (transactions, expected) in cases {
+            let transactions: Vec<&String> = transactions.iter().collect();
+            assert_eq!(
+                super::sum(&transactions).as_deref(),
+                expected,
+                "{:?}",
+                transactions
+            );
+        }
+    }
+}
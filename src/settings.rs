@@ -0,0 +1,328 @@
+use crate::actions::{self, Actions};
+use crate::alerts::{self, AlertSettings};
+use crate::config;
+use crate::content::{self, ContentSettings};
+use crate::currency::{self, CurrencySettings};
+use crate::dates::{self, DateSettings};
+use crate::digest::{self, DigestSettings};
+use crate::disk::{self, DiskSettings};
+use crate::duplicates::{self, DuplicateSettings};
+use crate::error::ConfigError;
+use crate::error_reporting::{self, SentrySettings};
+use crate::events::{self, EventSettings};
+#[cfg(feature = "grpc")]
+use crate::grpc::{self, GrpcSettings};
+use crate::heartbeat::{self, HeartbeatSettings};
+use crate::http::{self, HttpSettings};
+use crate::ledger::{self, LedgerSettings};
+use crate::logging::{self, LogSettings};
+use crate::notifications::{self, NotificationSettings};
+use crate::plugins::{self, PluginSettings};
+use crate::privileges::{self, PrivilegeSettings};
+use crate::remote_rules::{self, RemoteRuleSettings};
+use crate::retry::{self, RetrySettings};
+use crate::scan::{self, ClamavSettings};
+use crate::schedule::{self, ScheduleSettings};
+use crate::secrets;
+use crate::sepa::{self, SepaSettings};
+use crate::telemetry::{self, OtelSettings};
+use crate::tenants::{self, Tenant};
+use crate::vies::{self, ViesSettings};
+use crate::watcher::Trigger;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// Everything in the config file except the rules, which are loaded and
+/// reloaded on their own by [`RuleSet`](crate::RuleSet).
+pub struct Settings {
+    pub(crate) watch_directory: PathBuf,
+    pub(crate) max_lock_retries: u32,
+    pub(crate) lock_retry_delay_ms: u64,
+    pub(crate) recursive: bool,
+    pub(crate) catch_up_on_start: bool,
+    pub(crate) startup_summary: bool,
+    pub(crate) sweep_interval: Option<Duration>,
+    pub(crate) trigger_events: Vec<Trigger>,
+    pub(crate) ignore_older_than: Option<Duration>,
+    pub(crate) scan_workers: usize,
+    pub(crate) privileges: Option<PrivilegeSettings>,
+    pub(crate) retry: RetrySettings,
+    pub(crate) clamav: Option<ClamavSettings>,
+    pub(crate) content: ContentSettings,
+    pub(crate) dates: DateSettings,
+    pub(crate) actions: Actions,
+    pub(crate) schedule: Option<ScheduleSettings>,
+    pub(crate) heartbeat: Option<HeartbeatSettings>,
+    pub(crate) ledger: Option<LedgerSettings>,
+    pub(crate) duplicates: Option<DuplicateSettings>,
+    pub(crate) vies: Option<ViesSettings>,
+    pub(crate) sepa: Option<SepaSettings>,
+    pub(crate) currency: Option<CurrencySettings>,
+    pub(crate) events: EventSettings,
+    pub(crate) logging: LogSettings,
+    pub(crate) otel: Option<OtelSettings>,
+    pub(crate) sentry: Option<SentrySettings>,
+    pub(crate) http: Option<HttpSettings>,
+    #[cfg(feature = "grpc")]
+    pub(crate) grpc: Option<GrpcSettings>,
+    pub(crate) digest: Option<DigestSettings>,
+    pub(crate) notifications: NotificationSettings,
+    pub(crate) alerts: Option<AlertSettings>,
+    pub(crate) disk: Option<DiskSettings>,
+    pub(crate) plugins: Option<PluginSettings>,
+    pub(crate) remote_rules: Option<RemoteRuleSettings>,
+    pub(crate) tenants: Vec<Tenant>,
+}
+
+impl Settings {
+    pub fn load(config_path: &Path) -> Result<Self, ConfigError> {
+        let mut ini = config::load(config_path)?;
+        secrets::resolve(&mut ini)?;
+
+        let section = ini
+            .section(Some("settings"))
+            .ok_or("Missing [settings] section in config.ini")?;
+
+        let watch_directory = section
+            .get("watch_directory")
+            .ok_or("Missing 'watch_directory' in [settings]")?;
+
+        let max_lock_retries: u32 = section
+            .get("max_lock_retries")
+            .unwrap_or("30")
+            .parse()
+            .map_err(|e| format!("Invalid max_lock_retries: {}", e))?;
+
+        let lock_retry_delay_ms: u64 = section
+            .get("lock_retry_delay_ms")
+            .unwrap_or("1000")
+            .parse()
+            .map_err(|e| format!("Invalid lock_retry_delay_ms: {}", e))?;
+
+        let recursive: bool = section
+            .get("recursive")
+            .unwrap_or("false")
+            .parse()
+            .map_err(|e| format!("Invalid recursive: {}", e))?;
+
+        let catch_up_on_start: bool = section
+            .get("catch_up_on_start")
+            .unwrap_or("false")
+            .parse()
+            .map_err(|e| format!("Invalid catch_up_on_start: {}", e))?;
+
+        let startup_summary: bool = section
+            .get("startup_summary")
+            .unwrap_or("false")
+            .parse()
+            .map_err(|e| format!("Invalid startup_summary: {}", e))?;
+
+        let sweep_interval = section
+            .get("sweep_interval")
+            .map(|interval| parse_interval("sweep_interval", interval))
+            .transpose()?;
+
+        let trigger_events = match section.get("trigger_events") {
+            Some(events) => Trigger::parse_list(events)?,
+            None => Trigger::defaults(),
+        };
+
+        let ignore_older_than = section
+            .get("ignore_older_than")
+            .map(|age| parse_interval("ignore_older_than", age))
+            .transpose()?;
+
+        let scan_workers = match section.get("scan_workers") {
+            Some(workers) => match workers.parse() {
+                Ok(0) | Err(_) => return Err(format!("Invalid scan_workers '{}'", workers).into()),
+                Ok(workers) => workers,
+            },
+            None => thread::available_parallelism().map_or(1, |workers| workers.get()),
+        };
+
+        let privileges = privileges::load_privilege_settings(section)?;
+        let retry = retry::load_retry_settings(section)?;
+        let heartbeat = heartbeat::load_heartbeat_settings(section)?;
+        let logging = logging::load_log_settings(section)?;
+        let dates = dates::load_date_settings(section)?;
+        let clamav = scan::load_clamav_settings(&ini)?;
+        let content = content::load_content_settings(&ini)?;
+        let actions = actions::load_action_settings(&ini)?;
+        let schedule = schedule::load_schedule_settings(&ini)?;
+        let ledger = ledger::load_ledger_settings(&ini)?;
+        let duplicates = duplicates::load_duplicate_settings(&ini, ledger.as_ref())?;
+        let vies = vies::load_vies_settings(&ini)?;
+        let sepa = sepa::load_sepa_settings(&ini)?;
+        let currency = currency::load_currency_settings(&ini)?;
+        let events = events::load_event_settings(&ini)?;
+        let otel = telemetry::load_otel_settings(&ini)?;
+        let sentry = error_reporting::load_sentry_settings(&ini)?;
+        let http = http::load_http_settings(&ini)?;
+        #[cfg(feature = "grpc")]
+        let grpc = grpc::load_grpc_settings(&ini)?;
+        #[cfg(not(feature = "grpc"))]
+        if ini.section(Some("grpc")).is_some() {
+            return Err(ConfigError::Invalid(
+                "[grpc] needs a build with the 'grpc' feature".to_string(),
+            ));
+        }
+        let digest = digest::load_digest_settings(&ini)?;
+        let notifications = notifications::load_notification_settings(&ini)?;
+        let alerts = alerts::load_alert_settings(&ini)?;
+        let disk = disk::load_disk_settings(&ini)?;
+        let plugins = plugins::load_plugin_settings(&ini)?;
+        let remote_rules = remote_rules::load_remote_rule_settings(&ini)?;
+        let tenants = tenants::load_tenants(&ini, config_path)?;
+
+        Ok(Settings {
+            watch_directory: PathBuf::from(watch_directory),
+            max_lock_retries,
+            lock_retry_delay_ms,
+            recursive,
+            catch_up_on_start,
+            startup_summary,
+            sweep_interval,
+            trigger_events,
+            ignore_older_than,
+            scan_workers,
+            privileges,
+            retry,
+            clamav,
+            content,
+            dates,
+            actions,
+            schedule,
+            heartbeat,
+            ledger,
+            duplicates,
+            vies,
+            sepa,
+            currency,
+            events,
+            logging,
+            otel,
+            sentry,
+            http,
+            #[cfg(feature = "grpc")]
+            grpc,
+            digest,
+            notifications,
+            alerts,
+            disk,
+            plugins,
+            remote_rules,
+            tenants,
+        })
+    }
+
+    pub fn watch_directory(&self) -> &Path {
+        &self.watch_directory
+    }
+
+    /// Fails when the watch directory doesn't exist.
+    pub fn check_watch_directory(&self) -> Result<(), ConfigError> {
+        if !self.watch_directory.is_dir() {
+            return Err(ConfigError::WatchDirectory(self.watch_directory.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// Accepts a number of seconds or a number followed by `s`, `m`, `h` or `d`,
+/// such as `15m`. `name` is the setting, for errors.
+pub(crate) fn parse_interval(name: &str, value: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid {} '{}'", name, value);
+
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let multiplier = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+
+    match number.parse::<u64>() {
+        Ok(0) => Err(format!("{} must be greater than 0", name)),
+        Ok(n) => Ok(Duration::from_secs(
+            n.checked_mul(multiplier).ok_or_else(invalid)?,
+        )),
+        Err(_) => Err(invalid()),
+    }
+}
+
+/// `~/.invoicehandler` on Linux and `config.ini` in the platform's config
+/// directory elsewhere.
+pub fn default_config_path() -> PathBuf {
+    #[cfg(target_os = "linux")]
+    {
+        dirs::home_dir()
+            .expect("Failed to get home directory")
+            .join(".invoicehandler")
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        dirs::config_dir()
+            .expect("Failed to get config directory")
+            .join("invoicehandler")
+            .join("config.ini")
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        dirs::config_dir()
+            .expect("Failed to get config directory")
+            .join("invoicehandler")
+            .join("config.ini")
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    {
+        dirs::config_dir()
+            .expect("Failed to get config directory")
+            .join("invoicehandler")
+            .join("config.ini")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval() {
+        let cases: Vec<(&str, Result<u64, &str>)> = vec![
+            ("90", Ok(90)),
+            ("30s", Ok(30)),
+            ("15m", Ok(15 * 60)),
+            (" 2h ", Ok(2 * 60 * 60)),
+            ("1d", Ok(24 * 60 * 60)),
+            ("0", Err("interval must be greater than 0")),
+            ("0m", Err("interval must be greater than 0")),
+            ("", Err("Invalid interval")),
+            ("m", Err("Invalid interval")),
+            ("5w", Err("Invalid interval")),
+            ("1.5h", Err("Invalid interval")),
+            ("-5", Err("Invalid interval")),
+            ("18446744073709551615", Ok(u64::MAX)),
+            ("18446744073709551615m", Err("Invalid interval")),
+            ("213503982334602d", Err("Invalid interval")),
+            ("99999999999999999999", Err("Invalid interval")),
+        ];
+        for (value, expected) in cases {
+            match (parse_interval("interval", value), expected) {
+                (Ok(interval), Ok(secs)) => {
+                    assert_eq!(interval, Duration::from_secs(secs), "{:?}", value)
+                }
+                (Err(e), Err(prefix)) => assert!(e.starts_with(prefix), "{:?}: {}", value, e),
+                (result, expected) => panic!("{:?}: {:?}, expected {:?}", value, result, expected),
+            }
+        }
+    }
+}
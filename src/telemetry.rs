@@ -0,0 +1,175 @@
+use crate::events::FileEvent;
+use crate::logging::BoxedLayer;
+use crate::metrics::Stage;
+use opentelemetry::metrics::{Counter, Histogram};
+use opentelemetry::trace::{TraceContextExt, TracerProvider};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::Layer;
+
+pub struct OtelSettings {
+    endpoint: String,
+    service_name: String,
+}
+
+pub fn load_otel_settings(ini: &ini::Ini) -> Result<Option<OtelSettings>, String> {
+    let section = match ini.section(Some("otel")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    Ok(Some(OtelSettings {
+        endpoint: section
+            .get("endpoint")
+            .unwrap_or("http://localhost:4318")
+            .trim_end_matches('/')
+            .to_string(),
+        service_name: section
+            .get("service_name")
+            .unwrap_or("invoicehandler")
+            .to_string(),
+    }))
+}
+
+/// Any HTTP response counts: collectors answer a plain GET with an error, but
+/// only a reachable one answers at all.
+pub fn check_endpoint(settings: &OtelSettings) -> Result<(), String> {
+    match ureq::get(&settings.endpoint).call() {
+        Ok(_) | Err(ureq::Error::StatusCode(_)) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Keeps the providers alive and flushes pending spans and metrics on drop.
+pub struct Telemetry {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        let _ = self.tracer_provider.shutdown();
+        let _ = self.meter_provider.shutdown();
+    }
+}
+
+/// Sets up OTLP/HTTP export and returns the tracing layer that turns spans into
+/// OpenTelemetry traces, so each file becomes one trace.
+pub fn init(settings: &OtelSettings) -> Result<(BoxedLayer, Telemetry), String> {
+    let resource = Resource::builder()
+        .with_service_name(settings.service_name.clone())
+        .build();
+
+    let span_exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", settings.endpoint))
+        .build()
+        .map_err(|e| format!("Failed to create OTLP span exporter: {}", e))?;
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_batch_exporter(span_exporter)
+        .with_resource(resource.clone())
+        .build();
+
+    let metric_exporter = MetricExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/metrics", settings.endpoint))
+        .build()
+        .map_err(|e| format!("Failed to create OTLP metric exporter: {}", e))?;
+    let meter_provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(metric_exporter)
+        .with_resource(resource)
+        .build();
+    global::set_meter_provider(meter_provider.clone());
+
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(tracer_provider.tracer("invoicehandler"))
+        .boxed();
+
+    Ok((
+        layer,
+        Telemetry {
+            tracer_provider,
+            meter_provider,
+        },
+    ))
+}
+
+struct Instruments {
+    files: Counter<u64>,
+    duration: Histogram<f64>,
+    stage: Histogram<f64>,
+}
+
+// Without a configured meter provider the global meter is a no-op, so the
+// recording functions can be called unconditionally.
+fn instruments() -> &'static Instruments {
+    static INSTRUMENTS: OnceLock<Instruments> = OnceLock::new();
+    INSTRUMENTS.get_or_init(|| {
+        let meter = global::meter("invoicehandler");
+        Instruments {
+            files: meter
+                .u64_counter("invoicehandler.files")
+                .with_description("Files handled, by outcome and rule")
+                .build(),
+            duration: meter
+                .f64_histogram("invoicehandler.file.duration")
+                .with_description("Time spent handling a file, including lock waits")
+                .with_unit("s")
+                .build(),
+            stage: meter
+                .f64_histogram("invoicehandler.stage.duration")
+                .with_description("Time spent in each processing stage, by rule")
+                .with_unit("s")
+                .build(),
+        }
+    })
+}
+
+pub fn record_event(event: &FileEvent) {
+    let mut attributes = vec![KeyValue::new("outcome", event.outcome.as_str())];
+    if let Some(rule) = &event.rule {
+        attributes.push(KeyValue::new("rule", rule.clone()));
+    }
+    instruments().files.add(1, &attributes);
+}
+
+pub fn record_duration(elapsed: Duration) {
+    instruments().duration.record(elapsed.as_secs_f64(), &[]);
+}
+
+/// `rule` is the rule the file matched, or `None` when it matched none (or
+/// stayed locked before matching).
+pub fn record_stage(stage: Stage, rule: Option<&str>, elapsed: Duration) {
+    let mut attributes = vec![KeyValue::new("stage", stage.as_str())];
+    if let Some(rule) = rule {
+        attributes.push(KeyValue::new("rule", rule.to_string()));
+    }
+    instruments()
+        .stage
+        .record(elapsed.as_secs_f64(), &attributes);
+}
+
+/// W3C `traceparent` of the current span, for correlating published events
+/// with the trace of the file they describe.
+pub fn current_traceparent() -> Option<String> {
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+
+    if !span_context.is_valid() {
+        return None;
+    }
+
+    Some(format!(
+        "00-{}-{}-{:02x}",
+        span_context.trace_id(),
+        span_context.span_id(),
+        span_context.trace_flags().to_u8()
+    ))
+}
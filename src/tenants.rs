@@ -0,0 +1,43 @@
+use std::path::{Path, PathBuf};
+
+/// A client company whose invoices are handled by the same process but with
+/// a config of its own, listed in `[tenants]` as `name = config path`.
+pub struct Tenant {
+    pub(crate) name: String,
+    pub(crate) config_path: PathBuf,
+}
+
+/// Relative config paths are resolved against the directory of the config
+/// that lists them.
+pub fn load_tenants(ini: &ini::Ini, config_path: &Path) -> Result<Vec<Tenant>, String> {
+    let section = match ini.section(Some("tenants")) {
+        Some(section) => section,
+        None => return Ok(Vec::new()),
+    };
+
+    let base = config_path.parent().unwrap_or(Path::new(""));
+    let mut tenants: Vec<Tenant> = Vec::new();
+    for (name, path) in section.iter() {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "Invalid tenant name '{}', use letters, digits, '-' and '_'",
+                name
+            ));
+        }
+        if tenants.iter().any(|tenant| tenant.name == name) {
+            return Err(format!("Tenant '{}' is listed twice in [tenants]", name));
+        }
+        if path.is_empty() {
+            return Err(format!("Missing config path for tenant '{}'", name));
+        }
+        tenants.push(Tenant {
+            name: name.to_string(),
+            config_path: base.join(path),
+        });
+    }
+    Ok(tenants)
+}
@@ -0,0 +1,266 @@
+use crate::activity::Activity;
+use crate::control::{Command, Control};
+use crate::health::Health;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tao::event::{Event, StartCause};
+use tao::event_loop::{ControlFlow, EventLoop};
+use tracing::{error, warn};
+use tray_icon::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu};
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+
+/// Menu clicks are picked up this often.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+const RECENT_ITEMS: usize = 10;
+
+pub struct TrayState {
+    pub watch_directory: PathBuf,
+    pub health: Arc<Health>,
+    pub control: Arc<Control>,
+    pub activity: Activity,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Running,
+    Attention,
+    Paused,
+    Broken,
+}
+
+impl Status {
+    fn label(&self) -> &'static str {
+        match self {
+            Status::Running => "Running",
+            Status::Attention => "Running - files need attention",
+            Status::Paused => "Paused",
+            Status::Broken => "Not watching",
+        }
+    }
+
+    fn color(&self) -> [u8; 3] {
+        match self {
+            Status::Running => [0x2e, 0x7d, 0x32],
+            Status::Attention | Status::Paused => [0xef, 0x6c, 0x00],
+            Status::Broken => [0xc6, 0x28, 0x28],
+        }
+    }
+}
+
+struct Tray {
+    state: TrayState,
+    icon: TrayIcon,
+    status_item: MenuItem,
+    recent: Submenu,
+    toggle: MenuItem,
+    open_folder: MenuItem,
+    quit: MenuItem,
+    status: Option<Status>,
+    last_event: Option<String>,
+}
+
+impl Tray {
+    fn new(state: TrayState) -> Result<Self, String> {
+        let status_item = MenuItem::new("Starting", false, None);
+        let recent = Submenu::new("Recent activity", true);
+        let toggle = MenuItem::new("Pause", true, None);
+        let open_folder = MenuItem::new("Open watch folder", true, None);
+        let quit = MenuItem::new("Quit", true, None);
+
+        let menu = Menu::new();
+        menu.append_items(&[
+            &status_item,
+            &PredefinedMenuItem::separator(),
+            &recent,
+            &toggle,
+            &open_folder,
+            &PredefinedMenuItem::separator(),
+            &quit,
+        ])
+        .map_err(|e| e.to_string())?;
+
+        let icon = TrayIconBuilder::new()
+            .with_menu(Box::new(menu))
+            .with_tooltip("invoicehandler")
+            .with_icon(status_icon(Status::Running)?)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let mut tray = Tray {
+            state,
+            icon,
+            status_item,
+            recent,
+            toggle,
+            open_folder,
+            quit,
+            status: None,
+            last_event: None,
+        };
+        tray.refresh();
+        Ok(tray)
+    }
+
+    fn refresh(&mut self) {
+        let report = self.state.health.report();
+        let paused = self.state.control.is_paused();
+        let status = if !report.healthy {
+            Status::Broken
+        } else if paused {
+            Status::Paused
+        } else if !self.state.activity.pending().is_empty() {
+            Status::Attention
+        } else {
+            Status::Running
+        };
+
+        if self.status != Some(status) {
+            self.status = Some(status);
+            self.status_item.set_text(status.label());
+            let _ = self
+                .icon
+                .set_tooltip(Some(format!("invoicehandler - {}", status.label())));
+            match status_icon(status) {
+                Ok(icon) => {
+                    let _ = self.icon.set_icon(Some(icon));
+                }
+                Err(e) => warn!("Failed to update tray icon: {}", e),
+            }
+        }
+        self.toggle
+            .set_text(if paused { "Resume" } else { "Pause" });
+
+        let recent = self.state.activity.recent();
+        let newest = recent
+            .first()
+            .and_then(|event| event["timestamp"].as_str())
+            .map(str::to_string);
+        if newest != self.last_event {
+            self.last_event = newest;
+            while self.recent.remove_at(0).is_some() {}
+            for event in recent.iter().take(RECENT_ITEMS) {
+                let _ = self
+                    .recent
+                    .append(&MenuItem::new(activity_label(event), false, None));
+            }
+        }
+    }
+
+    /// Returns false once Quit was chosen.
+    fn handle(&mut self, event: &MenuEvent) -> bool {
+        if event.id == *self.quit.id() {
+            return false;
+        }
+
+        if event.id == *self.toggle.id() {
+            let command = if self.state.control.is_paused() {
+                Command::Resume
+            } else {
+                Command::Pause
+            };
+            if let Err(e) = self.state.control.send(command) {
+                error!("{}", e);
+            }
+        } else if event.id == *self.open_folder.id() {
+            open_folder(&self.state.watch_directory);
+        }
+        true
+    }
+}
+
+/// Runs the tray on the calling thread, which has to be the main thread on
+/// macOS. Only returns by exiting the process. On Windows the console window
+/// is closed, so logs should go to `log_file` or the event log.
+pub fn run(state: TrayState) -> ! {
+    #[cfg(windows)]
+    // SAFETY: FreeConsole has no preconditions.
+    unsafe {
+        windows_sys::Win32::System::Console::FreeConsole();
+    }
+
+    let event_loop = EventLoop::new();
+    let mut state = Some(state);
+    let mut tray: Option<Tray> = None;
+    let mut last_refresh = Instant::now();
+
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::WaitUntil(Instant::now() + POLL_INTERVAL);
+
+        // The icon can only be created once the event loop is running.
+        if let Event::NewEvents(StartCause::Init) = event {
+            match state.take().map(Tray::new) {
+                Some(Ok(created)) => tray = Some(created),
+                Some(Err(e)) => {
+                    error!("Failed to create tray icon: {}", e);
+                    std::process::exit(1);
+                }
+                None => {}
+            }
+        }
+
+        let tray = match &mut tray {
+            Some(tray) => tray,
+            None => return,
+        };
+
+        while let Ok(menu_event) = MenuEvent::receiver().try_recv() {
+            if !tray.handle(&menu_event) {
+                std::process::exit(0);
+            }
+            last_refresh = Instant::now() - REFRESH_INTERVAL;
+        }
+
+        if last_refresh.elapsed() >= REFRESH_INTERVAL {
+            tray.refresh();
+            last_refresh = Instant::now();
+        }
+    })
+}
+
+fn activity_label(event: &serde_json::Value) -> String {
+    let name = |key: &str| {
+        event[key]
+            .as_str()
+            .and_then(|path| Path::new(path).file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+
+    match event["outcome"].as_str().unwrap_or_default() {
+        "processed" => format!("{} → {}", name("path"), name("new_path")),
+        "failed" => format!("Failed: {}", name("path")),
+        _ => format!("Unmatched: {}", name("path")),
+    }
+}
+
+/// A filled circle in the status color.
+fn status_icon(status: Status) -> Result<Icon, String> {
+    const SIZE: u32 = 32;
+    let [r, g, b] = status.color();
+    let center = (SIZE as f32 - 1.0) / 2.0;
+    let radius = SIZE as f32 / 2.0 - 2.0;
+
+    let mut rgba = Vec::with_capacity((SIZE * SIZE * 4) as usize);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let distance = ((x as f32 - center).powi(2) + (y as f32 - center).powi(2)).sqrt();
+            let alpha = (radius + 0.5 - distance).clamp(0.0, 1.0);
+            rgba.extend_from_slice(&[r, g, b, (alpha * 255.0) as u8]);
+        }
+    }
+
+    Icon::from_rgba(rgba, SIZE, SIZE).map_err(|e| e.to_string())
+}
+
+fn open_folder(path: &Path) {
+    #[cfg(windows)]
+    let program = "explorer";
+    #[cfg(target_os = "macos")]
+    let program = "open";
+
+    if let Err(e) = std::process::Command::new(program).arg(path).spawn() {
+        warn!("Failed to open {}: {}", path.display(), e);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::notifications::{Notification, NotificationKind, Notifications};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const DEFAULT_URL: &str = "https://ec.europa.eu/taxation_customs/vies/rest-api";
+
+/// Checks the VAT IDs of renamed invoices with the EU's VIES service, from
+/// `[vies]`.
+pub struct ViesSettings {
+    field: String,
+    url: String,
+    timeout: Duration,
+    cache_for: Duration,
+}
+
+pub fn load_vies_settings(ini: &ini::Ini) -> Result<Option<ViesSettings>, String> {
+    let section = match ini.section(Some("vies")) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+
+    let timeout_secs: u64 = section
+        .get("timeout_secs")
+        .unwrap_or("10")
+        .parse()
+        .map_err(|e| format!("Invalid timeout_secs in [vies]: {}", e))?;
+
+    let cache_hours: u64 = section
+        .get("cache_hours")
+        .unwrap_or("24")
+        .parse()
+        .map_err(|e| format!("Invalid cache_hours in [vies]: {}", e))?;
+
+    Ok(Some(ViesSettings {
+        field: section.get("field").unwrap_or("vat_id").to_string(),
+        url: section
+            .get("url")
+            .unwrap_or(DEFAULT_URL)
+            .trim_end_matches('/')
+            .to_string(),
+        timeout: Duration::from_secs(timeout_secs),
+        cache_for: Duration::from_secs(cache_hours * 60 * 60),
+    }))
+}
+
+/// Looks up VAT IDs, remembering each answer for `cache_hours` so a
+/// supplier's invoices only cost one request a day.
+pub struct Vies<'a> {
+    settings: &'a ViesSettings,
+    agent: ureq::Agent,
+    notifications: Notifications,
+    cache: HashMap<String, (bool, Instant)>,
+}
+
+impl<'a> Vies<'a> {
+    pub fn new(settings: &'a ViesSettings, notifications: Notifications) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(settings.timeout))
+            .build()
+            .into();
+        Vies {
+            settings,
+            agent,
+            notifications,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Checks the VAT ID in `fields`, if there is one, and adds `vat_valid`
+    /// with `true` or `false`. An invalid ID raises an alert; when VIES
+    /// can't answer, the field is left out.
+    pub fn check(&mut self, path: &Path, fields: &mut BTreeMap<String, String>) {
+        let Some(vat_id) = fields.get(&self.settings.field).map(|id| normalize(id)) else {
+            return;
+        };
+        if vat_id.is_empty() {
+            return;
+        }
+
+        let valid = match self.cache.get(&vat_id) {
+            Some((valid, checked)) if checked.elapsed() < self.settings.cache_for => *valid,
+            _ => match self.lookup(&vat_id) {
+                Ok(valid) => {
+                    self.cache.insert(vat_id.clone(), (valid, Instant::now()));
+                    valid
+                }
+                Err(e) => {
+                    warn!(vat_id, error = %e, "VAT ID not checked");
+                    return;
+                }
+            },
+        };
+        fields.insert("vat_valid".to_string(), valid.to_string());
+        if valid {
+            return;
+        }
+
+        warn!(path = %path.display(), vat_id, "Invalid VAT ID");
+        let mut fields = fields.clone();
+        fields.insert("path".to_string(), path.display().to_string());
+        self.notifications.send(Notification {
+            kind: NotificationKind::Alert,
+            title: "Invalid VAT ID".to_string(),
+            body: format!(
+                "{} has the VAT ID {}, which VIES doesn't know. Send it back to the supplier.",
+                path.display(),
+                vat_id
+            ),
+            fields,
+        });
+    }
+
+    fn lookup(&self, vat_id: &str) -> Result<bool, String> {
+        let (country, number) = vat_id
+            .split_at_checked(2)
+            .filter(|(country, number)| {
+                country.chars().all(|c| c.is_ascii_uppercase()) && !number.is_empty()
+            })
+            .ok_or_else(|| format!("'{}' doesn't start with a country code", vat_id))?;
+        // VIES knows Greece as EL, but invoices often say GR.
+        let country = if country == "GR" { "EL" } else { country };
+
+        let url = format!("{}/ms/{}/vat/{}", self.settings.url, country, number);
+        let reply: serde_json::Value = self
+            .agent
+            .get(&url)
+            .call()
+            .map_err(|e| e.to_string())?
+            .body_mut()
+            .read_json()
+            .map_err(|e| e.to_string())?;
+
+        match (reply["isValid"].as_bool(), reply["userError"].as_str()) {
+            (Some(true), _) => Ok(true),
+            (Some(false), None | Some("VALID" | "INVALID")) => Ok(false),
+            (_, Some(error)) => Err(format!("VIES: {}", error)),
+            (None, None) => Err("Unexpected reply from VIES".to_string()),
+        }
+    }
+}
+
+/// Uppercase, without the spaces, dots and dashes IDs are often printed
+/// with.
+fn normalize(vat_id: &str) -> String {
+    vat_id
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '.' | '-'))
+        .collect::<String>()
+        .to_uppercase()
+}
@@ -0,0 +1,1143 @@
+use crate::activity::Activity;
+use crate::alerts::AlertMonitor;
+use crate::control::{Command, Control, Message, QueueSnapshot};
+use crate::digest;
+use crate::disk::{DiskChange, DiskMonitor};
+use crate::error::{ConfigError, ProcessError};
+use crate::events::{self, EventPublisher, EventSink, FileEvent, Outcome};
+#[cfg(feature = "grpc")]
+use crate::grpc;
+use crate::health::Health;
+use crate::heartbeat::Heartbeat;
+use crate::http;
+use crate::metrics::Metrics;
+use crate::notifications::{Notification, NotificationKind, Notifications};
+use crate::pipeline::{self, Pipeline};
+use crate::plugins::Plugins;
+use crate::privileges;
+use crate::remote_rules;
+use crate::rules::RuleSet;
+use crate::schedule::ScheduleSettings;
+use crate::settings::{self, Settings};
+use crate::tenants::Tenant;
+use notify::event::{AccessKind, AccessMode, ModifyKind};
+use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, info_span, warn};
+
+/// The daemon: watches the watch directory and the config file, runs new
+/// files through a [`Pipeline`] and serves the configured control
+/// interfaces. File events and control commands are handled in order on the
+/// thread that calls [`Watcher::run`], and those of each tenant on a thread
+/// of its own.
+pub struct Watcher {
+    config_path: PathBuf,
+    settings: Settings,
+    rules: RuleSet,
+    events: EventPublisher,
+    plugins: Plugins,
+    notifications: Notifications,
+    metrics: Metrics,
+    health: Arc<Health>,
+    control: Arc<Control>,
+    // Only the tray reads the activity; every other consumer gets a clone.
+    #[cfg(all(feature = "tray", any(windows, target_os = "macos")))]
+    activity: Activity,
+    tx: Sender<Message>,
+    rx: Receiver<Message>,
+    tenants: Vec<(String, Watcher)>,
+    limits: RunLimits,
+}
+
+/// When the watcher stops on its own, from `--max-files` and `--run-for`, so
+/// tests and canaries can run the real binary for a known stretch. Without
+/// either it runs until it is stopped.
+#[derive(Clone, Copy, Default)]
+pub struct RunLimits {
+    /// Files of the main config that were processed, failed or unmatched.
+    /// Locked files only count once they are given up on.
+    pub max_files: Option<usize>,
+    pub run_for: Option<Duration>,
+}
+
+impl RunLimits {
+    /// Reads the limits from the command line, ignoring other arguments.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut limits = RunLimits::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--max-files" => {
+                    let value = args.next().ok_or("--max-files needs a number")?;
+                    match value.parse() {
+                        Ok(0) | Err(_) => {
+                            return Err(format!("Invalid --max-files '{}'", value));
+                        }
+                        Ok(max) => limits.max_files = Some(max),
+                    }
+                }
+                "--run-for" => {
+                    let value = args
+                        .next()
+                        .ok_or("--run-for needs a duration, such as 10m")?;
+                    limits.run_for = Some(settings::parse_interval("--run-for", value)?);
+                }
+                _ => {}
+            }
+        }
+        Ok(limits)
+    }
+}
+
+/// Counts the files handled so far, for `--max-files`.
+#[derive(Clone, Default)]
+struct FileCounter(Arc<AtomicUsize>);
+
+impl EventSink for FileCounter {
+    fn name(&self) -> &'static str {
+        "limits"
+    }
+
+    fn publish(&self, event: &FileEvent, _payload: &str) -> Result<(), String> {
+        let locked = event.outcome == Outcome::Failed
+            && event.error.as_deref() == Some(events::LOCKED_ERROR);
+        if !locked {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// The limits of a running event loop.
+struct Limits {
+    max_files: Option<usize>,
+    deadline: Option<Instant>,
+    files: FileCounter,
+}
+
+impl Limits {
+    fn reached(&self) -> bool {
+        let files = self
+            .max_files
+            .is_some_and(|max| self.files.0.load(Ordering::Relaxed) >= max);
+        files
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// A kind of file event that gets a file processed, from `trigger_events`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Trigger {
+    Create,
+    /// Writes and metadata changes.
+    Modify,
+    /// The file was closed after being opened for writing, so it is
+    /// complete unless it is opened again. Only reported on Linux.
+    CloseWrite,
+    /// Renamed or moved into the watch directory.
+    Moved,
+}
+
+impl Trigger {
+    const NAMES: [(&'static str, Trigger); 4] = [
+        ("create", Trigger::Create),
+        ("modify", Trigger::Modify),
+        ("close_write", Trigger::CloseWrite),
+        ("moved", Trigger::Moved),
+    ];
+
+    /// Close-write where Linux reports it, so files aren't picked up while
+    /// they are still being written, and otherwise every change.
+    pub(crate) fn defaults() -> Vec<Trigger> {
+        if cfg!(target_os = "linux") {
+            vec![Trigger::CloseWrite, Trigger::Moved]
+        } else {
+            vec![Trigger::Create, Trigger::Modify, Trigger::Moved]
+        }
+    }
+
+    pub(crate) fn parse_list(value: &str) -> Result<Vec<Trigger>, String> {
+        let triggers = value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let trigger = Trigger::NAMES
+                    .iter()
+                    .find(|(known, _)| *known == name)
+                    .map(|(_, trigger)| *trigger)
+                    .ok_or_else(|| {
+                        format!(
+                            "Unknown trigger event '{}', use create, modify, close_write or moved",
+                            name
+                        )
+                    })?;
+                if trigger == Trigger::CloseWrite && !cfg!(target_os = "linux") {
+                    return Err(
+                        "The close_write trigger event is only reported on Linux".to_string()
+                    );
+                }
+                Ok(trigger)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if triggers.is_empty() {
+            return Err("No trigger_events".to_string());
+        }
+        Ok(triggers)
+    }
+
+    fn matches(self, kind: &EventKind) -> bool {
+        match self {
+            Trigger::Create => matches!(kind, EventKind::Create(_)),
+            Trigger::Modify => {
+                matches!(kind, EventKind::Modify(modify) if !matches!(modify, ModifyKind::Name(_)))
+            }
+            Trigger::CloseWrite => matches!(
+                kind,
+                EventKind::Access(AccessKind::Close(AccessMode::Write))
+            ),
+            Trigger::Moved => matches!(kind, EventKind::Modify(ModifyKind::Name(_))),
+        }
+    }
+}
+
+/// How often the watch directory is checked for having gone away, e.g. with
+/// an unmounted share or a dropped VPN, which the watch itself doesn't
+/// report on every platform.
+const WATCH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// The first and the longest wait between attempts to watch the directory
+/// again once it is gone.
+const REWATCH_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_REWATCH_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// The watch on the watch directory, which is set up again when the
+/// directory comes back after it went away.
+struct DirectoryWatch {
+    // Watching stops when this is dropped.
+    watcher: RecommendedWatcher,
+    mode: RecursiveMode,
+    next_check: Instant,
+    /// The wait before the next attempt while the directory is gone.
+    lost: Option<Duration>,
+}
+
+impl DirectoryWatch {
+    fn time_until_check(&self) -> Duration {
+        self.next_check.saturating_duration_since(Instant::now())
+    }
+
+    /// Checks right away, e.g. when the watch reported the directory removed.
+    fn check_now(&mut self) {
+        if self.lost.is_none() {
+            self.next_check = Instant::now();
+        }
+    }
+
+    /// Checks that the directory is still there, or tries to watch it again
+    /// when it is gone. Returns `true` when it is watched again, so the files
+    /// that arrived in the meantime can be caught up on.
+    fn check_if_due(
+        &mut self,
+        directory: &Path,
+        health: &Health,
+        notifications: &Notifications,
+    ) -> bool {
+        let now = Instant::now();
+        if now < self.next_check {
+            return false;
+        }
+
+        let Some(backoff) = self.lost else {
+            self.next_check = now + WATCH_CHECK_INTERVAL;
+            if directory.is_dir() {
+                return false;
+            }
+            warn!(
+                path = %directory.display(),
+                "Watch directory is gone, waiting for it to come back"
+            );
+            health.watcher_failed(format!("{} is gone", directory.display()));
+            // The old watch may still follow the directory if it was moved.
+            let _ = self.watcher.unwatch(directory);
+            self.lost = Some(REWATCH_BACKOFF);
+            self.next_check = now + REWATCH_BACKOFF;
+
+            let mut fields = BTreeMap::new();
+            fields.insert("path".to_string(), directory.display().to_string());
+            notifications.send(Notification {
+                kind: NotificationKind::Alert,
+                title: "Watch directory unavailable".to_string(),
+                body: format!(
+                    "{} is gone, e.g. because the share was unmounted. It is watched again once it is back.",
+                    directory.display()
+                ),
+                fields,
+            });
+            return false;
+        };
+
+        let result = if directory.is_dir() {
+            self.watcher
+                .watch(directory, self.mode)
+                .map_err(|e| e.to_string())
+        } else {
+            Err("not found".to_string())
+        };
+        match result {
+            Ok(()) => {
+                info!(path = %directory.display(), "Watch directory is back, watching it again");
+                health.watcher_recovered();
+                self.lost = None;
+                self.next_check = now + WATCH_CHECK_INTERVAL;
+                true
+            }
+            Err(e) => {
+                let backoff = (backoff * 2).min(MAX_REWATCH_BACKOFF);
+                debug!(error = %e, retry_in = ?backoff, "Watch directory still unavailable");
+                self.lost = Some(backoff);
+                self.next_check = now + backoff;
+                false
+            }
+        }
+    }
+}
+
+impl Watcher {
+    /// Loads the plugins, connects the configured event sinks and notifiers
+    /// and starts the HTTP and gRPC servers, and does the same for every
+    /// tenant. Files are only processed once [`Watcher::run`] is called.
+    pub fn new(
+        config_path: PathBuf,
+        settings: Settings,
+        rules: RuleSet,
+    ) -> Result<Self, ProcessError> {
+        let plugins = Plugins::load(settings.plugins.as_ref()).map_err(ProcessError::Plugins)?;
+
+        let mut events = EventPublisher::connect(&settings.events).map_err(ProcessError::Events)?;
+        plugins.add_sinks(&mut events);
+
+        let notifications = Notifications::start(&settings.notifications);
+        events.add_sink(Box::new(notifications.clone()));
+
+        if let Some(alerts) = &settings.alerts {
+            events.add_sink(Box::new(AlertMonitor::new(alerts, notifications.clone())));
+        }
+
+        if let Some(digest) = &settings.digest {
+            events.add_sink(Box::new(digest::start(digest, notifications.clone())));
+        }
+
+        let health = Arc::new(Health::new(settings.watch_directory.clone()));
+
+        let (tx, rx) = channel();
+
+        let control = Arc::new(Control::new(settings.watch_directory.clone(), tx.clone()));
+        control.set_rules(&rules);
+
+        let activity = Activity::new(&settings.watch_directory);
+        events.add_sink(Box::new(activity.clone()));
+
+        let metrics = Metrics::default();
+        events.add_sink(Box::new(metrics.clone()));
+
+        if let Some(http) = &settings.http {
+            http::start(
+                http,
+                health.clone(),
+                control.clone(),
+                activity.clone(),
+                metrics.clone(),
+            )
+            .map_err(ProcessError::Http)?;
+        }
+
+        #[cfg(feature = "grpc")]
+        if let Some(grpc) = &settings.grpc {
+            let sink =
+                grpc::start(grpc, health.clone(), control.clone()).map_err(ProcessError::Grpc)?;
+            events.add_sink(Box::new(sink));
+        }
+
+        let tenants = load_tenants(&settings)?;
+
+        Ok(Watcher {
+            config_path,
+            settings,
+            rules,
+            events,
+            plugins,
+            notifications,
+            metrics,
+            health,
+            control,
+            #[cfg(all(feature = "tray", any(windows, target_os = "macos")))]
+            activity,
+            tx,
+            rx,
+            tenants,
+            limits: RunLimits::default(),
+        })
+    }
+
+    /// Receives every published event, after the configured sinks.
+    pub fn add_sink(&mut self, sink: Box<dyn EventSink>) {
+        self.events.add_sink(sink);
+    }
+
+    /// Makes [`Watcher::run`] return once a limit is reached. Tenants keep
+    /// running until then and aren't counted.
+    pub fn set_limits(&mut self, limits: RunLimits) {
+        self.limits = limits;
+    }
+
+    /// Starts watching and handles events until the process exits, with each
+    /// tenant on a thread of its own. Fails when a directory or config file
+    /// can't be watched.
+    pub fn run(mut self) -> Result<(), ProcessError> {
+        let tenants = std::mem::take(&mut self.tenants);
+        let started = self.start()?;
+        let tenants = tenants
+            .into_iter()
+            .map(|(name, watcher)| {
+                let span = info_span!("tenant", tenant = %name);
+                match span.in_scope(|| watcher.start()) {
+                    Ok(started) => Ok((span, started)),
+                    Err(source) => Err(ProcessError::Tenant {
+                        name,
+                        source: Box::new(source),
+                    }),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Everything that may need root, from binding the HTTP port to
+        // watching a restricted directory, is done by now.
+        if let Some(privileges) = &started.settings.privileges {
+            privileges::drop_privileges(privileges).map_err(ProcessError::Privileges)?;
+            info!("Dropped privileges");
+        }
+
+        for (span, tenant) in tenants {
+            thread::spawn(move || span.in_scope(|| tenant.run()));
+        }
+
+        info!("File watcher started. Press Ctrl+C to stop.");
+        started.run();
+
+        Ok(())
+    }
+
+    /// Recovers after an interrupted run and sets up the watches.
+    fn start(self) -> Result<Started, ProcessError> {
+        let Watcher {
+            config_path,
+            settings,
+            rules,
+            mut events,
+            plugins,
+            notifications,
+            metrics,
+            health,
+            control,
+            tx,
+            rx,
+            limits,
+            ..
+        } = self;
+
+        let files = FileCounter::default();
+        events.add_sink(Box::new(files.clone()));
+
+        settings
+            .actions
+            .recover(&settings.watch_directory, settings.recursive);
+
+        let watcher_health = health.clone();
+        let mut watcher = RecommendedWatcher::new(
+            move |result: Result<Event, notify::Error>| match result {
+                Ok(event) => {
+                    watcher_health.event_queued();
+                    let _ = tx.send(Message::File(event, Instant::now()));
+                }
+                Err(e) => {
+                    error!("File watcher error: {}", e);
+                    watcher_health.watcher_failed(e.to_string());
+                }
+            },
+            Config::default(),
+        )
+        .map_err(ProcessError::Watcher)?;
+
+        let watch_mode = if settings.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        for (path, mode) in [
+            (&settings.watch_directory, watch_mode),
+            (&config_path, RecursiveMode::NonRecursive),
+        ] {
+            watcher
+                .watch(path, mode)
+                .map_err(|source| ProcessError::Watch {
+                    path: path.clone(),
+                    source,
+                })?;
+        }
+
+        info!("Watching directory: {:?}", settings.watch_directory);
+        info!("Watching config: {:?}", config_path);
+        info!("Loaded {} translation rules", rules.len());
+
+        Ok(Started {
+            config_path,
+            settings,
+            rules,
+            events,
+            plugins,
+            notifications,
+            metrics,
+            health,
+            control,
+            rx,
+            limits: Limits {
+                max_files: limits.max_files,
+                deadline: limits.run_for.map(|run_for| Instant::now() + run_for),
+                files,
+            },
+            watch: DirectoryWatch {
+                watcher,
+                mode: watch_mode,
+                next_check: Instant::now() + WATCH_CHECK_INTERVAL,
+                lost: None,
+            },
+        })
+    }
+
+    /// Runs the watcher on a background thread and a system tray icon on the
+    /// calling thread, which has to be the main thread on macOS.
+    #[cfg(all(feature = "tray", any(windows, target_os = "macos")))]
+    pub fn run_in_tray(self) -> ! {
+        let state = crate::tray::TrayState {
+            watch_directory: self.settings.watch_directory.clone(),
+            health: self.health.clone(),
+            control: self.control.clone(),
+            activity: self.activity.clone(),
+        };
+        std::thread::spawn(move || {
+            if let Err(e) = self.run() {
+                error!("{}", e);
+                std::process::exit(e.exit_code());
+            }
+        });
+        crate::tray::run(state);
+    }
+}
+
+/// Starts a watcher for every tenant in `[tenants]`. Tenants share the
+/// process, its logging and its user, and nothing else: each has its own
+/// directories, rules, ledger, counters and notifications.
+fn load_tenants(settings: &Settings) -> Result<Vec<(String, Watcher)>, ProcessError> {
+    let mut watch_directories = vec![canonical(&settings.watch_directory)];
+    settings
+        .tenants
+        .iter()
+        .map(|tenant| {
+            let _tenant = info_span!("tenant", tenant = %tenant.name).entered();
+            match load_tenant(tenant, &mut watch_directories) {
+                Ok(watcher) => Ok((tenant.name.clone(), watcher)),
+                Err(source) => Err(ProcessError::Tenant {
+                    name: tenant.name.clone(),
+                    source: Box::new(source),
+                }),
+            }
+        })
+        .collect()
+}
+
+fn load_tenant(
+    tenant: &Tenant,
+    watch_directories: &mut Vec<PathBuf>,
+) -> Result<Watcher, ProcessError> {
+    let settings = Settings::load(&tenant.config_path)?;
+    if !settings.tenants.is_empty() {
+        return Err(ConfigError::from("[tenants] can only be used in the main config").into());
+    }
+    if settings.privileges.is_some() {
+        return Err(ConfigError::from("user and group can only be set in the main config").into());
+    }
+
+    settings.check_watch_directory()?;
+    let watch_directory = canonical(&settings.watch_directory);
+    if watch_directories.contains(&watch_directory) {
+        return Err(ConfigError::Invalid(format!(
+            "{} is already watched by another tenant",
+            settings.watch_directory.display()
+        ))
+        .into());
+    }
+    watch_directories.push(watch_directory);
+
+    let rules = RuleSet::load(&tenant.config_path)?;
+    Watcher::new(tenant.config_path.clone(), settings, rules)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// A watcher whose watches are set up, ready to handle events.
+struct Started {
+    config_path: PathBuf,
+    settings: Settings,
+    rules: RuleSet,
+    events: EventPublisher,
+    plugins: Plugins,
+    notifications: Notifications,
+    metrics: Metrics,
+    health: Arc<Health>,
+    control: Arc<Control>,
+    rx: Receiver<Message>,
+    limits: Limits,
+    watch: DirectoryWatch,
+}
+
+impl Started {
+    fn run(self) {
+        let Started {
+            config_path,
+            settings,
+            rules,
+            events,
+            plugins,
+            notifications,
+            metrics,
+            health,
+            control,
+            rx,
+            limits,
+            watch,
+        } = self;
+
+        let pipeline =
+            Pipeline::with_events(&settings, events, plugins, notifications.clone(), metrics);
+        EventLoop {
+            config_path: &config_path,
+            settings: &settings,
+            rules,
+            pipeline,
+            health,
+            control,
+            notifications,
+            rx,
+            limits,
+            watch,
+        }
+        .run();
+    }
+}
+
+/// State owned by the event loop, which processes file events and control
+/// commands in order.
+struct EventLoop<'a> {
+    config_path: &'a Path,
+    settings: &'a Settings,
+    rules: RuleSet,
+    pipeline: Pipeline<'a>,
+    health: Arc<Health>,
+    control: Arc<Control>,
+    notifications: Notifications,
+    rx: Receiver<Message>,
+    limits: Limits,
+    watch: DirectoryWatch,
+}
+
+impl EventLoop<'_> {
+    fn run(self) {
+        let EventLoop {
+            config_path,
+            settings,
+            mut rules,
+            mut pipeline,
+            health,
+            control,
+            notifications,
+            rx,
+            limits,
+            mut watch,
+        } = self;
+
+        let mut heartbeat = settings.heartbeat.as_ref().map(Heartbeat::new);
+
+        let mut disk = settings.disk.as_ref().map(|disk| {
+            let mut paths = vec![settings.watch_directory.clone()];
+            if let Some(dir) = settings.ledger.as_ref().and_then(|l| l.file.parent()) {
+                if !dir.as_os_str().is_empty() {
+                    paths.push(dir.to_path_buf());
+                }
+            }
+            DiskMonitor::new(disk, paths, notifications.clone())
+        });
+        let mut auto_pause = AutoPause::default();
+
+        // Files seen while paused, processed on resume.
+        let mut held: BTreeSet<PathBuf> = BTreeSet::new();
+
+        if let Some(remote) = &settings.remote_rules {
+            remote_rules::start(remote, control.clone());
+        }
+
+        if let Some(schedule) = &settings.schedule {
+            check_quiet_hours(schedule, &mut auto_pause);
+        }
+        auto_pause.apply(&control);
+
+        match pipeline::scan(settings, &rules) {
+            Ok(scan) => {
+                if settings.catch_up_on_start {
+                    let _catch_up = info_span!("catch_up", trigger = "start").entered();
+                    catch_up_on(
+                        scan.unprocessed.clone(),
+                        &mut pipeline,
+                        &rules,
+                        &control,
+                        &mut held,
+                        &limits,
+                    );
+                }
+                report_backlog(&scan, settings, &pipeline, &notifications);
+            }
+            Err(e) => error!(error = %e, "Scanning the watch directory failed"),
+        }
+
+        // Catches files the watcher never reported, e.g. on network shares.
+        let mut last_sweep = Instant::now();
+
+        loop {
+            if limits.reached() {
+                info!("Run limit reached, stopping");
+                break;
+            }
+
+            if let Some(heartbeat) = &mut heartbeat {
+                heartbeat.beat_if_due();
+            }
+
+            if let Some(disk) = &mut disk {
+                match disk.check_if_due() {
+                    Some(DiskChange::Low) if disk.pauses() => {
+                        warn!("Pausing processing until disk space is freed");
+                        auto_pause.disk_low = true;
+                    }
+                    Some(DiskChange::Recovered) if auto_pause.disk_low => {
+                        info!("Disk space freed");
+                        auto_pause.disk_low = false;
+                    }
+                    _ => {}
+                }
+            }
+
+            if let Some(schedule) = &settings.schedule {
+                check_quiet_hours(schedule, &mut auto_pause);
+            }
+            auto_pause.apply(&control);
+
+            if watch.check_if_due(&settings.watch_directory, &health, &notifications) {
+                catch_up(
+                    "remount",
+                    settings,
+                    &mut pipeline,
+                    &rules,
+                    &control,
+                    &mut held,
+                    &limits,
+                );
+            }
+
+            if let Some(interval) = settings.sweep_interval {
+                if last_sweep.elapsed() >= interval {
+                    catch_up(
+                        "sweep",
+                        settings,
+                        &mut pipeline,
+                        &rules,
+                        &control,
+                        &mut held,
+                        &limits,
+                    );
+                    last_sweep = Instant::now();
+                }
+            }
+
+            let paused = control.is_paused();
+
+            if !paused {
+                pipeline.process_due_retries(&rules);
+            }
+
+            control.set_queue(QueueSnapshot {
+                held: held.iter().cloned().collect(),
+                retry: pipeline.retry_snapshot(),
+            });
+
+            let timeout = [
+                heartbeat.as_ref().map(Heartbeat::time_until_due),
+                disk.as_ref().map(DiskMonitor::time_until_due),
+                settings
+                    .schedule
+                    .as_ref()
+                    .map(|schedule| schedule.time_until_change(schedule.now())),
+                pipeline.time_until_retry().filter(|_| !paused),
+                settings
+                    .sweep_interval
+                    .map(|interval| interval.saturating_sub(last_sweep.elapsed())),
+                limits
+                    .deadline
+                    .map(|deadline| deadline.saturating_duration_since(Instant::now())),
+                Some(watch.time_until_check()),
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+
+            let message = match timeout {
+                Some(timeout) => match rx.recv_timeout(timeout) {
+                    Ok(message) => message,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match rx.recv() {
+                    Ok(message) => message,
+                    Err(_) => break,
+                },
+            };
+
+            let (event, received) = match message {
+                Message::File(event, received) => (event, received),
+                Message::Command(command) => {
+                    let _command = info_span!("command").entered();
+                    match command {
+                        Command::Pause => {
+                            control.set_paused(true);
+                            info!("Processing paused");
+                        }
+                        Command::Resume => {
+                            control.set_paused(false);
+                            info!(held = held.len(), "Processing resumed");
+                            for path in std::mem::take(&mut held) {
+                                if limits.reached() {
+                                    break;
+                                }
+                                let _ = pipeline.process(&path, &rules);
+                            }
+                        }
+                        Command::Reload => reload_rules(config_path, &mut rules, &health, &control),
+                        Command::Reprocess(path) => {
+                            pipeline.forget_rename(&path);
+                            if control.is_paused() {
+                                info!(path = %path.display(), "Holding file to reprocess while paused");
+                                held.insert(path);
+                            } else {
+                                info!(path = %path.display(), "Reprocessing file");
+                                held.remove(&path);
+                                let _ = pipeline.process(&path, &rules);
+                            }
+                        }
+                        Command::CatchUp => catch_up(
+                            "api",
+                            settings,
+                            &mut pipeline,
+                            &rules,
+                            &control,
+                            &mut held,
+                            &limits,
+                        ),
+                    }
+                    continue;
+                }
+            };
+
+            health.event_received();
+
+            let _event = info_span!("event", kind = ?event.kind).entered();
+            debug!("Event received");
+            match event.kind {
+                EventKind::Remove(_) if event.paths.contains(&settings.watch_directory) => {
+                    watch.check_now();
+                }
+                kind => {
+                    let config_changed =
+                        matches!(kind, EventKind::Create(_) | EventKind::Modify(_));
+                    let triggered = settings.trigger_events.iter().any(|t| t.matches(&kind));
+                    let closed = Trigger::CloseWrite.matches(&kind);
+                    for path in &event.paths {
+                        if path == config_path {
+                            if config_changed {
+                                info!("Config file changed, reloading rules...");
+                                reload_rules(config_path, &mut rules, &health, &control);
+                            }
+                        } else if triggered {
+                            if pipeline.renamed_recently(path, received) {
+                                debug!("Ignoring event for renamed file {:?}", &path);
+                                continue;
+                            }
+                            if closed && pipeline.checked_recently(path, received) {
+                                debug!("Ignoring the close of the lock check of {:?}", &path);
+                                continue;
+                            }
+
+                            if control.is_paused() {
+                                debug!("Holding {:?} while paused", &path);
+                                held.insert(path.clone());
+                                continue;
+                            }
+
+                            debug!("Found file at {:?}", &path);
+                            let _ = pipeline.process(path, &rules);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// What pauses processing on its own: low disk space with `pause_below`,
+/// and quiet hours. Each is kept apart from the pause of the control API,
+/// and processing is only resumed once neither holds it any more.
+#[derive(Default)]
+struct AutoPause {
+    disk_low: bool,
+    quiet: bool,
+    /// Whether processing was paused by this rather than through the
+    /// control API, so that only this pause is lifted.
+    paused: bool,
+}
+
+impl AutoPause {
+    /// Pauses or resumes processing for the current state. The pause takes
+    /// effect right away so that nothing is processed once it started.
+    fn apply(&mut self, control: &Control) {
+        match self.update(control.is_paused()) {
+            Some(Command::Pause) => control.set_paused(true),
+            Some(command) => {
+                info!("Resuming processing");
+                let _ = control.send(command);
+            }
+            None => {}
+        }
+    }
+
+    /// The command that brings processing in line, given whether it is
+    /// `paused` now. Processing that is already paused is left alone, and
+    /// when it is resumed through the control API while held, it is paused
+    /// again only if this never paused it.
+    fn update(&mut self, paused: bool) -> Option<Command> {
+        let holds = self.disk_low || self.quiet;
+        if holds && !self.paused && !paused {
+            self.paused = true;
+            Some(Command::Pause)
+        } else if !holds && self.paused {
+            self.paused = false;
+            paused.then_some(Command::Resume)
+        } else {
+            None
+        }
+    }
+}
+
+/// Holds processing while a quiet hours window is on.
+fn check_quiet_hours(schedule: &ScheduleSettings, auto_pause: &mut AutoPause) {
+    let quiet = schedule.is_quiet(schedule.now());
+    if quiet && !auto_pause.quiet {
+        info!("Quiet hours started, deferring processing");
+    } else if !quiet && auto_pause.quiet {
+        info!("Quiet hours ended");
+    }
+    auto_pause.quiet = quiet;
+}
+
+/// Processes the files that arrived while the daemon wasn't watching, or
+/// holds them when processing is paused. `trigger` says why, for the log.
+fn catch_up(
+    trigger: &str,
+    settings: &Settings,
+    pipeline: &mut Pipeline,
+    rules: &RuleSet,
+    control: &Control,
+    held: &mut BTreeSet<PathBuf>,
+    limits: &Limits,
+) {
+    let _catch_up = info_span!("catch_up", trigger).entered();
+    match pipeline::unprocessed_files(settings, rules) {
+        Ok(files) => catch_up_on(files, pipeline, rules, control, held, limits),
+        Err(e) => error!(error = %e, "Catch-up failed"),
+    }
+}
+
+/// Processes or holds `files`, except the locked ones, which keep their
+/// place in the retry queue.
+fn catch_up_on(
+    files: Vec<PathBuf>,
+    pipeline: &mut Pipeline,
+    rules: &RuleSet,
+    control: &Control,
+    held: &mut BTreeSet<PathBuf>,
+    limits: &Limits,
+) {
+    let files: Vec<PathBuf> = files
+        .into_iter()
+        .filter(|path| !pipeline.is_queued(path))
+        .collect();
+    if files.is_empty() {
+        debug!("No unprocessed files");
+        return;
+    }
+
+    info!(files = files.len(), "Catching up on unprocessed files");
+    if control.is_paused() {
+        held.extend(files);
+    } else {
+        pipeline.process_batch(&files, rules, || limits.reached());
+    }
+}
+
+/// Keeps the previous rules when the new ones fail to load.
+fn reload_rules(config_path: &Path, rules: &mut RuleSet, health: &Health, control: &Control) {
+    match RuleSet::load(config_path) {
+        Ok(new_rules) => {
+            *rules = new_rules;
+            control.set_rules(rules);
+            health.config_loaded(Ok(()));
+            info!("Reloaded {} translation rules", rules.len());
+        }
+        Err(e) => {
+            error!("Failed to reload config: {}. Keeping old rules.", e);
+            health.config_loaded(Err(e.to_string()));
+        }
+    }
+}
+
+/// Logs what is left of the backlog the startup scan found, once the start
+/// catch-up is done, and sends it as a `summary` notification with
+/// `startup_summary`. Waiting files are the ones a rule would still rename:
+/// held while paused, failed, or all of them without `catch_up_on_start`.
+fn report_backlog(
+    scan: &pipeline::Scan,
+    settings: &Settings,
+    pipeline: &Pipeline,
+    notifications: &Notifications,
+) {
+    let locked = scan
+        .unprocessed
+        .iter()
+        .filter(|path| pipeline.is_queued(path))
+        .count();
+    let waiting = scan
+        .unprocessed
+        .iter()
+        .filter(|path| path.exists() && !pipeline.is_queued(path))
+        .count();
+    info!(
+        waiting,
+        unmatched = scan.unmatched,
+        locked,
+        "Backlog after startup"
+    );
+
+    if !settings.startup_summary {
+        return;
+    }
+    let mut notification = Notification::summary(format!(
+        "{}: {} file(s) waiting, {} matched no rule, {} locked.",
+        settings.watch_directory.display(),
+        waiting,
+        scan.unmatched,
+        locked
+    ));
+    notification.title = "invoicehandler started".to_string();
+    for (name, value) in [
+        ("waiting", waiting),
+        ("unmatched", scan.unmatched),
+        ("locked", locked),
+    ] {
+        notification
+            .fields
+            .insert(name.to_string(), value.to_string());
+    }
+    notification.fields.insert(
+        "path".to_string(),
+        settings.watch_directory.display().to_string(),
+    );
+    notifications.send(notification);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A state change, whether processing is paused after it, and the
+    /// command that should follow.
+    type Step = (&'static str, fn(&mut AutoPause), bool, Option<&'static str>);
+
+    fn check(steps: &[Step]) {
+        let mut auto_pause = AutoPause::default();
+        for (step, change, paused, expected) in steps {
+            change(&mut auto_pause);
+            let command = auto_pause.update(*paused).map(|command| match command {
+                Command::Pause => "pause",
+                Command::Resume => "resume",
+                _ => "other",
+            });
+            assert_eq!(command, *expected, "{}", step);
+        }
+    }
+
+    #[test]
+    fn auto_pause() {
+        // Low disk during quiet hours keeps processing paused after them.
+        check(&[
+            (
+                "quiet hours start",
+                |p| p.quiet = true,
+                false,
+                Some("pause"),
+            ),
+            ("disk low", |p| p.disk_low = true, true, None),
+            ("quiet hours end", |p| p.quiet = false, true, None),
+            ("disk freed", |p| p.disk_low = false, true, Some("resume")),
+        ]);
+        // Disk low while paused through the control API is kept.
+        check(&[
+            ("disk low", |p| p.disk_low = true, true, None),
+            ("resumed by the API", |_| {}, false, Some("pause")),
+            ("disk freed", |p| p.disk_low = false, true, Some("resume")),
+        ]);
+        // A pause of the control API is never lifted.
+        check(&[
+            ("quiet hours start", |p| p.quiet = true, true, None),
+            ("quiet hours end", |p| p.quiet = false, true, None),
+            ("disk low", |p| p.disk_low = true, true, None),
+            ("disk freed", |p| p.disk_low = false, true, None),
+        ]);
+        // Resuming through the API during quiet hours overrides them.
+        check(&[
+            (
+                "quiet hours start",
+                |p| p.quiet = true,
+                false,
+                Some("pause"),
+            ),
+            ("resumed by the API", |_| {}, false, None),
+            ("quiet hours end", |p| p.quiet = false, false, None),
+        ]);
+    }
+}